@@ -17,13 +17,29 @@ if [ -z "${triplet}" ]; then
     exit 1
 fi
 echo "Current target triplet: ${triplet}"
+
+echo "Building the standard library"
+if ! (cd std && cargo run ${mode} -- buildpkg) &> /tmp/compile_output.log; then
+	echo "Failed to build the standard library"
+	cat /tmp/compile_output.log
+	exit 1
+fi
+
 fail_count=0
 success_count=0
 
 for file in testcode/*.mhr; do
 	name=$(basename -s .mhr ${file})
 	echo "Testing ${name}"
-	if ! cargo run ${mode} -- build ${file} &> /tmp/compile_output.log; then
+	c_file=testcode/${name}.c
+	if [ -f ${c_file} ]; then
+		# Tests with a C file are built as a library, and linked with the C code, which contains main
+		build="cargo run ${mode} -- build --lib static ${file} && gcc -o build/${triplet}/${name}/${name} ${c_file} build/${triplet}/${name}/lib${name}.a"
+	else
+		build="cargo run ${mode} -- build ${file}"
+	fi
+
+	if ! eval ${build} &> /tmp/compile_output.log; then
 		echo "*********************"
 		echo "  Compile failed"
 		cat /tmp/compile_output.log
@@ -41,6 +57,21 @@ for file in testcode/*.mhr; do
 			echo "  Run succeeded"
 		fi
 	fi
+
+	# Run the test again with the interpreter, tests which need C code or std can only be compiled
+	if [ ! -f ${c_file} ] && ! grep -q "import std" ${file}; then
+		cargo run ${mode} -- interp ${file} &> /tmp/interp_output.log
+		interp_ret_value=$?
+		test_expected_ret_value=$(head -n 1 $file | cut -b 6-)
+		if [ "$interp_ret_value" -ne "$test_expected_ret_value" ]; then
+			fail_count=$((fail_count + 1))
+			echo "  Interpreter failed, expected $test_expected_ret_value, got $interp_ret_value"
+			cat /tmp/interp_output.log
+		else
+			success_count=$((success_count + 1))
+			echo "  Interpreter succeeded"
+		fi
+	fi
 done 
 
 echo "Tests:"
@@ -23,6 +23,7 @@ pub struct Binding
 pub struct GlobalBinding
 {
     pub mutable: bool,
+    pub constant: bool,
     pub name: String,
     pub init: Expression,
     pub typ: Type,
@@ -71,6 +72,7 @@ pub fn global_binding(name: String, init: Expression, mutable: bool, span: Span)
 {
     GlobalBinding{
         mutable: mutable,
+        constant: false,
         name: name,
         init: init,
         typ: Type::Unknown,
@@ -112,7 +114,7 @@ impl TreePrinter for GlobalBinding
     fn print(&self, level: usize)
     {
         let p = prefix(level);
-        println!("{}global {} ({}) =", p, self.name, self.span);
+        println!("{}{} {} ({}) =", p, if self.constant {"const"} else {"global"}, self.name, self.span);
         self.init.print(level + 1);
     }
 }
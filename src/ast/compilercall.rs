@@ -1,11 +1,35 @@
-use ast::{Type, IntSize, Expression, TreePrinter, prefix};
+use std::fmt;
+use ast::{Type, IntSize, Expression, TreePrinter, prefix, optional_type};
 use span::Span;
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ConversionMode
+{
+    Checked,    // Results in nil if the value does not fit in the destination type
+    Wrapping,   // Truncates the value, only for integers
+    Saturating, // Clamps the value to the range of the destination type
+}
+
+impl fmt::Display for ConversionMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            ConversionMode::Checked => write!(f, "@try_cast"),
+            ConversionMode::Wrapping => write!(f, "@wrapping_cast"),
+            ConversionMode::Saturating => write!(f, "@saturating_cast"),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum CompilerCall
 {
     SizeOf(Type, Span),
-    Slice{data: Box<Expression>, len: Box<Expression>, typ: Type, span: Span}
+    Slice{data: Box<Expression>, len: Box<Expression>, typ: Type, span: Span},
+    Convert{inner: Box<Expression>, typ: Type, mode: ConversionMode, span: Span},
+    Assert{condition: Box<Expression>, message: Box<Expression>, span: Span},
 }
 
 
@@ -16,6 +40,9 @@ impl CompilerCall
         match *self {
             CompilerCall::SizeOf(_, _) => Type::UInt(int_size),
             CompilerCall::Slice{ref typ, ..} => typ.clone(),
+            CompilerCall::Convert{ref typ, mode: ConversionMode::Checked, ..} => optional_type(typ.clone()),
+            CompilerCall::Convert{ref typ, ..} => typ.clone(),
+            CompilerCall::Assert{..} => Type::Void,
         }
     }
 }
@@ -32,6 +59,15 @@ impl TreePrinter for CompilerCall
                 data.print(level + 1);
                 len.print(level + 1);
             }
+            CompilerCall::Convert{ref inner, ref typ, mode, ref span} => {
+                println!("{}{}({}) (span: {})", p, mode, typ, span);
+                inner.print(level + 1);
+            }
+            CompilerCall::Assert{ref condition, ref message, ref span} => {
+                println!("{}@assert (span: {})", p, span);
+                condition.print(level + 1);
+                message.print(level + 1);
+            }
         }
     }
 }
\ No newline at end of file
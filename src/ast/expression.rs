@@ -32,6 +32,34 @@ pub struct Return
     pub span: Span,
 }
 
+// Expression which is executed when the enclosing scope is exited
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Defer
+{
+    pub expression: Expression,
+    pub span: Span,
+}
+
+// Postfix ? operator, unwraps the success value or returns the failure from the enclosing function
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Propagation
+{
+    pub inner: Expression,
+    pub on_failure: Expression,
+    pub var_name: String,
+    pub typ: Type,
+    pub span: Span,
+}
+
+// Aborts the program with a message, the result of panic(msg) and unreachable
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Panic
+{
+    pub message: Expression,
+    pub typ: Type,
+    pub span: Span,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Expression
 {
@@ -61,7 +89,11 @@ pub enum Expression
     Cast(Box<TypeCast>),
     CompilerCall(CompilerCall),
     IndexOperation(Box<IndexOperation>),
+    SliceOperation(Box<SliceOperation>),
     Return(Box<Return>),
+    Propagate(Box<Propagation>),
+    Defer(Box<Defer>),
+    Panic(Box<Panic>),
     Void,
 }
 
@@ -95,6 +127,37 @@ pub fn return_expr(expression: Expression, span: Span) -> Expression
     Expression::Return(Box::new(Return{expression, span}))
 }
 
+pub fn defer(expression: Expression, span: Span) -> Expression
+{
+    Expression::Defer(Box::new(Defer{expression, span}))
+}
+
+pub fn panic_expr(message: Expression, span: Span) -> Expression
+{
+    Expression::Panic(Box::new(Panic{
+        message,
+        typ: Type::Void,
+        span,
+    }))
+}
+
+pub fn unreachable_expr(span: Span) -> Expression
+{
+    let message = Expression::Literal(Literal::String(span.clone(), "entered unreachable code".into()));
+    panic_expr(message, span)
+}
+
+pub fn propagate(inner: Expression, span: Span) -> Expression
+{
+    Expression::Propagate(Box::new(Propagation{
+        inner,
+        on_failure: Expression::Void,
+        var_name: format!("$propagate-{}-{}", span.start.line, span.start.offset),
+        typ: Type::Unknown,
+        span,
+    }))
+}
+
 pub fn nil_expr_with_type(span: Span, optional_inner_type: Type) -> Expression
 {
     Expression::Nil(Nil{
@@ -130,6 +193,17 @@ impl Expression
         }
     }
 
+    // Expressions which never complete, like a panic, can take on any type
+    pub fn diverges(&self) -> bool
+    {
+        match *self
+        {
+            Expression::Panic(_) => true,
+            Expression::Block(ref b) => b.expressions.last().map(|e| e.diverges()).unwrap_or(false),
+            _ => false,
+        }
+    }
+
     pub fn extract_binary_op(self) -> Option<Box<BinaryOp>>
     {
         match self
@@ -169,8 +243,14 @@ impl Expression
             Expression::Cast(ref t) => t.span.clone(),
             Expression::CompilerCall(CompilerCall::SizeOf(_, ref span)) => span.clone(),
             Expression::CompilerCall(CompilerCall::Slice{ref span, ..}) => span.clone(),
+            Expression::CompilerCall(CompilerCall::Convert{ref span, ..}) => span.clone(),
+            Expression::CompilerCall(CompilerCall::Assert{ref span, ..}) => span.clone(),
             Expression::IndexOperation(ref iop) => iop.span.clone(),
+            Expression::SliceOperation(ref sop) => sop.span.clone(),
             Expression::Return(ref r) => r.span.clone(),
+            Expression::Propagate(ref p) => p.span.clone(),
+            Expression::Defer(ref d) => d.span.clone(),
+            Expression::Panic(ref p) => p.span.clone(),
             Expression::Void => Span::default(),
         }
     }
@@ -202,10 +282,14 @@ impl Expression
             Expression::Cast(ref t) => t.destination_type.clone(),
             Expression::CompilerCall(ref cc) => cc.get_type(int_size),
             Expression::IndexOperation(ref iop) => iop.typ.clone(),
+            Expression::SliceOperation(ref sop) => sop.typ.clone(),
             Expression::Return(ref r) => r.expression.get_type(int_size),
+            Expression::Propagate(ref p) => p.typ.clone(),
+            Expression::Panic(ref p) => p.typ.clone(),
             Expression::Void |
             Expression::While(_) |
             Expression::Delete(_) |
+            Expression::Defer(_) |
             Expression::For(_) => Type::Void,
         }
     }
@@ -288,6 +372,19 @@ impl Expression
                 r.expression.visit_mut(op)
             },
 
+            Expression::Propagate(ref mut p) => {
+                p.inner.visit_mut(op)?;
+                p.on_failure.visit_mut(op)
+            },
+
+            Expression::Defer(ref mut d) => {
+                d.expression.visit_mut(op)
+            },
+
+            Expression::Panic(ref mut p) => {
+                p.message.visit_mut(op)
+            },
+
             Expression::If(ref mut i) => {
                 i.condition.visit_mut(op)?;
                 i.on_true.visit_mut(op)?;
@@ -369,11 +466,31 @@ impl Expression
                 iop.index_expr.visit_mut(op)
             }
 
+            Expression::SliceOperation(ref mut sop) => {
+                sop.target.visit_mut(op)?;
+                if let Some(ref mut start) = sop.start {
+                    start.visit_mut(op)?;
+                }
+                if let Some(ref mut end) = sop.end {
+                    end.visit_mut(op)?;
+                }
+                Ok(())
+            }
+
             Expression::CompilerCall(CompilerCall::Slice{ref mut data, ref mut len, ..}) => {
                 data.visit_mut(op)?;
                 len.visit_mut(op)
             }
 
+            Expression::CompilerCall(CompilerCall::Convert{ref mut inner, ..}) => {
+                inner.visit_mut(op)
+            }
+
+            Expression::CompilerCall(CompilerCall::Assert{ref mut condition, ref mut message, ..}) => {
+                condition.visit_mut(op)?;
+                message.visit_mut(op)
+            }
+
             Expression::Literal(_) |
             Expression::Void |
             Expression::CompilerCall(_) |
@@ -460,6 +577,19 @@ impl Expression
                 r.expression.visit(op)
             },
 
+            Expression::Propagate(ref p) => {
+                p.inner.visit(op)?;
+                p.on_failure.visit(op)
+            },
+
+            Expression::Defer(ref d) => {
+                d.expression.visit(op)
+            },
+
+            Expression::Panic(ref p) => {
+                p.message.visit(op)
+            },
+
             Expression::If(ref i) => {
                 i.condition.visit(op)?;
                 i.on_true.visit(op)?;
@@ -541,11 +671,31 @@ impl Expression
                 iop.index_expr.visit(op)
             }
 
+            Expression::SliceOperation(ref sop) => {
+                sop.target.visit(op)?;
+                if let Some(ref start) = sop.start {
+                    start.visit(op)?;
+                }
+                if let Some(ref end) = sop.end {
+                    end.visit(op)?;
+                }
+                Ok(())
+            }
+
             Expression::CompilerCall(CompilerCall::Slice{ref data, ref len, ..}) => {
                 data.visit(op)?;
                 len.visit(op)
             }
 
+            Expression::CompilerCall(CompilerCall::Convert{ref inner, ..}) => {
+                inner.visit(op)
+            }
+
+            Expression::CompilerCall(CompilerCall::Assert{ref condition, ref message, ..}) => {
+                condition.visit(op)?;
+                message.visit(op)
+            }
+
             Expression::Literal(_) |
             Expression::Void |
             Expression::CompilerCall(_) |
@@ -613,11 +763,25 @@ impl TreePrinter for Expression
             },
             Expression::CompilerCall(ref cc) => cc.print(level),
             Expression::IndexOperation(ref iop) => iop.print(level),
+            Expression::SliceOperation(ref sop) => sop.print(level),
             Expression::Void => println!("{}void", p),
             Expression::Return(ref r) => {
                 println!("{}return", p);
                 r.expression.print(level + 1)
             }
+            Expression::Propagate(ref prop) => {
+                println!("{}propagate (type: {}) ({})", p, prop.typ, prop.span);
+                prop.inner.print(level + 1);
+                prop.on_failure.print(level + 1)
+            }
+            Expression::Defer(ref d) => {
+                println!("{}defer ({})", p, d.span);
+                d.expression.print(level + 1)
+            }
+            Expression::Panic(ref pe) => {
+                println!("{}panic (type: {}) ({})", p, pe.typ, pe.span);
+                pe.message.print(level + 1)
+            }
         }
     }
 }
@@ -1,7 +1,7 @@
-use ast::{Type, Expression, TreePrinter, prefix, func_type};
+use ast::{Type, CallingConvention, Expression, TreePrinter, prefix, func_type, c_func_type};
 use span::{Span};
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct Argument
 {
     pub name: String,
@@ -32,7 +32,16 @@ impl TreePrinter for Argument
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+// Set with an @inline or @noinline annotation, Auto leaves the decision to the inliner
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum InlineHint
+{
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct FunctionSignature
 {
     pub name: String,
@@ -40,6 +49,9 @@ pub struct FunctionSignature
     pub args: Vec<Argument>,
     pub span: Span,
     pub typ: Type,
+    pub variadic: bool,
+    pub calling_convention: CallingConvention,
+    pub inline_hint: InlineHint,
 }
 
 impl FunctionSignature
@@ -60,6 +72,9 @@ impl FunctionSignature
                 }).collect(),
                 span: Span::default(),
                 typ: typ.clone(),
+                variadic: ft.variadic,
+                calling_convention: ft.calling_convention,
+                inline_hint: InlineHint::Auto,
             };
 
             Some(s)
@@ -70,10 +85,22 @@ impl FunctionSignature
 
     pub fn get_type(&self) -> Type
     {
-        func_type(
-            self.args.iter().map(|arg| arg.typ.clone()).collect(),
-            self.return_type.clone()
-        )
+        let args = self.args.iter().map(|arg| arg.typ.clone()).collect();
+        match self.calling_convention
+        {
+            CallingConvention::Nomad => func_type(args, self.return_type.clone()),
+            CallingConvention::C => c_func_type(args, self.return_type.clone(), self.variadic),
+        }
+    }
+
+    // The name of the function in the object file, C functions are not namespaced
+    pub fn symbol_name(&self) -> &str
+    {
+        match self.calling_convention
+        {
+            CallingConvention::Nomad => &self.name,
+            CallingConvention::C => self.name.rsplit("::").next().unwrap_or(&self.name),
+        }
     }
 }
 
@@ -133,6 +160,21 @@ impl TreePrinter for Function
     }
 }
 
+// Destructors are declared as fn ~Name(self), so for type ns::Name, the destructor is ns::~Name
+pub fn destructor_name(type_name: &str) -> String
+{
+    match type_name.rfind("::")
+    {
+        Some(idx) => format!("{}::~{}", &type_name[..idx], &type_name[idx + 2..]),
+        None => format!("~{}", type_name),
+    }
+}
+
+pub fn is_destructor_name(name: &str) -> bool
+{
+    name.rsplit("::").next().map(|n| n.starts_with('~')).unwrap_or(false)
+}
+
 pub fn sig(name: &str, ret: Type, args: Vec<Argument>, span: Span) -> FunctionSignature
 {
     FunctionSignature{
@@ -141,6 +183,9 @@ pub fn sig(name: &str, ret: Type, args: Vec<Argument>, span: Span) -> FunctionSi
         args: args,
         span: span,
         typ: Type::Unknown,
+        variadic: false,
+        calling_convention: CallingConvention::Nomad,
+        inline_hint: InlineHint::Auto,
     }
 }
 
@@ -153,8 +198,10 @@ pub struct ExternalFunction
 
 impl ExternalFunction
 {
-    pub fn new(sig: FunctionSignature, span: Span) -> ExternalFunction
+    pub fn new(mut sig: FunctionSignature, span: Span) -> ExternalFunction
     {
+        // Externs are always C functions
+        sig.calling_convention = CallingConvention::C;
         ExternalFunction{
             sig: sig,
             span: span,
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::rc::Rc;
 use itertools::join;
@@ -7,7 +7,7 @@ use ast::Function;
 use super::{Type};
 
 
-#[derive(Eq, PartialEq, Hash, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Serialize, Deserialize)]
 pub struct ImportName
 {
     namespace: Vec<String>,
@@ -72,10 +72,10 @@ impl Symbol
 pub struct Import
 {
     pub namespace: String,
-    pub symbols: HashMap<String, Symbol>,
-    pub generics: HashMap<String, Function>,
-    pub imported_symbols: HashMap<String, Symbol>
-
+    pub symbols: BTreeMap<String, Symbol>,
+    pub generics: BTreeMap<String, Function>,
+    pub default_impls: BTreeMap<String, Function>,
+    pub imported_symbols: BTreeMap<String, Symbol>
 }
 
 impl Import
@@ -84,15 +84,16 @@ impl Import
     {
         Import{
             namespace,
-            symbols: HashMap::new(),
-            generics: HashMap::new(),
-            imported_symbols: HashMap::new(),
+            symbols: BTreeMap::new(),
+            generics: BTreeMap::new(),
+            default_impls: BTreeMap::new(),
+            imported_symbols: BTreeMap::new(),
         }
     }
 
     pub fn resolve(&self, name: &str, allow_imported_symbols: bool) -> Option<Symbol>
     {
-        let resolve = |symbols: &HashMap<String, Symbol>| {
+        let resolve = |symbols: &BTreeMap<String, Symbol>| {
             if let Some(s) = symbols.get(name) {
                 return Some(s.clone())
             }
@@ -128,6 +129,10 @@ impl fmt::Display for Import
             writeln!(f, " G {}: {}", function.sig.name, function.sig.typ)?;
         }
 
+        for (name, function) in &self.default_impls {
+            writeln!(f, " D {}: {}", name, function.sig.typ)?;
+        }
+
         for symbol in self.imported_symbols.values() {
             writeln!(f, " U {}: {}", symbol.name, symbol.typ)?;
         }
@@ -137,4 +142,4 @@ impl fmt::Display for Import
 }
 
 
-pub type ImportMap = HashMap<String, Rc<Import>>;
\ No newline at end of file
+pub type ImportMap = BTreeMap<String, Rc<Import>>;
\ No newline at end of file
@@ -35,4 +35,49 @@ impl TreePrinter for IndexOperation
         println!("{} index:", p);
         self.index_expr.print(level + 2);
     }
-}
\ No newline at end of file
+}
+
+// Indexing with a range: target[start..end], both ends are optional
+#[derive(Debug, Eq, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SliceOperation
+{
+    pub target: Expression,
+    pub start: Option<Expression>,
+    pub end: Option<Expression>,
+    pub span: Span,
+    pub typ: Type,
+}
+
+pub fn slice_op(target: Expression, start: Option<Expression>, end: Option<Expression>, span: Span) -> Expression
+{
+    Expression::SliceOperation(
+        Box::new(
+            SliceOperation{
+                target: target,
+                start: start,
+                end: end,
+                span: span,
+                typ: Type::Unknown,
+            }
+        )
+    )
+}
+
+impl TreePrinter for SliceOperation
+{
+    fn print(&self, level: usize)
+    {
+        let p = prefix(level);
+        println!("{}slice operation ({}) (type: {})", p, self.span, self.typ);
+        println!("{} target:", p);
+        self.target.print(level + 2);
+        if let Some(ref start) = self.start {
+            println!("{} start:", p);
+            start.print(level + 2);
+        }
+        if let Some(ref end) = self.end {
+            println!("{} end:", p);
+            end.print(level + 2);
+        }
+    }
+}
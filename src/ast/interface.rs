@@ -6,20 +6,30 @@ pub struct Interface
 {
     pub name: String,
     pub functions: Vec<FunctionSignature>,
+    pub default_impls: Vec<Function>,
     pub typ: Type,
     pub span: Span,
 }
 
-pub fn interface(name: String, functions: Vec<FunctionSignature>, span: Span) -> Interface
+pub fn interface(name: String, functions: Vec<FunctionSignature>, default_impls: Vec<Function>, span: Span) -> Interface
 {
     Interface{
         name: name,
         functions: functions,
+        default_impls: default_impls,
         typ: Type::Unknown,
         span: span,
     }
 }
 
+impl Interface
+{
+    pub fn get_default_impl(&self, name: &str) -> Option<&Function>
+    {
+        self.default_impls.iter().find(|f| f.sig.name == name)
+    }
+}
+
 impl TreePrinter for Interface
 {
     fn print(&self, level: usize)
@@ -29,5 +39,9 @@ impl TreePrinter for Interface
         for func in &self.functions {
             func.print(level + 1);
         }
+
+        for func in &self.default_impls {
+            func.print(level + 1);
+        }
     }
 }
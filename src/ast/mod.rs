@@ -69,10 +69,10 @@ pub trait TreePrinter
     fn print(&self, level: usize);
 }
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use itertools::join;
 
-pub type GenericMapping = HashMap<Type, Type>;
+pub type GenericMapping = BTreeMap<Type, Type>;
 
 pub fn new_func_name(func_name: &str, generic_args: &GenericMapping) -> String
 {
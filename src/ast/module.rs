@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 use super::{Expression, Call, TreePrinter, TypeDeclaration, Import, ImportName, Symbol, SymbolType, GlobalBinding, Function, ExternalFunction, prefix};
 use target::Target;
 use compileerror::CompileResult;
@@ -6,11 +6,11 @@ use compileerror::CompileResult;
 pub struct Module
 {
     pub name: String,
-    pub globals: HashMap<String, GlobalBinding>,
-    pub functions: HashMap<String, Function>,
-    pub externals: HashMap<String, ExternalFunction>,
-    pub types: HashMap<String, TypeDeclaration>,
-    pub import_names: HashSet<ImportName>,
+    pub globals: BTreeMap<String, GlobalBinding>,
+    pub functions: BTreeMap<String, Function>,
+    pub externals: BTreeMap<String, ExternalFunction>,
+    pub types: BTreeMap<String, TypeDeclaration>,
+    pub import_names: BTreeSet<ImportName>,
     pub type_checked: bool,
 }
 
@@ -20,11 +20,11 @@ impl Module
     {
         Module{
             name: name.into(),
-            globals: HashMap::new(),
-            functions: HashMap::new(),
-            externals: HashMap::new(),
-            types: HashMap::new(),
-            import_names: HashSet::new(),
+            globals: BTreeMap::new(),
+            functions: BTreeMap::new(),
+            externals: BTreeMap::new(),
+            types: BTreeMap::new(),
+            import_names: BTreeSet::new(),
             type_checked: false,
         }
     }
@@ -34,9 +34,9 @@ impl Module
         !self.externals.contains_key(&call.callee.name)
     }
 
-    fn get_imported_symbols(&self, target: &Target) -> HashMap<String, Symbol>
+    fn get_imported_symbols(&self, target: &Target) -> BTreeMap<String, Symbol>
     {
-        let mut symbols = HashMap::new();
+        let mut symbols = BTreeMap::new();
         for func in self.functions.values() {
             let mut find_imported_calls = |e: &Expression| -> CompileResult<()> {
                 match *e {
@@ -76,6 +76,11 @@ impl Module
 
         for (name, type_decl) in &self.types {
             import.symbols.insert(name.clone(), Symbol::new(name, &type_decl.get_type(), false, &type_decl.span(), SymbolType::Normal));
+            if let TypeDeclaration::Interface(ref i) = *type_decl {
+                for function in &i.default_impls {
+                    import.default_impls.insert(format!("{}.{}", name, function.sig.name), function.clone());
+                }
+            }
         }
 
 
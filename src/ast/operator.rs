@@ -10,6 +10,12 @@ pub enum BinaryOperator
     Mul,
     Div,
     Mod,
+    WrappingAdd,
+    WrappingSub,
+    WrappingMul,
+    SaturatingAdd,
+    SaturatingSub,
+    SaturatingMul,
     LessThan,
     GreaterThan,
     LessThanEquals,
@@ -34,6 +40,12 @@ impl fmt::Display for BinaryOperator
             BinaryOperator::Mul => write!(fmt, "*"),
             BinaryOperator::Div => write!(fmt, "/"),
             BinaryOperator::Mod => write!(fmt, "%"),
+            BinaryOperator::WrappingAdd => write!(fmt, "+%"),
+            BinaryOperator::WrappingSub => write!(fmt, "-%"),
+            BinaryOperator::WrappingMul => write!(fmt, "*%"),
+            BinaryOperator::SaturatingAdd => write!(fmt, "+|"),
+            BinaryOperator::SaturatingSub => write!(fmt, "-|"),
+            BinaryOperator::SaturatingMul => write!(fmt, "*|"),
             BinaryOperator::LessThan => write!(fmt, "<"),
             BinaryOperator::GreaterThan => write!(fmt, ">"),
             BinaryOperator::LessThanEquals => write!(fmt, "<="),
@@ -55,14 +67,47 @@ impl BinaryOperator
         match *self
         {
             BinaryOperator::Dot | BinaryOperator::As => TOP_PRECEDENCE,
-            BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Mod => TOP_PRECEDENCE - 100,
-            BinaryOperator::Add | BinaryOperator::Sub => TOP_PRECEDENCE - 200,
+            BinaryOperator::Mul | BinaryOperator::Div | BinaryOperator::Mod |
+            BinaryOperator::WrappingMul | BinaryOperator::SaturatingMul => TOP_PRECEDENCE - 100,
+            BinaryOperator::Add | BinaryOperator::Sub |
+            BinaryOperator::WrappingAdd | BinaryOperator::WrappingSub |
+            BinaryOperator::SaturatingAdd | BinaryOperator::SaturatingSub => TOP_PRECEDENCE - 200,
             BinaryOperator::LessThan | BinaryOperator::GreaterThan | BinaryOperator::LessThanEquals |
             BinaryOperator::GreaterThanEquals | BinaryOperator::Equals | BinaryOperator::NotEquals => TOP_PRECEDENCE - 300,
             BinaryOperator::And => TOP_PRECEDENCE - 400,
             BinaryOperator::Or => TOP_PRECEDENCE - 500,
         }
     }
+
+    pub fn is_wrapping(&self) -> bool
+    {
+        match *self
+        {
+            BinaryOperator::WrappingAdd | BinaryOperator::WrappingSub | BinaryOperator::WrappingMul => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_saturating(&self) -> bool
+    {
+        match *self
+        {
+            BinaryOperator::SaturatingAdd | BinaryOperator::SaturatingSub | BinaryOperator::SaturatingMul => true,
+            _ => false,
+        }
+    }
+
+    // The plain arithmetic operator of a wrapping or saturating operator
+    pub fn base_operator(&self) -> BinaryOperator
+    {
+        match *self
+        {
+            BinaryOperator::WrappingAdd | BinaryOperator::SaturatingAdd => BinaryOperator::Add,
+            BinaryOperator::WrappingSub | BinaryOperator::SaturatingSub => BinaryOperator::Sub,
+            BinaryOperator::WrappingMul | BinaryOperator::SaturatingMul => BinaryOperator::Mul,
+            op => op,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
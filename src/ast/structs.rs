@@ -6,6 +6,8 @@ pub struct StructMemberDeclaration
 {
     pub name: String,
     pub typ: Type,
+    // Members with a default value can be left out at the end of a struct initializer
+    pub default: Option<Expression>,
     pub span: Span,
 }
 
@@ -14,6 +16,7 @@ pub fn struct_member_declaration(name: &str, typ: Type, span: Span) -> StructMem
     StructMemberDeclaration{
         name: name.into(),
         typ: typ,
+        default: None,
         span: span,
     }
 }
@@ -89,5 +92,8 @@ impl TreePrinter for StructMemberDeclaration
     {
         let p = prefix(level);
         println!("{}{}:{} ({})", p, self.name, self.typ, self.span);
+        if let Some(ref default) = self.default {
+            default.print(level + 1);
+        }
     }
 }
@@ -28,6 +28,7 @@ pub struct SumTypeDeclaration
     pub cases: Vec<SumTypeCaseDeclaration>,
     pub span: Span,
     pub typ: Type,
+    pub result: bool,
 }
 
 pub fn sum_type_decl(name: &str, cases: Vec<SumTypeCaseDeclaration>, span: Span) -> SumTypeDeclaration
@@ -37,6 +38,7 @@ pub fn sum_type_decl(name: &str, cases: Vec<SumTypeCaseDeclaration>, span: Span)
         cases: cases,
         span: span,
         typ: Type::Unknown,
+        result: false,
     }
 }
 
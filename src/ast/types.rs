@@ -6,7 +6,7 @@ use ast::*;
 use target::Target;
 use span::Span;
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct SumTypeCase
 {
     pub name: String,
@@ -19,11 +19,39 @@ pub trait SumTypeCaseIndexOf
     fn num_cases(&self) -> usize;
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct SumType
 {
     pub name: String,
     pub cases: Vec<SumTypeCase>,
+    pub result: bool, // Declared with @result, so it can be used with the ? operator
+}
+
+impl SumType
+{
+    // The first case of a result type holds the value on success
+    pub fn success_case(&self) -> Option<(usize, Type)>
+    {
+        if !self.result {
+            return None;
+        }
+
+        match self.cases.first().map(|c| &c.typ)
+        {
+            Some(&Type::Struct(ref st)) if st.members.len() == 1 => Some((0, st.members[0].typ.clone())),
+            _ => None,
+        }
+    }
+
+    // The second case of a result type is the failure
+    pub fn failure_case(&self) -> Option<(usize, Type)>
+    {
+        if self.result && self.cases.len() == 2 {
+            Some((1, self.cases[1].typ.clone()))
+        } else {
+            None
+        }
+    }
 }
 
 impl SumTypeCaseIndexOf for SumType
@@ -39,7 +67,7 @@ impl SumTypeCaseIndexOf for SumType
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct EnumType
 {
     pub name: String,
@@ -59,56 +87,112 @@ impl SumTypeCaseIndexOf for EnumType
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct StructMember
 {
     pub name: String,
     pub typ: Type,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct StructType
 {
     pub name: String,
     pub members: Vec<StructMember>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum CallingConvention
+{
+    Nomad,
+    C, // The C ABI of the platform, used by externs and functions declared with export "C"
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct FuncType
 {
     pub args: Vec<Type>,
     pub return_type: Type,
+    pub variadic: bool, // C style variadic function, only for externs
+    pub calling_convention: CallingConvention,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
+pub enum ArrayLength
+{
+    Known(usize),
+    Const(String), // Refers to a const declaration, replaced by the known value during type resolution
+    Generic(String), // Generic length ($N), replaced by the known value during instantiation
+}
+
+impl ArrayLength
+{
+    pub fn is_generic(&self) -> bool
+    {
+        match *self
+        {
+            ArrayLength::Generic(_) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ArrayLength
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match *self
+        {
+            ArrayLength::Known(len) => write!(f, "{}", len),
+            ArrayLength::Const(ref name) => write!(f, "{}", name),
+            ArrayLength::Generic(ref name) => write!(f, "${}", name),
+        }
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct ArrayType
 {
     pub element_type: Type,
-    pub len: usize,
+    pub length: ArrayLength,
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(len_without_is_empty))]
+impl ArrayType
+{
+    pub fn len(&self) -> usize
+    {
+        match self.length
+        {
+            ArrayLength::Known(len) => len,
+            _ => panic!("Internal Compiler Error: array length {} has not been resolved", self.length),
+        }
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct SliceType
 {
     pub element_type: Type,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct UnresolvedType
 {
     pub name: String,
     pub generic_args: Vec<Type>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub struct InterfaceType
 {
     pub name: String,
     pub generic_args: Vec<Type>,
-    pub functions: Vec<FunctionSignature>
+    pub functions: Vec<FunctionSignature>,
+    pub default_impls: Vec<String>,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub enum GenericType
 {
     Any(String),
@@ -116,7 +200,7 @@ pub enum GenericType
 }
 
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum IntSize
 {
     I8,
@@ -146,7 +230,7 @@ impl fmt::Display for IntSize
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum FloatSize
 {
     F32,
@@ -164,7 +248,7 @@ impl fmt::Display for FloatSize
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Hash, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Serialize, Deserialize)]
 pub enum Type
 {
     Void,
@@ -310,11 +394,12 @@ impl Type
 
         match *self
         {
-            Type::Int(_) | Type::UInt(_) => op == BinaryOperator::Mod || GENERAL_NUMERIC_OPERATORS.contains(&op),
+            Type::Int(_) | Type::UInt(_) => op == BinaryOperator::Mod || op.is_wrapping() || op.is_saturating() || GENERAL_NUMERIC_OPERATORS.contains(&op),
             Type::Float(_) => GENERAL_NUMERIC_OPERATORS.contains(&op),
             Type::Char=> COMPARISON_OPERATORS.contains(&op),
             Type::Bool => COMPARISON_OPERATORS.contains(&op) || op == BinaryOperator::And || op == BinaryOperator::Or,
-            Type::String | Type::Pointer(_) | Type::Optional(_) => op == BinaryOperator::Equals || op == BinaryOperator::NotEquals,
+            Type::String => op == BinaryOperator::Add || COMPARISON_OPERATORS.contains(&op),
+            Type::Pointer(_) | Type::Optional(_) => op == BinaryOperator::Equals || op == BinaryOperator::NotEquals,
             _ => false,
         }
     }
@@ -324,13 +409,13 @@ impl Type
         match *self
         {
             Type::Generic(_) => true,
-            Type::Array(ref at) => at.element_type.is_generic(),
+            Type::Array(ref at) => at.element_type.is_generic() || at.length.is_generic(),
             Type::Slice(ref st) => st.element_type.is_generic(),
             Type::Func(ref ft) => ft.return_type.is_generic() || ft.args.iter().any(|a| a.is_generic()),
             Type::Struct(ref st) => st.members.iter().any(|m| m.typ.is_generic()),
             Type::Sum(ref st) => st.cases.iter().any(|c| c.typ.is_generic()),
             Type::Unresolved(ref ut) => ut.generic_args.iter().any(|t| t.is_generic()),
-            Type::Pointer(ref inner) => inner.is_generic(),
+            Type::Pointer(ref inner) | Type::Optional(ref inner) => inner.is_generic(),
             Type::Interface(ref i) => !i.generic_args.is_empty(),
             _ => false,
         }
@@ -345,6 +430,15 @@ impl Type
         }
     }
 
+    pub fn is_integer(&self) -> bool
+    {
+        match *self
+        {
+            Type::Int(_) | Type::UInt(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn is_unknown(&self) -> bool
     {
         match *self
@@ -465,17 +559,40 @@ pub fn func_type(args: Vec<Type>, ret: Type) -> Type
     Type::Func(Rc::new(FuncType{
         args: args,
         return_type: ret,
+        variadic: false,
+        calling_convention: CallingConvention::Nomad,
+    }))
+}
+
+pub fn c_func_type(args: Vec<Type>, ret: Type, variadic: bool) -> Type
+{
+    Type::Func(Rc::new(FuncType{
+        args: args,
+        return_type: ret,
+        variadic: variadic,
+        calling_convention: CallingConvention::C,
     }))
 }
 
 pub fn array_type(element_type: Type, len: usize) -> Type
+{
+    array_type_with_length(element_type, ArrayLength::Known(len))
+}
+
+pub fn array_type_with_length(element_type: Type, length: ArrayLength) -> Type
 {
     Type::Array(Rc::new(ArrayType{
         element_type: element_type,
-        len: len
+        length: length,
     }))
 }
 
+// Generic array lengths are stored in a GenericMapping as arrays with an unknown element type
+pub fn array_length_type(length: &ArrayLength) -> Type
+{
+    array_type_with_length(Type::Unknown, length.clone())
+}
+
 pub fn slice_type(element_type: Type) -> Type
 {
     Type::Slice(Rc::new(SliceType{
@@ -507,11 +624,12 @@ pub fn sum_type_case(name: &str, typ: Type) -> SumTypeCase
     }
 }
 
-pub fn sum_type(name: &str, cases: Vec<SumTypeCase>) -> Type
+pub fn sum_type(name: &str, cases: Vec<SumTypeCase>, result: bool) -> Type
 {
     Type::Sum(Rc::new(SumType{
         name: name.into(),
         cases: cases,
+        result: result,
     }))
 }
 
@@ -579,12 +697,13 @@ pub fn unresolved_type(name: &str, generic_args: Vec<Type>) -> Type
     }))
 }
 
-pub fn interface_type(name: &str, generic_args: Vec<Type>, functions: Vec<FunctionSignature>) -> Type
+pub fn interface_type(name: &str, generic_args: Vec<Type>, functions: Vec<FunctionSignature>, default_impls: Vec<String>) -> Type
 {
     Type::Interface(Rc::new(InterfaceType{
         name: name.into(),
         generic_args: generic_args,
         functions: functions,
+        default_impls: default_impls,
     }))
 }
 
@@ -596,9 +715,9 @@ impl fmt::Display for Type
         {
             Type::Void => write!(f, "void"),
             Type::Unknown => write!(f, "unknown"),
-            Type::Int(precision) => write!(f, "int{}", precision),
-            Type::UInt(precision) => write!(f, "uint{}", precision),
-            Type::Float(precision) => write!(f, "float{}", precision),
+            Type::Int(precision) => write!(f, "i{}", precision),
+            Type::UInt(precision) => write!(f, "u{}", precision),
+            Type::Float(precision) => write!(f, "f{}", precision),
             Type::Char => write!(f, "char"),
             Type::Bool => write!(f, "bool"),
             Type::String => write!(f, "string"),
@@ -609,10 +728,21 @@ impl fmt::Display for Type
                 } else {
                     write!(f, "{}<{}>", s.name, join(s.generic_args.iter(), ","))
                 },
-            Type::Array(ref at) => write!(f, "{}[{}]", at.element_type, at.len),
+            Type::Array(ref at) => write!(f, "{}[{}]", at.element_type, at.length),
             Type::Slice(ref at) => write!(f, "{}[]", at.element_type),
             Type::Generic(ref g) => write!(f, "${}", g),
-            Type::Func(ref ft) => write!(f, "({}) -> {}", join(ft.args.iter(), ", "), ft.return_type),
+            Type::Func(ref ft) => {
+                if ft.calling_convention == CallingConvention::C {
+                    write!(f, "extern \"C\" ")?;
+                }
+
+                match (ft.variadic, ft.args.is_empty())
+                {
+                    (true, true) => write!(f, "(...) -> {}", ft.return_type),
+                    (true, false) => write!(f, "({}, ...) -> {}", join(ft.args.iter(), ", "), ft.return_type),
+                    _ => write!(f, "({}) -> {}", join(ft.args.iter(), ", "), ft.return_type),
+                }
+            },
             Type::Struct(ref st) => write!(f, "{{{}}}", join(st.members.iter(), ", ")),
             Type::Sum(ref st) => write!(f, "{}", join(st.cases.iter().map(|m| &m.typ), " | ")),
             Type::Enum(ref st) => write!(f, "{}", join(st.cases.iter(), " | ")),
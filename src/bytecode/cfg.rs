@@ -0,0 +1,351 @@
+/*
+    Control flow graph of a function: the predecessors and successors of every block, the dominator tree
+    and the loops. Only blocks which can be reached from the entry block are part of the graph, so
+    an unreachable block is never a predecessor.
+*/
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use bytecode::function::{BasicBlockRef, ByteCodeFunction};
+
+// A natural loop, the header dominates all blocks in it, and the latches branch back to the header
+#[derive(Debug)]
+pub struct Loop
+{
+    pub header: BasicBlockRef,
+    pub latches: Vec<BasicBlockRef>,
+    pub blocks: BTreeSet<BasicBlockRef>,
+}
+
+impl Loop
+{
+    pub fn contains(&self, bb_ref: BasicBlockRef) -> bool
+    {
+        self.blocks.contains(&bb_ref)
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph
+{
+    successors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>>,
+    predecessors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>>,
+    // Reachable blocks, every block comes before its successors, except when the edge to it is a back edge
+    order: Vec<BasicBlockRef>,
+    idoms: BTreeMap<BasicBlockRef, BasicBlockRef>,
+}
+
+// Successors of a block, without branches to blocks which don't exist, and without duplicates
+fn block_successors(func: &ByteCodeFunction, bb_ref: BasicBlockRef) -> Vec<BasicBlockRef>
+{
+    let mut succs = Vec::new();
+    if let Some(bb) = func.blocks.get(&bb_ref) {
+        for succ in bb.successors() {
+            if func.blocks.contains_key(&succ) && !succs.contains(&succ) {
+                succs.push(succ);
+            }
+        }
+    }
+    succs
+}
+
+fn reverse_postorder(func: &ByteCodeFunction, successors: &BTreeMap<BasicBlockRef, Vec<BasicBlockRef>>) -> Vec<BasicBlockRef>
+{
+    let entry = match func.blocks.keys().next()
+    {
+        Some(entry) => *entry,
+        None => return Vec::new(),
+    };
+
+    let mut visited = BTreeSet::new();
+    let mut postorder = Vec::new();
+    // Blocks on the current path, with the index of the next successor to visit
+    let mut stack = vec![(entry, 0)];
+    visited.insert(entry);
+    while let Some((bb_ref, idx)) = stack.pop() {
+        match successors[&bb_ref].get(idx)
+        {
+            Some(&succ) => {
+                stack.push((bb_ref, idx + 1));
+                if visited.insert(succ) {
+                    stack.push((succ, 0));
+                }
+            },
+            None => postorder.push(bb_ref),
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+/*
+    Immediate dominators, using the iterative algorithm from "A Simple, Fast Dominance Algorithm"
+    by Cooper, Harvey and Kennedy. The entry block is its own immediate dominator.
+*/
+fn immediate_dominators(order: &[BasicBlockRef], predecessors: &BTreeMap<BasicBlockRef, Vec<BasicBlockRef>>) -> BTreeMap<BasicBlockRef, BasicBlockRef>
+{
+    let mut idoms = BTreeMap::new();
+    let entry = match order.first()
+    {
+        Some(entry) => *entry,
+        None => return idoms,
+    };
+
+    let position: HashMap<BasicBlockRef, usize> = order.iter().enumerate().map(|(idx, bb_ref)| (*bb_ref, idx)).collect();
+    let intersect = |idoms: &BTreeMap<BasicBlockRef, BasicBlockRef>, mut a: BasicBlockRef, mut b: BasicBlockRef| {
+        while a != b {
+            while position[&a] > position[&b] {
+                a = idoms[&a];
+            }
+            while position[&b] > position[&a] {
+                b = idoms[&b];
+            }
+        }
+        a
+    };
+
+    idoms.insert(entry, entry);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for bb_ref in order.iter().skip(1) {
+            let mut new_idom = None;
+            for pred in &predecessors[bb_ref] {
+                if !idoms.contains_key(pred) {
+                    continue;
+                }
+
+                new_idom = Some(match new_idom
+                {
+                    Some(idom) => intersect(&idoms, idom, *pred),
+                    None => *pred,
+                });
+            }
+
+            if let Some(new_idom) = new_idom {
+                if idoms.get(bb_ref) != Some(&new_idom) {
+                    idoms.insert(*bb_ref, new_idom);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    idoms
+}
+
+impl ControlFlowGraph
+{
+    pub fn new(func: &ByteCodeFunction) -> ControlFlowGraph
+    {
+        let all_successors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>> = func.blocks.keys()
+            .map(|bb_ref| (*bb_ref, block_successors(func, *bb_ref)))
+            .collect();
+
+        let order = reverse_postorder(func, &all_successors);
+        let successors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>> = all_successors.into_iter()
+            .filter(|&(ref bb_ref, _)| order.contains(bb_ref))
+            .collect();
+
+        let mut predecessors: BTreeMap<BasicBlockRef, Vec<BasicBlockRef>> = order.iter().map(|bb_ref| (*bb_ref, Vec::new())).collect();
+        for (bb_ref, succs) in &successors {
+            for succ in succs {
+                if let Some(preds) = predecessors.get_mut(succ) {
+                    preds.push(*bb_ref);
+                }
+            }
+        }
+
+        let idoms = immediate_dominators(&order, &predecessors);
+        ControlFlowGraph{
+            successors: successors,
+            predecessors: predecessors,
+            order: order,
+            idoms: idoms,
+        }
+    }
+
+    pub fn entry(&self) -> Option<BasicBlockRef>
+    {
+        self.order.first().cloned()
+    }
+
+    pub fn is_reachable(&self, bb_ref: BasicBlockRef) -> bool
+    {
+        self.successors.contains_key(&bb_ref)
+    }
+
+    pub fn successors(&self, bb_ref: BasicBlockRef) -> &[BasicBlockRef]
+    {
+        self.successors.get(&bb_ref).map(|succs| &succs[..]).unwrap_or(&[])
+    }
+
+    // Predecessors in block order
+    pub fn predecessors(&self, bb_ref: BasicBlockRef) -> &[BasicBlockRef]
+    {
+        self.predecessors.get(&bb_ref).map(|preds| &preds[..]).unwrap_or(&[])
+    }
+
+    // The reachable blocks, ordered so that every block comes before its successors, except along back edges
+    pub fn reverse_postorder(&self) -> &[BasicBlockRef]
+    {
+        &self.order
+    }
+
+    // The closest block which is on every path from the entry block to bb_ref, None for the entry block
+    pub fn immediate_dominator(&self, bb_ref: BasicBlockRef) -> Option<BasicBlockRef>
+    {
+        match self.idoms.get(&bb_ref)
+        {
+            Some(idom) if *idom != bb_ref => Some(*idom),
+            _ => None,
+        }
+    }
+
+    // Whether every path from the entry block to b passes through a, a block dominates itself
+    pub fn dominates(&self, a: BasicBlockRef, b: BasicBlockRef) -> bool
+    {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+
+        let mut current = b;
+        loop {
+            if current == a {
+                return true;
+            }
+
+            match self.immediate_dominator(current)
+            {
+                Some(idom) => current = idom,
+                None => return false,
+            }
+        }
+    }
+
+    // The blocks immediately dominated by bb_ref, in block order
+    pub fn dominator_tree_children(&self, bb_ref: BasicBlockRef) -> Vec<BasicBlockRef>
+    {
+        self.idoms.iter()
+            .filter(|&(child, idom)| *idom == bb_ref && *child != bb_ref)
+            .map(|(child, _)| *child)
+            .collect()
+    }
+
+    // For every block, the blocks where its dominance ends: they have a predecessor it dominates, but are not strictly dominated by it
+    pub fn dominance_frontiers(&self) -> BTreeMap<BasicBlockRef, BTreeSet<BasicBlockRef>>
+    {
+        let mut frontiers: BTreeMap<BasicBlockRef, BTreeSet<BasicBlockRef>> = self.order.iter().map(|bb_ref| (*bb_ref, BTreeSet::new())).collect();
+        for (bb_ref, preds) in &self.predecessors {
+            if preds.len() < 2 {
+                continue;
+            }
+
+            let idom = self.idoms[bb_ref];
+            for pred in preds {
+                let mut runner = *pred;
+                while runner != idom {
+                    if let Some(frontier) = frontiers.get_mut(&runner) {
+                        frontier.insert(*bb_ref);
+                    }
+                    match self.immediate_dominator(runner)
+                    {
+                        Some(next) => runner = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+        frontiers
+    }
+
+    // The natural loops, a back edge is a branch to a block which dominates the branching block. Loops sharing a header are merged.
+    pub fn loops(&self) -> Vec<Loop>
+    {
+        let mut loops: Vec<Loop> = Vec::new();
+        for header in &self.order {
+            let latches: Vec<BasicBlockRef> = self.predecessors(*header)
+                .iter()
+                .filter(|pred| self.dominates(*header, **pred))
+                .cloned()
+                .collect();
+
+            if latches.is_empty() {
+                continue;
+            }
+
+            let mut blocks = BTreeSet::new();
+            blocks.insert(*header);
+            let mut todo = latches.clone();
+            while let Some(bb_ref) = todo.pop() {
+                if blocks.insert(bb_ref) {
+                    todo.extend(self.predecessors(bb_ref).iter().cloned());
+                }
+            }
+
+            loops.push(Loop{
+                header: *header,
+                latches: latches,
+                blocks: blocks,
+            });
+        }
+        loops
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_function;
+
+    #[test]
+    fn test_cfg()
+    {
+        let func = parse_function(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  store (x: i32) (i32 0)
+  br block1
+block1:
+  brif (b: bool) block2 block4
+block2:
+  brif (b: bool) block3 block1
+block3:
+  bop (x: i32) add (x: i32) (a: i32)
+  br block1
+block4:
+  ret (x: i32)
+block5:
+  br block4
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        let cfg = ControlFlowGraph::new(&func);
+        assert_eq!(cfg.entry(), Some(0));
+        assert_eq!(cfg.successors(1), &[2, 4]);
+        assert_eq!(cfg.predecessors(1), &[0, 2, 3]);
+        // block5 is not reachable, so it is not a predecessor of block4
+        assert!(!cfg.is_reachable(5));
+        assert_eq!(cfg.predecessors(4), &[1]);
+        assert_eq!(cfg.reverse_postorder()[0], 0);
+
+        assert_eq!(cfg.immediate_dominator(0), None);
+        assert_eq!(cfg.immediate_dominator(3), Some(2));
+        assert_eq!(cfg.immediate_dominator(4), Some(1));
+        assert!(cfg.dominates(1, 3));
+        assert!(!cfg.dominates(2, 4));
+        assert_eq!(cfg.dominator_tree_children(1), vec![2, 4]);
+
+        let frontiers = cfg.dominance_frontiers();
+        assert!(frontiers[&3].contains(&1));
+        assert!(frontiers[&2].contains(&1));
+        assert!(frontiers[&0].is_empty());
+
+        let loops = cfg.loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].header, 1);
+        assert_eq!(loops[0].latches, vec![2, 3]);
+        assert!(loops[0].contains(2) && loops[0].contains(3));
+        assert!(!loops[0].contains(4));
+    }
+}
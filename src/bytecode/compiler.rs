@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use ast::*;
 use target::{Target};
-use bytecode::{ByteCodeModule, ByteCodeFunction};
+use bytecode::{ByteCodeModule, ByteCodeFunction, ByteCodeOptions, NEXT_CHAR_FUNCTION, verify_module};
 use compileerror::{CompileResult, type_error_result};
 use package::Package;
+use span::Span;
 use super::consteval::expr_to_const;
 use super::function::*;
 use super::instruction::*;
@@ -34,11 +35,154 @@ fn get_dst(func: &mut ByteCodeFunction, typ: &Type) -> Var
     stack_alloc(func, typ, None)
 }
 
+// The name of the binding which is the result of an expression, using the result moves ownership of the binding
+fn returned_name(e: &Expression) -> Option<&str>
+{
+    match *e
+    {
+        Expression::NameRef(ref nr) => Some(&nr.name),
+        Expression::Block(ref b) => b.expressions.last().and_then(returned_name),
+        _ => None,
+    }
+}
+
+// Whether or not a binding owns the value it is initialized with, if it doesn't it is a copy of something else
+fn owns_value(func: &ByteCodeFunction, init: &Expression) -> bool
+{
+    match *init
+    {
+        // Only names which own their value (and are moved) can pass on ownership, globals can't
+        Expression::NameRef(ref nr) => func.live_flag(&nr.name).is_some(),
+        Expression::MemberAccess(_) |
+        Expression::Dereference(_) |
+        Expression::IndexOperation(_) |
+        Expression::SliceOperation(_) => false,
+        _ => true,
+    }
+}
+
+// The binding name no longer owns its value, so it will not be destroyed
+fn move_binding(func: &mut ByteCodeFunction, name: &str)
+{
+    if func.is_current_bb_terminated() {
+        return;
+    }
+
+    if let Some(live) = func.live_flag(name) {
+        func.add(store_operand_instr(&live, Operand::const_bool(false)));
+    }
+}
+
+fn move_value(func: &mut ByteCodeFunction, e: &Expression)
+{
+    if let Some(name) = returned_name(e) {
+        move_binding(func, name);
+    }
+}
+
+// Destroy var at the end of the current scope, unless it is moved before that
+fn add_destroy_cleanup(func: &mut ByteCodeFunction, var: Var, destructor: String)
+{
+    let live = stack_alloc(func, &Type::Bool, Some(&format!("{}.live", var.name)));
+    func.add(store_operand_instr(&live, Operand::const_bool(true)));
+    func.add_cleanup(CleanupAction::Destroy{var: var, destructor: destructor, live: live});
+}
+
+fn cleanup_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, actions: Vec<CleanupAction>, target: &Target)
+{
+    if func.is_current_bb_terminated() {
+        // Early return, which already did the cleanup
+        return;
+    }
+
+    func.push_destination(None);
+    for action in actions
+    {
+        match action
+        {
+            CleanupAction::Destroy{ref var, ref destructor, ref live} => {
+                let destroy_bb = func.create_basic_block();
+                let next_bb = func.create_basic_block();
+                func.add(branch_if_instr(live, destroy_bb, next_bb));
+                func.set_current_bb(destroy_bb);
+                func.add(void_call_instr(destructor, vec![Operand::AddressOf(var.clone())]));
+                func.add(Instruction::Branch(next_bb));
+                func.set_current_bb(next_bb);
+            },
+
+            CleanupAction::Defer(ref e) => {
+                expr_to_bc(bc_mod, func, e, target);
+            },
+        }
+    }
+    func.pop_destination();
+}
+
+fn exit_scope(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, target: &Target)
+{
+    exit_scope_returning(bc_mod, func, None, target);
+}
+
+// Exit a scope, the returned binding is the result of the scope, so it is not destroyed
+fn exit_scope_returning(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, returned: Option<&str>, target: &Target)
+{
+    if let Some(name) = returned {
+        move_binding(func, name);
+    }
+    let actions = func.cleanup_actions(1);
+    cleanup_to_bc(bc_mod, func, actions, target);
+    func.pop_scope();
+}
+
+/*
+    The result of an expression with a scope of its own, backends bind a var in the scope where it is first assigned,
+    so it is allocated before the scope starts, to be still available after the scope ends.
+*/
+fn scope_result(func: &mut ByteCodeFunction, typ: &Type) -> Option<Var>
+{
+    if *typ == Type::Void {
+        return None;
+    }
+
+    let result = func.new_var(typ.clone());
+    func.add(Instruction::StackAlloc(result.clone()));
+    Some(result)
+}
+
+fn store_scope_result(func: &mut ByteCodeFunction, result: Var) -> Var
+{
+    match func.get_destination()
+    {
+        Some(dst) => {
+            func.add(store_instr(&dst, &result));
+            dst
+        },
+        None => result,
+    }
+}
+
+// Compile an expression in a scope of its own, so its bindings are destroyed and its defers run at the end of it
+fn scoped_expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, e: &Expression, target: &Target)
+{
+    func.push_scope();
+    match *e
+    {
+        Expression::Block(ref b) => {
+            block_body_to_bc(bc_mod, func, b, target);
+        },
+        _ => {
+            expr_to_bc(bc_mod, func, e, target);
+        },
+    }
+    exit_scope_returning(bc_mod, func, returned_name(e), target);
+}
+
 fn array_lit_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, a: &ArrayLiteral, dst: &Var, target: &Target)
 {
     for (idx, element) in a.elements.iter().enumerate() {
         func.push_destination(None);
         let v = to_bc(bc_mod, func, element, target);
+        move_value(func, element);
         func.pop_destination();
         func.add(store_member_instr(dst, idx, v, target.int_size));
     }
@@ -52,7 +196,11 @@ fn call_args_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c:
         args.push(Operand::Var(s));
     }
 
-    args.extend(c.args.iter().map(|arg| Operand::Var(to_bc(bc_mod, func, arg, target))));
+    for arg in &c.args {
+        let v = to_bc(bc_mod, func, arg, target);
+        move_value(func, arg);
+        args.push(Operand::Var(v));
+    }
     func.pop_destination();
     args
 }
@@ -77,6 +225,7 @@ fn struct_initializer_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunc
     let init_members = |bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, si: &StructInitializer, dst: &Var| {
         for (idx, expr) in si.member_initializers.iter().enumerate() {
             let v = to_bc(bc_mod, func, expr, target);
+            move_value(func, expr);
             func.add(store_member_instr(dst, idx, v, target.int_size));
         }
     };
@@ -93,7 +242,79 @@ fn struct_initializer_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunc
     }
 }
 
-fn block_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Block, target: &Target) -> Option<Var>
+// Check that index is within the bounds of seq, pointers are not checked
+fn bounds_check_to_bc(bc_mod: &ByteCodeModule, func: &mut ByteCodeFunction, seq: &Var, index: &Var, span: &Span, target: &Target)
+{
+    if !bc_mod.options.bounds_checks {
+        return;
+    }
+
+    let len = match seq.typ
+    {
+        Type::Array(_) | Type::Slice(_) => sequence_len(func, seq, target),
+        _ => return,
+    };
+
+    func.add(bounds_check_instr(index, len, span));
+}
+
+// The length of an array, slice or string, arrays have a constant length
+fn sequence_len(func: &mut ByteCodeFunction, seq: &Var, target: &Target) -> Operand
+{
+    if let Type::Array(ref at) = seq.typ {
+        Operand::const_uint(at.len() as u64, target.int_size)
+    } else {
+        let len = stack_alloc(func, &target.native_uint_type, None);
+        func.add(get_prop_instr(&len, seq, ByteCodeProperty::Len));
+        var_op(&len)
+    }
+}
+
+fn to_native_uint(func: &mut ByteCodeFunction, v: Var, target: &Target) -> Operand
+{
+    if v.typ == target.native_uint_type {
+        return var_op(&v);
+    }
+
+    let dst = stack_alloc(func, &target.native_uint_type, None);
+    func.add(cast_instr(&dst, &v));
+    var_op(&dst)
+}
+
+fn slice_operation_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, sop: &SliceOperation, target: &Target) -> Var
+{
+    func.push_destination(None);
+    let seq = to_bc(bc_mod, func, &sop.target, target);
+    let start = match sop.start {
+        Some(ref start) => {
+            let start = to_bc(bc_mod, func, start, target);
+            to_native_uint(func, start, target)
+        },
+        None => Operand::const_uint(0, target.int_size),
+    };
+
+    let end = match sop.end {
+        Some(ref end) => {
+            let end = to_bc(bc_mod, func, end, target);
+            to_native_uint(func, end, target)
+        },
+        None => sequence_len(func, &seq, target),
+    };
+    func.pop_destination();
+
+    // Strings are always checked, a substring must start and end on a character boundary
+    if seq.typ == Type::String || bc_mod.options.bounds_checks {
+        func.add(range_check_instr(&seq, start.clone(), end.clone(), &sop.span));
+    }
+
+    let len = stack_alloc(func, &target.native_uint_type, None);
+    func.add(binary_op_instr(&len, BinaryOperator::Sub, end, start.clone()));
+    let dst = get_dst(func, &sop.typ);
+    func.add(slice_instr(&dst, &seq, start, var_op(&len)));
+    dst
+}
+
+fn block_body_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Block, target: &Target) -> Option<Var>
 {
     let do_block = |bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Block| {
         for (idx, e) in b.expressions.iter().enumerate() {
@@ -122,6 +343,17 @@ fn block_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Blo
     }
 }
 
+fn block_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Block, target: &Target) -> Option<Var>
+{
+    let result = scope_result(func, &b.typ);
+    func.push_destination(result.clone());
+    func.push_scope();
+    block_body_to_bc(bc_mod, func, b, target);
+    exit_scope_returning(bc_mod, func, b.expressions.last().and_then(returned_name), target);
+    func.pop_destination();
+    result.map(|result| store_scope_result(func, result))
+}
+
 fn add_struct_pattern_bindings(p: &StructPattern, struct_var: &Var, func: &mut ByteCodeFunction, target: &Target)
 {
     for (idx, b) in p.bindings.iter().enumerate() {
@@ -148,9 +380,17 @@ fn add_binding(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, b: &Bin
     {
         BindingType::Name(ref name) => {
             let dst = stack_alloc(func, &b.typ, Some(name));
-            func.push_destination(Some(dst));
+            func.push_destination(Some(dst.clone()));
             expr_to_bc(bc_mod, func, &b.init, target);
             func.pop_destination();
+            let owns = owns_value(func, &b.init);
+            move_value(func, &b.init);
+
+            if let Some(destructor) = bc_mod.get_destructor(&b.typ) {
+                if owns {
+                    add_destroy_cleanup(func, dst, destructor);
+                }
+            }
         },
 
         BindingType::Struct(ref s) => {
@@ -293,7 +533,7 @@ fn member_access_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction,
         },
 
         (&Type::Array(ref at), &MemberAccessType::Property(Property::Len)) => {
-            func.add(store_operand_instr(dst, Operand::const_uint(at.len as u64, target.int_size)))
+            func.add(store_operand_instr(dst, Operand::const_uint(at.len() as u64, target.int_size)))
         },
 
         (&Type::Slice(_), &MemberAccessType::Property(Property::Len)) |
@@ -361,7 +601,7 @@ fn match_case_body_to_bc(
     func.set_current_bb(match_case_bb);
     expr_to_bc(bc_mod, func, &mc.to_execute, target);
     if end_scope {
-        func.pop_scope();
+        exit_scope(bc_mod, func, target);
     }
     func.add(Instruction::Branch(match_end_bb));
     func.set_current_bb(next_bb);
@@ -608,7 +848,7 @@ fn match_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, m: &Mat
 
     func.add(Instruction::Branch(match_end_bb));
     func.set_current_bb(match_end_bb);
-    func.pop_scope();
+    exit_scope(bc_mod, func, target);
     dst
 }
 
@@ -623,7 +863,9 @@ fn while_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, w: &Whi
     let cond = to_bc(bc_mod, func, &w.cond, target);
     func.add(branch_if_instr(&cond, body_bb, post_while_bb));
     func.set_current_bb(body_bb);
+    func.push_scope();
     expr_to_bc(bc_mod, func, &w.body, target);
+    exit_scope(bc_mod, func, target);
     func.add(Instruction::Branch(cond_bb));
 
     func.set_current_bb(post_while_bb);
@@ -641,14 +883,7 @@ fn for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLo
     let index = stack_alloc(func, &target.native_uint_type, None);
     func.add(store_operand_instr(&index, Operand::const_uint(0, target.int_size)));
 
-
-    let len = if let Type::Array(ref at) = iterable.typ {
-        Operand::const_uint(at.len as u64, target.int_size)
-    } else {
-        let len = stack_alloc(func, &target.native_uint_type, None);
-        func.add(get_prop_instr(&len, &iterable, ByteCodeProperty::Len));
-        var_op(&len)
-    };
+    let len = sequence_len(func, &iterable, target);
 
     let cond_bb = func.create_basic_block();
     let body_bb = func.create_basic_block();
@@ -657,19 +892,34 @@ fn for_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, f: &ForLo
     func.add(Instruction::Branch(cond_bb));
     func.set_current_bb(cond_bb);
     let cmp = stack_alloc(func, &Type::Bool, None);
-    func.add(binary_op_instr(&cmp, BinaryOperator::LessThan, var_op(&index), len));
+    func.add(binary_op_instr(&cmp, BinaryOperator::LessThan, var_op(&index), len.clone()));
     func.add(branch_if_instr(&cmp, body_bb, post_for_bb));
 
     func.set_current_bb(body_bb);
-    func.add(load_member_instr_with_var(&loop_variable, &iterable, &index));
+    let is_string = iterable.typ == Type::String;
+    if is_string {
+        // Decodes the UTF-8 character at index, and moves index to the next character
+        let data = stack_alloc(func, &ptr_type(Type::UInt(IntSize::I8)), None);
+        func.add(get_prop_instr(&data, &iterable, ByteCodeProperty::Data));
+        func.add(call_instr(&loop_variable, NEXT_CHAR_FUNCTION, vec![var_op(&data), len, Operand::AddressOf(index.clone())]));
+    } else {
+        if bc_mod.options.bounds_checks {
+            func.add(bounds_check_instr(&index, len, &f.span));
+        }
+        func.add(load_member_instr_with_var(&loop_variable, &iterable, &index));
+    }
     func.push_destination(None);
+    func.push_scope();
     expr_to_bc(bc_mod, func, &f.body, target);
+    exit_scope(bc_mod, func, target);
     func.pop_destination();
-    func.add(binary_op_instr(&index, BinaryOperator::Add, var_op(&index), Operand::const_uint(1, target.int_size)));
+    if !is_string {
+        func.add(binary_op_instr(&index, BinaryOperator::Add, var_op(&index), Operand::const_uint(1, target.int_size)));
+    }
     func.add(Instruction::Branch(cond_bb));
 
     func.set_current_bb(post_for_bb);
-    func.pop_scope();
+    exit_scope(bc_mod, func, target);
 }
 
 fn cast_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &TypeCast, target: &Target) -> Var
@@ -682,6 +932,142 @@ fn cast_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, c: &Type
     dst
 }
 
+// Range of an integer type, the minimum as a signed and the maximum as an unsigned number
+fn int_range(typ: &Type) -> (i64, u64)
+{
+    use std::{i64, u64};
+    match *typ
+    {
+        Type::Int(int_size) => {
+            let shift = 64 - int_size.size_in_bits();
+            (i64::MIN >> shift, (i64::MAX >> shift) as u64)
+        },
+        Type::UInt(int_size) => (0, u64::MAX >> (64 - int_size.size_in_bits())),
+        _ => panic!("Internal Compiler Error: {} is not an integer type", typ),
+    }
+}
+
+fn int_constant(typ: &Type, min: bool) -> Constant
+{
+    let (min_value, max_value) = int_range(typ);
+    match *typ
+    {
+        Type::Int(int_size) => Constant::Int(if min {min_value} else {max_value as i64}, int_size),
+        Type::UInt(int_size) => Constant::UInt(if min {0} else {max_value}, int_size),
+        _ => panic!("Internal Compiler Error: {} is not an integer type", typ),
+    }
+}
+
+/*
+    The range checks a value of type from has to pass, to fit in type to.
+    Each check is the comparison the value must satisfy, and the value to saturate to when it doesn't.
+*/
+fn conversion_checks(from: &Type, to: &Type) -> Vec<(BinaryOperator, Constant, Constant)>
+{
+    use std::f32;
+    let mut checks = Vec::new();
+    match (from, to)
+    {
+        (&Type::Int(_), &Type::Int(_)) |
+        (&Type::Int(_), &Type::UInt(_)) |
+        (&Type::UInt(_), &Type::Int(_)) |
+        (&Type::UInt(_), &Type::UInt(_)) => {
+            let (from_min, from_max) = int_range(from);
+            let (to_min, to_max) = int_range(to);
+            if let Type::Int(int_size) = *from {
+                if to_min > from_min {
+                    checks.push((BinaryOperator::GreaterThanEquals, Constant::Int(to_min, int_size), int_constant(to, true)));
+                }
+
+                if to_max < from_max {
+                    checks.push((BinaryOperator::LessThanEquals, Constant::Int(to_max as i64, int_size), int_constant(to, false)));
+                }
+            } else if let Type::UInt(int_size) = *from {
+                if to_max < from_max {
+                    checks.push((BinaryOperator::LessThanEquals, Constant::UInt(to_max, int_size), int_constant(to, false)));
+                }
+            }
+        },
+
+        (&Type::Float(float_size), &Type::Int(_)) |
+        (&Type::Float(float_size), &Type::UInt(_)) => {
+            let (to_min, to_max) = int_range(to);
+            checks.push((BinaryOperator::GreaterThanEquals, Constant::Float(to_min as f64, float_size), int_constant(to, true)));
+            // The maximum is not always representable as a float, so compare against the first value which doesn't fit
+            checks.push((BinaryOperator::LessThan, Constant::Float(to_max as f64 + 1.0, float_size), int_constant(to, false)));
+        },
+
+        (&Type::Float(FloatSize::F64), &Type::Float(FloatSize::F32)) => {
+            let max = f32::MAX as f64;
+            checks.push((BinaryOperator::GreaterThanEquals, Constant::Float(-max, FloatSize::F64), Constant::Float(-max, FloatSize::F32)));
+            checks.push((BinaryOperator::LessThanEquals, Constant::Float(max, FloatSize::F64), Constant::Float(max, FloatSize::F32)));
+        },
+
+        _ => (),
+    }
+
+    checks
+}
+
+fn conversion_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, inner: &Expression, typ: &Type, mode: ConversionMode, target: &Target) -> Var
+{
+    let dst_type = if mode == ConversionMode::Checked {optional_type(typ.clone())} else {typ.clone()};
+    let dst = get_dst(func, &dst_type);
+    func.push_destination(None);
+    let src = to_bc(bc_mod, func, inner, target);
+    func.pop_destination();
+
+    let end_bb = func.create_basic_block();
+    let on_failure = |func: &mut ByteCodeFunction, saturated: Constant| {
+        if mode == ConversionMode::Checked {
+            func.add(Instruction::StoreNil(dst.clone()));
+        } else {
+            func.add(store_operand_instr(&dst, Operand::Const(saturated)));
+        }
+        func.add(Instruction::Branch(end_bb));
+    };
+
+    if let (&Type::Float(_), true) = (&src.typ, typ.is_integer()) {
+        // NaN is not equal to itself, it cannot be represented as an integer, so it becomes nil or zero
+        let ok_bb = func.create_basic_block();
+        let nan_bb = func.create_basic_block();
+        let is_nan = stack_alloc(func, &Type::Bool, None);
+        func.add(binary_op_instr(&is_nan, BinaryOperator::NotEquals, var_op(&src), var_op(&src)));
+        func.add(branch_if_instr(&is_nan, nan_bb, ok_bb));
+        func.set_current_bb(nan_bb);
+        let zero = match *typ {
+            Type::Int(int_size) => Constant::Int(0, int_size),
+            _ => int_constant(typ, true),
+        };
+        on_failure(func, zero);
+        func.set_current_bb(ok_bb);
+    }
+
+    if mode != ConversionMode::Wrapping {
+        for (op, bound, saturated) in conversion_checks(&src.typ, typ) {
+            let ok_bb = func.create_basic_block();
+            let fail_bb = func.create_basic_block();
+            let in_range = stack_alloc(func, &Type::Bool, None);
+            func.add(binary_op_instr(&in_range, op, var_op(&src), Operand::Const(bound)));
+            func.add(branch_if_instr(&in_range, ok_bb, fail_bb));
+            func.set_current_bb(fail_bb);
+            on_failure(func, saturated);
+            func.set_current_bb(ok_bb);
+        }
+    }
+
+    if src.typ == *typ {
+        func.add(store_instr(&dst, &src));
+    } else {
+        let converted = stack_alloc(func, typ, None);
+        func.add(cast_instr(&converted, &src));
+        func.add(store_instr(&dst, &converted));
+    }
+    func.add(Instruction::Branch(end_bb));
+    func.set_current_bb(end_bb);
+    dst
+}
+
 fn to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &Expression, target: &Target) -> Var
 {
     expr_to_bc(bc_mod, func, expr, target).expect("Expression must return a value")
@@ -729,6 +1115,20 @@ fn optional_compare_to_bc(
     func.set_current_bb(end_bb);
 }
 
+// Operators which can overflow or divide by zero, and are checked at runtime in checked arithmetic mode
+fn is_checked_operator(op: BinaryOperator) -> bool
+{
+    match op
+    {
+        BinaryOperator::Add |
+        BinaryOperator::Sub |
+        BinaryOperator::Mul |
+        BinaryOperator::Div |
+        BinaryOperator::Mod => true,
+        _ => false,
+    }
+}
+
 fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op: &BinaryOp, target: &Target) -> Var
 {
     func.push_destination(None);
@@ -771,6 +1171,10 @@ fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op:
             }
         },
 
+        Type::Int(_) | Type::UInt(_) if bc_mod.options.checked_arithmetic && is_checked_operator(op.operator) => {
+            func.add(checked_binary_op_instr(&dst, op.operator, var_op(&l), var_op(&r), &op.span));
+        },
+
         _ => {
             func.add(binary_op_instr(&dst, op.operator, var_op(&l), var_op(&r)));
         }
@@ -781,7 +1185,8 @@ fn binary_op_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, op:
 
 fn if_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, if_expr: &IfExpression, target: &Target) -> Var
 {
-    let dst = get_dst(func, &if_expr.typ);
+    // Both branches have their own scope, so only the branch which is taken cleans up
+    let result = scope_result(func, &if_expr.typ);
     let true_bb = func.create_basic_block();
     let end_bb = func.create_basic_block();
 
@@ -789,27 +1194,148 @@ fn if_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, if_expr: &
     let cond = to_bc(bc_mod, func, &if_expr.condition, target);
     func.pop_destination();
 
-    func.push_destination(Some(dst.clone()));
+    func.push_destination(result.clone());
 
     if let Some(ref on_false) = if_expr.on_false {
         let false_bb = func.create_basic_block();
         func.add(branch_if_instr(&cond, true_bb, false_bb));
         func.set_current_bb(false_bb);
-        expr_to_bc(bc_mod, func, on_false, target);
+        scoped_expr_to_bc(bc_mod, func, on_false, target);
         func.add(Instruction::Branch(end_bb));
     } else {
         func.add(branch_if_instr(&cond, true_bb, end_bb));
     }
 
     func.set_current_bb(true_bb);
-    expr_to_bc(bc_mod, func, &if_expr.on_true, target);
+    scoped_expr_to_bc(bc_mod, func, &if_expr.on_true, target);
     func.add(Instruction::Branch(end_bb));
 
     func.pop_destination();
     func.set_current_bb(end_bb);
+    match result
+    {
+        Some(result) => store_scope_result(func, result),
+        None => get_dst(func, &if_expr.typ),
+    }
+}
+
+// Convert a failure to the result type the function returns, the failure cases of both hold the same values
+fn convert_failure(func: &mut ByteCodeFunction, failure: &Var, return_type: &Type, name: &str, target: &Target)
+{
+    let failure_case = |typ: &Type| match *typ
+    {
+        Type::Sum(ref st) => st.failure_case(),
+        _ => None,
+    };
+
+    let (idx, src_case) = failure_case(&failure.typ).expect("Internal Compiler Error: ? operator on a type which is not a result type");
+    let (_, dst_case) = failure_case(return_type).expect("Internal Compiler Error: function does not return a result type");
+    let dst = stack_alloc(func, return_type, Some(name));
+    func.add(set_prop_instr(&dst, ByteCodeProperty::SumTypeIndex, idx));
+    if let (&Type::Struct(ref st), &Type::Struct(_)) = (&src_case, &dst_case) {
+        let src_ptr = stack_alloc(func, &ptr_type(src_case.clone()), None);
+        let dst_ptr = stack_alloc(func, &ptr_type(dst_case.clone()), None);
+        func.add(address_of_member_instr(&src_ptr, failure, idx, target.int_size));
+        func.add(address_of_member_instr(&dst_ptr, &dst, idx, target.int_size));
+        for (member_idx, member) in st.members.iter().enumerate() {
+            let value = stack_alloc(func, &member.typ, None);
+            func.add(load_member_instr(&value, &src_ptr, member_idx, target.int_size));
+            func.add(store_member_instr(&dst_ptr, member_idx, value, target.int_size));
+        }
+    }
+}
+
+fn propagation_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, p: &Propagation, target: &Target) -> Var
+{
+    let inner_type = p.inner.get_type(target.int_size);
+    let return_type = func.sig.return_type.clone();
+    // A failure of another result type is converted, before it is returned
+    let convert = match inner_type
+    {
+        Type::Sum(_) => inner_type != return_type,
+        _ => false,
+    };
+    let tmp = stack_alloc(func, &inner_type, if convert {None} else {Some(p.var_name.as_str())});
+    func.push_destination(Some(tmp.clone()));
+    expr_to_bc(bc_mod, func, &p.inner, target);
+    func.pop_destination();
+
+    let ok_bb = func.create_basic_block();
+    let failure_bb = func.create_basic_block();
+    let cond = stack_alloc(func, &Type::Bool, None);
+    let ok_idx = match inner_type
+    {
+        Type::Optional(_) => {
+            func.add(load_optional_flag_instr(&cond, &tmp));
+            None
+        },
+        Type::Sum(ref st) => {
+            let (idx, _) = st.success_case().expect("Internal Compiler Error: sum type has no success case");
+            let sum_type_index = stack_alloc(func, &target.native_uint_type, None);
+            func.add(get_prop_instr(&sum_type_index, &tmp, ByteCodeProperty::SumTypeIndex));
+            func.add(binary_op_instr(&cond, BinaryOperator::Equals, var_op(&sum_type_index), Operand::const_uint(idx as u64, target.int_size)));
+            Some((idx, st.cases[idx].typ.clone()))
+        },
+        _ => panic!("Internal Compiler Error: ? operator on a type which is not optional or a sum type"),
+    };
+
+    func.add(branch_if_instr(&cond, ok_bb, failure_bb));
+    func.set_current_bb(failure_bb);
+    if convert {
+        convert_failure(func, &tmp, &return_type, &p.var_name, target);
+    }
+
+    func.push_destination(None);
+    expr_to_bc(bc_mod, func, &p.on_failure, target);
+    func.pop_destination();
+
+    func.set_current_bb(ok_bb);
+    let dst = get_dst(func, &p.typ);
+    match ok_idx
+    {
+        None => func.add(load_instr(&dst, &tmp)),
+        Some((idx, case_type)) => {
+            let struct_ptr = stack_alloc(func, &ptr_type(case_type), None);
+            func.add(address_of_member_instr(&struct_ptr, &tmp, idx, target.int_size));
+            func.add(load_member_instr(&dst, &struct_ptr, 0, target.int_size));
+        },
+    }
     dst
 }
 
+// Code following a panic ends up in a new block, which is never entered
+fn panic_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, p: &Panic, target: &Target) -> Option<Var>
+{
+    func.push_destination(None);
+    let msg = to_bc(bc_mod, func, &p.message, target);
+    func.pop_destination();
+    func.add(panic_instr(&msg, &p.span));
+
+    let unreachable_bb = func.create_basic_block();
+    func.set_current_bb(unreachable_bb);
+    if p.typ != Type::Void {
+        Some(get_dst(func, &p.typ))
+    } else {
+        None
+    }
+}
+
+fn assert_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, condition: &Expression, message: &Expression, span: &Span, target: &Target)
+{
+    let ok_bb = func.create_basic_block();
+    let fail_bb = func.create_basic_block();
+
+    func.push_destination(None);
+    let cond = to_bc(bc_mod, func, condition, target);
+    func.add(branch_if_instr(&cond, ok_bb, fail_bb));
+    func.set_current_bb(fail_bb);
+    let msg = to_bc(bc_mod, func, message, target);
+    func.add(panic_instr(&msg, span));
+    func.pop_destination();
+
+    func.set_current_bb(ok_bb);
+}
+
 fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign: &Assign, target: &Target)
 {
     // During type checking, other assigns, will be converted in a regular assign
@@ -817,10 +1343,15 @@ fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign
 
     func.push_destination(None);
     let r = to_bc(bc_mod, func, &assign.right, target);
+    move_value(func, &assign.right);
     match assign.left {
         AssignTarget::Var(ref nr) => {
             let var = Var::named(&nr.name, nr.typ.clone());
             func.add(store_instr(&var, &r));
+            // The binding owns the newly assigned value
+            if let Some(live) = func.live_flag(&nr.name) {
+                func.add(store_operand_instr(&live, Operand::const_bool(true)));
+            }
         },
 
         AssignTarget::MemberAccess(ref ma) => {
@@ -835,6 +1366,7 @@ fn assign_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, assign
         AssignTarget::IndexOperation(ref iop) => {
             let tgt = to_bc(bc_mod, func, &iop.target, target);
             let idx = to_bc(bc_mod, func, &iop.index_expr, target);
+            bounds_check_to_bc(bc_mod, func, &tgt, &idx, &iop.span, target);
             func.add(store_member_with_var_instr(tgt, idx, r));
         }
     }
@@ -1067,17 +1599,35 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             Some(dst)
         }
 
+        Expression::CompilerCall(CompilerCall::Convert{ref inner, ref typ, mode, ..}) => {
+            Some(conversion_to_bc(bc_mod, func, inner, typ, mode, target))
+        }
+
+        Expression::CompilerCall(CompilerCall::Assert{ref condition, ref message, ref span}) => {
+            assert_to_bc(bc_mod, func, condition, message, span, target);
+            None
+        }
+
         Expression::IndexOperation(ref iop) => {
             let tgt = to_bc(bc_mod, func, &iop.target, target);
             let idx = to_bc(bc_mod, func, &iop.index_expr, target);
+            bounds_check_to_bc(bc_mod, func, &tgt, &idx, &iop.span, target);
             let dst = get_dst(func, &iop.typ);
             func.add(load_member_instr_with_var(&dst, &tgt, &idx));
             Some(dst)
         }
 
+        Expression::SliceOperation(ref sop) => {
+            Some(slice_operation_to_bc(bc_mod, func, sop, target))
+        }
+
         Expression::Return(ref r) => {
             func.push_destination(None);
-            if let Some(var) = expr_to_bc(bc_mod, func, &r.expression, target) {
+            let ret = expr_to_bc(bc_mod, func, &r.expression, target);
+            move_value(func, &r.expression);
+            let actions = func.cleanup_actions(func.num_scopes());
+            cleanup_to_bc(bc_mod, func, actions, target);
+            if let Some(var) = ret {
                 func.add(Instruction::Return(Operand::Var(var)));
             } else {
                 func.add(Instruction::ReturnVoid)
@@ -1085,22 +1635,54 @@ fn expr_to_bc(bc_mod: &mut ByteCodeModule, func: &mut ByteCodeFunction, expr: &E
             func.pop_destination();
             None
         }
+
+        Expression::Propagate(ref p) => {
+            Some(propagation_to_bc(bc_mod, func, p, target))
+        }
+
+        Expression::Defer(ref d) => {
+            func.add_cleanup(CleanupAction::Defer(d.expression.clone()));
+            None
+        }
+
+        Expression::Panic(ref p) => {
+            panic_to_bc(bc_mod, func, p, target)
+        }
     }
 }
 
 fn func_to_bc(sig: &FunctionSignature, bc_mod: &mut ByteCodeModule, expression: &Expression, target: &Target) -> ByteCodeFunction
 {
     let mut llfunc = ByteCodeFunction::new(sig, false);
-    match expr_to_bc(bc_mod, &mut llfunc, expression, target)
+    // Arguments passed by value are owned by the function
+    if !is_destructor_name(&sig.name) {
+        for arg in &sig.args {
+            if let Some(destructor) = bc_mod.get_destructor(&arg.typ) {
+                add_destroy_cleanup(&mut llfunc, Var::named(&arg.name, arg.typ.clone()), destructor);
+            }
+        }
+    }
+
+    // The body of a function is in the scope of the function
+    let result = match *expression
+    {
+        Expression::Block(ref b) => block_body_to_bc(bc_mod, &mut llfunc, b, target),
+        _ => expr_to_bc(bc_mod, &mut llfunc, expression, target),
+    };
+
+    match result
     {
         Some(ref var) if var.typ != Type::Void => {
             // Pop final scope before returning
+            move_value(&mut llfunc, expression);
+            let actions = llfunc.cleanup_actions(1);
+            cleanup_to_bc(bc_mod, &mut llfunc, actions, target);
             llfunc.pop_scope();
             llfunc.add(ret_instr(var));
         },
 
         _ => {
-            llfunc.pop_scope();
+            exit_scope(bc_mod, &mut llfunc, target);
             llfunc.add(Instruction::ReturnVoid);
         }
     }
@@ -1108,15 +1690,25 @@ fn func_to_bc(sig: &FunctionSignature, bc_mod: &mut ByteCodeModule, expression:
     llfunc
 }
 
-pub fn compile_to_byte_code(pkg: &Package, target: &Target) -> CompileResult<ByteCodeModule>
+pub fn compile_to_byte_code(pkg: &Package, target: &Target, options: ByteCodeOptions) -> CompileResult<ByteCodeModule>
 {
     let mut ll_mod = ByteCodeModule{
         name: pkg.name.clone(),
-        functions: HashMap::new(),
-        globals: HashMap::new(),
+        options: options,
+        functions: BTreeMap::new(),
+        globals: BTreeMap::new(),
         imported_functions: Vec::new(),
+        destructors: BTreeSet::new(),
     };
 
+    for md in pkg.modules.values() {
+        ll_mod.destructors.extend(md.functions.keys().filter(|name| is_destructor_name(name)).cloned());
+    }
+
+    for import in pkg.import_data.imports.values() {
+        ll_mod.destructors.extend(import.symbols.keys().filter(|name| is_destructor_name(name)).cloned());
+    }
+
 
     for md in pkg.modules.values() {
         for func in md.externals.values() {
@@ -1151,6 +1743,9 @@ pub fn compile_to_byte_code(pkg: &Package, target: &Target) -> CompileResult<Byt
         }
     }
 
+    if options.verify {
+        verify_module(&ll_mod, "compilation")?;
+    }
 
     Ok(ll_mod)
 }
@@ -1,5 +1,13 @@
-use ast::{Expression, Literal, UnaryOperator, UnaryOp, BinaryOperator, BinaryOp, Block, IntSize};
+use std::collections::{BTreeMap, HashMap};
+use ast::*;
 use bytecode::Constant;
+use compileerror::{CompileResult, type_error_result, unknown_name_result};
+use span::Span;
+use target::Target;
+use typechecker::ConstEvaluation;
+
+const MAX_EVALUATION_STEPS: usize = 100_000;
+const MAX_CALL_DEPTH: usize = 256;
 
 fn lit_to_const(lit: &Literal) -> Option<Constant>
 {
@@ -37,12 +45,16 @@ fn lit_to_const(lit: &Literal) -> Option<Constant>
 fn unary_op_to_const(uop: &UnaryOp) -> Option<Constant>
 {
     let cst = try_opt!(expr_to_const(&uop.expression));
+    unary_op_const(uop.operator, cst)
+}
 
-    match (uop.operator, cst)  {
+pub fn unary_op_const(op: UnaryOperator, cst: Constant) -> Option<Constant>
+{
+    match (op, cst)  {
         (UnaryOperator::Not, Constant::Bool(v)) =>
             Some(Constant::Bool(!v)),
 
-        (UnaryOperator::Sub, Constant::Int(v, int_size)) =>
+        (UnaryOperator::Sub, Constant::Int(v, int_size)) if v != int_limits(int_size).0 =>
             Some(Constant::Int(-v, int_size)),
 
         (UnaryOperator::Sub, Constant::UInt(v, int_size)) =>
@@ -55,51 +67,132 @@ fn unary_op_to_const(uop: &UnaryOp) -> Option<Constant>
     }
 }
 
-#[cfg_attr(feature = "cargo-clippy", allow(float_cmp))]
 fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
 {
     let left = try_opt!(expr_to_const(&bop.left));
     let right = try_opt!(expr_to_const(&bop.right));
-    
-    match (bop.operator, left, right) {
-        (BinaryOperator::Add, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l + r, ls)),
-        (BinaryOperator::Add, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l + r, ls)),
-        (BinaryOperator::Add, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l + r, ls)),
+    binary_op_const(bop.operator, left, right)
+}
 
-        (BinaryOperator::Sub, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l - r, ls)),
-        (BinaryOperator::Sub, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l - r, ls)),
-        (BinaryOperator::Sub, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l - r, ls)),
+fn is_integer_arithmetic(op: BinaryOperator) -> bool
+{
+    match op.base_operator()
+    {
+        BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul => true,
+        _ => false,
+    }
+}
+
+fn int_limits(int_size: IntSize) -> (i64, i64)
+{
+    use std::i64;
+    let shift = 64 - int_size.size_in_bits();
+    (i64::MIN >> shift, i64::MAX >> shift)
+}
+
+fn uint_max(int_size: IntSize) -> u64
+{
+    use std::u64;
+    u64::MAX >> (64 - int_size.size_in_bits())
+}
+
+// Add, sub or mul two signed integers of size int_size, None if a checked operation overflows
+pub fn int_arithmetic(op: BinaryOperator, l: i64, r: i64, int_size: IntSize) -> Option<i64>
+{
+    let (min, max) = int_limits(int_size);
+    let (checked, wrapped) = match op.base_operator()
+    {
+        BinaryOperator::Add => (l.checked_add(r), l.wrapping_add(r)),
+        BinaryOperator::Sub => (l.checked_sub(r), l.wrapping_sub(r)),
+        BinaryOperator::Mul => (l.checked_mul(r), l.wrapping_mul(r)),
+        _ => return None,
+    };
+
+    let checked = checked.and_then(|v| if v >= min && v <= max {Some(v)} else {None});
+    if op.is_wrapping() {
+        let shift = 64 - int_size.size_in_bits();
+        Some((wrapped << shift) >> shift)
+    } else if op.is_saturating() {
+        checked.or_else(|| match op.base_operator() {
+            BinaryOperator::Add => Some(if r > 0 {max} else {min}),
+            BinaryOperator::Sub => Some(if r < 0 {max} else {min}),
+            _ => Some(if (l < 0) != (r < 0) {min} else {max}),
+        })
+    } else {
+        checked
+    }
+}
+
+pub fn uint_arithmetic(op: BinaryOperator, l: u64, r: u64, int_size: IntSize) -> Option<u64>
+{
+    let max = uint_max(int_size);
+    let (checked, wrapped) = match op.base_operator()
+    {
+        BinaryOperator::Add => (l.checked_add(r), l.wrapping_add(r)),
+        BinaryOperator::Sub => (l.checked_sub(r), l.wrapping_sub(r)),
+        BinaryOperator::Mul => (l.checked_mul(r), l.wrapping_mul(r)),
+        _ => return None,
+    };
+
+    let checked = checked.and_then(|v| if v <= max {Some(v)} else {None});
+    if op.is_wrapping() {
+        Some(wrapped & max)
+    } else if op.is_saturating() {
+        checked.or_else(|| if op.base_operator() == BinaryOperator::Sub {Some(0)} else {Some(max)})
+    } else {
+        checked
+    }
+}
 
-        (BinaryOperator::Mul, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l * r, ls)),
-        (BinaryOperator::Mul, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l * r, ls)),
+// Division by zero, and dividing the minimum value by -1 overflow
+fn int_division_defined(l: i64, r: i64, int_size: IntSize) -> bool
+{
+    r != 0 && !(r == -1 && l == int_limits(int_size).0)
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(float_cmp))]
+pub fn binary_op_const(op: BinaryOperator, left: Constant, right: Constant) -> Option<Constant>
+{
+    match (op, left, right) {
+        (op, Constant::Int(l, ls), Constant::Int(r, _)) if is_integer_arithmetic(op) =>
+            int_arithmetic(op, l, r, ls).map(|v| Constant::Int(v, ls)),
+        (op, Constant::UInt(l, ls), Constant::UInt(r, _)) if is_integer_arithmetic(op) =>
+            uint_arithmetic(op, l, r, ls).map(|v| Constant::UInt(v, ls)),
+
+        (BinaryOperator::Add, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l + r, ls)),
+        (BinaryOperator::Sub, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l - r, ls)),
         (BinaryOperator::Mul, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l * r, ls)),
 
-        (BinaryOperator::Div, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l / r, ls)),
-        (BinaryOperator::Div, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l / r, ls)),
+        (BinaryOperator::Div, Constant::Int(l, ls), Constant::Int(r, _)) if int_division_defined(l, r, ls) => Some(Constant::Int(l / r, ls)),
+        (BinaryOperator::Div, Constant::UInt(l, ls), Constant::UInt(r, _)) if r != 0 => Some(Constant::UInt(l / r, ls)),
         (BinaryOperator::Div, Constant::Float(l, ls), Constant::Float(r, _)) => Some(Constant::Float(l / r, ls)),
 
-        (BinaryOperator::Mod, Constant::Int(l, ls), Constant::Int(r, _)) => Some(Constant::Int(l % r, ls)),
-        (BinaryOperator::Mod, Constant::UInt(l, ls), Constant::UInt(r, _)) => Some(Constant::UInt(l % r, ls)),
+        (BinaryOperator::Mod, Constant::Int(l, ls), Constant::Int(r, _)) if int_division_defined(l, r, ls) => Some(Constant::Int(l % r, ls)),
+        (BinaryOperator::Mod, Constant::UInt(l, ls), Constant::UInt(r, _)) if r != 0 => Some(Constant::UInt(l % r, ls)),
 
         (BinaryOperator::LessThan, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l < r)),
         (BinaryOperator::LessThan, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l < r)),
         (BinaryOperator::LessThan, Constant::Float(l, _), Constant::Float(r, _)) => Some(Constant::Bool(l < r)),
         (BinaryOperator::LessThan, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l < r)),
+        (BinaryOperator::LessThan, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l < *r)),
 
         (BinaryOperator::GreaterThan, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l > r)),
         (BinaryOperator::GreaterThan, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l > r)),
         (BinaryOperator::GreaterThan, Constant::Float(l, _), Constant::Float(r, _)) => Some(Constant::Bool(l > r)),
         (BinaryOperator::GreaterThan, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l > r)),
+        (BinaryOperator::GreaterThan, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l > *r)),
 
         (BinaryOperator::LessThanEquals, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l <= r)),
         (BinaryOperator::LessThanEquals, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l <= r)),
         (BinaryOperator::LessThanEquals, Constant::Float(l, _), Constant::Float(r, _)) => Some(Constant::Bool(l <= r)),
         (BinaryOperator::LessThanEquals, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l <= r)),
+        (BinaryOperator::LessThanEquals, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l <= *r)),
 
         (BinaryOperator::GreaterThanEquals, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l >= r)),
         (BinaryOperator::GreaterThanEquals, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l >= r)),
         (BinaryOperator::GreaterThanEquals, Constant::Float(l, _), Constant::Float(r, _)) => Some(Constant::Bool(l >= r)),
         (BinaryOperator::GreaterThanEquals, Constant::Char(l), Constant::Char(r)) => Some(Constant::Bool(l >= r)),
+        (BinaryOperator::GreaterThanEquals, Constant::String(ref l), Constant::String(ref r)) => Some(Constant::Bool(*l >= *r)),
 
         (BinaryOperator::Equals, Constant::Int(l, _), Constant::Int(r, _)) => Some(Constant::Bool(l == r)),
         (BinaryOperator::Equals, Constant::UInt(l, _), Constant::UInt(r, _)) => Some(Constant::Bool(l == r)),
@@ -122,6 +215,30 @@ fn binary_op_to_const(bop: &BinaryOp) -> Option<Constant>
     }
 }
 
+// Numeric casts, integers are truncated to the size of the destination type
+pub fn cast_const(cst: Constant, dst_type: &Type) -> Option<Constant>
+{
+    let (int_value, float_value) = match cst
+    {
+        Constant::Int(v, _) => (v as u64, v as f64),
+        Constant::UInt(v, _) => (v, v as f64),
+        Constant::Float(v, _) => (v as i64 as u64, v),
+        _ => return None,
+    };
+
+    match (dst_type, &cst)
+    {
+        (&Type::UInt(int_size), &Constant::Float(v, _)) => Some(Constant::UInt((v as u64) & uint_max(int_size), int_size)),
+        (&Type::Int(int_size), _) => {
+            let shift = 64 - int_size.size_in_bits();
+            Some(Constant::Int(((int_value << shift) as i64) >> shift, int_size))
+        },
+        (&Type::UInt(int_size), _) => Some(Constant::UInt(int_value & uint_max(int_size), int_size)),
+        (&Type::Float(float_size), _) => Some(Constant::Float(float_value, float_size)),
+        _ => None,
+    }
+}
+
 fn block_to_const(block: &Block) -> Option<Constant>
 {
     let mut ret = Constant::Int(0, IntSize::I64);
@@ -151,8 +268,441 @@ pub fn expr_to_const(expr: &Expression) -> Option<Constant>
 
         Expression::Block(ref block) => {
             block_to_const(block)
+        },
+
+        Expression::Cast(ref c) => {
+            cast_const(try_opt!(expr_to_const(&c.inner)), &c.destination_type)
+        }
+
+        Expression::StructInitializer(ref si) => {
+            struct_initializer_to_const(si)
         }
 
         _ => None,
     }
-}
\ No newline at end of file
+}
+
+// Only type checked initializers of plain structs, so the constant gets the resolved struct type
+fn struct_initializer_to_const(si: &StructInitializer) -> Option<Constant>
+{
+    if let Type::Struct(_) = si.typ {
+        let mut members = Vec::with_capacity(si.member_initializers.len());
+        for mi in &si.member_initializers {
+            members.push(try_opt!(expr_to_const(mi)));
+        }
+        Some(Constant::Struct(si.typ.clone(), members))
+    } else {
+        None
+    }
+}
+
+// Struct constants become struct initializers again, so type checking can convert the members
+pub fn const_to_expression(cst: &Constant, span: &Span) -> Expression
+{
+    match *cst
+    {
+        Constant::Struct(ref typ, ref members) => {
+            let name = match *typ
+            {
+                Type::Unresolved(ref ut) => ut.name.clone(),
+                Type::Struct(ref st) => st.name.clone(),
+                _ => panic!("Internal Compiler Error: struct constant of type {}", typ),
+            };
+
+            let members = members.iter().map(|m| const_to_expression(m, span)).collect();
+            Expression::StructInitializer(struct_initializer(&name, members, span.clone()))
+        },
+        _ => Expression::Literal(const_to_literal(cst, span)),
+    }
+}
+
+fn const_to_literal(cst: &Constant, span: &Span) -> Literal
+{
+    match *cst
+    {
+        Constant::Int(v, int_size) => Literal::Int(span.clone(), v, int_size),
+        Constant::UInt(v, int_size) => Literal::UInt(span.clone(), v, int_size),
+        Constant::Float(v, float_size) => Literal::Float(span.clone(), format!("{:?}", v), float_size),
+        Constant::Char(v) => Literal::Char(span.clone(), v),
+        Constant::String(ref v) => Literal::String(span.clone(), v.clone()),
+        Constant::Bool(v) => Literal::Bool(span.clone(), v),
+        Constant::NullPtr(ref typ) => Literal::NullPtr(span.clone(), typ.clone()),
+        Constant::Array(ref elements) => {
+            let elements = elements.iter()
+                .map(|e| const_to_expression(e, span))
+                .collect();
+            array_lit(elements, span.clone())
+        }
+        Constant::Struct(..) => panic!("Internal Compiler Error: struct constants are not literals"),
+    }
+}
+
+/*
+    Evaluates global initializers at compile time, this includes calls to functions in the same module.
+    Evaluation happens on the AST before type checking, so only a subset of the language is supported.
+*/
+pub struct ConstEvaluator<'a>
+{
+    module: &'a Module,
+    int_size: IntSize,
+    frames: Vec<HashMap<String, Constant>>,
+    results: HashMap<String, Constant>,
+    evaluating: Vec<String>,
+    steps: usize,
+}
+
+impl<'a> ConstEvaluator<'a>
+{
+    pub fn new(module: &'a Module, int_size: IntSize) -> ConstEvaluator<'a>
+    {
+        ConstEvaluator{
+            module: module,
+            int_size: int_size,
+            frames: Vec::new(),
+            results: HashMap::new(),
+            evaluating: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    pub fn evaluate_global(&mut self, name: &str) -> CompileResult<Constant>
+    {
+        if let Some(cst) = self.results.get(name) {
+            return Ok(cst.clone());
+        }
+
+        let module = self.module;
+        let global = match module.globals.get(name) {
+            Some(g) => g,
+            None => panic!("Internal Compiler Error: unknown global {}", name),
+        };
+
+        if self.evaluating.iter().any(|n| n == name) {
+            return type_error_result(&global.span, format!("Global {} depends on its own value", name));
+        }
+
+        self.evaluating.push(name.into());
+        let frames = ::std::mem::replace(&mut self.frames, Vec::new());
+        let result = self.eval(&global.init);
+        self.frames = frames;
+        self.evaluating.pop();
+
+        let cst = result?;
+        self.results.insert(name.into(), cst.clone());
+        Ok(cst)
+    }
+
+    pub fn evaluate_expression(&mut self, expr: &Expression) -> CompileResult<Constant>
+    {
+        let frames = ::std::mem::replace(&mut self.frames, Vec::new());
+        let result = self.eval(expr);
+        self.frames = frames;
+        result
+    }
+
+    // Names are either fully qualified, or relative to the namespace of the module
+    fn find<'b, T>(map: &'b BTreeMap<String, T>, namespace: &str, name: &str) -> Option<(&'b String, &'b T)>
+    {
+        let full_name = format!("{}::{}", namespace, name);
+        map.iter()
+            .find(|&(key, _)| key == name)
+            .or_else(|| map.iter().find(|&(key, _)| *key == full_name))
+    }
+
+    fn find_struct(&self, name: &str) -> Option<&'a StructDeclaration>
+    {
+        let module = self.module;
+        match ConstEvaluator::find(&module.types, &module.name, name) {
+            Some((_, &TypeDeclaration::Struct(ref sd))) => Some(sd),
+            _ => None,
+        }
+    }
+
+    fn lookup(&mut self, nr: &NameRef) -> CompileResult<Constant>
+    {
+        if let Some(cst) = self.frames.last().and_then(|f| f.get(&nr.name)) {
+            return Ok(cst.clone());
+        }
+
+        let module = self.module;
+        match ConstEvaluator::find(&module.globals, &module.name, &nr.name) {
+            Some((name, global)) => {
+                if global.mutable {
+                    type_error_result(&nr.span, format!("Global {} is mutable and cannot be used in a constant expression", name))
+                } else {
+                    self.evaluate_global(name)
+                }
+            },
+            None => unknown_name_result(&nr.span, format!("Unknown name {} in constant expression", nr.name)),
+        }
+    }
+
+    fn step(&mut self, span: &Span) -> CompileResult<()>
+    {
+        self.steps += 1;
+        if self.steps > MAX_EVALUATION_STEPS {
+            type_error_result(span, format!("Compile time evaluation exceeded the maximum of {} steps", MAX_EVALUATION_STEPS))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn eval(&mut self, expr: &Expression) -> CompileResult<Constant>
+    {
+        self.step(&expr.span())?;
+        match *expr
+        {
+            Expression::Literal(Literal::Array(ref al)) => {
+                let mut elements = Vec::with_capacity(al.elements.len());
+                for e in &al.elements {
+                    elements.push(self.eval(e)?);
+                }
+                Ok(Constant::Array(elements))
+            },
+
+            Expression::Literal(ref lit) => {
+                Ok(lit_to_const(lit).expect("Internal Compiler Error: literal is not a constant"))
+            },
+
+            Expression::UnaryOp(ref uop) => {
+                let cst = self.eval(&uop.expression)?;
+                unary_op_const(uop.operator, cst)
+                    .map_or_else(|| type_error_result(&uop.span, format!("Operator {} cannot be evaluated at compile time", uop.operator)), Ok)
+            },
+
+            Expression::BinaryOp(ref bop) => self.eval_binary_op(bop),
+            Expression::Block(ref block) => self.eval_block(block),
+            Expression::NameRef(ref nr) => self.lookup(nr),
+            Expression::Call(ref call) => self.eval_call(call),
+
+            Expression::If(ref ie) => {
+                match self.eval(&ie.condition)? {
+                    Constant::Bool(true) => self.eval(&ie.on_true),
+                    Constant::Bool(false) => match ie.on_false {
+                        Some(ref on_false) => self.eval(on_false),
+                        None => type_error_result(&ie.span, "An if expression without an else branch does not produce a constant"),
+                    },
+                    _ => type_error_result(&ie.condition.span(), "Condition of an if expression must be a boolean"),
+                }
+            },
+
+            Expression::Match(ref m) => self.eval_match(m),
+
+            Expression::Cast(ref c) => {
+                let cst = self.eval(&c.inner)?;
+                cast_const(cst, &c.destination_type)
+                    .map_or_else(|| type_error_result(&c.span, format!("Cast to {} cannot be evaluated at compile time", c.destination_type)), Ok)
+            },
+
+            Expression::StructInitializer(ref si) => self.eval_struct_initializer(si),
+
+            Expression::MemberAccess(ref ma) => {
+                let is_len = match ma.right {
+                    MemberAccessType::Property(Property::Len) => true,
+                    MemberAccessType::Name(ref field) => field.name == "len",
+                    _ => false,
+                };
+
+                match (is_len, self.eval(&ma.left)?) {
+                    (_, Constant::Struct(ref typ, ref members)) => self.eval_struct_member(ma, typ, members),
+                    (true, Constant::Array(ref elements)) => Ok(Constant::Int(elements.len() as i64, self.int_size)),
+                    (true, Constant::String(ref s)) => Ok(Constant::Int(s.len() as i64, self.int_size)),
+                    _ => type_error_result(&ma.span, "Member access cannot be evaluated at compile time"),
+                }
+            },
+
+            Expression::IndexOperation(ref iop) => {
+                let target = self.eval(&iop.target)?;
+                let index = match self.eval(&iop.index_expr)? {
+                    Constant::Int(v, _) if v >= 0 => v as usize,
+                    Constant::UInt(v, _) => v as usize,
+                    _ => return type_error_result(&iop.index_expr.span(), "Index must be a positive integer"),
+                };
+
+                match target {
+                    Constant::Array(mut elements) => {
+                        if index < elements.len() {
+                            Ok(elements.swap_remove(index))
+                        } else {
+                            type_error_result(&iop.span, format!("Index {} out of bounds, array has length {}", index, elements.len()))
+                        }
+                    },
+                    _ => type_error_result(&iop.target.span(), "Only arrays can be indexed at compile time"),
+                }
+            },
+
+            _ => type_error_result(&expr.span(), "Expression cannot be evaluated at compile time"),
+        }
+    }
+
+    fn eval_struct_initializer(&mut self, si: &StructInitializer) -> CompileResult<Constant>
+    {
+        let mut defaults = Vec::new();
+        let name = if si.struct_name.is_empty() {
+            String::new()
+        } else {
+            let count = si.member_initializers.len();
+            match self.find_struct(&si.struct_name) {
+                // Members left out at the end get their default value
+                Some(sd) if sd.members.len() >= count && sd.members[count..].iter().all(|m| m.default.is_some()) => {
+                    defaults.extend(sd.members[count..].iter().filter_map(|m| m.default.as_ref()));
+                    sd.name.clone()
+                },
+                Some(sd) => return type_error_result(&si.span,
+                    format!("Type {} has {} members, but attempting to initialize {} members", sd.name, sd.members.len(), count)),
+                None => return type_error_result(&si.span,
+                    format!("Only structs of the same module can be created at compile time, {} is not one of them", si.struct_name)),
+            }
+        };
+
+        let mut members = Vec::with_capacity(si.member_initializers.len() + defaults.len());
+        for mi in si.member_initializers.iter().chain(defaults.into_iter()) {
+            members.push(self.eval(mi)?);
+        }
+        Ok(Constant::Struct(unresolved_type(&name, Vec::new()), members))
+    }
+
+    fn eval_struct_member(&self, ma: &MemberAccess, typ: &Type, members: &[Constant]) -> CompileResult<Constant>
+    {
+        let sd = match *typ
+        {
+            Type::Unresolved(ref ut) => self.find_struct(&ut.name),
+            _ => None,
+        };
+
+        let index = match (sd, &ma.right)
+        {
+            (Some(sd), &MemberAccessType::Name(ref field)) => sd.members.iter().position(|m| m.name == field.name),
+            _ => None,
+        };
+
+        match index.and_then(|idx| members.get(idx))
+        {
+            Some(cst) => Ok(cst.clone()),
+            None => type_error_result(&ma.span, "Member access cannot be evaluated at compile time"),
+        }
+    }
+
+    fn eval_binary_op(&mut self, bop: &BinaryOp) -> CompileResult<Constant>
+    {
+        let left = self.eval(&bop.left)?;
+        let right = self.eval(&bop.right)?;
+        match (bop.operator, &right) {
+            (BinaryOperator::Div, &Constant::Int(0, _)) |
+            (BinaryOperator::Div, &Constant::UInt(0, _)) |
+            (BinaryOperator::Mod, &Constant::Int(0, _)) |
+            (BinaryOperator::Mod, &Constant::UInt(0, _)) => {
+                return type_error_result(&bop.span, "Division by zero in constant expression");
+            },
+            _ => (),
+        }
+
+        let integer_arithmetic = is_integer_arithmetic(bop.operator) && left.get_type().is_integer();
+        match binary_op_const(bop.operator, left, right)
+        {
+            Some(cst) => Ok(cst),
+            None if integer_arithmetic => type_error_result(&bop.span, format!("Integer overflow in constant expression (operator {})", bop.operator)),
+            None => type_error_result(&bop.span, format!("Operator {} cannot be evaluated at compile time", bop.operator)),
+        }
+    }
+
+    fn eval_block(&mut self, block: &Block) -> CompileResult<Constant>
+    {
+        let saved = self.frames.last().cloned();
+        let mut result = None;
+        for e in &block.expressions {
+            if let Expression::Bindings(ref bl) = *e {
+                for b in &bl.bindings {
+                    let name = match b.binding_type {
+                        BindingType::Name(ref name) => name.clone(),
+                        _ => return type_error_result(&b.span, "Destructuring bindings cannot be evaluated at compile time"),
+                    };
+
+                    let cst = self.eval(&b.init)?;
+                    match self.frames.last_mut() {
+                        Some(frame) => {frame.insert(name, cst);},
+                        None => self.frames.push(vec![(name, cst)].into_iter().collect()),
+                    }
+                }
+                result = None;
+            } else {
+                result = Some(self.eval(e)?);
+            }
+        }
+
+        match saved {
+            Some(frame) => *self.frames.last_mut().expect("Empty frame stack") = frame,
+            None => self.frames.clear(),
+        }
+
+        result.map_or_else(|| type_error_result(&block.span, "Block does not produce a constant value"), Ok)
+    }
+
+    fn eval_call(&mut self, call: &Call) -> CompileResult<Constant>
+    {
+        let module = self.module;
+        let func = match ConstEvaluator::find(&module.functions, &module.name, &call.callee.name) {
+            Some((_, func)) => func,
+            None => return type_error_result(&call.span, format!("Only functions in the same module can be called at compile time, {} is not one of them", call.callee.name)),
+        };
+
+        if func.sig.args.len() != call.args.len() {
+            return type_error_result(&call.span, format!("Function {} expects {} arguments, but {} are given", func.sig.name, func.sig.args.len(), call.args.len()));
+        }
+
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return type_error_result(&call.span, format!("Compile time evaluation exceeded the maximum call depth of {}", MAX_CALL_DEPTH));
+        }
+
+        let mut frame = HashMap::new();
+        for (arg, e) in func.sig.args.iter().zip(call.args.iter()) {
+            frame.insert(arg.name.clone(), self.eval(e)?);
+        }
+
+        self.frames.push(frame);
+        let result = self.eval(&func.expression);
+        self.frames.pop();
+        result
+    }
+
+    fn pattern_matches(&mut self, pattern: &Pattern, value: &Constant) -> CompileResult<bool>
+    {
+        let pattern_value = match *pattern
+        {
+            Pattern::Any(_) => return Ok(true),
+            Pattern::Literal(ref lit) => self.eval(&Expression::Literal(lit.clone()))?,
+            Pattern::Name(ref nr) => self.lookup(nr)?,
+            _ => return type_error_result(&pattern.span(), "Pattern cannot be evaluated at compile time"),
+        };
+
+        match binary_op_const(BinaryOperator::Equals, value.clone(), pattern_value) {
+            Some(Constant::Bool(b)) => Ok(b),
+            _ => type_error_result(&pattern.span(), "Pattern cannot be evaluated at compile time"),
+        }
+    }
+
+    fn eval_match(&mut self, m: &MatchExpression) -> CompileResult<Constant>
+    {
+        let target = self.eval(&m.target)?;
+        for case in &m.cases {
+            if self.pattern_matches(&case.pattern, &target)? {
+                return self.eval(&case.to_execute);
+            }
+        }
+
+        type_error_result(&m.span, "No match case matches in constant expression")
+    }
+}
+
+// The type checker evaluates consts and default values of struct members with this
+pub struct CompileTimeEvaluator;
+
+impl ConstEvaluation for CompileTimeEvaluator
+{
+    fn evaluate(&self, module: &Module, expression: &Expression, target: &Target) -> CompileResult<Expression>
+    {
+        let mut evaluator = ConstEvaluator::new(module, target.int_size);
+        let cst = evaluator.evaluate_expression(expression)?;
+        Ok(const_to_expression(&cst, &expression.span()))
+    }
+}
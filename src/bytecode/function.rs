@@ -1,8 +1,8 @@
 use std::fmt;
 use std::collections::{BTreeMap, HashMap};
-use itertools::free::join;
-use ast::{Type, FunctionSignature};
+use ast::{Type, FunctionSignature, Expression};
 use bytecode::instruction::Instruction;
+use bytecode::text::{TypeTable, write_var, write_function};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Var
@@ -34,15 +34,24 @@ impl fmt::Display for Var
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        write!(f, "({}: {})", self.name, self.typ)
+        write_var(f, self, &TypeTable::new(false))
     }
 }
 
+// Actions to perform when a scope is exited
+#[derive(Debug, Clone)]
+pub enum CleanupAction
+{
+    // live is a bool which is cleared when var is moved, so moved values are not destroyed
+    Destroy{var: Var, destructor: String, live: Var},
+    Defer(Expression),
+}
+
 #[derive(Debug)]
 pub struct Scope
 {
     named_vars: HashMap<String, Var>,
-    to_cleanup: Vec<Var>,
+    to_cleanup: Vec<CleanupAction>,
     insert_block: BasicBlockRef,
     insert_position: usize,
 }
@@ -64,30 +73,23 @@ impl Scope
         self.named_vars.insert(var.name.clone(), var);
     }
 
-/*
-    pub fn add_cleanup_target(&mut self, v: &Var) -> bool
+    pub fn add_cleanup(&mut self, action: CleanupAction)
     {
-        if self.named_vars.get(&v.name).is_none() {
-            false
-        } else {
-            self.to_cleanup.push(v.clone());
-            true
-        }
+        self.to_cleanup.push(action);
     }
 
-    pub fn remove_cleanup_target(&mut self, v: &Var) -> bool
+    pub fn cleanup_actions(&self) -> Vec<CleanupAction>
     {
-        let len = self.to_cleanup.len();
-        self.to_cleanup.retain(|e| e != v);
-        self.to_cleanup.len() < len
+        // Cleanup in reverse construction order
+        self.to_cleanup.iter().rev().cloned().collect()
     }
-*/
-    pub fn cleanup(&self, _func: &mut ByteCodeFunction)
+
+    pub fn live_flag(&self, name: &str) -> Option<Var>
     {
-        // Cleanup in reverse construction order
-        for _v in self.to_cleanup.iter().rev() {
-            panic!("TODO: add destructor calls");
-        }
+        self.to_cleanup.iter().rev().filter_map(|action| match *action {
+            CleanupAction::Destroy{ref var, ref live, ..} if var.name == name => Some(live.clone()),
+            _ => None,
+        }).next()
     }
 }
 
@@ -122,13 +124,45 @@ impl BasicBlock
 
     pub fn add(&mut self, inst: Instruction)
     {
-        if inst.is_terminator() && self.instructions.last().map(|i| i.is_terminator()).unwrap_or(false) {
+        if inst.is_terminator() && self.is_terminated() {
             // Already a terminator drop this, this only happens with an early return
             return;
         }
 
         self.instructions.push(inst);
     }
+
+    // The instruction ending the block, scope ends can follow an early return, so look past them
+    pub fn terminator(&self) -> Option<&Instruction>
+    {
+        let last = self.instructions.iter()
+            .rev()
+            .find(|i| match **i {
+                Instruction::StartScope | Instruction::EndScope => false,
+                _ => true,
+            });
+
+        match last
+        {
+            Some(instr) if instr.is_terminator() => Some(instr),
+            _ => None,
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool
+    {
+        self.terminator().is_some()
+    }
+
+    pub fn successors(&self) -> Vec<BasicBlockRef>
+    {
+        match self.terminator()
+        {
+            Some(&Instruction::Branch(target)) => vec![target],
+            Some(&Instruction::BranchIf{on_true, on_false, ..}) => vec![on_true, on_false],
+            _ => Vec::new(),
+        }
+    }
 }
 
 
@@ -173,6 +207,30 @@ impl ByteCodeFunction
         f
     }
 
+    // Create a function from already constructed blocks, var_counter is the index of the next temporary
+    pub fn from_blocks(sig: &FunctionSignature, external: bool, blocks: BTreeMap<BasicBlockRef, BasicBlock>, var_counter: usize) -> ByteCodeFunction
+    {
+        let bb_counter = blocks.keys().next_back().map(|bb| bb + 1).unwrap_or(0);
+        ByteCodeFunction{
+            sig: sig.clone(),
+            blocks: blocks,
+            external: external,
+            current_bb: 0,
+            bb_counter: bb_counter,
+            var_counter: var_counter,
+            scopes: vec![Scope::new(0, 0)],
+            destinations: Vec::new(),
+        }
+    }
+
+    // Replace all blocks, used by optimizations which restructure the function
+    pub fn replace_blocks(&mut self, blocks: BTreeMap<BasicBlockRef, BasicBlock>)
+    {
+        self.bb_counter = blocks.keys().next_back().map(|bb| bb + 1).unwrap_or(0);
+        self.current_bb = 0;
+        self.blocks = blocks;
+    }
+
     pub fn add(&mut self, inst: Instruction)
     {
         let idx = self.current_bb;
@@ -215,14 +273,50 @@ impl ByteCodeFunction
 
     pub fn pop_scope(&mut self)
     {
-        let s = self.scopes.pop().expect("Empty Scope Stack");
-        s.cleanup(self);
+        self.scopes.pop().expect("Empty Scope Stack");
         if !self.scopes.is_empty() {
             // Add an endscope instruction, but not at function exit
             self.add(Instruction::EndScope);
         }
     }
 
+    pub fn num_scopes(&self) -> usize
+    {
+        self.scopes.len()
+    }
+
+    pub fn add_cleanup(&mut self, action: CleanupAction)
+    {
+        let scope = self.scopes.last_mut().expect("Empty Scope Stack");
+        scope.add_cleanup(action);
+    }
+
+    // Get the cleanup actions of the innermost num_scopes scopes, innermost scope first
+    pub fn cleanup_actions(&self, num_scopes: usize) -> Vec<CleanupAction>
+    {
+        self.scopes.iter()
+            .rev()
+            .take(num_scopes)
+            .flat_map(|s| s.cleanup_actions())
+            .collect()
+    }
+
+    // Get the live flag of a destructible binding, bindings in inner scopes shadow those in outer scopes
+    pub fn live_flag(&self, name: &str) -> Option<Var>
+    {
+        self.scopes.iter()
+            .rev()
+            .find(|s| s.named_vars.contains_key(name))
+            .and_then(|s| s.live_flag(name))
+    }
+
+    pub fn is_current_bb_terminated(&self) -> bool
+    {
+        self.blocks.get(&self.current_bb)
+            .map(|bb| bb.is_terminated())
+            .unwrap_or(false)
+    }
+
     pub fn push_destination(&mut self, var: Option<Var>)
     {
         self.destinations.push(var);
@@ -296,15 +390,6 @@ impl ByteCodeFunction
                 block.instructions.retain(|instr| !pred(instr));
             }
         }
-
-        pub fn add_cleanup_target(&mut self, v: &Var)
-        {
-            for scope in self.scopes.iter_mut().rev() {
-                if scope.add_cleanup_target(v) {
-                    break;
-                }
-            }
-        }
         */
 }
 
@@ -312,17 +397,6 @@ impl fmt::Display for ByteCodeFunction
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        writeln!(f, "{}({}) -> {}:",
-            self.sig.name,
-            join(self.sig.args.iter().map(|arg| format!("{}: {}", arg.name, arg.typ)), ", "),
-            self.sig.return_type)?;
-        for bb in self.blocks.values() {
-            writeln!(f, " {}:", bb.name)?;
-            for inst in &bb.instructions {
-                inst.fmt(f)?;
-            }
-        }
-
-        Ok(())
+        write_function(f, self, false, &TypeTable::new(false))
     }
 }
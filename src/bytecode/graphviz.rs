@@ -0,0 +1,279 @@
+/*
+    Graphviz output of bytecode, for debugging. The control flow graph has one cluster per function,
+    with the instructions of every block as node label. The call graph has a node for every function
+    of the module and every imported function. Render them with dot, for example:
+
+        dot -Tsvg build/x86_64-unknown-linux-gnu/test/test.cfg.dot -o test.cfg.svg
+*/
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::Write;
+use bytecode::{ByteCodeModule, ByteCodeFunction, BasicBlockRef, ControlFlowGraph, Instruction, NEXT_CHAR_FUNCTION, bb_name};
+use bytecode::text::{TypeTable, write_instruction};
+
+// Quote a string for dot, a newline ends a left aligned line
+fn write_quoted<W: Write>(w: &mut W, s: &str) -> fmt::Result
+{
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c
+        {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\l")?,
+            _ => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+// Blocks of different functions have the same names, so nodes are named after the function and the block
+fn node_name(func: &ByteCodeFunction, bb_ref: BasicBlockRef) -> String
+{
+    format!("{}.{}", func.sig.name, bb_name(bb_ref))
+}
+
+fn write_block<W: Write>(w: &mut W, func: &ByteCodeFunction, bb_ref: BasicBlockRef, attributes: &[&str], loop_depth: usize, table: &TypeTable) -> fmt::Result
+{
+    let mut label = format!("{}:", bb_name(bb_ref));
+    if loop_depth > 0 {
+        write!(label, " (loop depth {})", loop_depth)?;
+    }
+    label.push('\n');
+    for instr in &func.blocks[&bb_ref].instructions {
+        label.push_str("  ");
+        write_instruction(&mut label, instr, table)?;
+        label.push('\n');
+    }
+
+    w.write_str("        ")?;
+    write_quoted(w, &node_name(func, bb_ref))?;
+    w.write_str(" [label=")?;
+    write_quoted(w, &label)?;
+    for attribute in attributes {
+        write!(w, ", {}", attribute)?;
+    }
+    w.write_str("];\n")
+}
+
+fn write_edge<W: Write>(w: &mut W, func: &ByteCodeFunction, from: BasicBlockRef, to: BasicBlockRef, attributes: &[&str]) -> fmt::Result
+{
+    w.write_str("        ")?;
+    write_quoted(w, &node_name(func, from))?;
+    w.write_str(" -> ")?;
+    write_quoted(w, &node_name(func, to))?;
+    if !attributes.is_empty() {
+        write!(w, " [{}]", attributes.join(", "))?;
+    }
+    w.write_str(";\n")
+}
+
+/*
+    Write the control flow graph of a function as a cluster. Unreachable blocks are dashed, loop headers
+    are bold, and so are the branches back to a loop header.
+*/
+fn write_cfg<W: Write>(w: &mut W, func: &ByteCodeFunction) -> fmt::Result
+{
+    let cfg = ControlFlowGraph::new(func);
+    let loops = cfg.loops();
+    let table = TypeTable::new(false);
+
+    w.write_str("    subgraph ")?;
+    write_quoted(w, &format!("cluster_{}", func.sig.name))?;
+    w.write_str(" {\n        label=")?;
+    write_quoted(w, &func.sig.name)?;
+    w.write_str(";\n")?;
+
+    for bb_ref in func.blocks.keys() {
+        let mut attributes = Vec::new();
+        if !cfg.is_reachable(*bb_ref) {
+            attributes.push("style=dashed");
+        } else if loops.iter().any(|l| l.header == *bb_ref) {
+            attributes.push("style=bold");
+        }
+
+        let loop_depth = loops.iter().filter(|l| l.contains(*bb_ref)).count();
+        write_block(w, func, *bb_ref, &attributes, loop_depth, &table)?;
+    }
+
+    for (bb_ref, bb) in &func.blocks {
+        let back_edge = |target: BasicBlockRef| loops.iter().any(|l| l.header == target && l.latches.contains(bb_ref));
+        let edges = match bb.terminator()
+        {
+            Some(&Instruction::Branch(target)) => vec![(target, None)],
+            Some(&Instruction::BranchIf{on_true, on_false, ..}) => vec![(on_true, Some("label=\"true\"")), (on_false, Some("label=\"false\""))],
+            _ => Vec::new(),
+        };
+
+        for (target, label) in edges {
+            let mut attributes: Vec<&str> = label.into_iter().collect();
+            if back_edge(target) {
+                attributes.push("style=bold");
+            }
+            write_edge(w, func, *bb_ref, target, &attributes)?;
+        }
+    }
+
+    w.write_str("    }\n")
+}
+
+// The control flow graphs of all functions with a body, in one graph
+pub fn write_cfg_graphs<W: Write>(w: &mut W, module: &ByteCodeModule) -> fmt::Result
+{
+    w.write_str("digraph ")?;
+    write_quoted(w, &module.name)?;
+    w.write_str(" {\n    node [shape=box, fontname=\"monospace\"];\n")?;
+    for func in module.functions.values().filter(|func| !func.external) {
+        write_cfg(w, func)?;
+    }
+    w.write_str("}\n")
+}
+
+fn write_function_node<W: Write>(w: &mut W, name: &str, annotation: Option<&str>) -> fmt::Result
+{
+    w.write_str("    ")?;
+    write_quoted(w, name)?;
+    if let Some(annotation) = annotation {
+        w.write_str(" [label=")?;
+        write_quoted(w, &format!("{}\n({})", name, annotation))?;
+        w.write_str(", style=dashed]")?;
+    }
+    w.write_str(";\n")
+}
+
+/*
+    Write the call graph of a module, external, imported and runtime functions are dashed and annotated.
+    Calls through a function pointer have no known callee, so they are not part of the graph.
+*/
+pub fn write_call_graph<W: Write>(w: &mut W, module: &ByteCodeModule) -> fmt::Result
+{
+    let mut calls = BTreeSet::new();
+    for func in module.functions.values() {
+        for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+            if let Instruction::Call{func: ref callee, ..} = *instr {
+                calls.insert((func.sig.name.as_str(), callee.as_str()));
+            }
+        }
+    }
+
+    w.write_str("digraph ")?;
+    write_quoted(w, &module.name)?;
+    w.write_str(" {\n    node [shape=box];\n")?;
+
+    for func in module.functions.values() {
+        write_function_node(w, &func.sig.name, if func.external {Some("external")} else {None})?;
+    }
+
+    for func in &module.imported_functions {
+        write_function_node(w, &func.sig.name, Some("imported"))?;
+    }
+
+    if calls.iter().any(|&(_, callee)| callee == NEXT_CHAR_FUNCTION) {
+        write_function_node(w, NEXT_CHAR_FUNCTION, Some("runtime"))?;
+    }
+
+    let is_function = |name: &str| {
+        module.functions.contains_key(name) ||
+            module.imported_functions.iter().any(|func| func.sig.name == name) ||
+            name == NEXT_CHAR_FUNCTION
+    };
+
+    for (caller, callee) in calls {
+        if !is_function(callee) {
+            continue;
+        }
+
+        w.write_str("    ")?;
+        write_quoted(w, caller)?;
+        w.write_str(" -> ")?;
+        write_quoted(w, callee)?;
+        w.write_str(";\n")?;
+    }
+
+    w.write_str("}\n")
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_module;
+
+    #[test]
+    fn test_cfg_graph()
+    {
+        let module = parse_module(r#"module test
+options
+
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  store (x: i32) (i32 0)
+  br block1
+block1:
+  brif (b: bool) block2 block3
+block2:
+  bop (x: i32) add (x: i32) (a: i32)
+  br block1
+block3:
+  ret (x: i32)
+block4:
+  ret ("a \"quoted\" name": i32)
+}
+
+fn test::bar() -> i32 {
+entry:
+  ret (i32 1)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        let mut dot = String::new();
+        write_cfg_graphs(&mut dot, &module).expect("Writing succeeded");
+        assert!(dot.starts_with("digraph \"test\" {\n"));
+        assert_eq!(dot.matches("digraph").count(), 1);
+        assert!(dot.contains("    subgraph \"cluster_test::foo\" {\n        label=\"test::foo\";\n"));
+        assert!(dot.contains("    subgraph \"cluster_test::bar\" {\n"));
+        assert!(dot.contains("\"test::foo.block1\" [label=\"block1: (loop depth 1)\\l  brif (b: bool) block2 block3\\l\", style=bold];\n"));
+        assert!(dot.contains("\"test::foo.entry\" -> \"test::foo.block1\";\n"));
+        assert!(dot.contains("\"test::foo.block1\" -> \"test::foo.block2\" [label=\"true\"];\n"));
+        assert!(dot.contains("\"test::foo.block1\" -> \"test::foo.block3\" [label=\"false\"];\n"));
+        assert!(dot.contains("\"test::foo.block2\" -> \"test::foo.block1\" [style=bold];\n"));
+        assert!(dot.contains("\"test::bar.entry\" [label="));
+        assert!(dot.contains("\\\"quoted\\\""));
+        assert!(dot.contains(", style=dashed];\n"));
+        assert!(dot.ends_with("    }\n}\n"));
+    }
+
+    #[test]
+    fn test_call_graph()
+    {
+        let module = parse_module(r#"module test
+options
+
+fn test::main() -> i32 {
+entry:
+  call ($var0: i32) test::foo()
+  ret ($var0: i32)
+}
+
+fn test::foo() -> i32 {
+entry:
+  call ($var1: i32) puts((string "hello"))
+  call ($var2: i32) std::bar()
+  ret ($var2: i32)
+}
+
+extern fn puts(s: string) -> i32
+
+import fn std::bar() -> i32
+"#, "test.bc").expect("Parsing succeeded");
+
+        let mut dot = String::new();
+        write_call_graph(&mut dot, &module).expect("Writing succeeded");
+        assert!(dot.contains("    \"test::main\";\n"));
+        assert!(dot.contains("    \"puts\" [label=\"puts\\l(external)\", style=dashed];\n"));
+        assert!(dot.contains("    \"std::bar\" [label=\"std::bar\\l(imported)\", style=dashed];\n"));
+        assert!(dot.contains("    \"test::main\" -> \"test::foo\";\n"));
+        assert!(dot.contains("    \"test::foo\" -> \"puts\";\n"));
+        assert!(dot.contains("    \"test::foo\" -> \"std::bar\";\n"));
+    }
+}
@@ -1,7 +1,8 @@
 use std::fmt;
-use itertools::free::join;
 use ast::{UnaryOperator, BinaryOperator, Type, IntSize, FloatSize, ptr_type, array_type};
 use bytecode::function::{BasicBlockRef, Var};
+use bytecode::text::{TypeTable, write_constant, write_operand, write_instruction};
+use span::Span;
 
 
 #[derive(Debug, Clone, Copy)]
@@ -35,6 +36,8 @@ pub enum Constant
     String(String),
     Bool(bool),
     Array(Vec<Constant>),
+    // Before type checking, the type of a struct constant is the unresolved name of the struct
+    Struct(Type, Vec<Constant>),
     NullPtr(Type),
 }
 
@@ -54,6 +57,7 @@ impl Constant
             Constant::Array(ref members) => {
                 array_type(members[0].get_type(), members.len())
             }
+            Constant::Struct(ref typ, _) => typ.clone(),
         }
     }
 }
@@ -62,17 +66,7 @@ impl fmt::Display for Constant
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        match *self
-        {
-            Constant::Int(v, int_size) => write!(f, "(int{} {})", int_size, v),
-            Constant::UInt(v, int_size) => write!(f, "(uint{} {})", int_size, v),
-            Constant::Float(v, float_size) => write!(f, "(float{} {})", float_size, v),
-            Constant::Char(v) => write!(f, "(char {})", v),
-            Constant::String(ref v) => write!(f, "(string {})", v),
-            Constant::Bool(v) => write!(f, "(bool {})", v),
-            Constant::Array(ref m) => write!(f, "[{}]", join(m.iter(), ", ")),
-            Constant::NullPtr(_) => write!(f, "null"),
-        }
+        write_constant(f, self, &TypeTable::new(false))
     }
 }
 
@@ -138,15 +132,7 @@ impl fmt::Display for Operand
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        match *self
-        {
-            Operand::Var(ref var) => write!(f, "{}", var),
-            Operand::AddressOf(ref var) => write!(f, "&{}", var),
-            Operand::Dereference(ref var) => write!(f, "*{}", var),
-            Operand::Const(ref c) => write!(f, "{}", c),
-            Operand::Func(ref func) => write!(f, "(func {})", func),
-            Operand::SizeOf(ref typ) => write!(f, "@size({})", typ),
-        }
+        write_operand(f, self, &TypeTable::new(false))
     }
 }
 
@@ -178,6 +164,7 @@ pub enum Instruction
     SetProperty{obj: Var, prop: ByteCodeProperty, val: usize},
     UnaryOp{dst: Var, op: UnaryOperator, src: Operand},
     BinaryOp{dst: Var, op: BinaryOperator, left: Operand, right: Operand},
+    CheckedBinaryOp{dst: Var, op: BinaryOperator, left: Operand, right: Operand, span: Span},
     Call{dst: Option<Var>, func: String, args: Vec<Operand>},
     Slice{dst: Var, src: Var, start: Operand, len: Operand},
     MakeSlice{dst: Var, data: Var, len: Var},
@@ -193,6 +180,11 @@ pub enum Instruction
     Branch(BasicBlockRef),
     BranchIf{cond: Operand, on_true: BasicBlockRef, on_false: BasicBlockRef},
     Delete(Var),
+    BoundsCheck{index: Operand, len: Operand, span: Span},
+    RangeCheck{seq: Var, start: Operand, end: Operand, span: Span},
+    Panic{msg: Var, span: Span},
+    // Only in SSA form, the value of dst depends on the block control came from
+    Phi{dst: Var, incoming: Vec<(BasicBlockRef, Operand)>},
 }
 
 impl Instruction
@@ -203,10 +195,90 @@ impl Instruction
             Instruction::Branch(_) |
             Instruction::BranchIf{..} |
             Instruction::ReturnVoid |
-            Instruction::Return(_) => true,
+            Instruction::Return(_) |
+            Instruction::Panic{..} => true,
             _ => false
         }
     }
+
+    // The operands read by the instruction, vars which are not operands are not included
+    pub fn operands(&self) -> Vec<&Operand>
+    {
+        match *self
+        {
+            Instruction::Store{ref src, ..} |
+            Instruction::UnaryOp{ref src, ..} |
+            Instruction::Cast{ref src, ..} |
+            Instruction::Return(ref src) |
+            Instruction::BranchIf{cond: ref src, ..} => vec![src],
+            Instruction::LoadMember{ref member_index, ..} |
+            Instruction::AddressOfMember{ref member_index, ..} => vec![member_index],
+            Instruction::StoreMember{ref member_index, ref src, ..} => vec![member_index, src],
+            Instruction::BinaryOp{ref left, ref right, ..} |
+            Instruction::CheckedBinaryOp{ref left, ref right, ..} |
+            Instruction::Slice{start: ref left, len: ref right, ..} |
+            Instruction::BoundsCheck{index: ref left, len: ref right, ..} |
+            Instruction::RangeCheck{start: ref left, end: ref right, ..} => vec![left, right],
+            Instruction::Call{ref args, ..} => args.iter().collect(),
+            Instruction::Phi{ref incoming, ..} => incoming.iter().map(|&(_, ref op)| op).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand>
+    {
+        match *self
+        {
+            Instruction::Store{ref mut src, ..} |
+            Instruction::UnaryOp{ref mut src, ..} |
+            Instruction::Cast{ref mut src, ..} |
+            Instruction::Return(ref mut src) |
+            Instruction::BranchIf{cond: ref mut src, ..} => vec![src],
+            Instruction::LoadMember{ref mut member_index, ..} |
+            Instruction::AddressOfMember{ref mut member_index, ..} => vec![member_index],
+            Instruction::StoreMember{ref mut member_index, ref mut src, ..} => vec![member_index, src],
+            Instruction::BinaryOp{ref mut left, ref mut right, ..} |
+            Instruction::CheckedBinaryOp{ref mut left, ref mut right, ..} |
+            Instruction::Slice{start: ref mut left, len: ref mut right, ..} |
+            Instruction::BoundsCheck{index: ref mut left, len: ref mut right, ..} |
+            Instruction::RangeCheck{start: ref mut left, end: ref mut right, ..} => vec![left, right],
+            Instruction::Call{ref mut args, ..} => args.iter_mut().collect(),
+            Instruction::Phi{ref mut incoming, ..} => incoming.iter_mut().map(|&mut (_, ref mut op)| op).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // The vars of the instruction which are not part of an operand
+    pub fn vars_mut(&mut self) -> Vec<&mut Var>
+    {
+        match *self
+        {
+            Instruction::Store{ref mut dst, ..} |
+            Instruction::UnaryOp{ref mut dst, ..} |
+            Instruction::BinaryOp{ref mut dst, ..} |
+            Instruction::CheckedBinaryOp{ref mut dst, ..} |
+            Instruction::Cast{ref mut dst, ..} |
+            Instruction::Call{dst: Some(ref mut dst), ..} |
+            Instruction::StoreNil(ref mut dst) |
+            Instruction::StackAlloc(ref mut dst) |
+            Instruction::HeapAlloc(ref mut dst) |
+            Instruction::Delete(ref mut dst) |
+            Instruction::StoreMember{obj: ref mut dst, ..} |
+            Instruction::SetProperty{obj: ref mut dst, ..} |
+            Instruction::RangeCheck{seq: ref mut dst, ..} |
+            Instruction::Panic{msg: ref mut dst, ..} |
+            Instruction::Phi{ref mut dst, ..} => vec![dst],
+            Instruction::Load{ref mut dst, ptr: ref mut src} |
+            Instruction::LoadMember{ref mut dst, obj: ref mut src, ..} |
+            Instruction::AddressOf{ref mut dst, obj: ref mut src} |
+            Instruction::AddressOfMember{ref mut dst, obj: ref mut src, ..} |
+            Instruction::GetProperty{ref mut dst, obj: ref mut src, ..} |
+            Instruction::LoadOptionalFlag{ref mut dst, obj: ref mut src} |
+            Instruction::Slice{ref mut dst, ref mut src, ..} => vec![dst, src],
+            Instruction::MakeSlice{ref mut dst, ref mut data, ref mut len} => vec![dst, data, len],
+            _ => Vec::new(),
+        }
+    }
 }
 
 pub fn store_instr(dst: &Var, src: &Var) -> Instruction
@@ -319,6 +391,17 @@ pub fn binary_op_instr(dst: &Var, op: BinaryOperator, left: Operand, right: Oper
     }
 }
 
+pub fn checked_binary_op_instr(dst: &Var, op: BinaryOperator, left: Operand, right: Operand, span: &Span) -> Instruction
+{
+    Instruction::CheckedBinaryOp{
+        dst: dst.clone(),
+        op: op,
+        left: left,
+        right: right,
+        span: span.clone(),
+    }
+}
+
 pub fn branch_if_instr(cond: &Var, on_true: BasicBlockRef, on_false: BasicBlockRef) -> Instruction
 {
     Instruction::BranchIf{
@@ -328,6 +411,41 @@ pub fn branch_if_instr(cond: &Var, on_true: BasicBlockRef, on_false: BasicBlockR
     }
 }
 
+pub fn bounds_check_instr(index: &Var, len: Operand, span: &Span) -> Instruction
+{
+    Instruction::BoundsCheck{
+        index: var_op(index),
+        len: len,
+        span: span.clone(),
+    }
+}
+
+pub fn range_check_instr(seq: &Var, start: Operand, end: Operand, span: &Span) -> Instruction
+{
+    Instruction::RangeCheck{
+        seq: seq.clone(),
+        start: start,
+        end: end,
+        span: span.clone(),
+    }
+}
+
+pub fn panic_instr(msg: &Var, span: &Span) -> Instruction
+{
+    Instruction::Panic{
+        msg: msg.clone(),
+        span: span.clone(),
+    }
+}
+
+pub fn phi_instr(dst: &Var, incoming: Vec<(BasicBlockRef, Operand)>) -> Instruction
+{
+    Instruction::Phi{
+        dst: dst.clone(),
+        incoming: incoming,
+    }
+}
+
 pub fn call_instr(dst: &Var, func: &str, args: Vec<Operand>) -> Instruction
 {
     Instruction::Call{
@@ -403,110 +521,6 @@ impl fmt::Display for Instruction
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        match *self
-        {
-            Instruction::Store{ref dst, ref src} => {
-                writeln!(f, "  store {} {}", dst, src)
-            },
-
-            Instruction::Load{ref dst, ref ptr} => {
-                writeln!(f, "  load {} {}", dst, ptr)
-            },
-
-            Instruction::LoadMember{ref dst, ref obj, ref member_index} => {
-                writeln!(f, "  loadm {} {}.{}", dst, obj, member_index)
-            },
-
-            Instruction::StoreMember{ref obj, ref member_index, ref src} => {
-                writeln!(f, "  storem {}.{} {}", obj, member_index, src)
-            },
-
-            Instruction::AddressOf{ref dst, ref obj} => {
-                writeln!(f, "  addr {} {}", dst, obj)
-            },
-
-            Instruction::AddressOfMember{ref dst, ref obj, ref member_index} => {
-                writeln!(f, "  addrm {} {}.{}", dst, obj, member_index)
-            },
-
-            Instruction::GetProperty{ref dst, ref obj, ref prop} => {
-                writeln!(f, "  getp {} {}.{}", dst, obj, prop)
-            },
-
-            Instruction::SetProperty{ref obj, ref prop, ref val} => {
-                writeln!(f, "  setp {} {} {}", obj, prop, val)
-            },
-
-            Instruction::UnaryOp{ref dst, ref op, ref src} => {
-                writeln!(f, "  uop {} {} {}", dst, op, src)
-            },
-
-            Instruction::BinaryOp{ref dst, ref op, ref left, ref right} => {
-                writeln!(f, "  bop {} {} {} {}", dst, op, left, right)
-            },
-
-            Instruction::Call{ref dst, ref func, ref args} => {
-                match *dst {
-                    Some(ref dst) => writeln!(f, "  call {} {} {}", dst, func, join(args.iter(), " ")),
-                    None => writeln!(f, "  call {} {}", func, join(args.iter(), " ")),
-                }
-            },
-
-            Instruction::Cast{ref dst, ref src} => {
-                writeln!(f, "  cast {} {}", dst, src)
-            },
-
-            Instruction::StackAlloc(ref var) => {
-                writeln!(f, "  salloc {}", var)
-            },
-
-            Instruction::HeapAlloc(ref var) => {
-                writeln!(f, "  halloc {}", var)
-            },
-
-            Instruction::StartScope => {
-                writeln!(f, "  scope start")
-            },
-
-            Instruction::EndScope => {
-                writeln!(f, "  scope end")
-            },
-
-            Instruction::Return(ref var) => {
-                writeln!(f, "  ret {}", var)
-            },
-
-            Instruction::ReturnVoid => {
-                writeln!(f, "  ret void")
-            },
-
-            Instruction::Branch(ref name) => {
-                writeln!(f, "  br {}", name)
-            },
-
-            Instruction::BranchIf{ref cond, ref on_true, ref on_false} => {
-                writeln!(f, "  brif {} ? {} : {} ", cond, on_true, on_false)
-            },
-
-            Instruction::Delete(ref var) => {
-                writeln!(f, "  delete {}", var)
-            },
-
-            Instruction::Slice{ref dst, ref src, ref start, ref len} => {
-                writeln!(f, "  slice {} {} {} {}", dst, src, start, len)
-            },
-
-            Instruction::MakeSlice{ref dst, ref data, ref len} => {
-                writeln!(f, "  mkslice {} {} {}", dst, data, len)
-            }
-
-            Instruction::LoadOptionalFlag{ref dst, ref obj} => {
-                writeln!(f, "  loadoptf {} {}", dst, obj)
-            }
-
-            Instruction::StoreNil(ref v) => {
-                writeln!(f, "  storenil {}", v)
-            }
-        }
+        write_instruction(f, self, &TypeTable::new(false))
     }
 }
@@ -1,24 +1,66 @@
+mod cfg;
 mod compiler;
 mod consteval;
 mod function;
+mod graphviz;
 mod instruction;
 mod optimizer;
+mod text;
+mod verifier;
 
 use std::fmt;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use ast::{Type, destructor_name};
 
 pub use self::instruction::*;
 pub use self::function::*;
+pub use self::cfg::ControlFlowGraph;
 pub use self::compiler::{compile_to_byte_code};
-pub use self::optimizer::{OptimizationLevel, optimize_module};
+pub use self::consteval::{CompileTimeEvaluator, int_arithmetic, uint_arithmetic};
+pub use self::graphviz::{write_cfg_graphs, write_call_graph};
+pub use self::optimizer::{OptimizationLevel, optimize_module, dump_ssa, remove_phi_nodes};
+pub use self::text::parse_module;
+#[cfg(test)]
+pub use self::text::parse_function;
+pub use self::verifier::verify_module;
+
+// Runtime function which decodes the UTF-8 character at an index in a string, and advances the index past it
+pub const NEXT_CHAR_FUNCTION: &'static str = "menhir_next_char";
+
+#[derive(Debug, Clone, Copy)]
+pub struct ByteCodeOptions
+{
+    pub checked_arithmetic: bool,
+    pub bounds_checks: bool,
+    // Run the verifier after compilation and after every optimization pass
+    pub verify: bool,
+    // Print the heap allocations which escape analysis moves to the stack
+    pub dump_escape_analysis: bool,
+}
+
+impl Default for ByteCodeOptions
+{
+    fn default() -> ByteCodeOptions
+    {
+        ByteCodeOptions{
+            checked_arithmetic: false,
+            bounds_checks: false,
+            verify: cfg!(debug_assertions),
+            dump_escape_analysis: false,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ByteCodeModule
 {
     pub name: String,
-    pub functions: HashMap<String, ByteCodeFunction>,
+    pub options: ByteCodeOptions,
+    // Ordered by name, so the generated code is the same in every build
+    pub functions: BTreeMap<String, ByteCodeFunction>,
     pub imported_functions: Vec<ByteCodeFunction>,
-    pub globals: HashMap<String, Constant>,
+    pub globals: BTreeMap<String, Constant>,
+    pub destructors: BTreeSet<String>,
 }
 
 impl ByteCodeModule
@@ -32,36 +74,127 @@ impl ByteCodeModule
     {
         self.functions.get(name)
     }
+
+    pub fn get_destructor(&self, typ: &Type) -> Option<String>
+    {
+        let name = match *typ
+        {
+            Type::Struct(ref st) => destructor_name(&st.name),
+            Type::Sum(ref st) => destructor_name(&st.name),
+            _ => return None,
+        };
+
+        if self.destructors.contains(&name) {
+            Some(name)
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::Display for ByteCodeModule
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
     {
-        for (name, value) in &self.globals {
-            writeln!(f, "glob {} = {}", name, value)?;
-        }
-
-        writeln!(f, " ")?;
-
-        for func in self.functions.values() {
-            func.fmt(f)?;
-            writeln!(f, " ")?;
-        }
-        Ok(())
+        text::write_module(f, self)
     }
 }
 
 #[cfg(test)]
 pub mod test
 {
+    use std::fs;
+    use std::io::Read;
+    use std::path::{PathBuf, Path};
     use compileerror::CompileResult;
     use parser::{parse_str};
-    use bytecode::{ByteCodeModule, compile_to_byte_code};
+    use bytecode::{ByteCodeModule, ByteCodeOptions, CompileTimeEvaluator, compile_to_byte_code};
     use ast::{TreePrinter, IntSize};
     use target::Target;
 
+    // A test program from the testcode directory, the first line holds the expected return value
+    pub struct Test
+    {
+        pub name: String,
+        pub path: PathBuf,
+        pub ret: i64,
+        pub code: String,
+    }
+
+    impl Test
+    {
+        pub fn load(path: &Path) -> Test
+        {
+            let mut file = fs::File::open(path).unwrap();
+            let mut data = String::new();
+            file.read_to_string(&mut data).unwrap();
+
+            assert!(data.starts_with("#ret:"));
+            let ret: String = data.chars().skip(5).take_while(|c| c.is_numeric()).collect();
+            Test{
+                name: path.file_stem().unwrap().to_str().unwrap().into(),
+                path: path.to_path_buf(),
+                ret: i64::from_str_radix(&ret, 10).unwrap(),
+                code: data,
+            }
+        }
+
+        // Tests which link C code, or import the std package, need more than their own code to compile
+        pub fn is_standalone(&self) -> bool
+        {
+            !self.path.with_extension("c").exists() && !self.code.contains("import std")
+        }
+    }
+
+    // All tests in the testcode directory, sorted by name
+    pub fn load_tests() -> Vec<Test>
+    {
+        for path in &["testcode", "../testcode"] {
+            if let Ok(dir) = fs::read_dir(path) {
+                let mut paths: Vec<PathBuf> = dir.map(|dir_entry| dir_entry.unwrap().path()).collect();
+                paths.sort();
+                return paths.iter()
+                    .filter(|path| path.extension().unwrap_or_default() == "mhr")
+                    .map(|path| Test::load(path))
+                    .collect();
+            }
+        }
+
+        panic!("testcode directory not found");
+    }
+
+    // Run the tests with a backend, and check their return values, returns the number of failures
+    pub fn run_tests<F>(tests: &[Test], run: F) -> usize
+        where F: Fn(&Test) -> Result<i64, String>
+    {
+        println!();
+        println!("Running tests:");
+        println!("==============");
+        let mut failures = 0;
+        for test in tests {
+            let r = run(test).and_then(|ret| {
+                if ret != test.ret {
+                    Err(format!("Return value doesn't match: {}, expecting {}", ret, test.ret))
+                } else {
+                    Ok(ret)
+                }
+            });
+
+            println!("{}: {:?}", test.name, r);
+            if r.is_err() {
+                failures += 1;
+            }
+        }
+
+        failures
+    }
+
     pub fn generate_byte_code(prog: &str, dump: bool) -> CompileResult<ByteCodeModule>
+    {
+        generate_byte_code_with_options(prog, ByteCodeOptions::default(), dump)
+    }
+
+    pub fn generate_byte_code_with_options(prog: &str, options: ByteCodeOptions, dump: bool) -> CompileResult<ByteCodeModule>
     {
         let target = Target::new(IntSize::I32, "");
         let mut pkg = parse_str(prog, "test", &target)?;
@@ -72,7 +205,7 @@ pub mod test
             println!("-----------------");
         }
 
-        pkg.type_check(&target)?;
+        pkg.type_check(&target, &CompileTimeEvaluator)?;
 
         if dump {
             println!("After type check");
@@ -80,7 +213,7 @@ pub mod test
             println!("-----------------");
         }
 
-        let bc_mod = compile_to_byte_code(&pkg, &target)?;
+        let bc_mod = compile_to_byte_code(&pkg, &target, options)?;
         if dump {
             println!("ByteCode:");
             println!("{}", bc_mod);
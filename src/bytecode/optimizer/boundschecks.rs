@@ -0,0 +1,159 @@
+use ast::{BinaryOperator, Type};
+use bytecode::cfg::ControlFlowGraph;
+use bytecode::function::{BasicBlockRef, ByteCodeFunction, Var};
+use bytecode::instruction::{Constant, Instruction, Operand};
+use super::assigned_var;
+
+// A variable which has its address taken, can be modified behind our back
+fn is_address_taken(func: &ByteCodeFunction, var: &Var) -> bool
+{
+    let is_address_of = |op: &Operand| match *op {
+        Operand::AddressOf(ref v) => v.name == var.name,
+        _ => false,
+    };
+
+    func.blocks.values()
+        .flat_map(|bb| bb.instructions.iter())
+        .any(|instr| match *instr {
+            Instruction::AddressOf{ref obj, ..} => obj.name == var.name,
+            Instruction::Store{ref src, ..} => is_address_of(src),
+            Instruction::Call{ref args, ..} => args.iter().any(|a| is_address_of(a)),
+            _ => false,
+        })
+}
+
+fn modifies(instructions: &[Instruction], index: &Var, len: &Operand) -> bool
+{
+    instructions.iter().any(|instr| {
+        match (assigned_var(instr), len) {
+            (Some(v), _) if v.name == index.name => true,
+            (Some(v), &Operand::Var(ref len_var)) => v.name == len_var.name,
+            _ => false,
+        }
+    })
+}
+
+fn same_operand(a: &Operand, b: &Operand) -> bool
+{
+    match (a, b)
+    {
+        (&Operand::Var(ref a), &Operand::Var(ref b)) => a.name == b.name,
+        (&Operand::Const(Constant::Int(a, _)), &Operand::Const(Constant::Int(b, _))) => a == b,
+        (&Operand::Const(Constant::UInt(a, _)), &Operand::Const(Constant::UInt(b, _))) => a == b,
+        _ => false,
+    }
+}
+
+fn constant_in_bounds(index: &Operand, len: &Operand) -> bool
+{
+    let len = match *len {
+        Operand::Const(Constant::UInt(len, _)) => len,
+        _ => return false,
+    };
+
+    match *index {
+        Operand::Const(Constant::UInt(i, _)) => i < len,
+        Operand::Const(Constant::Int(i, _)) => i >= 0 && (i as u64) < len,
+        _ => false,
+    }
+}
+
+// The constant stored in var by the instructions before the check, if any
+fn known_constant<'a>(before: &'a [Instruction], var: &Var) -> Option<&'a Operand>
+{
+    for instr in before.iter().rev() {
+        if assigned_var(instr).map(|v| v.name == var.name).unwrap_or(false) {
+            return match *instr {
+                Instruction::Store{src: ref src @ Operand::Const(_), ..} => Some(src),
+                _ => None,
+            };
+        }
+    }
+
+    None
+}
+
+// Is the block only entered from a block ending in: brif (index < len) block
+fn guarded_by_comparison(
+    func: &ByteCodeFunction,
+    cfg: &ControlFlowGraph,
+    bb_ref: BasicBlockRef,
+    index: &Var,
+    len: &Operand) -> bool
+{
+    let pred = match cfg.predecessors(bb_ref) {
+        preds if preds.len() == 1 => &func.blocks[&preds[0]],
+        _ => return false,
+    };
+
+    let (cond, rest) = match pred.instructions.split_last() {
+        Some((&Instruction::BranchIf{cond: Operand::Var(ref cond), on_true, on_false}, rest)) if on_true == bb_ref && on_false != bb_ref => (cond, rest),
+        _ => return false,
+    };
+
+    for (pos, instr) in rest.iter().enumerate().rev() {
+        if assigned_var(instr).map(|v| v.name == cond.name).unwrap_or(false) {
+            return match *instr {
+                Instruction::BinaryOp{op: BinaryOperator::LessThan, left: Operand::Var(ref l), ref right, ..} =>
+                    l.name == index.name && same_operand(right, len) && !modifies(&rest[pos + 1..], index, len),
+                _ => false,
+            };
+        }
+    }
+
+    false
+}
+
+fn provably_in_bounds(
+    func: &ByteCodeFunction,
+    cfg: &ControlFlowGraph,
+    bb_ref: BasicBlockRef,
+    before: &[Instruction],
+    index: &Operand,
+    len: &Operand) -> bool
+{
+    let index_var = match *index {
+        Operand::Var(ref v) if !is_address_taken(func, v) => v,
+        _ => return constant_in_bounds(index, len),
+    };
+
+    if known_constant(before, index_var).map(|c| constant_in_bounds(c, len)).unwrap_or(false) {
+        return true;
+    }
+
+    // The same check was already done in this block
+    for (pos, instr) in before.iter().enumerate().rev() {
+        if let Instruction::BoundsCheck{index: ref prev_index, len: ref prev_len, ..} = *instr {
+            if same_operand(prev_index, index) && same_operand(prev_len, len) && !modifies(&before[pos + 1..], index_var, len) {
+                return true;
+            }
+        }
+    }
+
+    // An unsigned index which was compared against the length, like a for loop index
+    match index_var.typ {
+        Type::UInt(_) => !modifies(before, index_var, len) && guarded_by_comparison(func, cfg, bb_ref, index_var, len),
+        _ => false,
+    }
+}
+
+pub fn remove_redundant_bounds_checks(func: &mut ByteCodeFunction)
+{
+    let cfg = ControlFlowGraph::new(func);
+    let mut to_remove = Vec::new();
+    for (bb_ref, bb) in &func.blocks {
+        for (idx, instr) in bb.instructions.iter().enumerate() {
+            if let Instruction::BoundsCheck{ref index, ref len, ..} = *instr {
+                if provably_in_bounds(func, &cfg, *bb_ref, &bb.instructions[..idx], index, len) {
+                    to_remove.push((*bb_ref, idx));
+                }
+            }
+        }
+    }
+
+    for &(bb_ref, idx) in to_remove.iter().rev() {
+        if let Some(bb) = func.blocks.get_mut(&bb_ref) {
+            bb.instructions.remove(idx);
+        }
+    }
+}
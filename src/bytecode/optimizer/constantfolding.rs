@@ -0,0 +1,270 @@
+use std::collections::{HashMap, HashSet};
+use ast::{Type, FloatSize};
+use bytecode::consteval::{binary_op_const, unary_op_const, cast_const};
+use bytecode::function::ByteCodeFunction;
+use bytecode::instruction::{Constant, Instruction, Operand};
+use super::assigned_var;
+use super::liveness::is_global;
+
+fn is_scalar_constant(c: &Constant) -> bool
+{
+    match *c
+    {
+        Constant::Int(_, _) |
+        Constant::UInt(_, _) |
+        Constant::Float(_, _) |
+        Constant::Char(_) |
+        Constant::Bool(_) => true,
+        _ => false,
+    }
+}
+
+// Sizes of types which don't depend on the target, or on how a backend lays out aggregates
+fn size_of(typ: &Type) -> Option<u64>
+{
+    match *typ
+    {
+        Type::Int(int_size) | Type::UInt(int_size) => Some(u64::from(int_size.size_in_bits() / 8)),
+        Type::Float(FloatSize::F32) | Type::Char => Some(4),
+        Type::Float(FloatSize::F64) => Some(8),
+        Type::Bool => Some(1),
+        Type::Array(ref at) => size_of(&at.element_type).map(|s| s * at.len() as u64),
+        _ => None,
+    }
+}
+
+/*
+    Vars which get assigned a constant once, and are not modified in any other way.
+    Arguments, globals, and vars which have their address taken can change behind our back.
+*/
+fn constant_vars(func: &ByteCodeFunction) -> HashMap<String, Constant>
+{
+    let mut assignments: HashMap<&str, Option<&Constant>> = HashMap::new();
+    let mut excluded: HashSet<&str> = func.sig.args.iter().map(|a| a.name.as_str()).collect();
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        match *instr
+        {
+            Instruction::StackAlloc(_) => (),
+            Instruction::Store{ref dst, src: Operand::Const(ref c)} if is_scalar_constant(c) && c.get_type() == dst.typ => {
+                let value = if assignments.contains_key(dst.name.as_str()) {None} else {Some(c)};
+                assignments.insert(&dst.name, value);
+            },
+            Instruction::AddressOf{ref obj, ..} |
+            Instruction::AddressOfMember{ref obj, ..} |
+            Instruction::StoreMember{ref obj, ..} |
+            Instruction::SetProperty{ref obj, ..} => {
+                excluded.insert(&obj.name);
+            },
+            _ => {
+                if let Some(dst) = assigned_var(instr) {
+                    assignments.insert(&dst.name, None);
+                }
+            },
+        }
+
+        for op in instr.operands() {
+            if let Operand::AddressOf(ref v) = *op {
+                excluded.insert(&v.name);
+            }
+        }
+    }
+
+    assignments.into_iter()
+        .filter(|&(name, _)| !excluded.contains(name) && !is_global(name))
+        .filter_map(|(name, value)| value.map(|c| (name.to_string(), c.clone())))
+        .collect()
+}
+
+// Replace an instruction which only has constant operands, by a store of the result
+fn fold(instr: &Instruction) -> Option<Instruction>
+{
+    let (dst, result) = match *instr
+    {
+        Instruction::BinaryOp{ref dst, op, left: Operand::Const(ref l), right: Operand::Const(ref r)} |
+        Instruction::CheckedBinaryOp{ref dst, op, left: Operand::Const(ref l), right: Operand::Const(ref r), ..} =>
+            (dst, binary_op_const(op, l.clone(), r.clone())),
+        Instruction::UnaryOp{ref dst, op, src: Operand::Const(ref c)} => (dst, unary_op_const(op, c.clone())),
+        Instruction::Cast{ref dst, src: Operand::Const(ref c)} => (dst, cast_const(c.clone(), &dst.typ)),
+        Instruction::Store{ref dst, src: Operand::SizeOf(ref typ)} => match dst.typ
+        {
+            Type::UInt(int_size) => (dst, size_of(typ).map(|s| Constant::UInt(s, int_size))),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    match result
+    {
+        // Folding is done with 64 bit floats, round to the precision used at runtime
+        Some(Constant::Float(v, FloatSize::F32)) if dst.typ == Type::Float(FloatSize::F32) => Some(Instruction::Store{
+            dst: dst.clone(),
+            src: Operand::Const(Constant::Float(f64::from(v as f32), FloatSize::F32)),
+        }),
+        Some(c) if c.get_type() == dst.typ => Some(Instruction::Store{
+            dst: dst.clone(),
+            src: Operand::Const(c),
+        }),
+        _ => None,
+    }
+}
+
+fn fold_branch(instr: &Instruction) -> Option<Instruction>
+{
+    match *instr
+    {
+        Instruction::BranchIf{cond: Operand::Const(Constant::Bool(cond)), on_true, on_false} =>
+            Some(Instruction::Branch(if cond {on_true} else {on_false})),
+        Instruction::BranchIf{on_true, on_false, ..} if on_true == on_false =>
+            Some(Instruction::Branch(on_true)),
+        _ => None,
+    }
+}
+
+/*
+    Propagate constants into the instructions using them, and evaluate instructions with constant operands,
+    until nothing changes anymore. Branches on a constant condition become unconditional.
+*/
+pub fn fold_constants(func: &mut ByteCodeFunction)
+{
+    loop {
+        let constants = constant_vars(func);
+        let mut changed = false;
+        for bb in func.blocks.values_mut() {
+            for instr in &mut bb.instructions {
+                for op in instr.operands_mut() {
+                    let replacement = match *op
+                    {
+                        Operand::Var(ref v) => constants.get(&v.name).cloned(),
+                        _ => None,
+                    };
+
+                    if let Some(c) = replacement {
+                        *op = Operand::Const(c);
+                        changed = true;
+                    }
+                }
+
+                if let Some(folded) = fold(instr).or_else(|| fold_branch(instr)) {
+                    *instr = folded;
+                    changed = true;
+                }
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_function;
+    use bytecode::function::BasicBlock;
+
+    fn last_instruction(bb: &BasicBlock) -> &Instruction
+    {
+        bb.instructions.last().expect("Empty block")
+    }
+
+    #[test]
+    fn test_fold_constants()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  store ($var0: u64) (sizeof i64)
+  bop ($var1: bool) gt ($var0: u64) (u64 8)
+  brif ($var1: bool) block1 block2
+block1:
+  ret (a: i32)
+block2:
+  bop ($var2: i32) mul (i32 6) (i32 7)
+  uop ($var3: i32) neg ($var2: i32)
+  cast ($var4: i64) ($var3: i32)
+  bop ($var5: i32) add (a: i32) ($var2: i32)
+  ret ($var5: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        fold_constants(&mut func);
+
+        match *last_instruction(&func.blocks[&0])
+        {
+            Instruction::Branch(2) => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        let bb = &func.blocks[&2];
+        match bb.instructions[2]
+        {
+            Instruction::Store{src: Operand::Const(Constant::Int(-42, _)), ref dst} => assert_eq!(dst.typ, Type::Int(::ast::IntSize::I64)),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        match bb.instructions[3]
+        {
+            Instruction::BinaryOp{right: Operand::Const(Constant::Int(42, _)), ..} => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+
+    #[test]
+    fn test_no_folding_of_modified_vars()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  store ($var0: i32) (i32 1)
+  store ($var1: i32) (i32 1)
+  call foo::bar(&($var1: i32))
+  brif (b: bool) block1 block2
+block1:
+  store ($var0: i32) (i32 2)
+  br block2
+block2:
+  cbop ($var2: i32) add ($var0: i32) ($var1: i32) at "test" 1:1 1:5
+  cbop ($var3: i32) add (i32 2147483647) (i32 1) at "test" 1:1 1:5
+  ret ($var2: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        fold_constants(&mut func);
+        match func.blocks[&2].instructions[0]
+        {
+            Instruction::CheckedBinaryOp{left: Operand::Var(_), right: Operand::Var(_), ..} => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        // Overflow is left to the runtime check
+        match func.blocks[&2].instructions[1]
+        {
+            Instruction::CheckedBinaryOp{..} => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+
+    #[test]
+    fn test_no_folding_of_globals()
+    {
+        let mut func = parse_function(r#"
+fn test::foo() -> i32 {
+entry:
+  store ("test::counter": i32) (i32 1)
+  call test::increment()
+  bop ($var0: i32) add ("test::counter": i32) (i32 1)
+  ret ($var0: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        // The call can modify the global, so it must be read again
+        fold_constants(&mut func);
+        match func.blocks[&0].instructions[2]
+        {
+            Instruction::BinaryOp{left: Operand::Var(ref v), ..} => assert_eq!(v.name, "test::counter"),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::{HashMap, HashSet};
+use bytecode::function::{ByteCodeFunction, Var};
+use bytecode::instruction::{Instruction, Operand};
+use super::liveness::{uses_and_defs, escaped_vars, is_local, var_info, VarInfo};
+
+fn references(instr: &Instruction, name: &str) -> bool
+{
+    if let Instruction::StackAlloc(ref v) = *instr {
+        return v.name == name;
+    }
+
+    let (uses, defs) = uses_and_defs(instr);
+    uses.contains(&name) || defs.contains(&name)
+}
+
+/*
+    Find the instruction assigning the temporary which the store at idx copies, so it can assign the
+    destination of the copy directly. The temporary must be used only by the copy, and the destination
+    must not be accessed in between. Both have to be in the same block and scope, so backends bind
+    the destination in the same scope as before.
+*/
+fn coalescable_copy(
+    instructions: &[Instruction],
+    idx: usize,
+    info: &HashMap<String, VarInfo>,
+    escaped: &HashSet<String>) -> Option<(usize, String, Var)>
+{
+    let (dst, src) = match instructions[idx]
+    {
+        Instruction::Store{ref dst, src: Operand::Var(ref src)} if dst.typ == src.typ && dst.name != src.name => (dst, src),
+        _ => return None,
+    };
+
+    if !src.name.starts_with('$') || !is_local(&src.name, escaped) || !is_local(&dst.name, escaped) {
+        return None;
+    }
+
+    match info.get(&src.name)
+    {
+        Some(i) if i.defs == 1 && i.uses == 1 => (),
+        _ => return None,
+    }
+
+    for def_idx in (0..idx).rev() {
+        let instr = &instructions[def_idx];
+        match *instr
+        {
+            Instruction::StartScope | Instruction::EndScope => return None,
+            _ => (),
+        }
+
+        let (_, defs) = uses_and_defs(instr);
+        if defs.contains(&src.name.as_str()) {
+            return if references(instr, &dst.name) {None} else {Some((def_idx, src.name.clone(), dst.clone()))};
+        }
+
+        if references(instr, &dst.name) {
+            return None;
+        }
+    }
+
+    None
+}
+
+// Let the instruction computing a temporary assign the var it is copied to instead
+pub fn coalesce_copies(func: &mut ByteCodeFunction)
+{
+    let escaped = escaped_vars(func);
+    let info = var_info(func);
+    for bb in func.blocks.values_mut() {
+        let mut idx = 0;
+        while idx < bb.instructions.len() {
+            match coalescable_copy(&bb.instructions, idx, &info, &escaped)
+            {
+                Some((def_idx, src, dst)) => {
+                    for var in bb.instructions[def_idx].vars_mut() {
+                        if var.name == src {
+                            *var = dst.clone();
+                        }
+                    }
+                    bb.instructions.remove(idx);
+                },
+                None => idx += 1,
+            }
+        }
+    }
+}
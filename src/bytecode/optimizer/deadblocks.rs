@@ -0,0 +1,97 @@
+use bytecode::cfg::ControlFlowGraph;
+use bytecode::function::{BasicBlockRef, ByteCodeFunction};
+use bytecode::instruction::Instruction;
+
+/*
+    Remove the blocks which can't be reached from the entry block.
+    Backends handle scopes in block order, so the scope markers of a removed block are moved
+    to the end of the block before it, where they are never executed.
+*/
+pub fn remove_dead_blocks(func: &mut ByteCodeFunction)
+{
+    let cfg = ControlFlowGraph::new(func);
+    let dead: Vec<BasicBlockRef> = func.blocks.keys().filter(|bb_ref| !cfg.is_reachable(**bb_ref)).cloned().collect();
+    for bb_ref in dead {
+        let bb = match func.blocks.remove(&bb_ref)
+        {
+            Some(bb) => bb,
+            None => continue,
+        };
+
+        let mut markers: Vec<Instruction> = Vec::new();
+        for instr in bb.instructions {
+            match instr
+            {
+                Instruction::StartScope => markers.push(instr),
+                Instruction::EndScope => {
+                    // A scope which starts and ends in the removed block doesn't need to be kept
+                    let balanced = match markers.last()
+                    {
+                        Some(&Instruction::StartScope) => true,
+                        _ => false,
+                    };
+
+                    if balanced {
+                        markers.pop();
+                    } else {
+                        markers.push(instr);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        if markers.is_empty() {
+            continue;
+        }
+
+        // The entry block is always reachable, so there is a block before a dead one
+        if let Some(prev) = func.blocks.range_mut(..bb_ref).next_back().map(|(_, bb)| bb) {
+            prev.instructions.extend(markers);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_function;
+
+    #[test]
+    fn test_dead_block_removal()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  scope start
+  br block2
+block1:
+  scope end
+  scope start
+  ret (i32 1)
+block2:
+  scope end
+  ret (a: i32)
+block3:
+  scope start
+  ret (i32 3)
+  scope end
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        remove_dead_blocks(&mut func);
+        assert_eq!(func.blocks.len(), 2);
+        assert!(func.blocks.get(&1).is_none());
+        assert!(func.blocks.get(&3).is_none());
+
+        // The scope end of block1 is needed to balance the scope start in the entry block
+        let entry = &func.blocks[&0];
+        assert_eq!(entry.instructions.len(), 4);
+        match entry.instructions[2]
+        {
+            Instruction::EndScope => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use bytecode::function::ByteCodeFunction;
+use bytecode::instruction::Instruction;
+use super::liveness::{liveness, uses_and_defs, stores_through_pointer, escaped_vars, is_local, var_info};
+
+// Instructions without side effects, which can be removed when the var they assign is not needed
+fn is_pure(instr: &Instruction) -> bool
+{
+    match *instr
+    {
+        Instruction::Store{ref dst, ref src} => !stores_through_pointer(dst, src),
+        Instruction::StoreNil(ref dst) => !dst.typ.is_pointer(),
+        Instruction::Load{..} |
+        Instruction::LoadMember{..} |
+        Instruction::AddressOf{..} |
+        Instruction::AddressOfMember{..} |
+        Instruction::GetProperty{..} |
+        Instruction::UnaryOp{..} |
+        Instruction::BinaryOp{..} |
+        Instruction::Slice{..} |
+        Instruction::MakeSlice{..} |
+        Instruction::Cast{..} |
+        Instruction::LoadOptionalFlag{..} |
+        Instruction::Phi{..} => true,
+        _ => false,
+    }
+}
+
+// Remove pure instructions which assign a value that is never read
+fn remove_dead_assignments(func: &mut ByteCodeFunction) -> bool
+{
+    let live = liveness(func);
+    let escaped = escaped_vars(func);
+    let info = var_info(func);
+    let mut changed = false;
+    for (bb_ref, bb) in &mut func.blocks {
+        let mut live_vars = live.live_out[bb_ref].clone();
+        let mut keep = vec![true; bb.instructions.len()];
+        for (idx, instr) in bb.instructions.iter().enumerate().rev() {
+            let (uses, defs) = uses_and_defs(instr);
+            let dead = is_pure(instr) && !defs.is_empty() && defs.iter().all(|name| {
+                !live_vars.contains(*name) &&
+                    is_local(name, &escaped) &&
+                    info.get(*name).map(|i| i.assignments_removable()).unwrap_or(false)
+            });
+
+            if dead {
+                keep[idx] = false;
+                changed = true;
+                continue;
+            }
+
+            for name in defs {
+                live_vars.remove(name);
+            }
+            live_vars.extend(uses.into_iter().map(|name| name.to_string()));
+        }
+
+        let mut idx = 0;
+        bb.instructions.retain(|_| {
+            idx += 1;
+            keep[idx - 1]
+        });
+    }
+    changed
+}
+
+// Member stores and property changes of an object which is not a pointer, only modify the object itself
+fn modified_object(instr: &Instruction) -> Option<&str>
+{
+    match *instr
+    {
+        Instruction::StoreMember{ref obj, ..} |
+        Instruction::SetProperty{ref obj, ..} if !obj.typ.is_pointer() => Some(obj.name.as_str()),
+        _ => None,
+    }
+}
+
+// Remove all assignments and modifications of vars which are never read, and their stack allocations
+fn remove_unused_vars(func: &mut ByteCodeFunction) -> bool
+{
+    let escaped = escaped_vars(func);
+    let mut read: HashSet<String> = HashSet::new();
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        let (uses, _) = uses_and_defs(instr);
+        let modified = modified_object(instr);
+        read.extend(uses.into_iter().filter(|name| Some(*name) != modified).map(|name| name.to_string()));
+    }
+
+    let unused = |name: &str| is_local(name, &escaped) && !read.contains(name);
+    let mut changed = false;
+    for bb in func.blocks.values_mut() {
+        let len = bb.instructions.len();
+        bb.instructions.retain(|instr| {
+            let removable = match *instr
+            {
+                Instruction::StackAlloc(ref v) => unused(&v.name),
+                _ => match modified_object(instr)
+                {
+                    Some(obj) => unused(obj),
+                    None => {
+                        let (_, defs) = uses_and_defs(instr);
+                        is_pure(instr) && !defs.is_empty() && defs.into_iter().all(|name| unused(name))
+                    },
+                },
+            };
+            !removable
+        });
+        changed = changed || bb.instructions.len() != len;
+    }
+    changed
+}
+
+/*
+    Remove instructions without side effects which assign vars that are not read anymore,
+    and vars which are never read at all, until nothing changes anymore.
+*/
+pub fn eliminate_dead_code(func: &mut ByteCodeFunction)
+{
+    loop {
+        let assignments_removed = remove_dead_assignments(func);
+        let vars_removed = remove_unused_vars(func);
+        if !assignments_removed && !vars_removed {
+            break;
+        }
+    }
+}
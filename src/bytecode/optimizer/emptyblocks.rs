@@ -1,10 +1,11 @@
+use std::collections::HashMap;
 use bytecode::function::{BasicBlock, BasicBlockRef, ByteCodeFunction};
 use bytecode::instruction::Instruction;
 
 // If the block only has a branch instruction to another block, it is considered empty
 fn empty_block(bb: &BasicBlock) -> Option<BasicBlockRef>
 {
-    if bb.instructions.len() > 1 {
+    if bb.instructions.len() != 1 {
         return None
     }
 
@@ -41,19 +42,34 @@ fn replace_branch_target(func: &mut ByteCodeFunction, to_replace: BasicBlockRef,
 
 pub fn remove_empty_blocks(func: &mut ByteCodeFunction)
 {
-    let mut candidates = Vec::new();
-    for (idx, bb) in func.blocks.values().enumerate() {
+    // The first block is the entry point of the function, so it has to stay
+    let mut candidates = HashMap::new();
+    for (bb_ref, bb) in func.blocks.iter().skip(1) {
         if let Some(next_bb) = empty_block(bb) {
-            candidates.push((idx, next_bb));
+            candidates.insert(*bb_ref, next_bb);
         }
     }
 
-    for &(to_replace, _) in &candidates {
-        func.blocks.remove(&to_replace);
+    // Follow chains of empty blocks to the block which will remain, unless they loop
+    let mut replacements = Vec::new();
+    for &to_replace in candidates.keys() {
+        let mut replacement = to_replace;
+        let mut steps = 0;
+        while let Some(&next_bb) = candidates.get(&replacement) {
+            replacement = next_bb;
+            steps += 1;
+            if steps > candidates.len() {
+                break;
+            }
+        }
+
+        if !candidates.contains_key(&replacement) {
+            replacements.push((to_replace, replacement));
+        }
     }
 
-    for &(to_replace, replacement) in &candidates {
+    for &(to_replace, replacement) in &replacements {
+        func.blocks.remove(&to_replace);
         replace_branch_target(func, to_replace, replacement);
     }
-
 }
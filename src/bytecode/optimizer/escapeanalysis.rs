@@ -0,0 +1,416 @@
+/*
+    Escape analysis: a heap allocation can be replaced by a stack allocation, when the object can only
+    be accessed through the pointer it was allocated in, while the function is running. So the pointer
+    may only be read through, compared, deleted or passed to functions of the module which do the same
+    with it. Copying, storing or returning the pointer, or passing it to an unknown function lets it escape.
+    Allocations in loops, and large or unsized objects stay on the heap, so the stack cannot overflow.
+*/
+use std::collections::HashMap;
+use ast::{Type, FloatSize, ArrayLength};
+use bytecode::{ByteCodeModule, ControlFlowGraph};
+use bytecode::function::{ByteCodeFunction, Var};
+use bytecode::instruction::{Instruction, Operand, store_operand_instr};
+use super::liveness::{stores_through_pointer, escaped_vars, is_local};
+
+// Objects larger than this are not moved to the stack
+const MAX_PROMOTED_SIZE: u64 = 4096;
+
+// An upper bound of the size of a type on any target, every member is assumed to be padded to 8 bytes
+fn max_size(typ: &Type) -> Option<u64>
+{
+    let padded = |size: u64| (size + 7) / 8 * 8;
+    match *typ
+    {
+        Type::Int(int_size) | Type::UInt(int_size) => Some(u64::from(int_size.size_in_bits() / 8)),
+        Type::Float(FloatSize::F32) | Type::Char | Type::Enum(_) => Some(4),
+        Type::Float(FloatSize::F64) | Type::Pointer(_) | Type::Func(_) => Some(8),
+        Type::Bool => Some(1),
+        Type::String | Type::Slice(_) => Some(16),
+        Type::Optional(ref inner) => max_size(inner).map(|s| 8 + padded(s)),
+        Type::Array(ref at) => match at.length
+        {
+            ArrayLength::Known(len) => max_size(&at.element_type).and_then(|s| padded(s).checked_mul(len as u64)),
+            _ => None,
+        },
+        Type::Struct(ref st) => {
+            let mut size = 0;
+            for m in &st.members {
+                size += padded(max_size(&m.typ)?);
+            }
+            Some(size)
+        },
+        Type::Sum(ref st) => {
+            let mut size = 0;
+            for c in &st.cases {
+                size = size.max(padded(max_size(&c.typ)?));
+            }
+            Some(8 + size)
+        },
+        _ => None,
+    }
+}
+
+struct EscapeAnalysis<'a>
+{
+    module: &'a ByteCodeModule,
+    // Whether a function lets the pointer passed as an argument escape, by function name and argument index
+    args: HashMap<(String, usize), bool>,
+}
+
+impl<'a> EscapeAnalysis<'a>
+{
+    fn new(module: &'a ByteCodeModule) -> EscapeAnalysis<'a>
+    {
+        EscapeAnalysis{
+            module: module,
+            args: HashMap::new(),
+        }
+    }
+
+    fn arg_escapes(&mut self, func_name: &str, idx: usize) -> bool
+    {
+        let key = (func_name.to_string(), idx);
+        if let Some(escapes) = self.args.get(&key) {
+            return *escapes;
+        }
+
+        // Recursive calls see the argument as escaping, so they are never analysed forever
+        self.args.insert(key.clone(), true);
+        let module = self.module;
+        let escapes = match module.functions.get(func_name)
+        {
+            Some(func) if !func.external => match func.sig.args.get(idx)
+            {
+                Some(arg) if !arg.mutable => self.var_escapes(func, &arg.name, false),
+                _ => true,
+            },
+            _ => true,
+        };
+        self.args.insert(key, escapes);
+        escapes
+    }
+
+    /*
+        Whether the object a pointer var points to can be accessed in another way than through the var.
+        The function which allocated the object owns it, so it may also allocate and delete it.
+    */
+    fn var_escapes(&mut self, func: &ByteCodeFunction, name: &str, owner: bool) -> bool
+    {
+        func.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .any(|instr| self.instruction_escapes(instr, name, owner))
+    }
+
+    fn instruction_escapes(&mut self, instr: &Instruction, name: &str, owner: bool) -> bool
+    {
+        let is_var = |v: &Var| v.name == name;
+        if let Instruction::Call{ref dst, ref func, ref args} = *instr {
+            if func == name || dst.as_ref().map(&is_var).unwrap_or(false) {
+                return true;
+            }
+
+            for (idx, arg) in args.iter().enumerate() {
+                let escapes = match *arg
+                {
+                    Operand::Var(ref v) => is_var(v) && self.arg_escapes(func, idx),
+                    Operand::AddressOf(ref v) => is_var(v),
+                    _ => false,
+                };
+
+                if escapes {
+                    return true;
+                }
+            }
+            return false;
+        }
+
+        // Reading the object through the pointer is fine, but using the pointer itself is not, except for comparing it
+        let operand_escapes = instr.operands().into_iter().any(|op| match *op {
+            Operand::Var(ref v) | Operand::AddressOf(ref v) => is_var(v),
+            _ => false,
+        });
+
+        match *instr
+        {
+            Instruction::BinaryOp{ref dst, ..} if !dst.typ.is_pointer() => is_var(dst),
+            _ if operand_escapes => true,
+            Instruction::Store{ref dst, ref src} => is_var(dst) && !stores_through_pointer(dst, src),
+            // Loaded pointers and aggregates can refer to the memory they are loaded from
+            Instruction::Load{ref dst, ptr: ref obj} |
+            Instruction::LoadMember{ref dst, ref obj, ..} |
+            Instruction::GetProperty{ref dst, ref obj, ..} => {
+                is_var(dst) || (is_var(obj) && (dst.typ.is_pointer() || !dst.typ.pass_by_value()))
+            },
+            Instruction::HeapAlloc(ref v) |
+            Instruction::Delete(ref v) => is_var(v) && !owner,
+            Instruction::AddressOf{ref dst, ref obj} |
+            Instruction::AddressOfMember{ref dst, ref obj, ..} |
+            Instruction::Slice{ref dst, src: ref obj, ..} |
+            Instruction::LoadOptionalFlag{ref dst, ref obj} => is_var(dst) || is_var(obj),
+            Instruction::MakeSlice{ref dst, ref data, ref len} => is_var(dst) || is_var(data) || is_var(len),
+            Instruction::UnaryOp{ref dst, ..} |
+            Instruction::BinaryOp{ref dst, ..} |
+            Instruction::CheckedBinaryOp{ref dst, ..} |
+            Instruction::Cast{ref dst, ..} |
+            Instruction::Phi{ref dst, ..} |
+            Instruction::RangeCheck{seq: ref dst, ..} |
+            Instruction::Panic{msg: ref dst, ..} => is_var(dst),
+            // Storing a member or nil through the pointer, and allocating the pointer var itself
+            _ => false,
+        }
+    }
+
+    // The pointers of heap allocations in a function, which can be moved to the stack
+    fn promotable_allocations(&mut self, func: &ByteCodeFunction) -> Vec<Var>
+    {
+        let escaped = escaped_vars(func);
+        let loops = ControlFlowGraph::new(func).loops();
+        let mut allocations: Vec<&Var> = Vec::new();
+        let mut rejected: Vec<&str> = Vec::new();
+        for (bb_ref, bb) in &func.blocks {
+            let in_loop = loops.iter().any(|l| l.contains(*bb_ref));
+            for instr in &bb.instructions {
+                if let Instruction::HeapAlloc(ref v) = *instr {
+                    // Every iteration would reuse the same stack object
+                    if in_loop || allocations.iter().any(|a| a.name == v.name) {
+                        rejected.push(&v.name);
+                    }
+                    allocations.push(v);
+                }
+            }
+        }
+
+        let small_enough = |v: &Var| match v.typ.get_pointer_element_type().and_then(max_size)
+        {
+            Some(size) => size <= MAX_PROMOTED_SIZE,
+            None => false,
+        };
+
+        allocations.into_iter()
+            .filter(|v| is_local(&v.name, &escaped) && !rejected.contains(&v.name.as_str()) && small_enough(v))
+            .filter(|v| !self.var_escapes(func, &v.name, true))
+            .cloned()
+            .collect()
+    }
+}
+
+// Allocate the object on the stack, the pointer now points to it, and deleting it is not needed anymore
+fn promote_allocation(func: &mut ByteCodeFunction, ptr: &Var)
+{
+    let obj = match ptr.typ.get_pointer_element_type()
+    {
+        Some(inner) => Var::named(&format!("{}.stack", ptr.name), inner.clone()),
+        None => panic!("Internal Compiler Error: Heap allocation of {}, which is not a pointer", ptr),
+    };
+
+    for bb in func.blocks.values_mut() {
+        bb.instructions.retain(|instr| match *instr {
+            Instruction::Delete(ref v) => v.name != ptr.name,
+            _ => true,
+        });
+
+        for instr in &mut bb.instructions {
+            let allocation = match *instr
+            {
+                Instruction::HeapAlloc(ref v) => v.name == ptr.name,
+                _ => false,
+            };
+
+            if allocation {
+                *instr = store_operand_instr(ptr, Operand::AddressOf(obj.clone()));
+            }
+        }
+    }
+
+    if let Some(entry) = func.blocks.values_mut().next() {
+        entry.instructions.insert(0, Instruction::StackAlloc(obj));
+    }
+}
+
+/*
+    Replace heap allocations which don't escape the function they are made in with stack allocations.
+    The promoted allocations are printed when the module options ask for it.
+*/
+pub fn promote_heap_allocations(module: &mut ByteCodeModule)
+{
+    let promotions: Vec<(String, Vec<Var>)> = {
+        let mut analysis = EscapeAnalysis::new(module);
+        module.functions.values()
+            .filter(|func| !func.external)
+            .map(|func| (func.sig.name.clone(), analysis.promotable_allocations(func)))
+            .filter(|&(_, ref ptrs)| !ptrs.is_empty())
+            .collect()
+    };
+
+    if module.options.dump_escape_analysis {
+        println!("escape analysis:");
+        println!("------\n");
+        for &(ref name, ref ptrs) in &promotions {
+            for ptr in ptrs {
+                println!("{}: {} moved to the stack", name, ptr);
+            }
+        }
+        println!("------\n");
+    }
+
+    for (name, ptrs) in promotions {
+        if let Some(func) = module.functions.get_mut(&name) {
+            for ptr in &ptrs {
+                promote_allocation(func, ptr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::{parse_module, verify_module};
+
+    fn count_instructions<F: Fn(&Instruction) -> bool>(func: &ByteCodeFunction, pred: F) -> usize
+    {
+        func.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|i| pred(i))
+            .count()
+    }
+
+    fn is_heap_alloc(instr: &Instruction) -> bool
+    {
+        match *instr
+        {
+            Instruction::HeapAlloc(_) => true,
+            _ => false,
+        }
+    }
+
+    fn is_delete(instr: &Instruction) -> bool
+    {
+        match *instr
+        {
+            Instruction::Delete(_) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_escape_analysis()
+    {
+        let mut module = parse_module(r#"module test
+options
+
+fn test::local(a: i32) -> i32 {
+entry:
+  halloc (p: *i32)
+  store (p: *i32) (a: i32)
+  call ($var0: i32) test::read((p: *i32))
+  delete (p: *i32)
+  ret ($var0: i32)
+}
+
+fn test::returned(a: i32) -> *i32 {
+entry:
+  halloc (p: *i32)
+  store (p: *i32) (a: i32)
+  ret (p: *i32)
+}
+
+fn test::passed(a: i32) -> i32 {
+entry:
+  halloc (p: *i32)
+  store (p: *i32) (a: i32)
+  call ($var0: *i32) test::keep((p: *i32))
+  load ($var1: i32) ($var0: *i32)
+  ret ($var1: i32)
+}
+
+fn test::read(p: *i32) -> i32 {
+entry:
+  load ($var0: i32) (p: *i32)
+  ret ($var0: i32)
+}
+
+fn test::keep(p: *i32) -> *i32 {
+entry:
+  ret (p: *i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        promote_heap_allocations(&mut module);
+        verify_module(&module, "escape analysis").expect("Verification succeeded");
+
+        let local = module.get_function("test::local").expect("Missing local");
+        assert_eq!(count_instructions(local, is_heap_alloc), 0);
+        assert_eq!(count_instructions(local, is_delete), 0);
+        assert_eq!(count_instructions(local, |i| match *i {
+            Instruction::StackAlloc(ref v) => v.name == "p.stack",
+            _ => false,
+        }), 1);
+
+        // The pointer outlives the function, directly or through the function it is passed to
+        let returned = module.get_function("test::returned").expect("Missing returned");
+        assert_eq!(count_instructions(returned, is_heap_alloc), 1);
+        let passed = module.get_function("test::passed").expect("Missing passed");
+        assert_eq!(count_instructions(passed, is_heap_alloc), 1);
+    }
+
+    #[test]
+    fn test_escape_analysis_keeps_heap_allocations()
+    {
+        let mut module = parse_module(r#"module test
+options
+
+fn test::copied(a: i32) -> i32 {
+entry:
+  halloc (p: *i32)
+  store (p: *i32) (a: i32)
+  store (q: *i32) (p: *i32)
+  load ($var0: i32) (q: *i32)
+  delete (p: *i32)
+  ret ($var0: i32)
+}
+
+fn test::stored(out: **i32, a: i32) -> i32 {
+entry:
+  halloc (p: *i32)
+  store (p: *i32) (a: i32)
+  store (out: **i32) (p: *i32)
+  ret (i32 0)
+}
+
+fn test::looped(n: i32) -> i32 {
+entry:
+  store (i: i32) (i32 0)
+  br block1
+block1:
+  bop ($var0: bool) lt (i: i32) (n: i32)
+  brif ($var0: bool) block2 block3
+block2:
+  halloc (p: *i32)
+  store (p: *i32) (i: i32)
+  load ($var1: i32) (p: *i32)
+  delete (p: *i32)
+  bop (i: i32) add ($var1: i32) (i32 1)
+  br block1
+block3:
+  ret (i: i32)
+}
+
+fn test::large() -> i64 {
+entry:
+  halloc (p: *[1000]i64)
+  delete (p: *[1000]i64)
+  ret (i64 0)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        promote_heap_allocations(&mut module);
+        verify_module(&module, "escape analysis").expect("Verification succeeded");
+
+        for name in &["test::copied", "test::stored", "test::looped", "test::large"] {
+            let func = module.get_function(name).expect("Missing function");
+            assert_eq!(count_instructions(func, is_heap_alloc), 1, "{} was promoted", name);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use ast::{Type, CallingConvention, InlineHint, ptr_type};
+use bytecode::ByteCodeModule;
+use bytecode::function::{BasicBlock, BasicBlockRef, ByteCodeFunction, Var, bb_name};
+use bytecode::instruction::{Constant, Instruction, Operand, store_operand_instr};
+
+// Functions with at most this many instructions are inlined, unless they are annotated with @noinline
+const INLINE_THRESHOLD: usize = 24;
+
+fn size(func: &ByteCodeFunction) -> usize
+{
+    func.blocks.values()
+        .flat_map(|bb| bb.instructions.iter())
+        .filter(|instr| match **instr {
+            Instruction::StartScope | Instruction::EndScope => false,
+            _ => true,
+        })
+        .count()
+}
+
+fn calls_itself(func: &ByteCodeFunction) -> bool
+{
+    let mut found = false;
+    func.for_each_instruction(|instr| {
+        if let Instruction::Call{func: ref name, ..} = *instr {
+            found = *name == func.sig.name;
+        }
+        !found
+    });
+    found
+}
+
+// An inlined return becomes a branch, which doesn't leave the scopes it is in
+fn returns_at_function_scope(func: &ByteCodeFunction) -> bool
+{
+    let mut depth = 0;
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        match *instr
+        {
+            Instruction::StartScope => depth += 1,
+            Instruction::EndScope => depth -= 1,
+            Instruction::Return(_) | Instruction::ReturnVoid if depth > 0 => return false,
+            _ => (),
+        }
+    }
+    true
+}
+
+fn inlinable(func: &ByteCodeFunction) -> bool
+{
+    if func.external || func.blocks.is_empty() || func.sig.variadic || func.sig.calling_convention != CallingConvention::Nomad {
+        return false;
+    }
+
+    let wanted = match func.sig.inline_hint
+    {
+        InlineHint::Never => false,
+        InlineHint::Always => true,
+        InlineHint::Auto => size(func) <= INLINE_THRESHOLD,
+    };
+
+    wanted && !calls_itself(func) && returns_at_function_scope(func)
+}
+
+fn operand_var(op: &mut Operand) -> Option<&mut Var>
+{
+    match *op
+    {
+        Operand::Var(ref mut v) |
+        Operand::AddressOf(ref mut v) |
+        Operand::Dereference(ref mut v) => Some(v),
+        _ => None,
+    }
+}
+
+// Arguments are stored in the parameters, aggregates passed by reference are not
+fn argument_matches(op: &Operand, typ: &Type) -> bool
+{
+    match *op
+    {
+        Operand::Var(ref v) => v.typ == *typ,
+        Operand::AddressOf(ref v) => ptr_type(v.typ.clone()) == *typ,
+        Operand::Dereference(ref v) => v.typ.get_pointer_element_type() == Some(typ),
+        Operand::Const(Constant::Array(ref members)) if members.is_empty() => false,
+        Operand::Const(ref c) => c.get_type() == *typ,
+        Operand::Func(_) => match *typ {
+            Type::Func(_) => true,
+            _ => false,
+        },
+        Operand::SizeOf(_) => match *typ {
+            Type::UInt(_) => true,
+            _ => false,
+        },
+    }
+}
+
+// A copy of the body of a function which can be inlined, taken before any call is replaced
+struct InlineCandidate
+{
+    params: Vec<Var>,
+    blocks: Vec<(BasicBlockRef, Vec<Instruction>)>,
+    local_vars: HashSet<String>,
+}
+
+impl InlineCandidate
+{
+    fn new(func: &ByteCodeFunction, not_local: &HashSet<String>) -> InlineCandidate
+    {
+        let params: Vec<Var> = func.sig.args.iter().map(|arg| Var::named(&arg.name, arg.typ.clone())).collect();
+        let mut blocks: Vec<(BasicBlockRef, Vec<Instruction>)> = func.blocks.iter()
+            .map(|(bb_ref, bb)| (*bb_ref, bb.instructions.clone()))
+            .collect();
+
+        let mut local_vars: HashSet<String> = params.iter().map(|p| p.name.clone()).collect();
+        for &mut (_, ref mut instructions) in &mut blocks {
+            for instr in instructions {
+                for op in instr.operands_mut() {
+                    if let Some(var) = operand_var(op) {
+                        local_vars.insert(var.name.clone());
+                    }
+                }
+
+                for var in instr.vars_mut() {
+                    local_vars.insert(var.name.clone());
+                }
+            }
+        }
+
+        // Globals and functions are referred to by name
+        local_vars.retain(|name| !not_local.contains(name));
+        InlineCandidate{
+            params: params,
+            blocks: blocks,
+            local_vars: local_vars,
+        }
+    }
+
+    fn accepts(&self, args: &[Operand]) -> bool
+    {
+        self.params.len() == args.len() &&
+            self.params.iter().zip(args).all(|(param, arg)| argument_matches(arg, &param.typ))
+    }
+}
+
+// Gives the vars of an inlined function names which don't clash with the vars of the caller
+struct Renamer<'a>
+{
+    local_vars: &'a HashSet<String>,
+    names: HashMap<String, String>,
+    suffix: String,
+}
+
+impl<'a> Renamer<'a>
+{
+    fn new_name(&mut self, func: &mut ByteCodeFunction, name: &str, typ: &Type) -> String
+    {
+        if !self.local_vars.contains(name) {
+            return name.into();
+        }
+
+        if let Some(new_name) = self.names.get(name) {
+            return new_name.clone();
+        }
+
+        // Temporaries, including $ret, become temporaries of the caller
+        let new_name = if name.starts_with('$') {
+            func.new_var(typ.clone()).name
+        } else {
+            format!("{}{}", name, self.suffix)
+        };
+        self.names.insert(name.into(), new_name.clone());
+        new_name
+    }
+
+    fn rename(&mut self, func: &mut ByteCodeFunction, var: &mut Var)
+    {
+        var.name = self.new_name(func, &var.name, &var.typ);
+    }
+}
+
+/*
+    Copy the blocks of the callee into the caller, numbered from first_block on.
+    The arguments are stored in the renamed parameters, and returns become a store to the destination
+    of the call, followed by a branch to the block after the inlined blocks.
+    Returns the instructions which replace the call, and the inlined blocks.
+*/
+fn inline_call(
+    func: &mut ByteCodeFunction,
+    callee: &InlineCandidate,
+    dst: Option<Var>,
+    args: Vec<Operand>,
+    first_block: BasicBlockRef,
+    suffix: String) -> (Vec<Instruction>, Vec<Vec<Instruction>>)
+{
+    let mut renamer = Renamer{
+        local_vars: &callee.local_vars,
+        names: HashMap::new(),
+        suffix: suffix,
+    };
+
+    let mut call_instructions = Vec::with_capacity(args.len() + 1);
+    for (param, arg) in callee.params.iter().zip(args) {
+        let mut param = param.clone();
+        renamer.rename(func, &mut param);
+        call_instructions.push(store_operand_instr(&param, arg));
+    }
+    call_instructions.push(Instruction::Branch(first_block));
+
+    let block_map: HashMap<BasicBlockRef, BasicBlockRef> = callee.blocks.iter()
+        .enumerate()
+        .map(|(idx, &(bb_ref, _))| (bb_ref, first_block + idx))
+        .collect();
+    let next_block = first_block + callee.blocks.len();
+
+    let mut blocks = Vec::with_capacity(callee.blocks.len());
+    for &(_, ref instructions) in &callee.blocks {
+        let mut block = Vec::with_capacity(instructions.len());
+        for instr in instructions {
+            let mut instr = instr.clone();
+            for op in instr.operands_mut() {
+                if let Some(var) = operand_var(op) {
+                    renamer.rename(func, var);
+                }
+            }
+
+            for var in instr.vars_mut() {
+                renamer.rename(func, var);
+            }
+
+            match instr
+            {
+                Instruction::Call{dst: call_dst, func: name, args} => {
+                    // Calls through a function pointer refer to a var by name
+                    let name = renamer.new_name(func, &name, &Type::Unknown);
+                    block.push(Instruction::Call{dst: call_dst, func: name, args: args});
+                },
+
+                Instruction::Return(op) => {
+                    if let Some(ref dst) = dst {
+                        block.push(store_operand_instr(dst, op));
+                    }
+                    block.push(Instruction::Branch(next_block));
+                },
+
+                Instruction::ReturnVoid => block.push(Instruction::Branch(next_block)),
+
+                Instruction::Branch(target) => block.push(Instruction::Branch(block_map[&target])),
+
+                Instruction::BranchIf{cond, on_true, on_false} => block.push(Instruction::BranchIf{
+                    cond: cond,
+                    on_true: block_map[&on_true],
+                    on_false: block_map[&on_false],
+                }),
+
+                instr => block.push(instr),
+            }
+        }
+        blocks.push(block);
+    }
+
+    (call_instructions, blocks)
+}
+
+fn inline_calls(func: &mut ByteCodeFunction, candidates: &HashMap<String, InlineCandidate>)
+{
+    let inlinable_call = |instr: &Instruction| match *instr
+    {
+        Instruction::Call{func: ref name, ref args, ..} => candidates.get(name).map(|c| c.accepts(args)).unwrap_or(false),
+        _ => false,
+    };
+
+    if !func.blocks.values().flat_map(|bb| bb.instructions.iter()).any(|instr| inlinable_call(instr)) {
+        return;
+    }
+
+    /*
+        Backends handle scopes in block order, so the inlined blocks are placed right after the block
+        containing the call, and the remainder of that block comes after them. The flag of each block
+        indicates whether its branch targets still refer to the old block numbers.
+    */
+    let old_blocks = mem::replace(&mut func.blocks, BTreeMap::new());
+    let mut new_blocks: Vec<(Vec<Instruction>, bool)> = Vec::new();
+    let mut block_map = HashMap::new();
+    let mut inline_count = 0;
+    for (bb_ref, bb) in old_blocks {
+        block_map.insert(bb_ref, new_blocks.len());
+        let mut current = Vec::new();
+        for instr in bb.instructions {
+            if !inlinable_call(&instr) {
+                current.push(instr);
+                continue;
+            }
+
+            if let Instruction::Call{dst, func: name, args} = instr {
+                let first_block = new_blocks.len() + 1;
+                let (call_instructions, blocks) = inline_call(func, &candidates[&name], dst, args, first_block, format!(".inline{}", inline_count));
+                inline_count += 1;
+
+                current.extend(call_instructions);
+                new_blocks.push((mem::replace(&mut current, Vec::new()), false));
+                new_blocks.extend(blocks.into_iter().map(|block| (block, false)));
+            }
+        }
+        new_blocks.push((current, true));
+    }
+
+    let blocks = new_blocks.into_iter()
+        .enumerate()
+        .map(|(idx, (mut instructions, old_targets))| {
+            if old_targets {
+                for instr in &mut instructions {
+                    match *instr
+                    {
+                        Instruction::Branch(ref mut target) => *target = block_map[&*target],
+                        Instruction::BranchIf{ref mut on_true, ref mut on_false, ..} => {
+                            *on_true = block_map[&*on_true];
+                            *on_false = block_map[&*on_false];
+                        },
+                        _ => (),
+                    }
+                }
+            }
+
+            (idx, BasicBlock{name: bb_name(idx), instructions: instructions})
+        })
+        .collect();
+
+    func.replace_blocks(blocks);
+}
+
+/*
+    Replace calls to small functions, and functions annotated with @inline, by the body of the function.
+    Each caller only gets the bodies as they were before inlining, so recursive functions don't expand forever.
+*/
+pub fn inline_functions(module: &mut ByteCodeModule)
+{
+    let mut not_local: HashSet<String> = module.globals.keys().cloned().collect();
+    not_local.extend(module.functions.keys().cloned());
+    not_local.extend(module.imported_functions.iter().map(|func| func.sig.name.clone()));
+
+    let candidates: HashMap<String, InlineCandidate> = module.functions.values()
+        .filter(|func| inlinable(func))
+        .map(|func| (func.sig.name.clone(), InlineCandidate::new(func, &not_local)))
+        .collect();
+
+    if candidates.is_empty() {
+        return;
+    }
+
+    for func in module.functions.values_mut() {
+        if !func.external {
+            inline_calls(func, &candidates);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::{parse_module, verify_module, OptimizationLevel, optimize_module};
+    use bytecode::test::generate_byte_code;
+
+    fn count_calls(func: &ByteCodeFunction, callee: &str) -> usize
+    {
+        let mut count = 0;
+        func.for_each_instruction(|instr| {
+            if let Instruction::Call{func: ref name, ..} = *instr {
+                if name == callee {
+                    count += 1;
+                }
+            }
+            true
+        });
+        count
+    }
+
+    const MODULE: &'static str = r#"module test
+
+fn test::add(a: i32, b: i32) -> i32 {
+entry:
+  scope start
+  bop ($var0: i32) add (a: i32) (b: i32)
+  scope end
+  ret ($var0: i32)
+}
+
+fn test::main() -> i32 {
+entry:
+  store (x: i32) (i32 1)
+  call ($var0: i32) test::add((x: i32), (i32 2))
+  call ($var1: i32) test::add(($var0: i32), (i32 3))
+  brif (bool true) block1 block2
+block1:
+  ret ($var1: i32)
+block2:
+  ret (i32 0)
+}
+"#;
+
+    #[test]
+    fn test_inline_function()
+    {
+        let mut module = parse_module(MODULE, "test.bc").expect("Parsing succeeded");
+        inline_functions(&mut module);
+        verify_module(&module, "inlining").expect("Valid bytecode");
+
+        let main = &module.functions["test::main"];
+        assert_eq!(count_calls(main, "test::add"), 0);
+        // Each call is replaced by the block of add, and the block with the instructions after the call
+        assert_eq!(main.blocks.len(), 7);
+
+        match main.blocks[&0].instructions[1]
+        {
+            Instruction::Store{ref dst, src: Operand::Var(ref src)} => {
+                assert_eq!(dst.name, "a.inline0");
+                assert_eq!(src.name, "x");
+            },
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        // The temporary of add becomes a new temporary of main, and the return a store to the destination
+        match main.blocks[&1].instructions[1]
+        {
+            Instruction::BinaryOp{ref dst, ..} => assert_eq!(dst.name, "$var2"),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        match main.blocks[&1].instructions[3]
+        {
+            Instruction::Store{ref dst, ..} => assert_eq!(dst.name, "$var0"),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+
+        // Branch targets of main refer to the renumbered blocks
+        match *main.blocks[&4].terminator().expect("Terminated block")
+        {
+            Instruction::BranchIf{on_true: 5, on_false: 6, ..} => (),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+
+    #[test]
+    fn test_noinline()
+    {
+        let text = MODULE.replace("fn test::add(a: i32, b: i32) -> i32 {", "fn test::add(a: i32, b: i32) -> i32 noinline {");
+        let mut module = parse_module(&text, "test.bc").expect("Parsing succeeded");
+        inline_functions(&mut module);
+        assert_eq!(count_calls(&module.functions["test::main"], "test::add"), 2);
+    }
+
+    #[test]
+    fn test_inline_member_function_and_return_value()
+    {
+        let mut module = generate_byte_code(r#"
+struct Point:
+    x: int
+    y: int
+
+fn Point.sum(self) -> int:
+    self.x + self.y
+
+fn make_point(x: int, y: int) -> Point:
+    Point{x, y}
+
+fn main() -> int:
+    let p = make_point(4, 5)
+    p.sum()
+"#, false).expect("Compiling succeeded");
+
+        optimize_module(&mut module, OptimizationLevel::Normal).expect("Optimization succeeded");
+        let main = &module.functions["test::main"];
+        assert_eq!(count_calls(main, "test::make_point"), 0);
+        assert_eq!(count_calls(main, "test::Point.sum"), 0);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use bytecode::function::{BasicBlockRef, ByteCodeFunction, Var};
+use bytecode::instruction::{Constant, Instruction, Operand};
+
+// Whether a store writes to the object a pointer points to, instead of assigning the pointer
+pub fn stores_through_pointer(dst: &Var, src: &Operand) -> bool
+{
+    if !dst.typ.is_pointer() {
+        return false;
+    }
+
+    match *src
+    {
+        Operand::Var(ref v) => v.typ != dst.typ,
+        Operand::AddressOf(_) | Operand::Func(_) | Operand::Const(Constant::NullPtr(_)) => false,
+        _ => true,
+    }
+}
+
+/*
+    The names of the vars an instruction reads, and of the vars it assigns a new value to.
+    Partial writes, like storing a member, and writes through a pointer are reads, because
+    they don't replace the whole value of the var. Taking the address of a var counts as reading it.
+*/
+pub fn uses_and_defs(instr: &Instruction) -> (Vec<&str>, Vec<&str>)
+{
+    let mut uses: Vec<&str> = instr.operands()
+        .into_iter()
+        .filter_map(|op| match *op {
+            Operand::Var(ref v) | Operand::AddressOf(ref v) | Operand::Dereference(ref v) => Some(v.name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut defs = Vec::new();
+
+    match *instr
+    {
+        Instruction::Store{ref dst, ref src} => {
+            if stores_through_pointer(dst, src) {
+                uses.push(&dst.name);
+            } else {
+                defs.push(dst.name.as_str());
+            }
+        },
+        Instruction::StoreNil(ref dst) => {
+            if dst.typ.is_pointer() {
+                uses.push(&dst.name);
+            } else {
+                defs.push(dst.name.as_str());
+            }
+        },
+        Instruction::Load{ref dst, ptr: ref obj} |
+        Instruction::LoadMember{ref dst, ref obj, ..} |
+        Instruction::AddressOf{ref dst, ref obj} |
+        Instruction::AddressOfMember{ref dst, ref obj, ..} |
+        Instruction::GetProperty{ref dst, ref obj, ..} |
+        Instruction::LoadOptionalFlag{ref dst, ref obj} |
+        Instruction::Slice{ref dst, src: ref obj, ..} => {
+            uses.push(&obj.name);
+            defs.push(dst.name.as_str());
+        },
+        Instruction::MakeSlice{ref dst, ref data, ref len} => {
+            uses.push(&data.name);
+            uses.push(&len.name);
+            defs.push(dst.name.as_str());
+        },
+        Instruction::UnaryOp{ref dst, ..} |
+        Instruction::BinaryOp{ref dst, ..} |
+        Instruction::CheckedBinaryOp{ref dst, ..} |
+        Instruction::Cast{ref dst, ..} |
+        Instruction::HeapAlloc(ref dst) |
+        Instruction::Phi{ref dst, ..} |
+        Instruction::Call{dst: Some(ref dst), ..} => defs.push(dst.name.as_str()),
+        Instruction::StoreMember{ref obj, ..} |
+        Instruction::SetProperty{ref obj, ..} |
+        Instruction::Delete(ref obj) |
+        Instruction::RangeCheck{seq: ref obj, ..} |
+        Instruction::Panic{msg: ref obj, ..} => uses.push(&obj.name),
+        _ => (),
+    }
+
+    // Calls through a function pointer read the var holding it
+    if let Instruction::Call{ref func, ..} = *instr {
+        uses.push(func);
+    }
+
+    (uses, defs)
+}
+
+pub struct Liveness
+{
+    pub live_in: BTreeMap<BasicBlockRef, HashSet<String>>,
+    pub live_out: BTreeMap<BasicBlockRef, HashSet<String>>,
+}
+
+// The vars which are read before being assigned in a block, and the vars assigned in it
+fn block_uses_and_defs(instructions: &[Instruction]) -> (HashSet<String>, HashSet<String>)
+{
+    let mut uses: HashSet<String> = HashSet::new();
+    let mut defs: HashSet<String> = HashSet::new();
+    for instr in instructions {
+        let (instr_uses, instr_defs) = uses_and_defs(instr);
+        for name in instr_uses {
+            if !defs.contains(name) {
+                uses.insert(name.to_string());
+            }
+        }
+        defs.extend(instr_defs.into_iter().map(|name| name.to_string()));
+    }
+    (uses, defs)
+}
+
+/*
+    Compute the vars which are live at the start and end of each block, a var is live when
+    its current value can still be read.
+*/
+pub fn liveness(func: &ByteCodeFunction) -> Liveness
+{
+    let block_info: BTreeMap<BasicBlockRef, (HashSet<String>, HashSet<String>)> = func.blocks.iter()
+        .map(|(bb_ref, bb)| (*bb_ref, block_uses_and_defs(&bb.instructions)))
+        .collect();
+
+    let mut live_in: BTreeMap<BasicBlockRef, HashSet<String>> = func.blocks.keys().map(|bb_ref| (*bb_ref, HashSet::new())).collect();
+    let mut live_out = live_in.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        // Liveness flows backwards, so visiting blocks in reverse order needs fewer iterations
+        for (bb_ref, bb) in func.blocks.iter().rev() {
+            let out: HashSet<String> = bb.successors()
+                .iter()
+                .filter_map(|succ| live_in.get(succ))
+                .flat_map(|vars| vars.iter().cloned())
+                .collect();
+
+            let (ref uses, ref defs) = block_info[bb_ref];
+            let mut vars_in = uses.clone();
+            vars_in.extend(out.iter().filter(|name| !defs.contains(*name)).cloned());
+
+            // Sets only grow, so comparing sizes is enough to detect changes
+            if vars_in.len() != live_in[bb_ref].len() || out.len() != live_out[bb_ref].len() {
+                changed = true;
+            }
+            live_in.insert(*bb_ref, vars_in);
+            live_out.insert(*bb_ref, out);
+        }
+    }
+
+    Liveness{
+        live_in: live_in,
+        live_out: live_out,
+    }
+}
+
+// Globals are namespaced, locals are not
+pub fn is_global(name: &str) -> bool
+{
+    name.contains("::")
+}
+
+// Whether a var can only be accessed by its name, so assignments to it can only be observed by reading it
+pub fn is_local(name: &str, escaped: &HashSet<String>) -> bool
+{
+    !is_global(name) && !escaped.contains(name)
+}
+
+/*
+    Vars which can be accessed in other ways than by name: arguments, which can refer to memory of the caller,
+    vars which have their address taken, and aggregates which refer to the memory of another var.
+*/
+pub fn escaped_vars(func: &ByteCodeFunction) -> HashSet<String>
+{
+    let mut escaped: HashSet<String> = func.sig.args.iter().map(|arg| arg.name.clone()).collect();
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        for op in instr.operands() {
+            if let Operand::AddressOf(ref v) = *op {
+                escaped.insert(v.name.clone());
+            }
+        }
+
+        match *instr
+        {
+            Instruction::AddressOf{ref obj, ..} |
+            Instruction::AddressOfMember{ref obj, ..} |
+            Instruction::Slice{src: ref obj, ..} => {
+                escaped.insert(obj.name.clone());
+            },
+            Instruction::Load{ref dst, ptr: ref obj} |
+            Instruction::LoadMember{ref dst, ref obj, ..} |
+            Instruction::GetProperty{ref dst, ref obj, ..} => {
+                if dst.typ.is_pointer() || !dst.typ.pass_by_value() {
+                    escaped.insert(obj.name.clone());
+                    escaped.insert(dst.name.clone());
+                }
+            },
+            _ => (),
+        }
+    }
+    escaped
+}
+
+// How a var is used in a function
+pub struct VarInfo
+{
+    pub defs: usize,
+    pub uses: usize,
+    pub stack_allocated: bool,
+    def_scope: Option<usize>,
+    defs_in_one_scope: bool,
+}
+
+impl VarInfo
+{
+    fn new() -> VarInfo
+    {
+        VarInfo{
+            defs: 0,
+            uses: 0,
+            stack_allocated: false,
+            def_scope: None,
+            defs_in_one_scope: true,
+        }
+    }
+
+    /*
+        Backends bind a var in the scope where it is first assigned, unless it is stack allocated.
+        So an assignment can only be removed when that doesn't move the binding to another scope.
+    */
+    pub fn assignments_removable(&self) -> bool
+    {
+        self.stack_allocated || self.defs_in_one_scope
+    }
+}
+
+pub fn var_info(func: &ByteCodeFunction) -> HashMap<String, VarInfo>
+{
+    let mut info: HashMap<String, VarInfo> = HashMap::new();
+    // Scopes are numbered in block order, which is how backends handle them
+    let mut scopes = vec![0];
+    let mut scope_counter = 0;
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        match *instr
+        {
+            Instruction::StartScope => {
+                scope_counter += 1;
+                scopes.push(scope_counter);
+                continue;
+            },
+            Instruction::EndScope => {
+                if scopes.len() > 1 {
+                    scopes.pop();
+                }
+                continue;
+            },
+            Instruction::StackAlloc(ref v) => {
+                info.entry(v.name.clone()).or_insert_with(VarInfo::new).stack_allocated = true;
+            },
+            _ => (),
+        }
+
+        let scope = scopes.last().cloned().unwrap_or(0);
+        let (uses, defs) = uses_and_defs(instr);
+        for name in uses {
+            info.entry(name.to_string()).or_insert_with(VarInfo::new).uses += 1;
+        }
+
+        for name in defs {
+            let var = info.entry(name.to_string()).or_insert_with(VarInfo::new);
+            var.defs += 1;
+            match var.def_scope
+            {
+                None => var.def_scope = Some(scope),
+                Some(s) if s != scope => var.defs_in_one_scope = false,
+                _ => (),
+            }
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_function;
+
+    #[test]
+    fn test_liveness()
+    {
+        let func = parse_function(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  store (x: i32) (i32 1)
+  store (y: i32) (i32 2)
+  br block1
+block1:
+  bop (x: i32) add (x: i32) (a: i32)
+  brif (b: bool) block1 block2
+block2:
+  store (y: i32) (x: i32)
+  ret (y: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        let live = liveness(&func);
+        // y is assigned again before it is read
+        assert!(live.live_out[&0].contains("x"));
+        assert!(!live.live_out[&0].contains("y"));
+        // x is read in the next iteration of the loop
+        assert!(live.live_out[&1].contains("x"));
+        assert!(live.live_in[&1].contains("a"));
+        assert!(live.live_in[&2].contains("x"));
+        assert!(live.live_out[&2].is_empty());
+    }
+}
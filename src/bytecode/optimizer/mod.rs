@@ -1,13 +1,32 @@
-use bytecode::{ByteCodeModule};
-use bytecode::function::{ByteCodeFunction};
+use bytecode::{ByteCodeModule, verify_module};
+use bytecode::function::{ByteCodeFunction, Var};
+use bytecode::instruction::Instruction;
+use compileerror::CompileResult;
 
+mod boundschecks;
+mod constantfolding;
+mod copycoalescing;
+mod deadblocks;
+mod deadcode;
 mod emptyblocks;
+mod escapeanalysis;
+mod inliner;
+mod liveness;
+mod ssa;
 mod unusedfunctions;
 mod returnvalueoptimization;
 
+use self::boundschecks::remove_redundant_bounds_checks;
+use self::constantfolding::fold_constants;
+use self::copycoalescing::coalesce_copies;
+use self::deadblocks::remove_dead_blocks;
+use self::deadcode::eliminate_dead_code;
 use self::emptyblocks::remove_empty_blocks;
+use self::escapeanalysis::promote_heap_allocations;
+use self::inliner::inline_functions;
 use self::unusedfunctions::eliminate_unused_functions;
 use self::returnvalueoptimization::return_value_optimization;
+use self::ssa::{to_ssa, from_ssa};
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
 pub enum OptimizationLevel
@@ -16,27 +35,132 @@ pub enum OptimizationLevel
     Normal,
 }
 
-pub fn optimize_function(func: &mut ByteCodeFunction, _lvl: OptimizationLevel)
+// The variable an instruction assigns a value to
+fn assigned_var(instr: &Instruction) -> Option<&Var>
 {
-    remove_empty_blocks(func);
+    match *instr
+    {
+        Instruction::Store{ref dst, ..} |
+        Instruction::Load{ref dst, ..} |
+        Instruction::LoadMember{ref dst, ..} |
+        Instruction::AddressOf{ref dst, ..} |
+        Instruction::AddressOfMember{ref dst, ..} |
+        Instruction::GetProperty{ref dst, ..} |
+        Instruction::UnaryOp{ref dst, ..} |
+        Instruction::BinaryOp{ref dst, ..} |
+        Instruction::CheckedBinaryOp{ref dst, ..} |
+        Instruction::Slice{ref dst, ..} |
+        Instruction::MakeSlice{ref dst, ..} |
+        Instruction::Cast{ref dst, ..} |
+        Instruction::LoadOptionalFlag{ref dst, ..} |
+        Instruction::Call{dst: Some(ref dst), ..} |
+        Instruction::StoreNil(ref dst) |
+        Instruction::StackAlloc(ref dst) |
+        Instruction::HeapAlloc(ref dst) |
+        Instruction::Phi{ref dst, ..} => Some(dst),
+        _ => None,
+    }
+}
+
+type FunctionPass = fn(&mut ByteCodeFunction);
+
+fn function_passes(lvl: OptimizationLevel) -> Vec<(&'static str, FunctionPass)>
+{
+    let mut passes = Vec::new();
+    if lvl == OptimizationLevel::Normal {
+        passes.push(("constant folding", fold_constants as FunctionPass));
+        passes.push(("dead block removal", remove_dead_blocks as FunctionPass));
+    }
+
+    passes.push(("bounds check elimination", remove_redundant_bounds_checks as FunctionPass));
+    if lvl == OptimizationLevel::Normal {
+        passes.push(("copy coalescing", coalesce_copies as FunctionPass));
+        passes.push(("dead code elimination", eliminate_dead_code as FunctionPass));
+    }
+    passes.push(("empty block removal", remove_empty_blocks as FunctionPass));
+    passes
+}
+
+pub fn optimize_function(func: &mut ByteCodeFunction, lvl: OptimizationLevel)
+{
+    for (_, pass) in function_passes(lvl) {
+        pass(func);
+    }
 }
 
-pub fn optimize_module(module: &mut ByteCodeModule, lvl: OptimizationLevel)
+// Run an optimization pass, and check that it produced valid bytecode if verification is enabled
+fn run_pass<F>(module: &mut ByteCodeModule, name: &str, pass: F) -> CompileResult<()>
+    where F: FnOnce(&mut ByteCodeModule)
 {
-    eliminate_unused_functions(module);
-    return_value_optimization(module);
-    for func in module.functions.values_mut() {
-        if !func.external {
-            optimize_function(func, lvl);
+    pass(module);
+    if module.options.verify {
+        verify_module(module, name)?;
+    }
+    Ok(())
+}
+
+/*
+    Print the module in SSA form, for debugging. It is converted back afterwards, because the
+    backends don't support phi nodes.
+*/
+pub fn dump_ssa(module: &mut ByteCodeModule) -> CompileResult<()>
+{
+    run_pass(module, "SSA construction", |module| module.functions.values_mut().for_each(to_ssa))?;
+    println!("ssa:");
+    println!("------\n");
+    println!("{}", module);
+    println!("------\n");
+    run_pass(module, "SSA destruction", |module| module.functions.values_mut().for_each(from_ssa))
+}
+
+// Modules parsed from text can contain phi nodes, which have to be replaced before running them
+pub fn remove_phi_nodes(module: &mut ByteCodeModule) -> CompileResult<()>
+{
+    run_pass(module, "SSA destruction", |module| {
+        for func in module.functions.values_mut() {
+            let has_phi_nodes = func.blocks.values()
+                .flat_map(|bb| bb.instructions.iter())
+                .any(|instr| match *instr {
+                    Instruction::Phi{..} => true,
+                    _ => false,
+                });
+
+            if has_phi_nodes {
+                from_ssa(func);
+            }
         }
+    })
+}
+
+pub fn optimize_module(module: &mut ByteCodeModule, lvl: OptimizationLevel) -> CompileResult<()>
+{
+    run_pass(module, "unused function elimination", eliminate_unused_functions)?;
+    run_pass(module, "return value optimization", return_value_optimization)?;
+    if lvl == OptimizationLevel::Normal {
+        run_pass(module, "inlining", inline_functions)?;
+    }
+    for (name, pass) in function_passes(lvl) {
+        run_pass(module, name, |module| {
+            for func in module.functions.values_mut() {
+                if !func.external {
+                    pass(func);
+                }
+            }
+        })?;
     }
+
+    if lvl == OptimizationLevel::Normal {
+        run_pass(module, "escape analysis", promote_heap_allocations)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test
 {
     use super::*;
-    use bytecode::test::generate_byte_code;
+    use bytecode::test::{generate_byte_code, generate_byte_code_with_options};
+    use bytecode::{ByteCodeOptions, parse_function};
     use bytecode::instruction::Instruction;
     use bytecode::function::ByteCodeFunction;
     use ast::{sig, Type};
@@ -74,10 +198,246 @@ mod test
         assert!(m.get_function("test::bar").is_some());
         assert!(m.get_function("test::main").is_some());
 
-        optimize_module(&mut m, OptimizationLevel::Normal);
+        optimize_module(&mut m, OptimizationLevel::Normal).expect("Optimization succeeded");
 
         assert!(m.get_function("test::foo").is_none());
         assert!(m.get_function("test::bar").is_some());
         assert!(m.get_function("test::main").is_some());
     }
+
+    fn count_branches(func: &ByteCodeFunction) -> usize
+    {
+        func.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|i| match **i {
+                Instruction::BranchIf{..} => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_constant_branch_elimination()
+    {
+        let code = r#"
+fn size_class() -> int:
+    if @size(i64) > 8:
+        2
+    else
+        1
+
+fn main() -> int: size_class()
+        "#;
+
+        let mut minimal = generate_byte_code(code, false).expect("Parsing succeeded");
+        optimize_module(&mut minimal, OptimizationLevel::Minimal).expect("Optimization succeeded");
+        let minimal_func = minimal.get_function("test::size_class").expect("Missing size_class");
+        assert_eq!(count_branches(minimal_func), 1);
+
+        let mut normal = generate_byte_code(code, false).expect("Parsing succeeded");
+        optimize_module(&mut normal, OptimizationLevel::Normal).expect("Optimization succeeded");
+        let normal_func = normal.get_function("test::size_class").expect("Missing size_class");
+        assert_eq!(count_branches(normal_func), 0);
+        assert!(normal_func.blocks.len() < minimal_func.blocks.len());
+    }
+
+    fn count_bounds_checks(func: &ByteCodeFunction) -> usize
+    {
+        func.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|i| match **i {
+                Instruction::BoundsCheck{..} => true,
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_bounds_check_elimination()
+    {
+        let options = ByteCodeOptions{
+            checked_arithmetic: false,
+            bounds_checks: true,
+            verify: true,
+            dump_escape_analysis: false,
+        };
+
+        let mut m = generate_byte_code_with_options(r#"
+fn sum(a: int[4], s: int[], i: uint) -> int:
+    var total = a[i] + a[2]
+    for x in a:
+        total = total + x
+    for y in s:
+        total = total + y
+    total
+
+fn main() -> int: sum([1, 2, 3, 4], [5, 6], 1)
+        "#, options, false).expect("Parsing succeeded");
+
+        assert!(count_bounds_checks(m.get_function("test::sum").expect("Missing sum")) == 4);
+
+        optimize_module(&mut m, OptimizationLevel::Normal).expect("Optimization succeeded");
+
+        // Only the check on a[i] remains
+        assert!(count_bounds_checks(m.get_function("test::sum").expect("Missing sum")) == 1);
+    }
+
+    #[test]
+    fn test_bounds_check_elimination_from_text()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(a: [4]i32, i: u32) -> i32 {
+entry:
+  bcheck (u32 2) (u32 4) at "test" 2:5 2:9
+  loadm ($var0: i32) (a: [4]i32) (u32 2)
+  bcheck (i: u32) (u32 4) at "test" 3:5 3:9
+  loadm ($var1: i32) (a: [4]i32) (i: u32)
+  bcheck (i: u32) (u32 4) at "test" 4:5 4:9
+  loadm ($var2: i32) (a: [4]i32) (i: u32)
+  bop ($var3: i32) add ($var0: i32) ($var1: i32)
+  bop ($var4: i32) add ($var3: i32) ($var2: i32)
+  ret ($var4: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        assert!(count_bounds_checks(&func) == 3);
+        optimize_function(&mut func, OptimizationLevel::Normal);
+        // The constant index is in bounds, and i is already checked
+        assert!(count_bounds_checks(&func) == 1);
+    }
+
+    fn count_instructions<F: Fn(&Instruction) -> bool>(func: &ByteCodeFunction, pred: F) -> usize
+    {
+        func.blocks.values()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|i| pred(i))
+            .count()
+    }
+
+    fn assigns(instr: &Instruction, name: &str) -> bool
+    {
+        assigned_var(instr).map(|v| v.name == name).unwrap_or(false)
+    }
+
+    fn optimized_function(code: &str, name: &str, lvl: OptimizationLevel) -> ByteCodeFunction
+    {
+        let mut m = generate_byte_code(code, false).expect("Parsing succeeded");
+        let mut func = m.functions.remove(name).expect("Missing function");
+        optimize_function(&mut func, lvl);
+        func
+    }
+
+    #[test]
+    fn test_dead_code_elimination()
+    {
+        let code = r#"
+fn foo(a: int) -> int:
+    let unused = a * 3
+    a + 1
+
+fn main() -> int: foo(2)
+        "#;
+
+        let minimal = optimized_function(code, "test::foo", OptimizationLevel::Minimal);
+        assert_eq!(count_instructions(&minimal, |i| assigns(i, "unused")), 1);
+
+        let normal = optimized_function(code, "test::foo", OptimizationLevel::Normal);
+        assert_eq!(count_instructions(&normal, |i| assigns(i, "unused")), 0);
+        assert_eq!(count_instructions(&normal, |i| match *i {
+            Instruction::BinaryOp{..} => true,
+            _ => false,
+        }), 1);
+    }
+
+    #[test]
+    fn test_copy_coalescing()
+    {
+        let code = r#"
+fn foo(a: int) -> int:
+    var x = 0
+    x = a + 1
+    x
+
+fn main() -> int: foo(2)
+        "#;
+
+        let is_store = |i: &Instruction| match *i {
+            Instruction::Store{..} => true,
+            _ => false,
+        };
+
+        let minimal = optimized_function(code, "test::foo", OptimizationLevel::Minimal);
+        assert_eq!(count_instructions(&minimal, &is_store), 2);
+
+        // The addition assigns x directly, after which the initial value of x is never read
+        let normal = optimized_function(code, "test::foo", OptimizationLevel::Normal);
+        assert_eq!(count_instructions(&normal, &is_store), 0);
+        assert_eq!(count_instructions(&normal, |i| match *i {
+            Instruction::BinaryOp{ref dst, ..} => dst.name == "x",
+            _ => false,
+        }), 1);
+    }
+
+    #[test]
+    fn test_no_elimination_of_escaped_vars()
+    {
+        let code = r#"
+fn set(var p: *int):
+    *p = 7
+
+fn foo() -> int:
+    var x = 1
+    set(&x)
+    x
+
+fn main() -> int: foo()
+        "#;
+
+        let set = optimized_function(code, "test::set", OptimizationLevel::Normal);
+        assert_eq!(count_instructions(&set, |i| match *i {
+            Instruction::Store{..} => true,
+            _ => false,
+        }), 1);
+
+        let foo = optimized_function(code, "test::foo", OptimizationLevel::Normal);
+        assert_eq!(count_instructions(&foo, |i| assigns(i, "x")), 1);
+    }
+
+    fn count_module_instructions<F: Fn(&Instruction) -> bool>(m: &ByteCodeModule, pred: F) -> usize
+    {
+        m.functions.values().map(|func| count_instructions(func, &pred)).sum()
+    }
+
+    #[test]
+    fn test_heap_allocation_promotion()
+    {
+        let code = r#"
+fn sum(a: int, b: int) -> int:
+    let p = new a
+    *p = *p + b
+    let r = *p
+    delete p
+    r
+
+fn main() -> int: sum(1, 2)
+        "#;
+
+        let is_heap_alloc = |i: &Instruction| match *i {
+            Instruction::HeapAlloc(_) => true,
+            _ => false,
+        };
+
+        let mut minimal = generate_byte_code(code, false).expect("Parsing succeeded");
+        optimize_module(&mut minimal, OptimizationLevel::Minimal).expect("Optimization succeeded");
+        assert_eq!(count_module_instructions(&minimal, &is_heap_alloc), 1);
+
+        // p is only read and written through, so the allocation is moved to the stack and the delete is gone
+        let mut normal = generate_byte_code(code, false).expect("Parsing succeeded");
+        optimize_module(&mut normal, OptimizationLevel::Normal).expect("Optimization succeeded");
+        assert_eq!(count_module_instructions(&normal, &is_heap_alloc), 0);
+        assert_eq!(count_module_instructions(&normal, |i| match *i {
+            Instruction::Delete(_) => true,
+            _ => false,
+        }), 0);
+    }
 }
@@ -1,5 +1,5 @@
 use std::mem;
-use ast::{Type, Argument, ptr_type};
+use ast::{Type, Argument, CallingConvention, ptr_type};
 use span::Span;
 use bytecode::{
     ByteCodeFunction,
@@ -11,9 +11,12 @@ use bytecode::{
     Operand,
 };
 
+// Functions using the C calling convention return their value as the C ABI prescribes
 fn rvo_needed(func: &ByteCodeFunction) -> bool
 {
-    !func.sig.return_type.pass_by_value() && func.sig.return_type != Type::Void
+    !func.sig.return_type.pass_by_value() &&
+        func.sig.return_type != Type::Void &&
+        func.sig.calling_convention == CallingConvention::Nomad
 }
 
 fn rvo_func(func: &mut ByteCodeFunction)
@@ -0,0 +1,504 @@
+/*
+    Conversion to and from SSA form. In SSA form every renamed var is assigned by exactly one instruction,
+    and phi nodes at the start of a block pick the value of the predecessor control came from.
+
+    Only local vars which are passed by value and always assigned as a whole are renamed, vars which
+    can be accessed through a pointer, or are modified member by member, keep their name. Versions
+    of a var are called name.N. Passes which run in SSA form must not propagate copies between
+    versions of a var, so no two versions of the same var are live at the same time, which
+    from_ssa depends on.
+*/
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use ast::Type;
+use bytecode::cfg::ControlFlowGraph;
+use bytecode::function::{BasicBlockRef, ByteCodeFunction, Var};
+use bytecode::instruction::{Instruction, Operand, phi_instr, store_operand_instr};
+use super::assigned_var;
+use super::deadblocks::remove_dead_blocks;
+use super::liveness::{liveness, uses_and_defs, escaped_vars, is_local};
+
+// The vars which can be renamed, with their type
+fn promotable_vars(func: &ByteCodeFunction) -> BTreeMap<String, Type>
+{
+    let escaped = escaped_vars(func);
+    let mut vars = BTreeMap::new();
+    let mut excluded = BTreeSet::new();
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        match *instr
+        {
+            Instruction::StackAlloc(ref v) => {
+                excluded.insert(v.name.clone());
+            },
+            Instruction::StoreMember{ref obj, ..} |
+            Instruction::SetProperty{ref obj, ..} if !obj.typ.is_pointer() => {
+                excluded.insert(obj.name.clone());
+            },
+            // Calls through a function pointer refer to the var by name
+            Instruction::Call{ref func, ..} => {
+                excluded.insert(func.clone());
+            },
+            _ => (),
+        }
+
+        let (_, defs) = uses_and_defs(instr);
+        if let Some(var) = assigned_var(instr) {
+            if defs.contains(&var.name.as_str()) && var.typ.pass_by_value() && is_local(&var.name, &escaped) {
+                vars.insert(var.name.clone(), var.typ.clone());
+            }
+        }
+    }
+
+    vars.into_iter().filter(|&(ref name, _)| !excluded.contains(name)).collect()
+}
+
+// Add empty phi nodes for every var which has different versions at the start of a block, and is read after it
+fn insert_phis(func: &mut ByteCodeFunction, cfg: &ControlFlowGraph, vars: &BTreeMap<String, Type>) -> BTreeMap<BasicBlockRef, Vec<String>>
+{
+    let live = liveness(func);
+    let frontiers = cfg.dominance_frontiers();
+    let mut def_blocks: HashMap<String, BTreeSet<BasicBlockRef>> = HashMap::new();
+    for (bb_ref, bb) in &func.blocks {
+        for instr in &bb.instructions {
+            let (_, defs) = uses_and_defs(instr);
+            for name in defs.into_iter().filter(|name| vars.contains_key(*name)) {
+                def_blocks.entry(name.to_string()).or_insert_with(BTreeSet::new).insert(*bb_ref);
+            }
+        }
+    }
+
+    let mut phis: BTreeMap<BasicBlockRef, Vec<String>> = BTreeMap::new();
+    for name in vars.keys() {
+        let blocks = match def_blocks.get(name)
+        {
+            Some(blocks) => blocks,
+            None => continue,
+        };
+
+        let mut has_phi = BTreeSet::new();
+        let mut todo: Vec<BasicBlockRef> = blocks.iter().cloned().collect();
+        while let Some(bb_ref) = todo.pop() {
+            for frontier in frontiers.get(&bb_ref).into_iter().flat_map(|f| f.iter()) {
+                if !live.live_in[frontier].contains(name) || !has_phi.insert(*frontier) {
+                    continue;
+                }
+
+                phis.entry(*frontier).or_insert_with(Vec::new).push(name.clone());
+                // The phi node is a new definition
+                if !blocks.contains(frontier) {
+                    todo.push(*frontier);
+                }
+            }
+        }
+    }
+
+    for (bb_ref, names) in &phis {
+        let bb = func.blocks.get_mut(bb_ref).expect("Internal Compiler Error: phi node in unknown block");
+        for (idx, name) in names.iter().enumerate() {
+            bb.instructions.insert(idx, phi_instr(&Var::named(name, vars[name].clone()), Vec::new()));
+        }
+    }
+
+    phis
+}
+
+struct Renamer<'a>
+{
+    vars: &'a BTreeMap<String, Type>,
+    phis: &'a BTreeMap<BasicBlockRef, Vec<String>>,
+    // The current version of every renamed var, on the path through the dominator tree
+    versions: HashMap<String, Vec<String>>,
+    counters: HashMap<String, usize>,
+}
+
+impl<'a> Renamer<'a>
+{
+    fn new_version(&mut self, name: &str) -> String
+    {
+        let counter = self.counters.entry(name.to_string()).or_insert(0);
+        *counter += 1;
+        format!("{}.{}", name, counter)
+    }
+
+    fn current_version(&self, name: &str) -> Option<&String>
+    {
+        self.versions.get(name).and_then(|versions| versions.last())
+    }
+
+    fn rename_use(&self, var: &mut Var)
+    {
+        if let Some(version) = self.current_version(&var.name) {
+            var.name = version.clone();
+        }
+    }
+
+    fn rename_instruction(&mut self, instr: &mut Instruction) -> Option<String>
+    {
+        let def = {
+            let (_, defs) = uses_and_defs(instr);
+            defs.into_iter().find(|name| self.vars.contains_key(*name)).map(|name| name.to_string())
+        };
+
+        // The operands of phi nodes are filled in when their predecessors are renamed
+        let is_phi = match *instr
+        {
+            Instruction::Phi{..} => true,
+            _ => false,
+        };
+
+        if !is_phi {
+            for op in instr.operands_mut() {
+                match *op
+                {
+                    Operand::Var(ref mut v) | Operand::Dereference(ref mut v) => self.rename_use(v),
+                    _ => (),
+                }
+            }
+        }
+
+        let version = def.as_ref().map(|name| self.new_version(name));
+        let mut def_renamed = false;
+        for var in instr.vars_mut() {
+            match (&def, &version)
+            {
+                (&Some(ref name), &Some(ref version)) if !def_renamed && var.name == *name => {
+                    var.name = version.clone();
+                    def_renamed = true;
+                },
+                _ if !is_phi => self.rename_use(var),
+                _ => (),
+            }
+        }
+
+        match (def, version)
+        {
+            (Some(name), Some(version)) => {
+                self.versions.entry(name.clone()).or_insert_with(Vec::new).push(version);
+                Some(name)
+            },
+            _ => None,
+        }
+    }
+
+    fn rename_block(&mut self, func: &mut ByteCodeFunction, cfg: &ControlFlowGraph, bb_ref: BasicBlockRef)
+    {
+        let mut defined = Vec::new();
+        if let Some(bb) = func.blocks.get_mut(&bb_ref) {
+            for instr in &mut bb.instructions {
+                if let Some(name) = self.rename_instruction(instr) {
+                    defined.push(name);
+                }
+            }
+        }
+
+        for succ in cfg.successors(bb_ref) {
+            let names = match self.phis.get(succ)
+            {
+                Some(names) => names,
+                None => continue,
+            };
+
+            let bb = func.blocks.get_mut(succ).expect("Internal Compiler Error: unknown block");
+            for (instr, name) in bb.instructions.iter_mut().zip(names.iter()) {
+                if let Instruction::Phi{ref mut incoming, ..} = *instr {
+                    let version = self.current_version(name).cloned().unwrap_or_else(|| name.clone());
+                    incoming.push((bb_ref, Operand::Var(Var::named(&version, self.vars[name].clone()))));
+                }
+            }
+        }
+
+        for child in cfg.dominator_tree_children(bb_ref) {
+            self.rename_block(func, cfg, child);
+        }
+
+        for name in defined {
+            if let Some(versions) = self.versions.get_mut(&name) {
+                versions.pop();
+            }
+        }
+    }
+}
+
+// Convert a function to SSA form, unreachable blocks are removed first
+pub fn to_ssa(func: &mut ByteCodeFunction)
+{
+    if func.external || func.blocks.is_empty() {
+        return;
+    }
+
+    remove_dead_blocks(func);
+    let cfg = ControlFlowGraph::new(func);
+    let vars = promotable_vars(func);
+    let phis = insert_phis(func, &cfg, &vars);
+    let mut renamer = Renamer{
+        vars: &vars,
+        phis: &phis,
+        versions: HashMap::new(),
+        counters: HashMap::new(),
+    };
+
+    if let Some(entry) = cfg.entry() {
+        renamer.rename_block(func, &cfg, entry);
+    }
+
+    for instr in func.blocks.values_mut().flat_map(|bb| bb.instructions.iter_mut()) {
+        if let Instruction::Phi{ref mut incoming, ..} = *instr {
+            incoming.sort_by_key(|&(bb_ref, _)| bb_ref);
+        }
+    }
+}
+
+// The position of the terminator of a block, scope ends can follow it
+fn terminator_position(instructions: &[Instruction]) -> usize
+{
+    instructions.iter()
+        .rposition(|instr| instr.is_terminator())
+        .unwrap_or(instructions.len())
+}
+
+/*
+    Backends bind a var in the scope where it is first assigned. After leaving SSA form, a var
+    can be assigned in several scopes, or be read after the scope it was assigned in, so those
+    vars are stack allocated at the start of the function instead.
+*/
+fn vars_to_hoist(func: &ByteCodeFunction, candidates: &BTreeMap<String, Type>) -> BTreeMap<String, Type>
+{
+    let mut def_scopes: HashMap<String, usize> = HashMap::new();
+    let mut hoist = BTreeMap::new();
+    let mut scopes = vec![0];
+    let mut scope_counter = 0;
+    for instr in func.blocks.values().flat_map(|bb| bb.instructions.iter()) {
+        match *instr
+        {
+            Instruction::StartScope => {
+                scope_counter += 1;
+                scopes.push(scope_counter);
+                continue;
+            },
+            Instruction::EndScope => {
+                if scopes.len() > 1 {
+                    scopes.pop();
+                }
+                continue;
+            },
+            _ => (),
+        }
+
+        let (uses, defs) = uses_and_defs(instr);
+        let current_scope = scopes.last().cloned().unwrap_or(0);
+        for name in defs.iter().chain(uses.iter()) {
+            let typ = match candidates.get(*name)
+            {
+                Some(typ) => typ,
+                None => continue,
+            };
+
+            let bound_in = match def_scopes.get(*name).cloned()
+            {
+                Some(scope) => scope,
+                None if defs.contains(name) => {
+                    def_scopes.insert(name.to_string(), current_scope);
+                    continue;
+                },
+                // Read before it is assigned in block order, which happens in loops
+                None => continue,
+            };
+
+            if !scopes.contains(&bound_in) {
+                hoist.insert(name.to_string(), typ.clone());
+            }
+        }
+    }
+    hoist
+}
+
+// Replace phi nodes by copies at the end of the predecessors
+pub fn from_ssa(func: &mut ByteCodeFunction)
+{
+    if func.external || func.blocks.is_empty() {
+        return;
+    }
+
+    let mut copies: BTreeMap<BasicBlockRef, Vec<Instruction>> = BTreeMap::new();
+    for bb in func.blocks.values_mut() {
+        for instr in &bb.instructions {
+            if let Instruction::Phi{ref dst, ref incoming} = *instr {
+                for &(pred, ref op) in incoming {
+                    // Copying a version into itself happens for vars which are not modified in a loop
+                    match *op
+                    {
+                        Operand::Var(ref v) if v.name == dst.name => continue,
+                        _ => (),
+                    }
+                    copies.entry(pred).or_insert_with(Vec::new).push(store_operand_instr(dst, op.clone()));
+                }
+            }
+        }
+
+        bb.instructions.retain(|instr| match *instr {
+            Instruction::Phi{..} => false,
+            _ => true,
+        });
+    }
+
+    for (pred, pred_copies) in copies {
+        if let Some(bb) = func.blocks.get_mut(&pred) {
+            let pos = terminator_position(&bb.instructions);
+            for (idx, copy) in pred_copies.into_iter().enumerate() {
+                bb.instructions.insert(pos + idx, copy);
+            }
+        }
+    }
+
+    let candidates = promotable_vars(func);
+    let hoisted = vars_to_hoist(func, &candidates);
+    if let Some(entry) = func.blocks.values_mut().next() {
+        for (idx, (name, typ)) in hoisted.into_iter().enumerate() {
+            entry.instructions.insert(idx, Instruction::StackAlloc(Var::named(&name, typ)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::{ByteCodeModule, ByteCodeOptions, parse_function, verify_module};
+
+    fn verify(func: ByteCodeFunction) -> ByteCodeFunction
+    {
+        let mut module = ByteCodeModule{
+            name: "test".into(),
+            options: ByteCodeOptions::default(),
+            functions: BTreeMap::new(),
+            imported_functions: Vec::new(),
+            globals: BTreeMap::new(),
+            destructors: Default::default(),
+        };
+
+        let name = func.sig.name.clone();
+        module.functions.insert(name.clone(), func);
+        if let Err(e) = verify_module(&module, "test") {
+            panic!("{}\n{}", e, module);
+        }
+        module.functions.remove(&name).expect("Missing function")
+    }
+
+    fn phis(func: &ByteCodeFunction, bb_ref: BasicBlockRef) -> Vec<(String, Vec<(BasicBlockRef, String)>)>
+    {
+        func.blocks[&bb_ref].instructions.iter()
+            .filter_map(|instr| match *instr {
+                Instruction::Phi{ref dst, ref incoming} => Some((
+                    dst.name.clone(),
+                    incoming.iter().map(|&(bb, ref op)| (bb, format!("{}", op))).collect()
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ssa_loop()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(n: i32) -> i32 {
+entry:
+  store (x: i32) (i32 0)
+  store (i: i32) (i32 0)
+  br block1
+block1:
+  bop ($var0: bool) lt (i: i32) (n: i32)
+  brif ($var0: bool) block2 block3
+block2:
+  bop (x: i32) add (x: i32) (i: i32)
+  bop (i: i32) add (i: i32) (i32 1)
+  br block1
+block3:
+  ret (x: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        to_ssa(&mut func);
+        let func = verify(func);
+        assert_eq!(phis(&func, 1), vec![
+            ("i.2".to_string(), vec![(0, "(\"i.1\": i32)".to_string()), (2, "(\"i.3\": i32)".to_string())]),
+            ("x.2".to_string(), vec![(0, "(\"x.1\": i32)".to_string()), (2, "(\"x.3\": i32)".to_string())]),
+        ]);
+        // $var0 is only assigned in the loop header, so it doesn't need a phi node
+        assert!(phis(&func, 2).is_empty());
+        assert!(phis(&func, 3).is_empty());
+
+        let mut func = func;
+        from_ssa(&mut func);
+        let func = verify(func);
+        assert!(phis(&func, 1).is_empty());
+        // The copies are done at the end of the predecessors, before the branch
+        match func.blocks[&2].instructions[2]
+        {
+            Instruction::Store{ref dst, src: Operand::Var(ref src)} => {
+                assert_eq!(dst.name, "i.2");
+                assert_eq!(src.name, "i.3");
+            },
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+
+    #[test]
+    fn test_ssa_skips_escaped_vars()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(b: bool) -> i32 {
+entry:
+  store (x: i32) (i32 0)
+  store (y: i32) (i32 0)
+  addr ($var0: *i32) (y: i32)
+  brif (b: bool) block1 block2
+block1:
+  store (x: i32) (i32 1)
+  store (y: i32) (i32 1)
+  br block2
+block2:
+  bop ($var1: i32) add (x: i32) (y: i32)
+  ret ($var1: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        to_ssa(&mut func);
+        let func = verify(func);
+        // y has its address taken, so only x is renamed
+        assert_eq!(phis(&func, 2).len(), 1);
+        assert_eq!(phis(&func, 2)[0].0, "x.3");
+    }
+
+    #[test]
+    fn test_from_ssa_hoists_vars_used_outside_their_scope()
+    {
+        let mut func = parse_function(r#"
+fn test::foo(b: bool) -> i32 {
+entry:
+  brif (b: bool) block1 block2
+block1:
+  scope start
+  store (x: i32) (i32 1)
+  br block3
+  scope end
+block2:
+  scope start
+  store (x: i32) (i32 2)
+  br block3
+  scope end
+block3:
+  ret (x: i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+        to_ssa(&mut func);
+        from_ssa(&mut func);
+        let func = verify(func);
+        // The result of the phi node is assigned in two scopes, and read after both have ended
+        match func.blocks[&0].instructions[0]
+        {
+            Instruction::StackAlloc(ref v) => assert_eq!(v.name, "x.3"),
+            ref i => panic!("Unexpected instruction {}", i),
+        }
+    }
+}
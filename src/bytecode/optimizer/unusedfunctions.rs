@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use ast::CallingConvention;
 use bytecode::{ByteCodeModule};
 use bytecode::function::{ByteCodeFunction};
 use bytecode::instruction::{Instruction, Operand};
@@ -38,9 +39,16 @@ pub fn eliminate_unused_functions(module: &mut ByteCodeModule)
     let mut unused_calls: HashSet<String> = module.functions.keys().cloned().collect();
     let mut unused_imported: HashSet<String> = module.imported_functions.iter().map(|func| func.sig.name.clone()).collect();
     if let Some(main) = module.get_function(&module.main_function_name()) {
-        unused_calls.remove(&main.sig.name);
-        find_used_calls(module, main, &mut unused_calls);
-        find_used_calls(module, main, &mut unused_imported);
+        // Functions exported to C can be called from outside the module, so they are used as well
+        let roots = module.functions.values()
+            .filter(|func| !func.external && func.sig.calling_convention == CallingConvention::C)
+            .chain(Some(main));
+
+        for root in roots {
+            unused_calls.remove(&root.sig.name);
+            find_used_calls(module, root, &mut unused_calls);
+            find_used_calls(module, root, &mut unused_imported);
+        }
     } else {
         return;
     }
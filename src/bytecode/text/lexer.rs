@@ -0,0 +1,242 @@
+use std::iter::Peekable;
+use std::str::Chars;
+use compileerror::{CompileResult, parse_error_result};
+use span::{Span, Pos};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind
+{
+    Name(String),
+    String(String),
+    Char(char),
+    Number(String),
+    Punct(char),
+    Arrow,
+    Ellipsis,
+    EOF,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token
+{
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+fn is_name_char(c: char) -> bool
+{
+    c.is_ascii_alphanumeric() || c == '_' || c == '$'
+}
+
+struct Lexer<'a>
+{
+    chars: Peekable<Chars<'a>>,
+    file_name: &'a str,
+    pos: Pos,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Lexer<'a>
+{
+    fn next_char(&mut self) -> Option<char>
+    {
+        let c = self.chars.next();
+        match c
+        {
+            Some('\n') => {
+                self.pos.line += 1;
+                self.pos.offset = 1;
+            },
+            Some(_) => self.pos.offset += 1,
+            None => (),
+        }
+        c
+    }
+
+    fn peek(&mut self) -> Option<char>
+    {
+        self.chars.peek().cloned()
+    }
+
+    fn span(&self, start: Pos) -> Span
+    {
+        Span::new(self.file_name, start, self.pos)
+    }
+
+    fn add(&mut self, kind: TokenKind, start: Pos)
+    {
+        let span = self.span(start);
+        self.tokens.push(Token{kind, span});
+    }
+
+    fn escaped_char(&mut self, start: Pos) -> CompileResult<char>
+    {
+        match self.next_char()
+        {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('u') => {
+                if self.next_char() != Some('{') {
+                    return parse_error_result(&self.span(start), "Expecting { after \\u");
+                }
+
+                let mut code = String::new();
+                loop {
+                    match self.next_char()
+                    {
+                        Some('}') => break,
+                        Some(c) => code.push(c),
+                        None => return parse_error_result(&self.span(start), "Unterminated escape code"),
+                    }
+                }
+
+                u32::from_str_radix(&code, 16).ok()
+                    .and_then(::std::char::from_u32)
+                    .map_or_else(|| parse_error_result(&self.span(start), format!("Invalid escape code \\u{{{}}}", code)), Ok)
+            },
+            Some(c) => Ok(c),
+            None => parse_error_result(&self.span(start), "Unterminated escape code"),
+        }
+    }
+
+    fn quoted(&mut self, quote: char, start: Pos) -> CompileResult<String>
+    {
+        let mut data = String::new();
+        loop {
+            match self.next_char()
+            {
+                Some('\\') => {
+                    let c = self.escaped_char(start)?;
+                    data.push(c);
+                },
+                Some(c) if c == quote => return Ok(data),
+                Some(c) => data.push(c),
+                None => return parse_error_result(&self.span(start), "Unterminated string"),
+            }
+        }
+    }
+
+    fn name(&mut self, first: char) -> String
+    {
+        let mut data = first.to_string();
+        loop {
+            match self.peek()
+            {
+                Some(c) if is_name_char(c) => {
+                    data.push(c);
+                    self.next_char();
+                },
+                Some(':') => {
+                    // Only :: is part of a name, a single : separates a name and a type
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    if lookahead.next() != Some(':') {
+                        break;
+                    }
+                    data.push_str("::");
+                    self.next_char();
+                    self.next_char();
+                },
+                _ => break,
+            }
+        }
+        data
+    }
+
+    fn number(&mut self, first: char) -> String
+    {
+        let mut data = first.to_string();
+        loop {
+            match self.peek()
+            {
+                Some(c) if is_name_char(c) || c == '.' => {
+                    data.push(c);
+                    self.next_char();
+                },
+                // The sign of an exponent
+                Some(c) if (c == '+' || c == '-') && (data.ends_with('e') || data.ends_with('E')) => {
+                    data.push(c);
+                    self.next_char();
+                },
+                _ => break,
+            }
+        }
+        data
+    }
+
+    fn run(&mut self) -> CompileResult<()>
+    {
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            self.next_char();
+            match c
+            {
+                ' ' | '\t' | '\r' | '\n' => (),
+                '#' => {
+                    while let Some(c) = self.next_char() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                },
+                '"' => {
+                    let s = self.quoted('"', start)?;
+                    self.add(TokenKind::String(s), start);
+                },
+                '\'' => {
+                    let s = self.quoted('\'', start)?;
+                    let mut chars = s.chars();
+                    match (chars.next(), chars.next())
+                    {
+                        (Some(c), None) => self.add(TokenKind::Char(c), start),
+                        _ => return parse_error_result(&self.span(start), format!("Invalid character literal '{}'", s)),
+                    }
+                },
+                '-' if self.peek() == Some('>') => {
+                    self.next_char();
+                    self.add(TokenKind::Arrow, start);
+                },
+                '-' if self.peek().map(|c| c.is_ascii_alphanumeric()).unwrap_or(false) => {
+                    let n = self.number(c);
+                    self.add(TokenKind::Number(n), start);
+                },
+                '.' => {
+                    if self.next_char() != Some('.') || self.next_char() != Some('.') {
+                        return parse_error_result(&self.span(start), "Expecting ...");
+                    }
+                    self.add(TokenKind::Ellipsis, start);
+                },
+                '0'...'9' => {
+                    let n = self.number(c);
+                    self.add(TokenKind::Number(n), start);
+                },
+                c if is_name_char(c) => {
+                    let n = self.name(c);
+                    self.add(TokenKind::Name(n), start);
+                },
+                '(' | ')' | '[' | ']' | '{' | '}' | ',' | ':' | '=' | '&' | '*' | '?' | '%' => {
+                    self.add(TokenKind::Punct(c), start);
+                },
+                _ => return parse_error_result(&self.span(start), format!("Unexpected character {}", c)),
+            }
+        }
+
+        let pos = self.pos;
+        self.add(TokenKind::EOF, pos);
+        Ok(())
+    }
+}
+
+pub fn tokenize(text: &str, file_name: &str) -> CompileResult<Vec<Token>>
+{
+    let mut lexer = Lexer{
+        chars: text.chars().peekable(),
+        file_name,
+        pos: Pos::new(1, 1),
+        tokens: Vec::new(),
+    };
+
+    lexer.run()?;
+    Ok(lexer.tokens)
+}
@@ -0,0 +1,733 @@
+/*
+    Textual format of bytecode, which can be read back with parse_module and parse_function.
+
+    A module starts with its name and options, followed by the declarations of the named types
+    it uses, its globals, destructors and functions:
+
+        module test
+        options checked_arithmetic bounds_checks
+        type struct test::Point {x: i32, y: i32}
+        global test::origin = (i32 0)
+        fn test::main() -> i32 {
+        entry:
+          loadm ($var0: i32) (p: %test::Point) (u32 0)
+          ret ($var0: i32)
+        }
+
+    Vars are written as (name: type), constants as (type value), array constants as [members],
+    struct constants as (struct type {members}), and a struct, sum or enum type
+    declared by the module as %name. Names which are not plain identifiers are quoted.
+    The spans of function signatures are not part of the format, they are default spans after parsing.
+    Phi nodes, which only appear in SSA form, list the incoming value for every predecessor block:
+
+        phi ("x.3": i32) [entry: ("x.1": i32), block2: ("x.2": i32)]
+*/
+
+mod lexer;
+mod parser;
+#[cfg(test)]
+mod tests;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::Write;
+use ast::{Type, BinaryOperator, UnaryOperator, CallingConvention, FunctionSignature, InlineHint};
+use bytecode::{ByteCodeModule, ByteCodeFunction, Instruction, Operand, Constant, Var, bb_name};
+use span::Span;
+
+pub use self::parser::parse_module;
+#[cfg(test)]
+pub use self::parser::parse_function;
+
+/*
+    Named types printed as %name. When enabled, every struct, sum and enum type with a name is
+    declared the first time it is printed, otherwise types are always printed in full.
+*/
+pub struct TypeTable
+{
+    enabled: bool,
+    names: RefCell<HashMap<String, Type>>,
+    declared: RefCell<Vec<Type>>,
+}
+
+fn type_name(typ: &Type) -> Option<&str>
+{
+    let name = match *typ
+    {
+        Type::Struct(ref st) => &st.name,
+        Type::Sum(ref st) => &st.name,
+        Type::Enum(ref et) => &et.name,
+        _ => return None,
+    };
+
+    if name.is_empty() {None} else {Some(name)}
+}
+
+impl TypeTable
+{
+    pub fn new(enabled: bool) -> TypeTable
+    {
+        TypeTable{
+            enabled,
+            names: RefCell::new(HashMap::new()),
+            declared: RefCell::new(Vec::new()),
+        }
+    }
+
+    // Declare all named types typ consists of
+    fn collect(&self, typ: &Type)
+    {
+        match *typ
+        {
+            Type::Pointer(ref inner) | Type::Optional(ref inner) => self.collect(inner),
+            Type::Array(ref at) => self.collect(&at.element_type),
+            Type::Slice(ref st) => self.collect(&st.element_type),
+            Type::Func(ref ft) => {
+                for arg in &ft.args {
+                    self.collect(arg);
+                }
+                self.collect(&ft.return_type);
+            },
+            Type::Struct(_) | Type::Sum(_) | Type::Enum(_) => {
+                self.declare(typ);
+            },
+            _ => (),
+        }
+    }
+
+    // Whether typ can be printed as a reference to a declaration, declares it if needed
+    fn declare(&self, typ: &Type) -> bool
+    {
+        if !self.enabled {
+            return false;
+        }
+
+        let name = match type_name(typ)
+        {
+            Some(name) => name,
+            None => return false,
+        };
+
+        let conflict = match self.names.borrow().get(name)
+        {
+            Some(existing) if *existing == *typ => return true,
+            Some(_) => true,
+            None => false,
+        };
+
+        // Members are declared first, so declarations only refer to earlier ones
+        match *typ
+        {
+            Type::Struct(ref st) => st.members.iter().for_each(|m| self.collect(&m.typ)),
+            Type::Sum(ref st) => st.cases.iter().for_each(|c| self.collect(&c.typ)),
+            _ => (),
+        }
+
+        // Different types with the same name are printed in full
+        if conflict {
+            return false;
+        }
+
+        self.names.borrow_mut().insert(name.into(), typ.clone());
+        self.declared.borrow_mut().push(typ.clone());
+        true
+    }
+}
+
+// Names consisting of letters, digits, _, $ and :: are printed as is, the others are quoted
+fn is_plain_name(name: &str) -> bool
+{
+    let starts_ok = name.chars().next().map(|c| c.is_ascii_alphabetic() || c == '_' || c == '$').unwrap_or(false);
+    starts_ok &&
+        !name.ends_with(':') &&
+        !name.replace("::", "").contains(':') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == ':')
+}
+
+fn write_escaped<W: Write>(w: &mut W, s: &str, quote: char) -> fmt::Result
+{
+    w.write_char(quote)?;
+    for c in s.chars() {
+        match c
+        {
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\t' => w.write_str("\\t")?,
+            '\r' => w.write_str("\\r")?,
+            c if c == quote => write!(w, "\\{}", c)?,
+            c if (c as u32) < 0x20 || c as u32 == 0x7F => write!(w, "\\u{{{:x}}}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char(quote)
+}
+
+pub fn write_name<W: Write>(w: &mut W, name: &str) -> fmt::Result
+{
+    if is_plain_name(name) {
+        w.write_str(name)
+    } else {
+        write_escaped(w, name, '"')
+    }
+}
+
+pub fn write_type<W: Write>(w: &mut W, typ: &Type, table: &TypeTable) -> fmt::Result
+{
+    match *typ
+    {
+        Type::Void => w.write_str("void"),
+        Type::Unknown => w.write_str("unknown"),
+        Type::Int(int_size) => write!(w, "i{}", int_size),
+        Type::UInt(int_size) => write!(w, "u{}", int_size),
+        Type::Float(float_size) => write!(w, "f{}", float_size),
+        Type::Char => w.write_str("char"),
+        Type::Bool => w.write_str("bool"),
+        Type::String => w.write_str("string"),
+        Type::SelfType => w.write_str("Self"),
+        Type::Pointer(ref inner) => {
+            w.write_char('*')?;
+            write_type(w, inner, table)
+        },
+        Type::Optional(ref inner) => {
+            w.write_char('?')?;
+            write_type(w, inner, table)
+        },
+        Type::Array(ref at) => {
+            write!(w, "[{}]", at.length)?;
+            write_type(w, &at.element_type, table)
+        },
+        Type::Slice(ref st) => {
+            w.write_str("[]")?;
+            write_type(w, &st.element_type, table)
+        },
+        Type::Func(ref ft) => {
+            if ft.calling_convention == CallingConvention::C {
+                w.write_str("extern ")?;
+            }
+            w.write_str("fn(")?;
+            for (idx, arg) in ft.args.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_type(w, arg, table)?;
+            }
+            if ft.variadic {
+                w.write_str(if ft.args.is_empty() {"..."} else {", ..."})?;
+            }
+            w.write_str(") -> ")?;
+            write_type(w, &ft.return_type, table)
+        },
+        Type::Struct(_) | Type::Sum(_) | Type::Enum(_) => {
+            if table.declare(typ) {
+                w.write_char('%')?;
+                write_name(w, type_name(typ).unwrap_or(""))
+            } else {
+                write_type_definition(w, typ, table)
+            }
+        },
+        Type::Unresolved(ref ut) => {
+            w.write_str("unresolved ")?;
+            write_name(w, &ut.name)
+        },
+        Type::Generic(ref g) => write!(w, "${}", g),
+        Type::Interface(ref i) => {
+            w.write_str("interface ")?;
+            write_name(w, &i.name)
+        },
+    }
+}
+
+// The full definition of a struct, sum or enum type
+fn write_type_definition<W: Write>(w: &mut W, typ: &Type, table: &TypeTable) -> fmt::Result
+{
+    match *typ
+    {
+        Type::Struct(ref st) => {
+            w.write_str("struct ")?;
+            if !st.name.is_empty() {
+                write_name(w, &st.name)?;
+                w.write_char(' ')?;
+            }
+            w.write_char('{')?;
+            for (idx, m) in st.members.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_name(w, &m.name)?;
+                w.write_str(": ")?;
+                write_type(w, &m.typ, table)?;
+            }
+            w.write_char('}')
+        },
+        Type::Sum(ref st) => {
+            w.write_str("sum ")?;
+            write_name(w, &st.name)?;
+            w.write_str(" {")?;
+            for (idx, c) in st.cases.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_name(w, &c.name)?;
+                w.write_str(": ")?;
+                write_type(w, &c.typ, table)?;
+            }
+            w.write_char('}')
+        },
+        Type::Enum(ref et) => {
+            w.write_str("enum ")?;
+            write_name(w, &et.name)?;
+            w.write_str(" {")?;
+            for (idx, c) in et.cases.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_name(w, c)?;
+            }
+            w.write_char('}')
+        },
+        _ => write_type(w, typ, table),
+    }
+}
+
+pub fn write_var<W: Write>(w: &mut W, var: &Var, table: &TypeTable) -> fmt::Result
+{
+    w.write_char('(')?;
+    write_name(w, &var.name)?;
+    w.write_str(": ")?;
+    write_type(w, &var.typ, table)?;
+    w.write_char(')')
+}
+
+pub fn write_constant<W: Write>(w: &mut W, c: &Constant, table: &TypeTable) -> fmt::Result
+{
+    match *c
+    {
+        Constant::Int(v, int_size) => write!(w, "(i{} {})", int_size, v),
+        Constant::UInt(v, int_size) => write!(w, "(u{} {})", int_size, v),
+        Constant::Float(v, float_size) => write!(w, "(f{} {:?})", float_size, v),
+        Constant::Char(v) => {
+            w.write_str("(char ")?;
+            write_escaped(w, &v.to_string(), '\'')?;
+            w.write_char(')')
+        },
+        Constant::String(ref v) => {
+            w.write_str("(string ")?;
+            write_escaped(w, v, '"')?;
+            w.write_char(')')
+        },
+        Constant::Bool(v) => write!(w, "(bool {})", v),
+        Constant::Array(ref members) => {
+            w.write_char('[')?;
+            for (idx, m) in members.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_constant(w, m, table)?;
+            }
+            w.write_char(']')
+        },
+        Constant::Struct(ref typ, ref members) => {
+            w.write_str("(struct ")?;
+            write_type(w, typ, table)?;
+            w.write_str(" {")?;
+            for (idx, m) in members.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_constant(w, m, table)?;
+            }
+            w.write_str("})")
+        },
+        Constant::NullPtr(ref typ) => {
+            w.write_str("(null ")?;
+            write_type(w, typ, table)?;
+            w.write_char(')')
+        },
+    }
+}
+
+pub fn write_operand<W: Write>(w: &mut W, op: &Operand, table: &TypeTable) -> fmt::Result
+{
+    match *op
+    {
+        Operand::Var(ref v) => write_var(w, v, table),
+        Operand::AddressOf(ref v) => {
+            w.write_char('&')?;
+            write_var(w, v, table)
+        },
+        Operand::Dereference(ref v) => {
+            w.write_char('*')?;
+            write_var(w, v, table)
+        },
+        Operand::Const(ref c) => write_constant(w, c, table),
+        Operand::Func(ref name) => {
+            w.write_str("(func ")?;
+            write_name(w, name)?;
+            w.write_char(')')
+        },
+        Operand::SizeOf(ref typ) => {
+            w.write_str("(sizeof ")?;
+            write_type(w, typ, table)?;
+            w.write_char(')')
+        },
+    }
+}
+
+pub fn binary_operator_name(op: BinaryOperator) -> &'static str
+{
+    match op
+    {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Sub => "sub",
+        BinaryOperator::Mul => "mul",
+        BinaryOperator::Div => "div",
+        BinaryOperator::Mod => "mod",
+        BinaryOperator::WrappingAdd => "wadd",
+        BinaryOperator::WrappingSub => "wsub",
+        BinaryOperator::WrappingMul => "wmul",
+        BinaryOperator::SaturatingAdd => "sadd",
+        BinaryOperator::SaturatingSub => "ssub",
+        BinaryOperator::SaturatingMul => "smul",
+        BinaryOperator::LessThan => "lt",
+        BinaryOperator::GreaterThan => "gt",
+        BinaryOperator::LessThanEquals => "le",
+        BinaryOperator::GreaterThanEquals => "ge",
+        BinaryOperator::Equals => "eq",
+        BinaryOperator::NotEquals => "ne",
+        BinaryOperator::And => "and",
+        BinaryOperator::Or => "or",
+        BinaryOperator::Dot => "dot",
+        BinaryOperator::As => "as",
+    }
+}
+
+pub fn unary_operator_name(op: UnaryOperator) -> &'static str
+{
+    match op
+    {
+        UnaryOperator::Sub => "neg",
+        UnaryOperator::Not => "not",
+    }
+}
+
+fn write_span<W: Write>(w: &mut W, span: &Span) -> fmt::Result
+{
+    w.write_str(" at ")?;
+    write_escaped(w, &span.file, '"')?;
+    write!(w, " {} {}", span.start, span.end)
+}
+
+fn write_operands<W: Write>(w: &mut W, ops: &[&Operand], table: &TypeTable) -> fmt::Result
+{
+    for op in ops {
+        w.write_char(' ')?;
+        write_operand(w, op, table)?;
+    }
+    Ok(())
+}
+
+fn write_vars<W: Write>(w: &mut W, vars: &[&Var], table: &TypeTable) -> fmt::Result
+{
+    for v in vars {
+        w.write_char(' ')?;
+        write_var(w, v, table)?;
+    }
+    Ok(())
+}
+
+pub fn write_instruction<W: Write>(w: &mut W, instr: &Instruction, table: &TypeTable) -> fmt::Result
+{
+    match *instr
+    {
+        Instruction::Store{ref dst, ref src} => {
+            w.write_str("store")?;
+            write_vars(w, &[dst], table)?;
+            write_operands(w, &[src], table)
+        },
+
+        Instruction::Load{ref dst, ref ptr} => {
+            w.write_str("load")?;
+            write_vars(w, &[dst, ptr], table)
+        },
+
+        Instruction::LoadMember{ref dst, ref obj, ref member_index} => {
+            w.write_str("loadm")?;
+            write_vars(w, &[dst, obj], table)?;
+            write_operands(w, &[member_index], table)
+        },
+
+        Instruction::StoreMember{ref obj, ref member_index, ref src} => {
+            w.write_str("storem")?;
+            write_vars(w, &[obj], table)?;
+            write_operands(w, &[member_index, src], table)
+        },
+
+        Instruction::AddressOf{ref dst, ref obj} => {
+            w.write_str("addr")?;
+            write_vars(w, &[dst, obj], table)
+        },
+
+        Instruction::AddressOfMember{ref dst, ref obj, ref member_index} => {
+            w.write_str("addrm")?;
+            write_vars(w, &[dst, obj], table)?;
+            write_operands(w, &[member_index], table)
+        },
+
+        Instruction::GetProperty{ref dst, ref obj, prop} => {
+            w.write_str("getp")?;
+            write_vars(w, &[dst, obj], table)?;
+            write!(w, " {}", prop)
+        },
+
+        Instruction::SetProperty{ref obj, prop, val} => {
+            w.write_str("setp")?;
+            write_vars(w, &[obj], table)?;
+            write!(w, " {} {}", prop, val)
+        },
+
+        Instruction::UnaryOp{ref dst, op, ref src} => {
+            w.write_str("uop")?;
+            write_vars(w, &[dst], table)?;
+            write!(w, " {}", unary_operator_name(op))?;
+            write_operands(w, &[src], table)
+        },
+
+        Instruction::BinaryOp{ref dst, op, ref left, ref right} => {
+            w.write_str("bop")?;
+            write_vars(w, &[dst], table)?;
+            write!(w, " {}", binary_operator_name(op))?;
+            write_operands(w, &[left, right], table)
+        },
+
+        Instruction::CheckedBinaryOp{ref dst, op, ref left, ref right, ref span} => {
+            w.write_str("cbop")?;
+            write_vars(w, &[dst], table)?;
+            write!(w, " {}", binary_operator_name(op))?;
+            write_operands(w, &[left, right], table)?;
+            write_span(w, span)
+        },
+
+        Instruction::Call{ref dst, ref func, ref args} => {
+            w.write_str("call")?;
+            if let Some(ref dst) = *dst {
+                write_vars(w, &[dst], table)?;
+            }
+            w.write_char(' ')?;
+            write_name(w, func)?;
+            w.write_char('(')?;
+            for (idx, arg) in args.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write_operand(w, arg, table)?;
+            }
+            w.write_char(')')
+        },
+
+        Instruction::Slice{ref dst, ref src, ref start, ref len} => {
+            w.write_str("slice")?;
+            write_vars(w, &[dst, src], table)?;
+            write_operands(w, &[start, len], table)
+        },
+
+        Instruction::MakeSlice{ref dst, ref data, ref len} => {
+            w.write_str("mkslice")?;
+            write_vars(w, &[dst, data, len], table)
+        },
+
+        Instruction::Cast{ref dst, ref src} => {
+            w.write_str("cast")?;
+            write_vars(w, &[dst], table)?;
+            write_operands(w, &[src], table)
+        },
+
+        Instruction::LoadOptionalFlag{ref dst, ref obj} => {
+            w.write_str("loadoptf")?;
+            write_vars(w, &[dst, obj], table)
+        },
+
+        Instruction::StoreNil(ref var) => {
+            w.write_str("storenil")?;
+            write_vars(w, &[var], table)
+        },
+
+        Instruction::StackAlloc(ref var) => {
+            w.write_str("salloc")?;
+            write_vars(w, &[var], table)
+        },
+
+        Instruction::HeapAlloc(ref var) => {
+            w.write_str("halloc")?;
+            write_vars(w, &[var], table)
+        },
+
+        Instruction::StartScope => w.write_str("scope start"),
+
+        Instruction::EndScope => w.write_str("scope end"),
+
+        Instruction::Return(ref op) => {
+            w.write_str("ret")?;
+            write_operands(w, &[op], table)
+        },
+
+        Instruction::ReturnVoid => w.write_str("ret void"),
+
+        Instruction::Branch(bb) => write!(w, "br {}", bb_name(bb)),
+
+        Instruction::BranchIf{ref cond, on_true, on_false} => {
+            w.write_str("brif")?;
+            write_operands(w, &[cond], table)?;
+            write!(w, " {} {}", bb_name(on_true), bb_name(on_false))
+        },
+
+        Instruction::Delete(ref var) => {
+            w.write_str("delete")?;
+            write_vars(w, &[var], table)
+        },
+
+        Instruction::BoundsCheck{ref index, ref len, ref span} => {
+            w.write_str("bcheck")?;
+            write_operands(w, &[index, len], table)?;
+            write_span(w, span)
+        },
+
+        Instruction::RangeCheck{ref seq, ref start, ref end, ref span} => {
+            w.write_str("rcheck")?;
+            write_vars(w, &[seq], table)?;
+            write_operands(w, &[start, end], table)?;
+            write_span(w, span)
+        },
+
+        Instruction::Panic{ref msg, ref span} => {
+            w.write_str("panic")?;
+            write_vars(w, &[msg], table)?;
+            write_span(w, span)
+        },
+
+        Instruction::Phi{ref dst, ref incoming} => {
+            w.write_str("phi")?;
+            write_vars(w, &[dst], table)?;
+            w.write_str(" [")?;
+            for (idx, &(bb, ref op)) in incoming.iter().enumerate() {
+                if idx > 0 {
+                    w.write_str(", ")?;
+                }
+                write!(w, "{}: ", bb_name(bb))?;
+                write_operand(w, op, table)?;
+            }
+            w.write_char(']')
+        },
+    }
+}
+
+fn write_signature<W: Write>(w: &mut W, sig: &FunctionSignature, table: &TypeTable) -> fmt::Result
+{
+    w.write_str("fn ")?;
+    write_name(w, &sig.name)?;
+    w.write_char('(')?;
+    for (idx, arg) in sig.args.iter().enumerate() {
+        if idx > 0 {
+            w.write_str(", ")?;
+        }
+        if arg.mutable {
+            w.write_str("mut ")?;
+        }
+        write_name(w, &arg.name)?;
+        w.write_str(": ")?;
+        write_type(w, &arg.typ, table)?;
+    }
+    if sig.variadic {
+        w.write_str(if sig.args.is_empty() {"..."} else {", ..."})?;
+    }
+    w.write_str(") -> ")?;
+    write_type(w, &sig.return_type, table)?;
+    if sig.calling_convention == CallingConvention::C {
+        w.write_str(" abi C")?;
+    }
+    match sig.inline_hint
+    {
+        InlineHint::Always => w.write_str(" inline")?,
+        InlineHint::Never => w.write_str(" noinline")?,
+        InlineHint::Auto => (),
+    }
+    Ok(())
+}
+
+pub fn write_function<W: Write>(w: &mut W, func: &ByteCodeFunction, imported: bool, table: &TypeTable) -> fmt::Result
+{
+    if imported {
+        w.write_str("import ")?;
+    }
+    if func.external {
+        w.write_str("extern ")?;
+    }
+    write_signature(w, &func.sig, table)?;
+    if func.blocks.is_empty() {
+        return w.write_char('\n');
+    }
+
+    w.write_str(" {\n")?;
+    for (bb_ref, bb) in &func.blocks {
+        writeln!(w, "{}:", bb_name(*bb_ref))?;
+        for instr in &bb.instructions {
+            w.write_str("  ")?;
+            write_instruction(w, instr, table)?;
+            w.write_char('\n')?;
+        }
+    }
+    w.write_str("}\n")
+}
+
+pub fn write_module<W: Write>(w: &mut W, module: &ByteCodeModule) -> fmt::Result
+{
+    // The body is written first, so the table knows which types have to be declared
+    let table = TypeTable::new(true);
+    let mut body = String::new();
+
+    for (name, value) in &module.globals {
+        body.push_str("global ");
+        write_name(&mut body, name)?;
+        body.push_str(" = ");
+        write_constant(&mut body, value, &table)?;
+        body.push('\n');
+    }
+
+    for name in &module.destructors {
+        body.push_str("destructor ");
+        write_name(&mut body, name)?;
+        body.push('\n');
+    }
+
+    for func in module.functions.values() {
+        body.push('\n');
+        write_function(&mut body, func, false, &table)?;
+    }
+
+    if !module.imported_functions.is_empty() {
+        body.push('\n');
+    }
+    for func in &module.imported_functions {
+        write_function(&mut body, func, true, &table)?;
+    }
+
+    w.write_str("module ")?;
+    write_name(w, &module.name)?;
+    w.write_str("\noptions")?;
+    if module.options.checked_arithmetic {
+        w.write_str(" checked_arithmetic")?;
+    }
+    if module.options.bounds_checks {
+        w.write_str(" bounds_checks")?;
+    }
+    w.write_char('\n')?;
+
+    let declared = table.declared.borrow().clone();
+    for typ in &declared {
+        w.write_str("type ")?;
+        write_type_definition(w, typ, &table)?;
+        w.write_char('\n')?;
+    }
+
+    w.write_str(&body)
+}
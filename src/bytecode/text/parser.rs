@@ -0,0 +1,805 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::rc::Rc;
+use compileerror::{CompileResult, parse_error_result};
+use ast::{Type, IntSize, FloatSize, BinaryOperator, UnaryOperator, CallingConvention, FunctionSignature, InlineHint, Argument,
+    ArrayType, ArrayLength, StructType, StructMember, SumType, SumTypeCase, EnumType, FuncType, UnresolvedType,
+    ptr_type, optional_type, slice_type};
+use bytecode::{ByteCodeModule, ByteCodeOptions, ByteCodeFunction, BasicBlock, BasicBlockRef, Instruction, Operand,
+    Constant, Var, ByteCodeProperty, bb_name};
+use span::{Span, Pos};
+use super::lexer::{Token, TokenKind, tokenize};
+
+struct Parser
+{
+    tokens: Vec<Token>,
+    idx: usize,
+    types: HashMap<String, Type>,
+    var_counter: usize,
+}
+
+fn int_size(bits: &str) -> Option<IntSize>
+{
+    match bits
+    {
+        "8" => Some(IntSize::I8),
+        "16" => Some(IntSize::I16),
+        "32" => Some(IntSize::I32),
+        "64" => Some(IntSize::I64),
+        _ => None,
+    }
+}
+
+fn float_size(bits: &str) -> Option<FloatSize>
+{
+    match bits
+    {
+        "32" => Some(FloatSize::F32),
+        "64" => Some(FloatSize::F64),
+        _ => None,
+    }
+}
+
+fn binary_operator(name: &str) -> Option<BinaryOperator>
+{
+    let op = match name
+    {
+        "add" => BinaryOperator::Add,
+        "sub" => BinaryOperator::Sub,
+        "mul" => BinaryOperator::Mul,
+        "div" => BinaryOperator::Div,
+        "mod" => BinaryOperator::Mod,
+        "wadd" => BinaryOperator::WrappingAdd,
+        "wsub" => BinaryOperator::WrappingSub,
+        "wmul" => BinaryOperator::WrappingMul,
+        "sadd" => BinaryOperator::SaturatingAdd,
+        "ssub" => BinaryOperator::SaturatingSub,
+        "smul" => BinaryOperator::SaturatingMul,
+        "lt" => BinaryOperator::LessThan,
+        "gt" => BinaryOperator::GreaterThan,
+        "le" => BinaryOperator::LessThanEquals,
+        "ge" => BinaryOperator::GreaterThanEquals,
+        "eq" => BinaryOperator::Equals,
+        "ne" => BinaryOperator::NotEquals,
+        "and" => BinaryOperator::And,
+        "or" => BinaryOperator::Or,
+        "dot" => BinaryOperator::Dot,
+        "as" => BinaryOperator::As,
+        _ => return None,
+    };
+    Some(op)
+}
+
+// Block names are entry for block 0, and blockN for the others
+fn block_ref(name: &str) -> Option<BasicBlockRef>
+{
+    if name == "entry" {
+        Some(0)
+    } else if name.starts_with("block") {
+        name["block".len()..].parse().ok().filter(|bb| *bb != 0)
+    } else {
+        None
+    }
+}
+
+impl Parser
+{
+    fn new(text: &str, file_name: &str) -> CompileResult<Parser>
+    {
+        Ok(Parser{
+            tokens: tokenize(text, file_name)?,
+            idx: 0,
+            types: HashMap::new(),
+            var_counter: 0,
+        })
+    }
+
+    fn peek(&self) -> &TokenKind
+    {
+        &self.tokens[self.idx].kind
+    }
+
+    fn peek_at(&self, offset: usize) -> &TokenKind
+    {
+        let idx = (self.idx + offset).min(self.tokens.len() - 1);
+        &self.tokens[idx].kind
+    }
+
+    fn span(&self) -> Span
+    {
+        self.tokens[self.idx].span.clone()
+    }
+
+    fn pop(&mut self) -> Token
+    {
+        let tok = self.tokens[self.idx].clone();
+        if tok.kind != TokenKind::EOF {
+            self.idx += 1;
+        }
+        tok
+    }
+
+    fn unexpected<T>(&self, expecting: &str) -> CompileResult<T>
+    {
+        parse_error_result(&self.span(), format!("Unexpected {:?}, expecting {}", self.peek(), expecting))
+    }
+
+    fn is_punct(&self, c: char) -> bool
+    {
+        *self.peek() == TokenKind::Punct(c)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool
+    {
+        match *self.peek()
+        {
+            TokenKind::Name(ref n) => n == keyword,
+            _ => false,
+        }
+    }
+
+    fn expect_punct(&mut self, c: char) -> CompileResult<()>
+    {
+        if self.is_punct(c) {
+            self.pop();
+            Ok(())
+        } else {
+            self.unexpected(&format!("{}", c))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> CompileResult<()>
+    {
+        if self.is_keyword(keyword) {
+            self.pop();
+            Ok(())
+        } else {
+            self.unexpected(keyword)
+        }
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool
+    {
+        let found = self.is_punct(c);
+        if found {
+            self.pop();
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool
+    {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pop();
+        }
+        found
+    }
+
+    fn keyword(&mut self) -> CompileResult<String>
+    {
+        match *self.peek()
+        {
+            TokenKind::Name(_) => (),
+            _ => return self.unexpected("a keyword"),
+        }
+
+        match self.pop().kind
+        {
+            TokenKind::Name(n) => Ok(n),
+            _ => unreachable!(),
+        }
+    }
+
+    // A plain or quoted name
+    fn name(&mut self) -> CompileResult<String>
+    {
+        match *self.peek()
+        {
+            TokenKind::Name(_) | TokenKind::String(_) => (),
+            _ => return self.unexpected("a name"),
+        }
+
+        match self.pop().kind
+        {
+            TokenKind::Name(n) | TokenKind::String(n) => Ok(n),
+            _ => unreachable!(),
+        }
+    }
+
+    fn number<T: ::std::str::FromStr>(&mut self) -> CompileResult<T>
+    {
+        let span = self.span();
+        let text = match self.pop().kind
+        {
+            TokenKind::Number(n) | TokenKind::Name(n) => n,
+            _ => return parse_error_result(&span, "Expecting a number"),
+        };
+
+        text.parse().or_else(|_| parse_error_result(&span, format!("Invalid number {}", text)))
+    }
+
+    fn comma_separated<T, F>(&mut self, close: char, mut parse_item: F) -> CompileResult<Vec<T>>
+        where F: FnMut(&mut Parser) -> CompileResult<T>
+    {
+        let mut items = Vec::new();
+        while !self.is_punct(close) {
+            if !items.is_empty() {
+                self.expect_punct(',')?;
+            }
+            items.push(parse_item(self)?);
+        }
+        self.expect_punct(close)?;
+        Ok(items)
+    }
+
+    fn parse_type(&mut self) -> CompileResult<Type>
+    {
+        if self.eat_punct('*') {
+            return Ok(ptr_type(self.parse_type()?));
+        }
+
+        if self.eat_punct('?') {
+            return Ok(optional_type(self.parse_type()?));
+        }
+
+        if self.eat_punct('[') {
+            if self.eat_punct(']') {
+                return Ok(slice_type(self.parse_type()?));
+            }
+
+            let len = self.number()?;
+            self.expect_punct(']')?;
+            let element_type = self.parse_type()?;
+            return Ok(Type::Array(Rc::new(ArrayType{element_type, length: ArrayLength::Known(len)})));
+        }
+
+        if self.eat_punct('%') {
+            let span = self.span();
+            let name = self.name()?;
+            return match self.types.get(&name)
+            {
+                Some(typ) => Ok(typ.clone()),
+                None => parse_error_result(&span, format!("Unknown type %{}", name)),
+            };
+        }
+
+        let span = self.span();
+        let keyword = self.keyword()?;
+        let typ = match keyword.as_str()
+        {
+            "void" => Type::Void,
+            "unknown" => Type::Unknown,
+            "char" => Type::Char,
+            "bool" => Type::Bool,
+            "string" => Type::String,
+            "Self" => Type::SelfType,
+            "fn" => self.parse_func_type(CallingConvention::Nomad)?,
+            "extern" => {
+                self.expect_keyword("fn")?;
+                self.parse_func_type(CallingConvention::C)?
+            },
+            "struct" | "sum" | "enum" => self.parse_type_definition(&keyword)?,
+            "unresolved" => {
+                let name = self.name()?;
+                Type::Unresolved(Rc::new(UnresolvedType{name, generic_args: Vec::new()}))
+            },
+            k if k.starts_with('i') && int_size(&k[1..]).is_some() => Type::Int(int_size(&k[1..]).unwrap_or(IntSize::I32)),
+            k if k.starts_with('u') && int_size(&k[1..]).is_some() => Type::UInt(int_size(&k[1..]).unwrap_or(IntSize::I32)),
+            k if k.starts_with('f') && float_size(&k[1..]).is_some() => Type::Float(float_size(&k[1..]).unwrap_or(FloatSize::F64)),
+            _ => return parse_error_result(&span, format!("Unknown type {}", keyword)),
+        };
+
+        Ok(typ)
+    }
+
+    fn parse_func_type(&mut self, calling_convention: CallingConvention) -> CompileResult<Type>
+    {
+        self.expect_punct('(')?;
+        let mut variadic = false;
+        let args = self.comma_separated(')', |p| {
+            if *p.peek() == TokenKind::Ellipsis {
+                p.pop();
+                variadic = true;
+                Ok(None)
+            } else {
+                p.parse_type().map(Some)
+            }
+        })?;
+
+        match *self.peek()
+        {
+            TokenKind::Arrow => {self.pop();},
+            _ => return self.unexpected("->"),
+        }
+
+        let return_type = self.parse_type()?;
+        Ok(Type::Func(Rc::new(FuncType{
+            args: args.into_iter().filter_map(|a| a).collect(),
+            return_type,
+            variadic,
+            calling_convention,
+        })))
+    }
+
+    // The definition of a struct, sum or enum type, after the keyword
+    fn parse_type_definition(&mut self, keyword: &str) -> CompileResult<Type>
+    {
+        let name = if keyword == "struct" && self.is_punct('{') {
+            String::new()
+        } else {
+            self.name()?
+        };
+
+        self.expect_punct('{')?;
+        let typ = match keyword
+        {
+            "struct" => {
+                let members = self.comma_separated('}', |p| {
+                    let name = p.name()?;
+                    p.expect_punct(':')?;
+                    Ok(StructMember{name, typ: p.parse_type()?})
+                })?;
+                Type::Struct(Rc::new(StructType{name, members}))
+            },
+            "sum" => {
+                let cases = self.comma_separated('}', |p| {
+                    let name = p.name()?;
+                    p.expect_punct(':')?;
+                    Ok(SumTypeCase{name, typ: p.parse_type()?})
+                })?;
+                Type::Sum(Rc::new(SumType{name, cases, result: false}))
+            },
+            _ => {
+                let cases = self.comma_separated('}', |p| p.name())?;
+                Type::Enum(Rc::new(EnumType{name, cases}))
+            },
+        };
+
+        Ok(typ)
+    }
+
+    fn parse_var(&mut self) -> CompileResult<Var>
+    {
+        self.expect_punct('(')?;
+        let name = self.name()?;
+        self.expect_punct(':')?;
+        let typ = self.parse_type()?;
+        self.expect_punct(')')?;
+
+        // Keep track of the temporaries, so new ones get a different name
+        if name.starts_with("$var") {
+            if let Ok(idx) = name["$var".len()..].parse::<usize>() {
+                self.var_counter = self.var_counter.max(idx + 1);
+            }
+        }
+
+        Ok(Var{name, typ})
+    }
+
+    fn parse_constant(&mut self) -> CompileResult<Constant>
+    {
+        if self.eat_punct('[') {
+            let members = self.comma_separated(']', |p| p.parse_constant())?;
+            return Ok(Constant::Array(members));
+        }
+
+        self.expect_punct('(')?;
+        let span = self.span();
+        let keyword = self.keyword()?;
+        let c = match keyword.as_str()
+        {
+            "bool" => match self.keyword()?.as_str()
+            {
+                "true" => Constant::Bool(true),
+                "false" => Constant::Bool(false),
+                _ => return parse_error_result(&span, "Expecting true or false"),
+            },
+            "char" => match self.pop().kind
+            {
+                TokenKind::Char(c) => Constant::Char(c),
+                _ => return parse_error_result(&span, "Expecting a character literal"),
+            },
+            "string" => match self.pop().kind
+            {
+                TokenKind::String(s) => Constant::String(s),
+                _ => return parse_error_result(&span, "Expecting a string literal"),
+            },
+            "null" => Constant::NullPtr(self.parse_type()?),
+            "struct" => {
+                let typ = self.parse_type()?;
+                self.expect_punct('{')?;
+                let members = self.comma_separated('}', |p| p.parse_constant())?;
+                Constant::Struct(typ, members)
+            },
+            k if k.starts_with('i') && int_size(&k[1..]).is_some() =>
+                Constant::Int(self.number()?, int_size(&k[1..]).unwrap_or(IntSize::I32)),
+            k if k.starts_with('u') && int_size(&k[1..]).is_some() =>
+                Constant::UInt(self.number()?, int_size(&k[1..]).unwrap_or(IntSize::I32)),
+            k if k.starts_with('f') && float_size(&k[1..]).is_some() =>
+                Constant::Float(self.number()?, float_size(&k[1..]).unwrap_or(FloatSize::F64)),
+            _ => return parse_error_result(&span, format!("Unknown constant type {}", keyword)),
+        };
+
+        self.expect_punct(')')?;
+        Ok(c)
+    }
+
+    fn parse_operand(&mut self) -> CompileResult<Operand>
+    {
+        if self.eat_punct('&') {
+            return Ok(Operand::AddressOf(self.parse_var()?));
+        }
+
+        if self.eat_punct('*') {
+            return Ok(Operand::Dereference(self.parse_var()?));
+        }
+
+        if self.is_punct('(') && *self.peek_at(2) == TokenKind::Punct(':') {
+            return Ok(Operand::Var(self.parse_var()?));
+        }
+
+        if self.is_punct('(') && *self.peek_at(1) == TokenKind::Name("func".into()) {
+            self.pop();
+            self.pop();
+            let name = self.name()?;
+            self.expect_punct(')')?;
+            return Ok(Operand::Func(name));
+        }
+
+        if self.is_punct('(') && *self.peek_at(1) == TokenKind::Name("sizeof".into()) {
+            self.pop();
+            self.pop();
+            let typ = self.parse_type()?;
+            self.expect_punct(')')?;
+            return Ok(Operand::SizeOf(typ));
+        }
+
+        Ok(Operand::Const(self.parse_constant()?))
+    }
+
+    fn parse_block_ref(&mut self) -> CompileResult<BasicBlockRef>
+    {
+        let span = self.span();
+        let name = self.keyword()?;
+        match block_ref(&name)
+        {
+            Some(bb) => Ok(bb),
+            None => parse_error_result(&span, format!("Invalid block name {}", name)),
+        }
+    }
+
+    fn parse_pos(&mut self) -> CompileResult<Pos>
+    {
+        let line = self.number()?;
+        self.expect_punct(':')?;
+        let offset = self.number()?;
+        Ok(Pos::new(line, offset))
+    }
+
+    fn parse_span(&mut self) -> CompileResult<Span>
+    {
+        self.expect_keyword("at")?;
+        let file = match *self.peek()
+        {
+            TokenKind::String(ref file) => file.clone(),
+            _ => return self.unexpected("a file name"),
+        };
+        self.pop();
+
+        let start = self.parse_pos()?;
+        let end = self.parse_pos()?;
+        Ok(Span::new(&file, start, end))
+    }
+
+    fn parse_property(&mut self) -> CompileResult<ByteCodeProperty>
+    {
+        let span = self.span();
+        match self.keyword()?.as_str()
+        {
+            "len" => Ok(ByteCodeProperty::Len),
+            "data" => Ok(ByteCodeProperty::Data),
+            "sum_type_index" => Ok(ByteCodeProperty::SumTypeIndex),
+            p => parse_error_result(&span, format!("Unknown property {}", p)),
+        }
+    }
+
+    fn parse_binary_operator(&mut self) -> CompileResult<BinaryOperator>
+    {
+        let span = self.span();
+        let name = self.keyword()?;
+        binary_operator(&name).map_or_else(|| parse_error_result(&span, format!("Unknown operator {}", name)), Ok)
+    }
+
+    fn parse_instruction(&mut self) -> CompileResult<Instruction>
+    {
+        let span = self.span();
+        let keyword = self.keyword()?;
+        let instr = match keyword.as_str()
+        {
+            "store" => Instruction::Store{dst: self.parse_var()?, src: self.parse_operand()?},
+            "load" => Instruction::Load{dst: self.parse_var()?, ptr: self.parse_var()?},
+            "loadm" => Instruction::LoadMember{dst: self.parse_var()?, obj: self.parse_var()?, member_index: self.parse_operand()?},
+            "storem" => Instruction::StoreMember{obj: self.parse_var()?, member_index: self.parse_operand()?, src: self.parse_operand()?},
+            "addr" => Instruction::AddressOf{dst: self.parse_var()?, obj: self.parse_var()?},
+            "addrm" => Instruction::AddressOfMember{dst: self.parse_var()?, obj: self.parse_var()?, member_index: self.parse_operand()?},
+            "getp" => Instruction::GetProperty{dst: self.parse_var()?, obj: self.parse_var()?, prop: self.parse_property()?},
+            "setp" => Instruction::SetProperty{obj: self.parse_var()?, prop: self.parse_property()?, val: self.number()?},
+            "uop" => {
+                let dst = self.parse_var()?;
+                let op_span = self.span();
+                let op = match self.keyword()?.as_str()
+                {
+                    "neg" => UnaryOperator::Sub,
+                    "not" => UnaryOperator::Not,
+                    op => return parse_error_result(&op_span, format!("Unknown operator {}", op)),
+                };
+                Instruction::UnaryOp{dst, op, src: self.parse_operand()?}
+            },
+            "bop" => Instruction::BinaryOp{
+                dst: self.parse_var()?,
+                op: self.parse_binary_operator()?,
+                left: self.parse_operand()?,
+                right: self.parse_operand()?,
+            },
+            "cbop" => Instruction::CheckedBinaryOp{
+                dst: self.parse_var()?,
+                op: self.parse_binary_operator()?,
+                left: self.parse_operand()?,
+                right: self.parse_operand()?,
+                span: self.parse_span()?,
+            },
+            "call" => {
+                let dst = if self.is_punct('(') {Some(self.parse_var()?)} else {None};
+                let func = self.name()?;
+                self.expect_punct('(')?;
+                let args = self.comma_separated(')', |p| p.parse_operand())?;
+                Instruction::Call{dst, func, args}
+            },
+            "slice" => Instruction::Slice{dst: self.parse_var()?, src: self.parse_var()?, start: self.parse_operand()?, len: self.parse_operand()?},
+            "mkslice" => Instruction::MakeSlice{dst: self.parse_var()?, data: self.parse_var()?, len: self.parse_var()?},
+            "cast" => Instruction::Cast{dst: self.parse_var()?, src: self.parse_operand()?},
+            "loadoptf" => Instruction::LoadOptionalFlag{dst: self.parse_var()?, obj: self.parse_var()?},
+            "storenil" => Instruction::StoreNil(self.parse_var()?),
+            "salloc" => Instruction::StackAlloc(self.parse_var()?),
+            "halloc" => Instruction::HeapAlloc(self.parse_var()?),
+            "scope" => {
+                let scope_span = self.span();
+                match self.keyword()?.as_str()
+                {
+                    "start" => Instruction::StartScope,
+                    "end" => Instruction::EndScope,
+                    _ => return parse_error_result(&scope_span, "Expecting start or end"),
+                }
+            },
+            "ret" => {
+                if self.eat_keyword("void") {
+                    Instruction::ReturnVoid
+                } else {
+                    Instruction::Return(self.parse_operand()?)
+                }
+            },
+            "br" => Instruction::Branch(self.parse_block_ref()?),
+            "brif" => Instruction::BranchIf{cond: self.parse_operand()?, on_true: self.parse_block_ref()?, on_false: self.parse_block_ref()?},
+            "delete" => Instruction::Delete(self.parse_var()?),
+            "bcheck" => Instruction::BoundsCheck{index: self.parse_operand()?, len: self.parse_operand()?, span: self.parse_span()?},
+            "rcheck" => Instruction::RangeCheck{seq: self.parse_var()?, start: self.parse_operand()?, end: self.parse_operand()?, span: self.parse_span()?},
+            "panic" => Instruction::Panic{msg: self.parse_var()?, span: self.parse_span()?},
+            "phi" => {
+                let dst = self.parse_var()?;
+                self.expect_punct('[')?;
+                let incoming = self.comma_separated(']', |p| {
+                    let bb = p.parse_block_ref()?;
+                    p.expect_punct(':')?;
+                    Ok((bb, p.parse_operand()?))
+                })?;
+                Instruction::Phi{dst, incoming}
+            },
+            _ => return parse_error_result(&span, format!("Unknown instruction {}", keyword)),
+        };
+
+        Ok(instr)
+    }
+
+    fn parse_argument(&mut self) -> CompileResult<Argument>
+    {
+        let mutable = self.eat_keyword("mut");
+        let name = self.name()?;
+        self.expect_punct(':')?;
+        let typ = self.parse_type()?;
+        Ok(Argument::new(name, typ, mutable, Span::default()))
+    }
+
+    fn parse_signature(&mut self) -> CompileResult<FunctionSignature>
+    {
+        self.expect_keyword("fn")?;
+        let name = self.name()?;
+        self.expect_punct('(')?;
+        let mut variadic = false;
+        let args = self.comma_separated(')', |p| {
+            if *p.peek() == TokenKind::Ellipsis {
+                p.pop();
+                variadic = true;
+                Ok(None)
+            } else {
+                p.parse_argument().map(Some)
+            }
+        })?;
+
+        match *self.peek()
+        {
+            TokenKind::Arrow => {self.pop();},
+            _ => return self.unexpected("->"),
+        }
+
+        let return_type = self.parse_type()?;
+        let calling_convention = if self.eat_keyword("abi") {
+            self.expect_keyword("C")?;
+            CallingConvention::C
+        } else {
+            CallingConvention::Nomad
+        };
+        let inline_hint = if self.eat_keyword("inline") {
+            InlineHint::Always
+        } else if self.eat_keyword("noinline") {
+            InlineHint::Never
+        } else {
+            InlineHint::Auto
+        };
+
+        let mut sig = FunctionSignature{
+            name,
+            return_type,
+            args: args.into_iter().filter_map(|a| a).collect(),
+            span: Span::default(),
+            typ: Type::Unknown,
+            variadic,
+            calling_convention,
+            inline_hint,
+        };
+        sig.typ = sig.get_type();
+        Ok(sig)
+    }
+
+    // A function, returns whether it is imported
+    fn parse_function(&mut self) -> CompileResult<(ByteCodeFunction, bool)>
+    {
+        let imported = self.eat_keyword("import");
+        let external = self.eat_keyword("extern");
+        let sig = self.parse_signature()?;
+        let mut blocks = BTreeMap::new();
+        self.var_counter = 0;
+
+        if self.eat_punct('{') {
+            while !self.eat_punct('}') {
+                let span = self.span();
+                let bb_ref = self.parse_block_ref()?;
+                self.expect_punct(':')?;
+                if blocks.contains_key(&bb_ref) {
+                    return parse_error_result(&span, format!("Block {} is defined twice", bb_name(bb_ref)));
+                }
+
+                let mut block = BasicBlock::new(bb_name(bb_ref));
+                while !self.is_punct('}') && !self.is_block_label() {
+                    if *self.peek() == TokenKind::EOF {
+                        return self.unexpected("}");
+                    }
+                    let instr = self.parse_instruction()?;
+                    block.instructions.push(instr);
+                }
+                blocks.insert(bb_ref, block);
+            }
+        }
+
+        Ok((ByteCodeFunction::from_blocks(&sig, external, blocks, self.var_counter), imported))
+    }
+
+    fn is_block_label(&self) -> bool
+    {
+        match *self.peek()
+        {
+            TokenKind::Name(ref n) => block_ref(n).is_some() && *self.peek_at(1) == TokenKind::Punct(':'),
+            _ => false,
+        }
+    }
+
+    fn parse_type_declaration(&mut self) -> CompileResult<()>
+    {
+        let span = self.span();
+        let keyword = self.keyword()?;
+        if keyword != "struct" && keyword != "sum" && keyword != "enum" {
+            return parse_error_result(&span, format!("Expecting struct, sum or enum, not {}", keyword));
+        }
+
+        let typ = self.parse_type_definition(&keyword)?;
+        let name = match typ
+        {
+            Type::Struct(ref st) => st.name.clone(),
+            Type::Sum(ref st) => st.name.clone(),
+            Type::Enum(ref et) => et.name.clone(),
+            _ => String::new(),
+        };
+
+        if name.is_empty() {
+            return parse_error_result(&span, "Declared types must have a name");
+        }
+
+        if self.types.insert(name.clone(), typ).is_some() {
+            return parse_error_result(&span, format!("Type {} is declared twice", name));
+        }
+        Ok(())
+    }
+
+    fn parse_module(&mut self) -> CompileResult<ByteCodeModule>
+    {
+        self.expect_keyword("module")?;
+        let name = self.name()?;
+        let mut options = ByteCodeOptions::default();
+        if self.eat_keyword("options") {
+            loop {
+                if self.eat_keyword("checked_arithmetic") {
+                    options.checked_arithmetic = true;
+                } else if self.eat_keyword("bounds_checks") {
+                    options.bounds_checks = true;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        let mut module = ByteCodeModule{
+            name,
+            options,
+            functions: BTreeMap::new(),
+            imported_functions: Vec::new(),
+            globals: BTreeMap::new(),
+            destructors: BTreeSet::new(),
+        };
+
+        loop {
+            let span = self.span();
+            if *self.peek() == TokenKind::EOF {
+                break;
+            } else if self.eat_keyword("type") {
+                self.parse_type_declaration()?;
+            } else if self.eat_keyword("global") {
+                let name = self.name()?;
+                self.expect_punct('=')?;
+                let value = self.parse_constant()?;
+                module.globals.insert(name, value);
+            } else if self.eat_keyword("destructor") {
+                let name = self.name()?;
+                module.destructors.insert(name);
+            } else {
+                let (func, imported) = self.parse_function()?;
+                if imported {
+                    module.imported_functions.push(func);
+                } else if module.functions.contains_key(&func.sig.name) {
+                    return parse_error_result(&span, format!("Function {} is defined twice", func.sig.name));
+                } else {
+                    module.functions.insert(func.sig.name.clone(), func);
+                }
+            }
+        }
+
+        Ok(module)
+    }
+}
+
+// Parse a module in the textual bytecode format, file_name is used in error messages
+pub fn parse_module(text: &str, file_name: &str) -> CompileResult<ByteCodeModule>
+{
+    let mut parser = Parser::new(text, file_name)?;
+    parser.parse_module()
+}
+
+// Parse a single function, which can only use types written out in full
+#[cfg(test)]
+pub fn parse_function(text: &str, file_name: &str) -> CompileResult<ByteCodeFunction>
+{
+    let mut parser = Parser::new(text, file_name)?;
+    let (func, _) = parser.parse_function()?;
+    if *parser.peek() != TokenKind::EOF {
+        return parser.unexpected("end of file");
+    }
+    Ok(func)
+}
@@ -0,0 +1,142 @@
+use ast::{Type, IntSize, BinaryOperator};
+use bytecode::{ByteCodeOptions, Instruction, Operand, Constant, parse_module, parse_function};
+use bytecode::test::{Test, generate_byte_code_with_options, load_tests};
+
+fn round_trip(test: &Test) -> Result<(), String>
+{
+    let options = ByteCodeOptions{checked_arithmetic: true, bounds_checks: true, verify: true, dump_escape_analysis: false};
+    let bc_mod = generate_byte_code_with_options(&test.code, options, false).map_err(|e| format!("Compile error: {}", e))?;
+    let text = format!("{}", bc_mod);
+    let parsed = parse_module(&text, "test.bc").map_err(|e| format!("Parse error: {}\n{}", e, text))?;
+    let reprinted = format!("{}", parsed);
+    if text != reprinted {
+        return Err(format!("Printed modules differ:\n{}\n-----------------\n{}", text, reprinted));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_round_trip()
+{
+    let mut failures = 0;
+    // Programs which link C code or import std can't be compiled in isolation
+    for test in load_tests().iter().filter(|t| t.is_standalone()) {
+        if let Err(e) = round_trip(test) {
+            println!("{}: {}", test.path.display(), e);
+            failures += 1;
+        }
+    }
+
+    assert_eq!(failures, 0);
+}
+
+#[test]
+fn test_parse_function()
+{
+    let func = parse_function(r#"
+fn "test::add"(a: i32, mut b: i32) -> i32 {
+entry:
+  bop ($var0: i32) add (a: i32) (b: i32)
+  brif (bool true) block1 block2
+block1:
+  ret ($var0: i32)
+block2:
+  ret (i32 -1)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+    assert_eq!(func.sig.name, "test::add");
+    assert_eq!(func.sig.args.len(), 2);
+    assert!(func.sig.args[1].mutable);
+    assert_eq!(func.sig.return_type, Type::Int(IntSize::I32));
+    assert_eq!(func.blocks.len(), 3);
+
+    let entry = &func.blocks[&0];
+    match entry.instructions[0]
+    {
+        Instruction::BinaryOp{ref dst, op: BinaryOperator::Add, ..} => assert_eq!(dst.name, "$var0"),
+        ref i => panic!("Unexpected instruction {}", i),
+    }
+
+    match func.blocks[&2].instructions[0]
+    {
+        Instruction::Return(Operand::Const(Constant::Int(-1, IntSize::I32))) => (),
+        ref i => panic!("Unexpected instruction {}", i),
+    }
+}
+
+#[test]
+fn test_parse_module_with_types()
+{
+    let text = r#"module test
+options bounds_checks
+type struct test::Point {x: i32, y: i32}
+global test::corner = (struct %test::Point {(i32 1), (i32 2)})
+global test::origin = [(i32 0), (i32 0)]
+destructor "test::~Point"
+
+fn test::main() -> i32 {
+entry:
+  salloc (p: %test::Point)
+  storem (p: %test::Point) (u32 0) (i32 4)
+  loadm ($var3: i32) (p: %test::Point) (u32 0)
+  ret ($var3: i32)
+}
+
+import extern fn printf(format: *char, ...) -> i32 abi C
+"#;
+
+    let module = parse_module(text, "test.bc").expect("Parsing succeeded");
+    assert!(module.options.bounds_checks);
+    assert!(!module.options.checked_arithmetic);
+    assert!(module.globals.contains_key("test::origin"));
+    match module.globals["test::corner"]
+    {
+        Constant::Struct(Type::Struct(ref st), ref members) => {
+            assert_eq!(st.name, "test::Point");
+            assert_eq!(members.len(), 2);
+        },
+        ref c => panic!("Unexpected constant {}", c),
+    }
+    assert!(module.destructors.contains("test::~Point"));
+    assert_eq!(module.imported_functions.len(), 1);
+    assert!(module.imported_functions[0].sig.variadic);
+    assert_eq!(format!("{}", module), text);
+}
+
+#[test]
+fn test_parse_phi()
+{
+    let text = r#"fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  brif (b: bool) block1 block2
+block1:
+  store ("x.1": i32) (a: i32)
+  br block2
+block2:
+  phi ("x.2": i32) [entry: (i32 0), block1: ("x.1": i32)]
+  ret ("x.2": i32)
+}
+"#;
+
+    let func = parse_function(text, "test.bc").expect("Parsing succeeded");
+    match func.blocks[&2].instructions[0]
+    {
+        Instruction::Phi{ref dst, ref incoming} => {
+            assert_eq!(dst.name, "x.2");
+            assert_eq!(incoming.len(), 2);
+            assert_eq!(incoming[1].0, 1);
+        },
+        ref i => panic!("Unexpected instruction {}", i),
+    }
+    assert_eq!(format!("{}", func), text);
+}
+
+#[test]
+fn test_parse_errors()
+{
+    assert!(parse_function("fn foo() -> i32 {\nentry:\n  frobnicate (a: i32)\n}\n", "test.bc").is_err());
+    assert!(parse_function("fn foo() -> i32 {\nentry:\n  ret (a: %Unknown)\n}\n", "test.bc").is_err());
+    assert!(parse_function("fn foo() -> i32 {\nentry:\n  ret (i32 1)\nentry:\n  ret (i32 2)\n}\n", "test.bc").is_err());
+    assert!(parse_module("fn foo() -> void\n", "test.bc").is_err());
+}
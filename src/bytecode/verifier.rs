@@ -0,0 +1,680 @@
+/*
+    Consistency checks on bytecode, so bugs in the bytecode compiler and the optimizer are reported
+    where they happen, instead of as an LLVM verification failure or a crash in one of the backends.
+*/
+use std::collections::{HashMap, HashSet};
+use ast::{Type, BinaryOperator, ptr_type};
+use compileerror::{CompileResult, CompileError};
+use bytecode::{ByteCodeModule, ByteCodeFunction, BasicBlockRef, ControlFlowGraph, Instruction, Operand, Constant, Var, bb_name};
+
+// The type of an operand, without panicking on malformed operands
+fn operand_type(op: &Operand) -> Type
+{
+    match *op
+    {
+        Operand::Var(ref v) => v.typ.clone(),
+        Operand::AddressOf(ref v) => ptr_type(v.typ.clone()),
+        Operand::Dereference(ref v) => v.typ.get_pointer_element_type().cloned().unwrap_or(Type::Unknown),
+        Operand::Const(Constant::Array(ref members)) if members.is_empty() => Type::Unknown,
+        Operand::Const(ref c) => c.get_type(),
+        Operand::Func(_) | Operand::SizeOf(_) => Type::Unknown,
+    }
+}
+
+// Whether values of type a and b can be mixed, enums are unsigned integers
+fn compatible(a: &Type, b: &Type) -> bool
+{
+    match (a, b)
+    {
+        (&Type::Unknown, _) | (_, &Type::Unknown) => true,
+        (&Type::Enum(_), &Type::UInt(_)) | (&Type::UInt(_), &Type::Enum(_)) => true,
+        (&Type::Pointer(ref pa), &Type::Pointer(ref pb)) => {
+            **pa == Type::Void || **pb == Type::Void || compatible(pa, pb)
+        },
+        _ => a == b,
+    }
+}
+
+// Whether a value of type src can be stored in a variable of type dst
+fn assignable(dst: &Type, src: &Type) -> bool
+{
+    if compatible(dst, src) || dst.is_pointer_to(src) || src.is_pointer_to(dst) {
+        return true;
+    }
+
+    match *dst
+    {
+        Type::Optional(ref inner) => assignable(inner, src),
+        _ => false,
+    }
+}
+
+// Whether the backends can apply op to operands of type typ
+fn supports_operator(op: BinaryOperator, typ: &Type) -> bool
+{
+    match op
+    {
+        BinaryOperator::Add => typ.is_numeric() || *typ == Type::String,
+        BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div => typ.is_numeric(),
+        BinaryOperator::Mod |
+        BinaryOperator::WrappingAdd |
+        BinaryOperator::WrappingSub |
+        BinaryOperator::WrappingMul |
+        BinaryOperator::SaturatingAdd |
+        BinaryOperator::SaturatingSub |
+        BinaryOperator::SaturatingMul => typ.is_integer(),
+        BinaryOperator::LessThan |
+        BinaryOperator::GreaterThan |
+        BinaryOperator::LessThanEquals |
+        BinaryOperator::GreaterThanEquals => typ.is_numeric() || *typ == Type::Char || *typ == Type::String,
+        BinaryOperator::Equals | BinaryOperator::NotEquals => match *typ
+        {
+            Type::Char | Type::Bool | Type::String | Type::Enum(_) => true,
+            _ => typ.is_numeric(),
+        },
+        BinaryOperator::And | BinaryOperator::Or => *typ == Type::Bool,
+        BinaryOperator::Dot | BinaryOperator::As => false,
+    }
+}
+
+fn is_comparison(op: BinaryOperator) -> bool
+{
+    match op
+    {
+        BinaryOperator::LessThan |
+        BinaryOperator::GreaterThan |
+        BinaryOperator::LessThanEquals |
+        BinaryOperator::GreaterThanEquals |
+        BinaryOperator::Equals |
+        BinaryOperator::NotEquals => true,
+        _ => false,
+    }
+}
+
+fn const_index(op: &Operand) -> Option<u64>
+{
+    match *op
+    {
+        Operand::Const(Constant::UInt(v, _)) => Some(v),
+        Operand::Const(Constant::Int(v, _)) if v >= 0 => Some(v as u64),
+        _ => None,
+    }
+}
+
+// The type of the object a variable holds, following pointers
+fn object_type(var: &Var) -> &Type
+{
+    let mut typ = &var.typ;
+    while let Type::Pointer(ref inner) = *typ {
+        typ = &**inner;
+    }
+    typ
+}
+
+fn operand_vars<'a>(op: &'a Operand, uses: &mut Vec<&'a Var>, defs: &mut Vec<&'a Var>)
+{
+    match *op
+    {
+        Operand::Var(ref v) | Operand::Dereference(ref v) => uses.push(v),
+        // Taking the address of a var can be used to initialize it
+        Operand::AddressOf(ref v) => defs.push(v),
+        _ => (),
+    }
+}
+
+// Writing into an object through a pointer reads the pointer
+fn object_var<'a>(obj: &'a Var, uses: &mut Vec<&'a Var>, defs: &mut Vec<&'a Var>)
+{
+    if obj.typ.is_pointer() {
+        uses.push(obj);
+    } else {
+        defs.push(obj);
+    }
+}
+
+// Vars an instruction reads, and the vars it (partially) assigns a value to
+fn uses_and_defs(instr: &Instruction) -> (Vec<&Var>, Vec<&Var>)
+{
+    let mut uses = Vec::new();
+    let mut defs = Vec::new();
+    for op in instr.operands() {
+        operand_vars(op, &mut uses, &mut defs);
+    }
+
+    match *instr
+    {
+        Instruction::Store{ref dst, ref src} => {
+            if dst.typ.is_pointer_to(&operand_type(src)) {
+                uses.push(dst);
+            } else {
+                defs.push(dst);
+            }
+        },
+        Instruction::StoreMember{ref obj, ..} |
+        Instruction::SetProperty{ref obj, ..} => object_var(obj, &mut uses, &mut defs),
+        Instruction::AddressOfMember{ref dst, ref obj, ..} => {
+            object_var(obj, &mut uses, &mut defs);
+            defs.push(dst);
+        },
+        Instruction::AddressOf{ref dst, ref obj} => {
+            defs.push(obj);
+            defs.push(dst);
+        },
+        Instruction::Load{ref dst, ptr: ref obj} |
+        Instruction::LoadMember{ref dst, ref obj, ..} |
+        Instruction::GetProperty{ref dst, ref obj, ..} |
+        Instruction::Slice{ref dst, src: ref obj, ..} |
+        Instruction::LoadOptionalFlag{ref dst, ref obj} => {
+            uses.push(obj);
+            defs.push(dst);
+        },
+        Instruction::MakeSlice{ref dst, ref data, ref len} => {
+            uses.push(data);
+            uses.push(len);
+            defs.push(dst);
+        },
+        Instruction::UnaryOp{ref dst, ..} |
+        Instruction::Cast{ref dst, ..} |
+        Instruction::BinaryOp{ref dst, ..} |
+        Instruction::CheckedBinaryOp{ref dst, ..} |
+        Instruction::Call{dst: Some(ref dst), ..} |
+        Instruction::StoreNil(ref dst) |
+        Instruction::StackAlloc(ref dst) |
+        Instruction::HeapAlloc(ref dst) |
+        Instruction::Phi{ref dst, ..} => defs.push(dst),
+        Instruction::Delete(ref v) |
+        Instruction::RangeCheck{seq: ref v, ..} |
+        Instruction::Panic{msg: ref v, ..} => uses.push(v),
+        _ => (),
+    }
+    (uses, defs)
+}
+
+fn add_defs<'a>(instr: &'a Instruction, defined: &mut HashSet<&'a str>)
+{
+    let (_, defs) = uses_and_defs(instr);
+    defined.extend(defs.into_iter().map(|v| v.name.as_str()));
+}
+
+// Intersection of the vars defined at the end of all analyzed predecessors
+fn defined_at_start<'a>(
+    bb_ref: BasicBlockRef,
+    entry: BasicBlockRef,
+    cfg: &ControlFlowGraph,
+    defined_at_end: &HashMap<BasicBlockRef, HashSet<&'a str>>) -> HashSet<&'a str>
+{
+    let mut result: Option<HashSet<&'a str>> = if bb_ref == entry {Some(HashSet::new())} else {None};
+    for pred_defs in cfg.predecessors(bb_ref).iter().filter_map(|p| defined_at_end.get(p)) {
+        result = Some(match result
+        {
+            Some(r) => r.intersection(pred_defs).cloned().collect(),
+            None => pred_defs.clone(),
+        });
+    }
+    result.unwrap_or_else(HashSet::new)
+}
+
+struct Verifier<'a>
+{
+    module: &'a ByteCodeModule,
+    func: &'a ByteCodeFunction,
+    cfg: ControlFlowGraph,
+    errors: Vec<String>,
+}
+
+impl<'a> Verifier<'a>
+{
+    fn error(&mut self, bb_ref: BasicBlockRef, msg: String)
+    {
+        self.errors.push(format!("{} {}: {}", self.func.sig.name, bb_name(bb_ref), msg));
+    }
+
+    fn check_terminators(&mut self)
+    {
+        let func = self.func;
+        for (bb_ref, bb) in &func.blocks {
+            match bb.terminator()
+            {
+                Some(_) => {
+                    let count = bb.instructions.iter().filter(|i| i.is_terminator()).count();
+                    if count > 1 {
+                        self.error(*bb_ref, format!("block has {} terminators", count));
+                    }
+                },
+                None => self.error(*bb_ref, "block does not end in a terminator".into()),
+            }
+
+            for target in bb.successors() {
+                if !func.blocks.contains_key(&target) {
+                    self.error(*bb_ref, format!("branch to non existing block {}", bb_name(target)));
+                }
+            }
+        }
+    }
+
+    // Backends handle scopes in block order, so they must be balanced in that order
+    fn check_scopes(&mut self)
+    {
+        let func = self.func;
+        let mut depth = 0usize;
+        for (bb_ref, bb) in &func.blocks {
+            for instr in &bb.instructions {
+                match *instr
+                {
+                    Instruction::StartScope => depth += 1,
+                    Instruction::EndScope if depth == 0 => self.error(*bb_ref, "scope end without a scope start".into()),
+                    Instruction::EndScope => depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+
+        if depth > 0 {
+            let last = func.blocks.keys().next_back().cloned().unwrap_or(0);
+            self.error(last, format!("{} scopes are never ended", depth));
+        }
+    }
+
+    fn check_member_access(&mut self, bb_ref: BasicBlockRef, obj: &Var, index: &Operand) -> Option<Type>
+    {
+        let idx = const_index(index);
+        let index_type = operand_type(index);
+        match *object_type(obj)
+        {
+            Type::Struct(ref st) => match idx
+            {
+                Some(i) if (i as usize) < st.members.len() => return Some(st.members[i as usize].typ.clone()),
+                Some(i) => self.error(bb_ref, format!("member index {} out of range, {} has {} members", i, obj, st.members.len())),
+                None => self.error(bb_ref, format!("member index {} of {} must be a constant", index, obj)),
+            },
+            Type::Sum(ref st) => match idx
+            {
+                Some(i) if (i as usize) < st.cases.len() => return Some(st.cases[i as usize].typ.clone()),
+                Some(i) => self.error(bb_ref, format!("case index {} out of range, {} has {} cases", i, obj, st.cases.len())),
+                None => self.error(bb_ref, format!("case index {} of {} must be a constant", index, obj)),
+            },
+            Type::Array(ref at) => match idx
+            {
+                Some(i) if (i as usize) >= at.len() => self.error(bb_ref, format!("index {} out of range, {} has {} elements", i, obj, at.len())),
+                _ if !index_type.is_integer() && index_type != Type::Unknown => self.error(bb_ref, format!("index {} of {} is not an integer", index, obj)),
+                _ => return Some(at.element_type.clone()),
+            },
+            Type::Slice(ref st) => {
+                if !index_type.is_integer() && index_type != Type::Unknown {
+                    self.error(bb_ref, format!("index {} of {} is not an integer", index, obj));
+                }
+                return Some(st.element_type.clone());
+            },
+            Type::String => {
+                if !index_type.is_integer() && index_type != Type::Unknown {
+                    self.error(bb_ref, format!("index {} of {} is not an integer", index, obj));
+                }
+            },
+            _ => self.error(bb_ref, format!("{} has no members", obj)),
+        }
+        None
+    }
+
+    fn check_instruction_types(&mut self, bb_ref: BasicBlockRef, instr: &Instruction)
+    {
+        match *instr
+        {
+            Instruction::Store{ref dst, ref src} => {
+                let src_type = operand_type(src);
+                if !assignable(&dst.typ, &src_type) {
+                    self.error(bb_ref, format!("cannot store a value of type {} in {}", src_type, dst));
+                }
+            },
+
+            Instruction::LoadMember{ref dst, ref obj, ref member_index} => {
+                if let Some(member_type) = self.check_member_access(bb_ref, obj, member_index) {
+                    if !compatible(&dst.typ, &member_type) && !dst.typ.is_pointer_to(&member_type) {
+                        self.error(bb_ref, format!("cannot load a member of type {} in {}", member_type, dst));
+                    }
+                }
+            },
+
+            Instruction::StoreMember{ref obj, ref member_index, ref src} => {
+                if let Some(member_type) = self.check_member_access(bb_ref, obj, member_index) {
+                    let src_type = operand_type(src);
+                    if !assignable(&member_type, &src_type) {
+                        self.error(bb_ref, format!("cannot store a value of type {} in a member of type {}", src_type, member_type));
+                    }
+                }
+            },
+
+            Instruction::AddressOfMember{ref obj, ref member_index, ..} => {
+                self.check_member_access(bb_ref, obj, member_index);
+            },
+
+            Instruction::BinaryOp{ref dst, op, ref left, ref right} |
+            Instruction::CheckedBinaryOp{ref dst, op, ref left, ref right, ..} => {
+                let left_type = operand_type(left);
+                let right_type = operand_type(right);
+                if !compatible(&left_type, &right_type) {
+                    self.error(bb_ref, format!("operands of {} have different types: {} and {}", op, left_type, right_type));
+                } else if left_type != Type::Unknown && !supports_operator(op, &left_type) {
+                    self.error(bb_ref, format!("operator {} is not supported on {}", op, left_type));
+                } else if is_comparison(op) && dst.typ != Type::Bool {
+                    self.error(bb_ref, format!("the result of {} must be stored in a bool, not in {}", op, dst));
+                } else if !is_comparison(op) && !compatible(&dst.typ, &left_type) {
+                    self.error(bb_ref, format!("the result of {} on {} cannot be stored in {}", op, left_type, dst));
+                }
+            },
+
+            Instruction::BranchIf{ref cond, ..} => {
+                let cond_type = operand_type(cond);
+                if cond_type != Type::Bool {
+                    self.error(bb_ref, format!("branch condition {} is not a bool", cond));
+                }
+            },
+
+            _ => (),
+        }
+
+        for op in instr.operands() {
+            if let Operand::Dereference(ref v) = *op {
+                if !v.typ.is_pointer() {
+                    self.error(bb_ref, format!("dereference of {}, which is not a pointer", v));
+                }
+            }
+        }
+    }
+
+    // Globals, functions and arguments are defined on entry
+    fn defined_on_entry(&self, name: &str) -> bool
+    {
+        name.contains("::") ||
+            self.module.globals.contains_key(name) ||
+            self.module.functions.contains_key(name) ||
+            self.module.imported_functions.iter().any(|f| f.sig.name == name) ||
+            self.func.sig.args.iter().any(|a| a.name == name)
+    }
+
+    // Check that every var is defined on all paths to the instructions reading it
+    fn check_definitions(&mut self)
+    {
+        let func = self.func;
+        let entry = match self.cfg.entry()
+        {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        // The vars defined at the end of each block, blocks which aren't in it are not analyzed yet
+        let mut defined_at_end: HashMap<BasicBlockRef, HashSet<&'a str>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for bb_ref in self.cfg.reverse_postorder() {
+                let mut defined = defined_at_start(*bb_ref, entry, &self.cfg, &defined_at_end);
+                for instr in &func.blocks[bb_ref].instructions {
+                    add_defs(instr, &mut defined);
+                }
+                if defined_at_end.get(bb_ref) != Some(&defined) {
+                    defined_at_end.insert(*bb_ref, defined);
+                    changed = true;
+                }
+            }
+        }
+
+        for (bb_ref, bb) in &func.blocks {
+            if !self.cfg.is_reachable(*bb_ref) {
+                continue;
+            }
+
+            let mut defined = defined_at_start(*bb_ref, entry, &self.cfg, &defined_at_end);
+            for instr in &bb.instructions {
+                if let Instruction::Phi{ref incoming, ..} = *instr {
+                    // The incoming values are read at the end of the predecessors
+                    for &(pred, ref op) in incoming {
+                        if let Operand::Var(ref v) = *op {
+                            let pred_defined = defined_at_end.get(&pred).map(|d| d.contains(v.name.as_str())).unwrap_or(false);
+                            if !pred_defined && !self.defined_on_entry(&v.name) {
+                                self.error(*bb_ref, format!("{} is not defined at the end of {} in: {}", v, bb_name(pred), instr));
+                            }
+                        }
+                    }
+                } else {
+                    let (uses, _) = uses_and_defs(instr);
+                    for v in uses {
+                        if !defined.contains(v.name.as_str()) && !self.defined_on_entry(&v.name) {
+                            self.error(*bb_ref, format!("{} is used before it is defined in: {}", v, instr));
+                        }
+                    }
+                }
+                add_defs(instr, &mut defined);
+            }
+        }
+    }
+
+    // Phi nodes come first in a block, and have an incoming value for every predecessor
+    fn check_phis(&mut self)
+    {
+        let func = self.func;
+        for (bb_ref, bb) in &func.blocks {
+            let mut at_start = true;
+            for instr in &bb.instructions {
+                let (dst, incoming) = match *instr
+                {
+                    Instruction::Phi{ref dst, ref incoming} => (dst, incoming),
+                    _ => {
+                        at_start = false;
+                        continue;
+                    },
+                };
+
+                if !at_start {
+                    self.error(*bb_ref, format!("phi node is not at the start of the block: {}", instr));
+                }
+
+                let mut blocks: Vec<BasicBlockRef> = incoming.iter().map(|&(bb, _)| bb).collect();
+                blocks.sort();
+                if self.cfg.is_reachable(*bb_ref) && blocks != self.cfg.predecessors(*bb_ref) {
+                    let preds: Vec<String> = self.cfg.predecessors(*bb_ref).iter().map(|bb| bb_name(*bb)).collect();
+                    self.error(*bb_ref, format!("incoming blocks of {} don't match the predecessors [{}]", instr, preds.join(", ")));
+                }
+
+                for &(_, ref op) in incoming {
+                    let src_type = operand_type(op);
+                    if !assignable(&dst.typ, &src_type) {
+                        self.error(*bb_ref, format!("phi node {} has an incoming value of type {}", dst, src_type));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_types(&mut self)
+    {
+        let func = self.func;
+        for (bb_ref, bb) in &func.blocks {
+            for instr in &bb.instructions {
+                self.check_instruction_types(*bb_ref, instr);
+            }
+        }
+    }
+}
+
+// Returns a description of every problem found in func
+pub fn verify_function(module: &ByteCodeModule, func: &ByteCodeFunction) -> Vec<String>
+{
+    if func.external {
+        return Vec::new();
+    }
+
+    let mut verifier = Verifier{
+        module,
+        func,
+        cfg: ControlFlowGraph::new(func),
+        errors: Vec::new(),
+    };
+
+    verifier.check_terminators();
+    verifier.check_scopes();
+    verifier.check_types();
+    verifier.check_phis();
+    verifier.check_definitions();
+    verifier.errors
+}
+
+// Verify all functions in a module, stage is used in the error message to tell where the invalid bytecode was produced
+pub fn verify_module(module: &ByteCodeModule, stage: &str) -> CompileResult<()>
+{
+    let errors: Vec<String> = module.functions.values()
+        .flat_map(|func| verify_function(module, func))
+        .collect();
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(CompileError::Other(format!("Internal Compiler Error: invalid bytecode after {}:\n  {}", stage, errors.join("\n  "))))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::*;
+    use bytecode::parse_module;
+
+    fn verify(func_text: &str) -> Vec<String>
+    {
+        let text = format!("module test\ntype struct test::Point {{x: i32, y: i32}}\n{}", func_text);
+        let module = parse_module(&text, "test.bc").expect("Parsing succeeded");
+        let func = module.get_function("test::foo").expect("Missing function");
+        verify_function(&module, func)
+    }
+
+    fn assert_error(func_text: &str, msg: &str)
+    {
+        let errors = verify(func_text);
+        assert!(errors.iter().any(|e| e.contains(msg)), "Expected error containing '{}', got {:?}", msg, errors);
+    }
+
+    #[test]
+    fn test_valid_function()
+    {
+        let errors = verify(r#"
+fn test::foo(a: i32, b: i32) -> i32 {
+entry:
+  scope start
+  bop ($var0: bool) lt (a: i32) (b: i32)
+  brif ($var0: bool) block1 block2
+block1:
+  salloc (p: %test::Point)
+  storem (p: %test::Point) (u32 1) (a: i32)
+  loadm ($var1: i32) (p: %test::Point) (u32 1)
+  ret ($var1: i32)
+  scope end
+block2:
+  ret (b: i32)
+}
+"#);
+        assert!(errors.is_empty(), "Unexpected errors {:?}", errors);
+    }
+
+    #[test]
+    fn test_missing_terminator()
+    {
+        assert_error(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  bop ($var0: i32) add (a: i32) (i32 1)
+}
+"#, "does not end in a terminator");
+    }
+
+    #[test]
+    fn test_bad_branch_target()
+    {
+        assert_error(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  br block7
+}
+"#, "branch to non existing block block7");
+    }
+
+    #[test]
+    fn test_use_before_definition()
+    {
+        assert_error(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  brif (bool true) block1 block2
+block1:
+  store ($var0: i32) (a: i32)
+  br block2
+block2:
+  ret ($var0: i32)
+}
+"#, "($var0: i32) is used before it is defined");
+    }
+
+    #[test]
+    fn test_member_index_out_of_range()
+    {
+        assert_error(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  salloc (p: %test::Point)
+  storem (p: %test::Point) (u32 2) (a: i32)
+  ret (a: i32)
+}
+"#, "member index 2 out of range");
+    }
+
+    #[test]
+    fn test_operand_types()
+    {
+        assert_error(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  bop ($var0: i32) add (a: i32) (b: bool)
+  ret ($var0: i32)
+}
+"#, "operands of + have different types");
+    }
+
+    #[test]
+    fn test_phi_predecessors()
+    {
+        assert_error(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  brif (b: bool) block1 block2
+block1:
+  br block2
+block2:
+  phi ($var0: i32) [block1: (a: i32)]
+  ret ($var0: i32)
+}
+"#, "don't match the predecessors [entry, block1]");
+
+        assert_error(r#"
+fn test::foo(a: i32, b: bool) -> i32 {
+entry:
+  brif (b: bool) block1 block2
+block1:
+  store ($var1: i32) (a: i32)
+  br block2
+block2:
+  phi ($var0: i32) [entry: ($var1: i32), block1: ($var1: i32)]
+  ret ($var0: i32)
+}
+"#, "($var1: i32) is not defined at the end of entry");
+    }
+
+    #[test]
+    fn test_unbalanced_scopes()
+    {
+        assert_error(r#"
+fn test::foo(a: i32) -> i32 {
+entry:
+  scope start
+  ret (a: i32)
+}
+"#, "1 scopes are never ended");
+    }
+}
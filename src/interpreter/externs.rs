@@ -0,0 +1,560 @@
+use std::ffi::CStr;
+use std::iter;
+use libc;
+use super::Trap;
+use super::memory::Value;
+
+/*
+    C functions which can be called from an interpreted program. Arguments and return values are
+    passed as scalars, extern functions which take or return structs by value are not supported.
+*/
+pub type ExternFunction = fn(&[Value]) -> Result<Value, Trap>;
+
+pub fn find_extern_function(name: &str) -> Option<ExternFunction>
+{
+    let func: ExternFunction = match name
+    {
+        "malloc" => ext_malloc,
+        "calloc" => ext_calloc,
+        "realloc" => ext_realloc,
+        "free" => ext_free,
+        "memcpy" | "memmove" => ext_memmove,
+        "memset" => ext_memset,
+        "memcmp" => ext_memcmp,
+        "strlen" => ext_strlen,
+        "write" => ext_write,
+        "read" => ext_read,
+        "open" => ext_open,
+        "close" => ext_close,
+        "getenv" => ext_getenv,
+        "setenv" => ext_setenv,
+        "unsetenv" => ext_unsetenv,
+        "exit" => ext_exit,
+        "abort" => ext_abort,
+        "putchar" => ext_putchar,
+        "puts" => ext_puts,
+        "printf" => ext_printf,
+        "dprintf" => ext_dprintf,
+        "snprintf" => ext_snprintf,
+        "sqrt" => ext_sqrt,
+        "pow" => ext_pow,
+        "floor" => ext_floor,
+        "ceil" => ext_ceil,
+        "fabs" => ext_fabs,
+        "fmod" => ext_fmod,
+        _ => return None,
+    };
+
+    Some(func)
+}
+
+fn arg(args: &[Value], idx: usize) -> Value
+{
+    args.get(idx).cloned().unwrap_or(Value::Int(0))
+}
+
+fn ptr_arg<T>(args: &[Value], idx: usize) -> *mut T
+{
+    arg(args, idx).as_address() as *mut T
+}
+
+fn const_ptr_arg<T>(args: &[Value], idx: usize) -> *const T
+{
+    arg(args, idx).as_address() as *const T
+}
+
+fn int_arg(args: &[Value], idx: usize) -> libc::c_int
+{
+    arg(args, idx).as_u64() as libc::c_int
+}
+
+fn size_arg(args: &[Value], idx: usize) -> libc::size_t
+{
+    arg(args, idx).as_u64() as libc::size_t
+}
+
+fn ptr_result<T>(p: *mut T) -> Result<Value, Trap>
+{
+    Ok(Value::Int(p as usize as u64))
+}
+
+fn int_result(v: i64) -> Result<Value, Trap>
+{
+    Ok(Value::Int(v as u64))
+}
+
+fn ext_malloc(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::malloc(size_arg(args, 0))})
+}
+
+fn ext_calloc(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::calloc(size_arg(args, 0), size_arg(args, 1))})
+}
+
+fn ext_realloc(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::realloc(ptr_arg(args, 0), size_arg(args, 1))})
+}
+
+fn ext_free(args: &[Value]) -> Result<Value, Trap>
+{
+    unsafe {libc::free(ptr_arg(args, 0))};
+    int_result(0)
+}
+
+fn ext_memmove(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::memmove(ptr_arg(args, 0), const_ptr_arg(args, 1), size_arg(args, 2))})
+}
+
+fn ext_memset(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::memset(ptr_arg(args, 0), int_arg(args, 1), size_arg(args, 2))})
+}
+
+fn ext_memcmp(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::memcmp(const_ptr_arg(args, 0), const_ptr_arg(args, 1), size_arg(args, 2))} as i64)
+}
+
+fn ext_strlen(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::strlen(const_ptr_arg(args, 0))} as i64)
+}
+
+fn ext_write(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::write(int_arg(args, 0), const_ptr_arg(args, 1), size_arg(args, 2))} as i64)
+}
+
+fn ext_read(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::read(int_arg(args, 0), ptr_arg(args, 1), size_arg(args, 2))} as i64)
+}
+
+fn ext_open(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::open(const_ptr_arg(args, 0), int_arg(args, 1), int_arg(args, 2))} as i64)
+}
+
+fn ext_close(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::close(int_arg(args, 0))} as i64)
+}
+
+fn ext_getenv(args: &[Value]) -> Result<Value, Trap>
+{
+    ptr_result(unsafe {libc::getenv(const_ptr_arg(args, 0))})
+}
+
+fn ext_setenv(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::setenv(const_ptr_arg(args, 0), const_ptr_arg(args, 1), int_arg(args, 2))} as i64)
+}
+
+fn ext_unsetenv(args: &[Value]) -> Result<Value, Trap>
+{
+    int_result(unsafe {libc::unsetenv(const_ptr_arg(args, 0))} as i64)
+}
+
+fn ext_exit(args: &[Value]) -> Result<Value, Trap>
+{
+    Err(Trap::Exit(int_arg(args, 0)))
+}
+
+fn ext_abort(_args: &[Value]) -> Result<Value, Trap>
+{
+    Err(Trap::Exit(134))
+}
+
+fn write_fd(fd: libc::c_int, data: &[u8]) -> i64
+{
+    let mut written = 0;
+    while written < data.len() {
+        let remaining = &data[written..];
+        let n = unsafe {libc::write(fd, remaining.as_ptr() as *const libc::c_void, remaining.len())};
+        if n <= 0 {
+            return -1;
+        }
+        written += n as usize;
+    }
+    written as i64
+}
+
+fn ext_putchar(args: &[Value]) -> Result<Value, Trap>
+{
+    let c = int_arg(args, 0);
+    if write_fd(1, &[c as u8]) < 0 {
+        int_result(-1)
+    } else {
+        int_result(i64::from(c as u8))
+    }
+}
+
+fn ext_puts(args: &[Value]) -> Result<Value, Trap>
+{
+    let mut line = unsafe {CStr::from_ptr(const_ptr_arg(args, 0))}.to_bytes().to_vec();
+    line.push(b'\n');
+    int_result(write_fd(1, &line))
+}
+
+fn ext_printf(args: &[Value]) -> Result<Value, Trap>
+{
+    let output = format_c_string(const_ptr_arg(args, 0), &args[1..]);
+    int_result(write_fd(1, &output))
+}
+
+fn ext_dprintf(args: &[Value]) -> Result<Value, Trap>
+{
+    let output = format_c_string(const_ptr_arg(args, 1), &args[2..]);
+    int_result(write_fd(int_arg(args, 0), &output))
+}
+
+fn ext_snprintf(args: &[Value]) -> Result<Value, Trap>
+{
+    let buf: *mut u8 = ptr_arg(args, 0);
+    let size = size_arg(args, 1);
+    let output = format_c_string(const_ptr_arg(args, 2), &args[3..]);
+    if size > 0 && !buf.is_null() {
+        let n = output.len().min(size - 1);
+        unsafe {
+            ::std::ptr::copy(output.as_ptr(), buf, n);
+            *buf.offset(n as isize) = 0;
+        }
+    }
+    int_result(output.len() as i64)
+}
+
+fn ext_sqrt(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64().sqrt()))
+}
+
+fn ext_pow(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64().powf(arg(args, 1).as_f64())))
+}
+
+fn ext_floor(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64().floor()))
+}
+
+fn ext_ceil(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64().ceil()))
+}
+
+fn ext_fabs(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64().abs()))
+}
+
+fn ext_fmod(args: &[Value]) -> Result<Value, Trap>
+{
+    Ok(Value::Float(arg(args, 0).as_f64() % arg(args, 1).as_f64()))
+}
+
+#[derive(Default)]
+struct FormatSpec
+{
+    left_justify: bool,
+    plus_sign: bool,
+    space_sign: bool,
+    alternate: bool,
+    zero_pad: bool,
+    width: usize,
+    precision: Option<usize>,
+    long: bool,
+    short: usize, // 1 for h, 2 for hh
+}
+
+impl FormatSpec
+{
+    fn sign(&self, negative: bool) -> &'static str
+    {
+        if negative {
+            "-"
+        } else if self.plus_sign {
+            "+"
+        } else if self.space_sign {
+            " "
+        } else {
+            ""
+        }
+    }
+
+    // Pad a converted value to the width, zero padding goes between the sign and the digits
+    fn pad_number(&self, sign: &str, digits: &[u8], zero_pad: bool, out: &mut Vec<u8>)
+    {
+        let padding = self.width.saturating_sub(sign.len() + digits.len());
+        if self.left_justify {
+            out.extend_from_slice(sign.as_bytes());
+            out.extend_from_slice(digits);
+            out.extend(iter::repeat(b' ').take(padding));
+        } else if zero_pad {
+            out.extend_from_slice(sign.as_bytes());
+            out.extend(iter::repeat(b'0').take(padding));
+            out.extend_from_slice(digits);
+        } else {
+            out.extend(iter::repeat(b' ').take(padding));
+            out.extend_from_slice(sign.as_bytes());
+            out.extend_from_slice(digits);
+        }
+    }
+
+    fn pad(&self, data: &[u8], out: &mut Vec<u8>)
+    {
+        self.pad_number("", data, false, out)
+    }
+
+    fn signed_arg(&self, v: Value) -> i64
+    {
+        let v = v.as_i64();
+        match (self.long, self.short)
+        {
+            (true, _) => v,
+            (false, 2) => i64::from(v as i8),
+            (false, 1) => i64::from(v as i16),
+            _ => i64::from(v as i32),
+        }
+    }
+
+    fn unsigned_arg(&self, v: Value) -> u64
+    {
+        let v = v.as_u64();
+        match (self.long, self.short)
+        {
+            (true, _) => v,
+            (false, 2) => u64::from(v as u8),
+            (false, 1) => u64::from(v as u16),
+            _ => u64::from(v as u32),
+        }
+    }
+
+    // Apply the precision to the digits of an integer, which is the minimum number of digits
+    fn int_digits(&self, digits: String) -> String
+    {
+        match self.precision
+        {
+            Some(0) if digits == "0" => String::new(),
+            Some(p) if p > digits.len() => format!("{}{}", "0".repeat(p - digits.len()), digits),
+            _ => digits,
+        }
+    }
+}
+
+// Format a float like %e, with C style exponents
+fn format_exponent(v: f64, precision: usize, upper: bool) -> String
+{
+    let s = format!("{:.*e}", precision, v);
+    let (mantissa, exponent) = s.split_at(s.find('e').expect("Exponent expected"));
+    let exponent: i32 = exponent[1..].parse().expect("Invalid exponent");
+    let s = format!("{}e{}{:02}", mantissa, if exponent < 0 {'-'} else {'+'}, exponent.abs());
+    if upper {s.to_uppercase()} else {s}
+}
+
+fn strip_trailing_zeros(s: String) -> String
+{
+    if !s.contains('.') {
+        return s;
+    }
+
+    let (number, exponent) = match s.find(|c| c == 'e' || c == 'E') {
+        Some(idx) => (s[..idx].to_owned(), s[idx..].to_owned()),
+        None => (s.clone(), String::new()),
+    };
+    let number = number.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", number, exponent)
+}
+
+fn format_float(spec: &FormatSpec, conversion: u8, v: f64) -> String
+{
+    let upper = conversion.is_ascii_uppercase();
+    if v.is_nan() || v.is_infinite() {
+        let s = if v.is_nan() {"nan"} else {"inf"};
+        return if upper {s.to_uppercase()} else {s.into()};
+    }
+
+    let precision = spec.precision.unwrap_or(6);
+    match conversion.to_ascii_lowercase()
+    {
+        b'f' => format!("{:.*}", precision, v),
+        b'e' => format_exponent(v, precision, upper),
+        _ => {
+            // %g uses %e when the exponent is smaller than -4, or not smaller than the precision
+            let precision = precision.max(1);
+            let exponent = if v == 0.0 {
+                0
+            } else {
+                let e = format_exponent(v, precision - 1, false);
+                e[e.find('e').expect("Exponent expected") + 1..].parse::<i32>().expect("Invalid exponent")
+            };
+
+            let s = if exponent < -4 || exponent >= precision as i32 {
+                format_exponent(v, precision - 1, upper)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent) as usize, v)
+            };
+
+            if spec.alternate {s} else {strip_trailing_zeros(s)}
+        },
+    }
+}
+
+/*
+    printf style formatting, supports the flags, width, precision and length modifiers,
+    and the d, i, u, x, X, o, c, s, p, f, F, e, E, g, G and % conversions
+*/
+pub fn format_c_string(format: *const u8, args: &[Value]) -> Vec<u8>
+{
+    let format = if format.is_null() {
+        &[]
+    } else {
+        unsafe {CStr::from_ptr(format as *const libc::c_char)}.to_bytes()
+    };
+
+    let mut out = Vec::new();
+    let mut args = args.iter().cloned();
+    let mut idx = 0;
+    while idx < format.len() {
+        let c = format[idx];
+        idx += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = FormatSpec::default();
+        while idx < format.len() {
+            match format[idx]
+            {
+                b'-' => spec.left_justify = true,
+                b'+' => spec.plus_sign = true,
+                b' ' => spec.space_sign = true,
+                b'#' => spec.alternate = true,
+                b'0' => spec.zero_pad = true,
+                _ => break,
+            }
+            idx += 1;
+        }
+
+        if idx < format.len() && format[idx] == b'*' {
+            let width = args.next().unwrap_or(Value::Int(0)).as_u64() as i32;
+            spec.left_justify |= width < 0;
+            spec.width = width.abs() as usize;
+            idx += 1;
+        } else {
+            while idx < format.len() && format[idx].is_ascii_digit() {
+                spec.width = spec.width * 10 + (format[idx] - b'0') as usize;
+                idx += 1;
+            }
+        }
+
+        if idx < format.len() && format[idx] == b'.' {
+            idx += 1;
+            let mut precision = 0;
+            if idx < format.len() && format[idx] == b'*' {
+                let p = args.next().unwrap_or(Value::Int(0)).as_u64() as i32;
+                idx += 1;
+                spec.precision = if p < 0 {None} else {Some(p as usize)};
+            } else {
+                while idx < format.len() && format[idx].is_ascii_digit() {
+                    precision = precision * 10 + (format[idx] - b'0') as usize;
+                    idx += 1;
+                }
+                spec.precision = Some(precision);
+            }
+        }
+
+        while idx < format.len() {
+            match format[idx]
+            {
+                b'l' | b'j' | b'z' | b't' | b'q' | b'L' => spec.long = true,
+                b'h' => spec.short += 1,
+                _ => break,
+            }
+            idx += 1;
+        }
+
+        if idx >= format.len() {
+            break;
+        }
+
+        let conversion = format[idx];
+        idx += 1;
+        let int_zero_pad = spec.zero_pad && spec.precision.is_none();
+        match conversion
+        {
+            b'%' => out.push(b'%'),
+
+            b'd' | b'i' => {
+                let v = spec.signed_arg(args.next().unwrap_or(Value::Int(0)));
+                let digits = spec.int_digits(format!("{}", v.wrapping_abs() as u64));
+                let sign = spec.sign(v < 0);
+                spec.pad_number(sign, digits.as_bytes(), int_zero_pad, &mut out);
+            },
+
+            b'u' | b'x' | b'X' | b'o' => {
+                let v = spec.unsigned_arg(args.next().unwrap_or(Value::Int(0)));
+                let digits = match conversion
+                {
+                    b'x' => format!("{:x}", v),
+                    b'X' => format!("{:X}", v),
+                    b'o' => format!("{:o}", v),
+                    _ => format!("{}", v),
+                };
+                let digits = spec.int_digits(digits);
+                let prefix = match conversion
+                {
+                    b'x' if spec.alternate && v != 0 => "0x",
+                    b'X' if spec.alternate && v != 0 => "0X",
+                    b'o' if spec.alternate && !digits.starts_with('0') => "0",
+                    _ => "",
+                };
+                spec.pad_number(prefix, digits.as_bytes(), int_zero_pad, &mut out);
+            },
+
+            b'c' => {
+                let v = args.next().unwrap_or(Value::Int(0)).as_u64() as u8;
+                spec.pad(&[v], &mut out);
+            },
+
+            b's' => {
+                let p = args.next().unwrap_or(Value::Int(0)).as_address();
+                let s: &[u8] = if p == 0 {
+                    b"(null)"
+                } else {
+                    unsafe {CStr::from_ptr(p as *const libc::c_char)}.to_bytes()
+                };
+                let s = match spec.precision {
+                    Some(p) if p < s.len() => &s[..p],
+                    _ => s,
+                };
+                spec.pad(s, &mut out);
+            },
+
+            b'p' => {
+                let p = args.next().unwrap_or(Value::Int(0)).as_u64();
+                spec.pad(format!("0x{:x}", p).as_bytes(), &mut out);
+            },
+
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                let v = args.next().unwrap_or(Value::Float(0.0)).as_f64();
+                let digits = format_float(&spec, conversion, v.abs());
+                let sign = spec.sign(v.is_sign_negative() && !v.is_nan());
+                spec.pad_number(sign, digits.as_bytes(), spec.zero_pad && v.is_finite(), &mut out);
+            },
+
+            other => {
+                // Unknown conversion, output it unchanged like most C libraries
+                out.push(b'%');
+                out.push(other);
+            },
+        }
+    }
+
+    out
+}
@@ -0,0 +1,137 @@
+use std::mem;
+use ast::{Type, IntSize, FloatSize, StructType, SumType, ptr_type};
+use target::Target;
+
+// The interpreter uses host memory, so pointers have the size of a host pointer
+pub const POINTER_SIZE: usize = mem::size_of::<usize>();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Layout
+{
+    pub size: usize,
+    pub align: usize,
+}
+
+impl Layout
+{
+    fn new(size: usize, align: usize) -> Layout
+    {
+        Layout{
+            size,
+            align,
+        }
+    }
+
+    // Distance between two consecutive elements of an array
+    pub fn stride(&self) -> usize
+    {
+        align_to(self.size, self.align)
+    }
+}
+
+pub fn align_to(offset: usize, align: usize) -> usize
+{
+    (offset + align - 1) / align * align
+}
+
+fn int_layout(int_size: IntSize) -> Layout
+{
+    let size = int_size.size_in_bits() as usize / 8;
+    Layout::new(size, size)
+}
+
+/*
+    Lay out members like a C struct, returns the offsets of the members and the layout of the whole struct.
+    This matches the struct types the LLVM backend generates.
+*/
+fn aggregate_layout<'a, I: Iterator<Item=&'a Type>>(members: I, target: &Target) -> (Vec<usize>, Layout)
+{
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    let mut align = 1;
+    for m in members {
+        let l = layout_of(m, target);
+        offset = align_to(offset, l.align);
+        offsets.push(offset);
+        offset += l.size;
+        align = align.max(l.align);
+    }
+
+    (offsets, Layout::new(align_to(offset, align), align))
+}
+
+// The case type which determines the layout of the data of a sum type, the first of the largest cases
+fn largest_case(st: &SumType, target: &Target) -> Type
+{
+    let mut largest: Option<(&Type, usize)> = None;
+    for c in &st.cases {
+        let size = layout_of(&c.typ, target).size;
+        if largest.map(|(_, s)| size > s).unwrap_or(true) {
+            largest = Some((&c.typ, size));
+        }
+    }
+
+    largest.map(|(t, _)| t.clone()).unwrap_or_else(|| target.native_uint_type.clone())
+}
+
+fn sum_type_members(st: &SumType, target: &Target) -> [Type; 2]
+{
+    [target.native_uint_type.clone(), largest_case(st, target)]
+}
+
+fn slice_members(target: &Target) -> [Type; 2]
+{
+    [ptr_type(Type::UInt(IntSize::I8)), target.native_uint_type.clone()]
+}
+
+pub fn layout_of(typ: &Type, target: &Target) -> Layout
+{
+    match *typ
+    {
+        Type::Void => Layout::new(0, 1),
+        Type::Int(int_size) | Type::UInt(int_size) => int_layout(int_size),
+        Type::Enum(_) => int_layout(target.int_size),
+        Type::Char => Layout::new(4, 4),
+        Type::Bool => Layout::new(1, 1),
+        Type::Float(FloatSize::F32) => Layout::new(4, 4),
+        Type::Float(FloatSize::F64) => Layout::new(8, 8),
+        Type::Pointer(_) | Type::Func(_) => Layout::new(POINTER_SIZE, POINTER_SIZE),
+        Type::Array(ref at) => {
+            let el = layout_of(&at.element_type, target);
+            Layout::new(el.stride() * at.len(), el.align)
+        },
+        Type::Slice(_) | Type::String => aggregate_layout(slice_members(target).iter(), target).1,
+        Type::Struct(ref st) => aggregate_layout(st.members.iter().map(|m| &m.typ), target).1,
+        Type::Sum(ref st) => aggregate_layout(sum_type_members(st, target).iter(), target).1,
+        Type::Optional(ref inner) => aggregate_layout([Type::Bool, (**inner).clone()].iter(), target).1,
+        _ => panic!("Internal Compiler Error: type {} has no memory layout", typ),
+    }
+}
+
+pub fn size_of(typ: &Type, target: &Target) -> usize
+{
+    layout_of(typ, target).size
+}
+
+pub fn struct_member_offset(st: &StructType, index: usize, target: &Target) -> usize
+{
+    aggregate_layout(st.members.iter().map(|m| &m.typ), target).0[index]
+}
+
+// Offset of the len member of a slice or string, the data pointer is at offset 0
+pub fn slice_len_offset(target: &Target) -> usize
+{
+    aggregate_layout(slice_members(target).iter(), target).0[1]
+}
+
+// Offset of the value of an optional, the flag is at offset 0
+pub fn optional_data_offset(inner: &Type, target: &Target) -> usize
+{
+    align_to(1, layout_of(inner, target).align)
+}
+
+// Offset of the data of a sum type, the tag is at offset 0
+pub fn sum_type_data_offset(st: &SumType, target: &Target) -> usize
+{
+    aggregate_layout(sum_type_members(st, target).iter(), target).0[1]
+}
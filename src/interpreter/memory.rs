@@ -0,0 +1,197 @@
+use std::ptr;
+use ast::{Type, IntSize, FloatSize};
+use target::Target;
+use super::layout::{POINTER_SIZE, size_of};
+
+/*
+    A scalar value, integers, chars, bools, enums and pointers are stored as Int.
+    Signed integers are sign extended to 64 bits, the other types are zero extended.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value
+{
+    Int(u64),
+    Float(f64),
+}
+
+impl Value
+{
+    pub fn from_bool(v: bool) -> Value
+    {
+        Value::Int(if v {1} else {0})
+    }
+
+    pub fn as_u64(&self) -> u64
+    {
+        match *self
+        {
+            Value::Int(v) => v,
+            Value::Float(v) => v as u64,
+        }
+    }
+
+    pub fn as_i64(&self) -> i64
+    {
+        match *self
+        {
+            Value::Int(v) => v as i64,
+            Value::Float(v) => v as i64,
+        }
+    }
+
+    pub fn as_f64(&self) -> f64
+    {
+        match *self
+        {
+            Value::Int(v) => v as i64 as f64,
+            Value::Float(v) => v,
+        }
+    }
+
+    pub fn as_bool(&self) -> bool
+    {
+        self.as_u64() != 0
+    }
+
+    pub fn as_address(&self) -> usize
+    {
+        self.as_u64() as usize
+    }
+}
+
+fn int_size_of(typ: &Type, target: &Target) -> Option<(IntSize, bool)>
+{
+    match *typ
+    {
+        Type::Int(int_size) => Some((int_size, true)),
+        Type::UInt(int_size) => Some((int_size, false)),
+        Type::Enum(_) => Some((target.int_size, false)),
+        Type::Char => Some((IntSize::I32, false)),
+        _ => None,
+    }
+}
+
+// Truncate a value to the size of typ, and extend it again to 64 bits
+pub fn normalize(typ: &Type, value: Value, target: &Target) -> Value
+{
+    if let Some((int_size, signed)) = int_size_of(typ, target) {
+        let shift = 64 - int_size.size_in_bits();
+        let v = value.as_u64();
+        if signed {
+            Value::Int((((v << shift) as i64) >> shift) as u64)
+        } else {
+            Value::Int((v << shift) >> shift)
+        }
+    } else {
+        match *typ
+        {
+            Type::Float(FloatSize::F32) => Value::Float(value.as_f64() as f32 as f64),
+            Type::Float(FloatSize::F64) => Value::Float(value.as_f64()),
+            Type::Bool => Value::from_bool(value.as_bool()),
+            _ => Value::Int(value.as_u64()),
+        }
+    }
+}
+
+pub unsafe fn read_value(addr: usize, typ: &Type, target: &Target) -> Value
+{
+    if let Some((int_size, signed)) = int_size_of(typ, target) {
+        let v = match (int_size, signed)
+        {
+            (IntSize::I8, true) => ptr::read_unaligned(addr as *const i8) as i64 as u64,
+            (IntSize::I16, true) => ptr::read_unaligned(addr as *const i16) as i64 as u64,
+            (IntSize::I32, true) => ptr::read_unaligned(addr as *const i32) as i64 as u64,
+            (IntSize::I64, true) => ptr::read_unaligned(addr as *const i64) as u64,
+            (IntSize::I8, false) => u64::from(ptr::read_unaligned(addr as *const u8)),
+            (IntSize::I16, false) => u64::from(ptr::read_unaligned(addr as *const u16)),
+            (IntSize::I32, false) => u64::from(ptr::read_unaligned(addr as *const u32)),
+            (IntSize::I64, false) => ptr::read_unaligned(addr as *const u64),
+        };
+        return Value::Int(v);
+    }
+
+    match *typ
+    {
+        Type::Bool => Value::from_bool(ptr::read_unaligned(addr as *const u8) & 1 != 0),
+        Type::Float(FloatSize::F32) => Value::Float(f64::from(ptr::read_unaligned(addr as *const f32))),
+        Type::Float(FloatSize::F64) => Value::Float(ptr::read_unaligned(addr as *const f64)),
+        Type::Pointer(_) | Type::Func(_) => Value::Int(ptr::read_unaligned(addr as *const usize) as u64),
+        _ => panic!("Internal Compiler Error: cannot load a value of type {}", typ),
+    }
+}
+
+pub unsafe fn write_value(addr: usize, typ: &Type, value: Value, target: &Target)
+{
+    if let Some((int_size, _)) = int_size_of(typ, target) {
+        let v = value.as_u64();
+        match int_size
+        {
+            IntSize::I8 => ptr::write_unaligned(addr as *mut u8, v as u8),
+            IntSize::I16 => ptr::write_unaligned(addr as *mut u16, v as u16),
+            IntSize::I32 => ptr::write_unaligned(addr as *mut u32, v as u32),
+            IntSize::I64 => ptr::write_unaligned(addr as *mut u64, v),
+        }
+        return;
+    }
+
+    match *typ
+    {
+        Type::Bool => ptr::write_unaligned(addr as *mut u8, value.as_bool() as u8),
+        Type::Float(FloatSize::F32) => ptr::write_unaligned(addr as *mut f32, value.as_f64() as f32),
+        Type::Float(FloatSize::F64) => ptr::write_unaligned(addr as *mut f64, value.as_f64()),
+        Type::Pointer(_) | Type::Func(_) => ptr::write_unaligned(addr as *mut usize, value.as_address()),
+        _ => panic!("Internal Compiler Error: cannot store a value of type {}", typ),
+    }
+}
+
+pub unsafe fn read_pointer(addr: usize) -> usize
+{
+    ptr::read_unaligned(addr as *const usize)
+}
+
+pub unsafe fn write_pointer(addr: usize, value: usize)
+{
+    ptr::write_unaligned(addr as *mut usize, value)
+}
+
+pub unsafe fn copy_memory(dst: usize, src: usize, size: usize)
+{
+    if dst != src {
+        ptr::copy(src as *const u8, dst as *mut u8, size);
+    }
+}
+
+pub unsafe fn read_bytes<'a>(addr: usize, len: usize) -> &'a [u8]
+{
+    if len == 0 {
+        &[]
+    } else {
+        ::std::slice::from_raw_parts(addr as *const u8, len)
+    }
+}
+
+// Zero initialized memory, aligned on 8 bytes, which is enough for every type
+pub struct Allocation
+{
+    data: Vec<u64>,
+}
+
+impl Allocation
+{
+    pub fn new(size: usize) -> Allocation
+    {
+        Allocation{
+            data: vec![0; ((size + 7) / 8).max(1)],
+        }
+    }
+
+    pub fn for_type(typ: &Type, target: &Target) -> Allocation
+    {
+        Allocation::new(size_of(typ, target).max(POINTER_SIZE))
+    }
+
+    pub fn address(&self) -> usize
+    {
+        self.data.as_ptr() as usize
+    }
+}
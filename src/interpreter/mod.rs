@@ -0,0 +1,1210 @@
+mod externs;
+mod layout;
+mod memory;
+#[cfg(test)]
+mod tests;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use libc;
+use ast::{Type, IntSize, FloatSize, BinaryOperator, UnaryOperator};
+use bytecode::*;
+use span::Span;
+use target::Target;
+use self::externs::find_extern_function;
+use self::layout::*;
+use self::memory::*;
+
+// Maximum number of nested calls, deeper recursion is reported as a stack overflow
+const MAX_CALL_DEPTH: usize = 100_000;
+// Function pointers are fake addresses in the first pages of memory, which are never mapped
+const FUNCTION_ADDRESS_BASE: usize = 0x1000;
+// Exit status of a program which panics, the same as the runtime of the LLVM backend
+const PANIC_EXIT_STATUS: i32 = 101;
+
+pub enum Trap
+{
+    Exit(i32),      // The program called exit or panicked
+    Error(String),  // The interpreter cannot continue
+}
+
+type ExecResult<T> = Result<T, Trap>;
+
+fn error<T>(msg: String) -> ExecResult<T>
+{
+    Err(Trap::Error(msg))
+}
+
+#[derive(Clone, Copy)]
+struct Binding
+{
+    address: usize,
+    alias: bool,    // The variable refers to memory owned by something else, like a member of a struct
+}
+
+struct Scope
+{
+    bindings: HashMap<String, Binding>,
+    allocations: Vec<Allocation>,
+}
+
+impl Scope
+{
+    fn new() -> Scope
+    {
+        Scope{
+            bindings: HashMap::new(),
+            allocations: Vec::new(),
+        }
+    }
+}
+
+struct Frame<'a>
+{
+    func: &'a ByteCodeFunction,
+    block: BasicBlockRef,
+    index: usize,
+    scopes: Vec<Scope>,
+    return_dst: Option<&'a Var>,
+}
+
+impl<'a> Frame<'a>
+{
+    fn new(func: &'a ByteCodeFunction, return_dst: Option<&'a Var>, args: Scope) -> Frame<'a>
+    {
+        Frame{
+            func,
+            block: func.blocks.keys().next().cloned().unwrap_or(0),
+            index: 0,
+            scopes: vec![args],
+            return_dst,
+        }
+    }
+}
+
+enum ReturnValue
+{
+    Scalar(Value),
+    Aggregate(Vec<u8>),
+}
+
+enum Flow
+{
+    Next,
+    Jump(BasicBlockRef),
+    Return(Option<ReturnValue>),
+}
+
+/*
+    Executes byte code directly, without generating machine code. Values live in host memory,
+    with the same layout the LLVM backend uses, so pointers can be passed to C functions.
+*/
+struct Interpreter<'a>
+{
+    module: &'a ByteCodeModule,
+    target: &'a Target,
+    frames: Vec<Frame<'a>>,
+    globals: HashMap<&'a str, usize>,
+    function_names: Vec<&'a str>,
+    function_addresses: HashMap<&'a str, usize>,
+    constants: HashMap<String, usize>,
+    memory: Vec<Allocation>,    // Globals, constants and command line arguments
+    strings: Vec<Vec<u8>>,      // Nul terminated data of strings
+}
+
+fn wrapping_operator(op: BinaryOperator) -> BinaryOperator
+{
+    match op
+    {
+        BinaryOperator::Add => BinaryOperator::WrappingAdd,
+        BinaryOperator::Sub => BinaryOperator::WrappingSub,
+        BinaryOperator::Mul => BinaryOperator::WrappingMul,
+        _ => op,
+    }
+}
+
+fn compare(op: BinaryOperator, ordering: Ordering) -> Option<bool>
+{
+    match op
+    {
+        BinaryOperator::LessThan => Some(ordering == Ordering::Less),
+        BinaryOperator::GreaterThan => Some(ordering == Ordering::Greater),
+        BinaryOperator::LessThanEquals => Some(ordering != Ordering::Greater),
+        BinaryOperator::GreaterThanEquals => Some(ordering != Ordering::Less),
+        BinaryOperator::Equals => Some(ordering == Ordering::Equal),
+        BinaryOperator::NotEquals => Some(ordering != Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn is_division(op: BinaryOperator) -> bool
+{
+    op == BinaryOperator::Div || op == BinaryOperator::Mod
+}
+
+/*
+    Apply a binary operator on two scalars of type typ.
+    Returns None when an integer operation overflows, or on a division by zero.
+*/
+fn binary_op_value(op: BinaryOperator, typ: &Type, l: Value, r: Value) -> Result<Option<Value>, String>
+{
+    let base = op.base_operator();
+    let result = match *typ
+    {
+        Type::Int(int_size) => {
+            let (a, b) = (l.as_i64(), r.as_i64());
+            match base
+            {
+                BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul =>
+                    int_arithmetic(op, a, b, int_size).map(|v| Value::Int(v as u64)),
+                BinaryOperator::Div | BinaryOperator::Mod => {
+                    let min = ::std::i64::MIN >> (64 - int_size.size_in_bits());
+                    if b == 0 || (a == min && b == -1) {
+                        None
+                    } else if base == BinaryOperator::Div {
+                        Some(Value::Int((a / b) as u64))
+                    } else {
+                        Some(Value::Int((a % b) as u64))
+                    }
+                },
+                _ => compare(base, a.cmp(&b)).map(Value::from_bool).map(Some)
+                    .ok_or_else(|| format!("Operator {} is not supported on {}", op, typ))?,
+            }
+        },
+
+        Type::UInt(int_size) => {
+            let (a, b) = (l.as_u64(), r.as_u64());
+            match base
+            {
+                BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul =>
+                    uint_arithmetic(op, a, b, int_size).map(Value::Int),
+                BinaryOperator::Div | BinaryOperator::Mod => {
+                    if b == 0 {
+                        None
+                    } else if base == BinaryOperator::Div {
+                        Some(Value::Int(a / b))
+                    } else {
+                        Some(Value::Int(a % b))
+                    }
+                },
+                _ => compare(base, a.cmp(&b)).map(Value::from_bool).map(Some)
+                    .ok_or_else(|| format!("Operator {} is not supported on {}", op, typ))?,
+            }
+        },
+
+        Type::Float(_) => {
+            let (a, b) = (l.as_f64(), r.as_f64());
+            match base
+            {
+                BinaryOperator::Add => Some(Value::Float(a + b)),
+                BinaryOperator::Sub => Some(Value::Float(a - b)),
+                BinaryOperator::Mul => Some(Value::Float(a * b)),
+                BinaryOperator::Div => Some(Value::Float(a / b)),
+                BinaryOperator::Mod => Some(Value::Float(a % b)),
+                // Comparisons are unordered, like the LLVM backend, so they are true when one of the operands is NaN
+                _ => {
+                    let result = match a.partial_cmp(&b) {
+                        Some(ordering) => compare(base, ordering),
+                        None => compare(base, Ordering::Equal).map(|_| true),
+                    };
+                    result.map(Value::from_bool).map(Some)
+                        .ok_or_else(|| format!("Operator {} is not supported on {}", op, typ))?
+                },
+            }
+        },
+
+        Type::Bool if base == BinaryOperator::And => Some(Value::from_bool(l.as_bool() && r.as_bool())),
+        Type::Bool if base == BinaryOperator::Or => Some(Value::from_bool(l.as_bool() || r.as_bool())),
+
+        Type::Bool | Type::Char | Type::Enum(_) | Type::Pointer(_) | Type::Func(_) => {
+            compare(base, l.as_u64().cmp(&r.as_u64())).map(Value::from_bool).map(Some)
+                .ok_or_else(|| format!("Operator {} is not supported on {}", op, typ))?
+        },
+
+        _ => return Err(format!("Operator {} is not supported on {}", op, typ)),
+    };
+
+    Ok(result)
+}
+
+impl<'a> Interpreter<'a>
+{
+    fn new(module: &'a ByteCodeModule, target: &'a Target) -> ExecResult<Interpreter<'a>>
+    {
+        let mut function_names: Vec<&'a str> = module.functions.keys()
+            .map(|name| name.as_str())
+            .chain(module.imported_functions.iter().map(|f| f.sig.name.as_str()))
+            .collect();
+        function_names.sort();
+        function_names.dedup();
+
+        let function_addresses = function_names.iter()
+            .enumerate()
+            .map(|(idx, name)| (*name, FUNCTION_ADDRESS_BASE + idx * 8))
+            .collect();
+
+        let mut interpreter = Interpreter{
+            module,
+            target,
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            function_names,
+            function_addresses,
+            constants: HashMap::new(),
+            memory: Vec::new(),
+            strings: Vec::new(),
+        };
+
+        for (name, value) in &module.globals {
+            let storage = Allocation::for_type(&value.get_type(), target);
+            interpreter.write_constant(storage.address(), value)?;
+            interpreter.globals.insert(name.as_str(), storage.address());
+            interpreter.memory.push(storage);
+        }
+
+        Ok(interpreter)
+    }
+
+    fn frame(&self) -> &Frame<'a>
+    {
+        self.frames.last().expect("Internal Compiler Error: empty call stack")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame<'a>
+    {
+        self.frames.last_mut().expect("Internal Compiler Error: empty call stack")
+    }
+
+    fn panic<T>(&self, span: &Span, msg: &str) -> ExecResult<T>
+    {
+        eprintln!("Panic at {}:{}: {}", span.file, span.start, msg);
+        Err(Trap::Exit(PANIC_EXIT_STATUS))
+    }
+
+    fn find_function(&self, name: &str) -> Option<&'a ByteCodeFunction>
+    {
+        let module = self.module;
+        module.functions.get(name)
+            .or_else(|| module.imported_functions.iter().find(|f| f.sig.name == name))
+    }
+
+    fn function_address(&self, name: &str) -> ExecResult<usize>
+    {
+        match self.function_addresses.get(name)
+        {
+            Some(&address) => Ok(address),
+            None => error(format!("Unknown function {}", name)),
+        }
+    }
+
+    fn function_at(&self, address: usize) -> ExecResult<&'a str>
+    {
+        let idx = address.wrapping_sub(FUNCTION_ADDRESS_BASE) / 8;
+        if address >= FUNCTION_ADDRESS_BASE && address % 8 == 0 && idx < self.function_names.len() {
+            Ok(self.function_names[idx])
+        } else {
+            error(format!("Call through an invalid function pointer 0x{:x}", address))
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding>
+    {
+        self.frame().scopes.iter()
+            .rev()
+            .filter_map(|s| s.bindings.get(name))
+            .next()
+            .cloned()
+    }
+
+    // Temporaries live as long as the function, named variables as long as their scope
+    fn scope_for(&mut self, name: &str) -> &mut Scope
+    {
+        let frame = self.frame_mut();
+        if name.starts_with('$') {
+            &mut frame.scopes[0]
+        } else {
+            frame.scopes.last_mut().expect("Internal Compiler Error: function without a scope")
+        }
+    }
+
+    fn bind_storage(&mut self, var: &Var) -> usize
+    {
+        let storage = Allocation::for_type(&var.typ, self.target);
+        let address = storage.address();
+        let scope = self.scope_for(&var.name);
+        scope.allocations.push(storage);
+        scope.bindings.insert(var.name.clone(), Binding{address, alias: false});
+        address
+    }
+
+    fn bind_alias(&mut self, var: &Var, address: usize)
+    {
+        for scope in self.frame_mut().scopes.iter_mut().rev() {
+            if let Some(binding) = scope.bindings.get_mut(&var.name) {
+                *binding = Binding{address, alias: true};
+                return;
+            }
+        }
+
+        self.scope_for(&var.name).bindings.insert(var.name.clone(), Binding{address, alias: true});
+    }
+
+    // The address of a variable, storage is allocated the first time a variable is used
+    fn var_address(&mut self, var: &Var) -> usize
+    {
+        if let Some(binding) = self.lookup(&var.name) {
+            return binding.address;
+        }
+
+        if let Some(&address) = self.globals.get(var.name.as_str()) {
+            return address;
+        }
+
+        let address = self.bind_storage(var);
+        if let Some(&func) = self.function_addresses.get(var.name.as_str()) {
+            unsafe { write_pointer(address, func) };
+        }
+        address
+    }
+
+    // Copy an aggregate into a variable with storage, otherwise let the variable refer to it
+    fn bind_or_copy(&mut self, dst: &Var, src: usize, typ: &Type)
+    {
+        let has_storage = match self.lookup(&dst.name)
+        {
+            Some(binding) => !binding.alias,
+            None => self.globals.contains_key(dst.name.as_str()),
+        };
+
+        if has_storage {
+            let address = self.var_address(dst);
+            unsafe { copy_memory(address, src, size_of(typ, self.target)) };
+        } else {
+            self.bind_alias(dst, src);
+        }
+    }
+
+    // The address and type of the object a variable holds, following pointers
+    fn object(&mut self, var: &Var) -> (usize, Type)
+    {
+        let mut address = self.var_address(var);
+        let mut typ = &var.typ;
+        while let Type::Pointer(ref inner) = *typ {
+            address = unsafe { read_pointer(address) };
+            typ = &**inner;
+        }
+        (address, typ.clone())
+    }
+
+    fn const_index(&self, index: &Operand) -> ExecResult<usize>
+    {
+        match *index
+        {
+            Operand::Const(Constant::Int(v, _)) => Ok(v as usize),
+            Operand::Const(Constant::UInt(v, _)) => Ok(v as usize),
+            _ => error(format!("Member index {} is not a constant", index)),
+        }
+    }
+
+    fn member_address(&mut self, obj: &Var, index: &Operand) -> ExecResult<(usize, Type)>
+    {
+        let (address, typ) = self.object(obj);
+        match typ
+        {
+            Type::Array(ref at) => {
+                let idx = self.eval(index)?.as_address();
+                let stride = layout_of(&at.element_type, self.target).stride();
+                Ok((address.wrapping_add(idx.wrapping_mul(stride)), at.element_type.clone()))
+            },
+            Type::Slice(ref st) => {
+                let idx = self.eval(index)?.as_address();
+                let stride = layout_of(&st.element_type, self.target).stride();
+                let data = unsafe { read_pointer(address) };
+                Ok((data.wrapping_add(idx.wrapping_mul(stride)), st.element_type.clone()))
+            },
+            Type::String => {
+                let idx = self.eval(index)?.as_address();
+                let data = unsafe { read_pointer(address) };
+                Ok((data.wrapping_add(idx), Type::UInt(IntSize::I8)))
+            },
+            Type::Struct(ref st) => {
+                let idx = self.const_index(index)?;
+                let offset = struct_member_offset(st, idx, self.target);
+                Ok((address + offset, st.members[idx].typ.clone()))
+            },
+            Type::Sum(ref st) => {
+                let idx = self.const_index(index)?;
+                Ok((address + sum_type_data_offset(st, self.target), st.cases[idx].typ.clone()))
+            },
+            _ => error(format!("{} has no members", obj)),
+        }
+    }
+
+    fn read(&self, address: usize, typ: &Type) -> ExecResult<Value>
+    {
+        if typ.pass_by_value() {
+            Ok(unsafe { read_value(address, typ, self.target) })
+        } else {
+            error(format!("A value of type {} is not a scalar", typ))
+        }
+    }
+
+    fn read_len(&self, address: usize) -> u64
+    {
+        unsafe { read_value(address + slice_len_offset(self.target), &self.target.native_uint_type, self.target).as_u64() }
+    }
+
+    fn write_slice(&self, address: usize, data: usize, len: u64)
+    {
+        unsafe {
+            write_pointer(address, data);
+            write_value(address + slice_len_offset(self.target), &self.target.native_uint_type, Value::Int(len), self.target);
+        }
+    }
+
+    fn read_var(&mut self, var: &Var) -> ExecResult<Value>
+    {
+        let address = self.var_address(var);
+        self.read(address, &var.typ)
+    }
+
+    fn write_var(&mut self, var: &Var, value: Value) -> ExecResult<()>
+    {
+        if !var.typ.pass_by_value() {
+            return error(format!("Cannot store a scalar in {}", var));
+        }
+
+        let address = self.var_address(var);
+        unsafe { write_value(address, &var.typ, value, self.target) };
+        Ok(())
+    }
+
+    fn const_value(&self, c: &Constant) -> ExecResult<Value>
+    {
+        let value = match *c
+        {
+            Constant::Int(v, _) => Value::Int(v as u64),
+            Constant::UInt(v, _) => Value::Int(v),
+            Constant::Float(v, FloatSize::F32) => Value::Float(f64::from(v as f32)),
+            Constant::Float(v, FloatSize::F64) => Value::Float(v),
+            Constant::Char(v) => Value::Int(u64::from(v as u32)),
+            Constant::Bool(v) => Value::from_bool(v),
+            Constant::NullPtr(_) => Value::Int(0),
+            Constant::String(_) | Constant::Array(_) | Constant::Struct(..) => return error(format!("Constant {} is not a scalar", c)),
+        };
+
+        Ok(normalize(&c.get_type(), value, self.target))
+    }
+
+    fn string_data(&mut self, s: &str) -> usize
+    {
+        let mut data = s.as_bytes().to_vec();
+        data.push(0);
+        let address = data.as_ptr() as usize;
+        self.strings.push(data);
+        address
+    }
+
+    fn write_constant(&mut self, address: usize, c: &Constant) -> ExecResult<()>
+    {
+        match *c
+        {
+            Constant::String(ref s) => {
+                let data = self.string_data(s);
+                self.write_slice(address, data, s.len() as u64);
+            },
+            Constant::Array(ref elements) => {
+                if let Some(first) = elements.first() {
+                    let stride = layout_of(&first.get_type(), self.target).stride();
+                    for (idx, e) in elements.iter().enumerate() {
+                        self.write_constant(address + idx * stride, e)?;
+                    }
+                }
+            },
+            Constant::Struct(Type::Struct(ref st), ref members) => {
+                for (idx, m) in members.iter().enumerate() {
+                    self.write_constant(address + struct_member_offset(st, idx, self.target), m)?;
+                }
+            },
+            _ => {
+                let value = self.const_value(c)?;
+                unsafe { write_value(address, &c.get_type(), value, self.target) };
+            },
+        }
+        Ok(())
+    }
+
+    // Aggregate constants are created once, and live as long as the interpreter
+    fn constant_address(&mut self, c: &Constant) -> ExecResult<usize>
+    {
+        let key = format!("{:?}", c);
+        if let Some(&address) = self.constants.get(&key) {
+            return Ok(address);
+        }
+
+        let storage = Allocation::for_type(&c.get_type(), self.target);
+        let address = storage.address();
+        self.write_constant(address, c)?;
+        self.memory.push(storage);
+        self.constants.insert(key, address);
+        Ok(address)
+    }
+
+    fn eval(&mut self, op: &Operand) -> ExecResult<Value>
+    {
+        match *op
+        {
+            Operand::Var(ref v) => self.read_var(v),
+            Operand::AddressOf(ref v) => Ok(Value::Int(self.var_address(v) as u64)),
+            Operand::Dereference(ref v) => {
+                let ptr = self.read_var(v)?.as_address();
+                match v.typ.get_pointer_element_type()
+                {
+                    Some(inner) => self.read(ptr, inner),
+                    None => error(format!("Dereference of {}, which is not a pointer", v)),
+                }
+            },
+            Operand::Const(ref c) => self.const_value(c),
+            Operand::Func(ref name) => self.function_address(name).map(|a| Value::Int(a as u64)),
+            Operand::SizeOf(ref typ) => Ok(Value::Int(size_of(typ, self.target) as u64)),
+        }
+    }
+
+    // The address of the aggregate of type typ an operand refers to
+    fn aggregate_address(&mut self, op: &Operand, typ: &Type) -> ExecResult<usize>
+    {
+        match *op
+        {
+            Operand::Var(ref v) => {
+                let address = self.var_address(v);
+                if v.typ.is_pointer_to(typ) {
+                    Ok(unsafe { read_pointer(address) })
+                } else {
+                    Ok(address)
+                }
+            },
+            Operand::Dereference(ref v) => {
+                let address = self.var_address(v);
+                Ok(unsafe { read_pointer(address) })
+            },
+            Operand::Const(ref c) => self.constant_address(c),
+            _ => error(format!("{} is not an object of type {}", op, typ)),
+        }
+    }
+
+    // Assign an operand to the memory at address, which holds an object of type typ
+    fn store(&mut self, address: usize, typ: &Type, src: &Operand) -> ExecResult<()>
+    {
+        if let Type::Optional(ref inner) = *typ {
+            let src_type = src.get_type(self.target.int_size);
+            if src_type != *typ && !src_type.is_pointer_to(typ) && src_type != Type::Unknown {
+                unsafe { write_value(address, &Type::Bool, Value::from_bool(true), self.target) };
+                return self.store(address + optional_data_offset(inner, self.target), inner, src);
+            }
+        }
+
+        if typ.pass_by_value() {
+            let value = self.eval(src)?;
+            unsafe { write_value(address, typ, value, self.target) };
+        } else {
+            let src_address = self.aggregate_address(src, typ)?;
+            unsafe { copy_memory(address, src_address, size_of(typ, self.target)) };
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, dst: &Var, src: usize, typ: &Type) -> ExecResult<()>
+    {
+        if typ.pass_by_value() {
+            let value = self.read(src, typ)?;
+            self.write_var(dst, value)
+        } else {
+            self.bind_or_copy(dst, src, typ);
+            Ok(())
+        }
+    }
+
+    fn string_parts(&mut self, op: &Operand) -> ExecResult<(usize, u64)>
+    {
+        let address = self.aggregate_address(op, &Type::String)?;
+        Ok((unsafe { read_pointer(address) }, self.read_len(address)))
+    }
+
+    fn string_op(&mut self, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand) -> ExecResult<()>
+    {
+        let (a_data, a_len) = self.string_parts(left)?;
+        let (b_data, b_len) = self.string_parts(right)?;
+        if op == BinaryOperator::Add {
+            let len = a_len + b_len;
+            let data = unsafe { libc::malloc(len as usize) } as usize;
+            if data == 0 && len > 0 {
+                return error("Out of memory".into());
+            }
+
+            unsafe {
+                copy_memory(data, a_data, a_len as usize);
+                copy_memory(data + a_len as usize, b_data, b_len as usize);
+            }
+            let address = self.var_address(dst);
+            self.write_slice(address, data, len);
+            Ok(())
+        } else {
+            let ordering = unsafe { read_bytes(a_data, a_len as usize).cmp(read_bytes(b_data, b_len as usize)) };
+            match compare(op, ordering)
+            {
+                Some(result) => self.write_var(dst, Value::from_bool(result)),
+                None => error(format!("Operator {} is not supported on strings", op)),
+            }
+        }
+    }
+
+    fn cast(&mut self, dst: &Var, src: &Operand) -> ExecResult<()>
+    {
+        let src_type = src.get_type(self.target.int_size);
+        let value = match (&dst.typ, &src_type)
+        {
+            (&Type::Pointer(_), &Type::Array(_)) => Value::Int(self.aggregate_address(src, &src_type)? as u64),
+            (&Type::Float(_), &Type::Int(_)) => Value::Float(self.eval(src)?.as_i64() as f64),
+            (&Type::Float(_), &Type::UInt(_)) => Value::Float(self.eval(src)?.as_u64() as f64),
+            (&Type::Int(_), &Type::Float(_)) => Value::Int(self.eval(src)?.as_f64() as i64 as u64),
+            (&Type::UInt(_), &Type::Float(_)) => Value::Int(self.eval(src)?.as_f64() as u64),
+            (&Type::Float(_), &Type::Float(_)) => self.eval(src)?,
+            (dst_type, src_type) if dst_type.pass_by_value() && src_type.pass_by_value() => {
+                // Integers are sign extended when the source is signed, writing them truncates
+                Value::Int(self.eval(src)?.as_u64())
+            },
+            _ => return error(format!("Cannot cast {} to {}", src_type, dst.typ)),
+        };
+
+        self.write_var(dst, value)
+    }
+
+    fn next_char(&mut self, dst: Option<&Var>, args: &[Operand]) -> ExecResult<()>
+    {
+        if args.len() != 3 {
+            return error(format!("{} expects 3 arguments", NEXT_CHAR_FUNCTION));
+        }
+
+        let data = self.eval(&args[0])?.as_address();
+        let len = self.eval(&args[1])?.as_u64();
+        let index_ptr = self.eval(&args[2])?.as_address();
+        let uint_type = self.target.native_uint_type.clone();
+        let index = unsafe { read_value(index_ptr, &uint_type, self.target).as_u64() };
+        let byte = |offset: u64| unsafe { u32::from(*((data + offset as usize) as *const u8)) };
+
+        let lead = byte(index);
+        let (num_bytes, mask) = if lead >= 0xF0 {
+            (4, 0x07)
+        } else if lead >= 0xE0 {
+            (3, 0x0F)
+        } else if lead >= 0xC0 {
+            (2, 0x1F)
+        } else {
+            (1, 0x7F)
+        };
+
+        let mut code_point = lead & mask;
+        let mut offset = 1;
+        while offset < num_bytes && index + offset < len {
+            code_point = (code_point << 6) | (byte(index + offset) & 0x3F);
+            offset += 1;
+        }
+
+        unsafe { write_value(index_ptr, &uint_type, Value::Int(index + offset), self.target) };
+        if let Some(dst) = dst {
+            self.write_var(dst, Value::Int(u64::from(code_point)))?;
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, dst: Option<&'a Var>, func: &'a str, args: &'a [Operand]) -> ExecResult<()>
+    {
+        // A variable holding a function pointer
+        let name = match self.lookup(func)
+        {
+            Some(binding) => self.function_at(unsafe { read_pointer(binding.address) })?,
+            None => func,
+        };
+
+        if name == NEXT_CHAR_FUNCTION {
+            return self.next_char(dst, args);
+        }
+
+        let function = match self.find_function(name)
+        {
+            Some(function) => function,
+            None => return error(format!("Unknown function {}", name)),
+        };
+
+        if function.external {
+            let symbol = function.sig.symbol_name();
+            let extern_function = match find_extern_function(symbol)
+            {
+                Some(f) => f,
+                None => return error(format!("Extern function {} is not available in the interpreter", symbol)),
+            };
+
+            let values = args.iter().map(|a| self.eval(a)).collect::<ExecResult<Vec<_>>>()?;
+            let result = extern_function(&values)?;
+            if let Some(dst) = dst {
+                let result = normalize(&dst.typ, result, self.target);
+                self.write_var(dst, result)?;
+            }
+            return Ok(());
+        }
+
+        if function.blocks.is_empty() {
+            return error(format!("Function {} is not available in the interpreter, its code is in a library", name));
+        }
+
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return error(format!("Stack overflow calling {}", name));
+        }
+
+        let mut scope = Scope::new();
+        for (arg, operand) in function.sig.args.iter().zip(args) {
+            let address = if arg.typ.pass_by_value() {
+                let value = self.eval(operand)?;
+                let storage = Allocation::for_type(&arg.typ, self.target);
+                unsafe { write_value(storage.address(), &arg.typ, value, self.target) };
+                let address = storage.address();
+                scope.allocations.push(storage);
+                address
+            } else if let Some(address) = self.argument_reference(operand, &arg.typ) {
+                address
+            } else {
+                let src = self.aggregate_address(operand, &arg.typ)?;
+                let storage = Allocation::for_type(&arg.typ, self.target);
+                unsafe { copy_memory(storage.address(), src, size_of(&arg.typ, self.target)) };
+                let address = storage.address();
+                scope.allocations.push(storage);
+                address
+            };
+
+            scope.bindings.insert(arg.name.clone(), Binding{address, alias: false});
+        }
+
+        self.frames.push(Frame::new(function, dst, scope));
+        Ok(())
+    }
+
+    // Aggregate arguments which are passed by reference, the others are copied
+    fn argument_reference(&mut self, op: &Operand, typ: &Type) -> Option<usize>
+    {
+        match *op
+        {
+            Operand::Var(ref v) if v.typ.is_pointer_to(typ) => {
+                let address = self.var_address(v);
+                Some(unsafe { read_pointer(address) })
+            },
+            Operand::Var(ref v) => self.lookup(&v.name).and_then(|b| if b.alias {Some(b.address)} else {None}),
+            Operand::AddressOf(ref v) => Some(self.var_address(v)),
+            Operand::Dereference(ref v) => {
+                let address = self.var_address(v);
+                Some(unsafe { read_pointer(address) })
+            },
+            _ => None,
+        }
+    }
+
+    fn return_value(&mut self, op: &Operand) -> ExecResult<ReturnValue>
+    {
+        let func = self.frame().func;
+        let typ = &func.sig.return_type;
+        if typ.pass_by_value() {
+            let value = self.eval(op)?;
+            Ok(ReturnValue::Scalar(normalize(typ, value, self.target)))
+        } else {
+            let address = self.aggregate_address(op, typ)?;
+            let data = unsafe { read_bytes(address, size_of(typ, self.target)).to_vec() };
+            Ok(ReturnValue::Aggregate(data))
+        }
+    }
+
+    fn store_return_value(&mut self, dst: &Var, value: Option<ReturnValue>) -> ExecResult<()>
+    {
+        match value
+        {
+            Some(ReturnValue::Scalar(v)) => self.write_var(dst, v),
+            Some(ReturnValue::Aggregate(data)) => {
+                let address = self.var_address(dst);
+                unsafe { copy_memory(address, data.as_ptr() as usize, data.len()) };
+                Ok(())
+            },
+            None => error(format!("Function returns nothing, but its result is stored in {}", dst)),
+        }
+    }
+
+    fn bounds_check(&mut self, index: &Operand, len: &Operand, span: &Span) -> ExecResult<()>
+    {
+        let index_type = index.get_type(self.target.int_size);
+        let idx = self.eval(index)?;
+        let len = self.eval(len)?.as_u64();
+        let native_index = normalize(&self.target.native_uint_type, idx, self.target).as_u64();
+        if native_index < len {
+            return Ok(());
+        }
+
+        let printed_index = match index_type
+        {
+            Type::Int(_) => format!("{}", idx.as_i64()),
+            _ => format!("{}", idx.as_u64()),
+        };
+        self.panic(span, &format!("index out of bounds, the index is {} but the length is {}", printed_index, len))
+    }
+
+    fn range_check(&mut self, seq: &Var, start: &Operand, end: &Operand, span: &Span) -> ExecResult<()>
+    {
+        let (address, typ) = self.object(seq);
+        let len = match typ
+        {
+            Type::Array(ref at) => at.len() as u64,
+            Type::Slice(_) | Type::String => self.read_len(address),
+            _ => return error(format!("Range check on {}, which is not a sequence", seq)),
+        };
+
+        let start = self.eval(start)?.as_u64();
+        let end = self.eval(end)?.as_u64();
+        if start > end || end > len {
+            return self.panic(span, &format!("range {}..{} out of bounds, the length is {}", start, end, len));
+        }
+
+        if typ == Type::String {
+            let data = unsafe { read_pointer(address) };
+            let is_boundary = |i: u64| i == len || unsafe { *((data + i as usize) as *const u8) } & 0xC0 != 0x80;
+            if !is_boundary(start) || !is_boundary(end) {
+                return self.panic(span, &format!("range {}..{} is not on a character boundary", start, end));
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, instr: &'a Instruction) -> ExecResult<Flow>
+    {
+        match *instr
+        {
+            Instruction::Store{ref dst, ref src} => {
+                let src_type = src.get_type(self.target.int_size);
+                let address = self.var_address(dst);
+                if dst.typ.is_pointer_to(&src_type) {
+                    let ptr = unsafe { read_pointer(address) };
+                    self.store(ptr, &src_type, src)?;
+                } else {
+                    self.store(address, &dst.typ, src)?;
+                }
+            },
+
+            Instruction::Load{ref dst, ref ptr} => {
+                let address = self.var_address(ptr);
+                match ptr.typ
+                {
+                    Type::Optional(ref inner) => self.load(dst, address + optional_data_offset(inner, self.target), inner)?,
+                    Type::Pointer(ref inner) => self.load(dst, unsafe { read_pointer(address) }, inner)?,
+                    _ => self.load(dst, address, &ptr.typ)?,
+                }
+            },
+
+            Instruction::LoadMember{ref dst, ref obj, ref member_index} |
+            Instruction::AddressOfMember{ref dst, ref obj, ref member_index} => {
+                let (address, member_type) = self.member_address(obj, member_index)?;
+                if dst.typ.is_pointer_to(&member_type) {
+                    let dst_address = self.var_address(dst);
+                    unsafe { write_pointer(dst_address, address) };
+                } else {
+                    self.bind_or_copy(dst, address, &member_type);
+                }
+            },
+
+            Instruction::StoreMember{ref obj, ref member_index, ref src} => {
+                let (address, member_type) = self.member_address(obj, member_index)?;
+                self.store(address, &member_type, src)?;
+            },
+
+            Instruction::AddressOf{ref dst, ref obj} => {
+                let address = self.var_address(obj);
+                // A pointer to a member is already an address
+                let value = if obj.typ == dst.typ && obj.typ.is_pointer() {
+                    unsafe { read_pointer(address) }
+                } else {
+                    address
+                };
+                self.write_var(dst, Value::Int(value as u64))?;
+            },
+
+            Instruction::GetProperty{ref dst, ref obj, prop} => {
+                let (address, typ) = self.object(obj);
+                let value = match (&typ, prop)
+                {
+                    (&Type::Array(ref at), ByteCodeProperty::Len) => Value::Int(at.len() as u64),
+                    (&Type::Slice(_), ByteCodeProperty::Len) | (&Type::String, ByteCodeProperty::Len) => Value::Int(self.read_len(address)),
+                    (&Type::Slice(_), ByteCodeProperty::Data) | (&Type::String, ByteCodeProperty::Data) => Value::Int(unsafe { read_pointer(address) } as u64),
+                    (&Type::Sum(_), ByteCodeProperty::SumTypeIndex) => unsafe { read_value(address, &self.target.native_uint_type, self.target) },
+                    _ => return error(format!("{} has no property {}", obj, prop)),
+                };
+                self.write_var(dst, value)?;
+            },
+
+            Instruction::SetProperty{ref obj, prop, val} => {
+                let (address, typ) = self.object(obj);
+                match (&typ, prop)
+                {
+                    (&Type::Sum(_), ByteCodeProperty::SumTypeIndex) => unsafe {
+                        write_value(address, &self.target.native_uint_type, Value::Int(val as u64), self.target)
+                    },
+                    _ => return error(format!("Cannot set property {} of {}", prop, obj)),
+                }
+            },
+
+            Instruction::UnaryOp{ref dst, op, ref src} => {
+                let value = self.eval(src)?;
+                let result = match (op, &dst.typ)
+                {
+                    (UnaryOperator::Sub, &Type::Int(_)) | (UnaryOperator::Sub, &Type::UInt(_)) => Value::Int(value.as_u64().wrapping_neg()),
+                    (UnaryOperator::Sub, &Type::Float(_)) => Value::Float(-value.as_f64()),
+                    (UnaryOperator::Not, &Type::Bool) => Value::from_bool(!value.as_bool()),
+                    _ => return error(format!("Operator {} is not supported on {}", op, dst.typ)),
+                };
+                self.write_var(dst, result)?;
+            },
+
+            Instruction::BinaryOp{ref dst, op, ref left, ref right} => {
+                let typ = left.get_type(self.target.int_size);
+                if typ == Type::String {
+                    self.string_op(dst, op, left, right)?;
+                } else {
+                    let l = self.eval(left)?;
+                    let r = self.eval(right)?;
+                    match binary_op_value(wrapping_operator(op), &typ, l, r)
+                    {
+                        Ok(Some(result)) => self.write_var(dst, result)?,
+                        Ok(None) => return error(format!("Arithmetic exception in {} {} {}", left, op, right)),
+                        Err(msg) => return error(msg),
+                    }
+                }
+            },
+
+            Instruction::CheckedBinaryOp{ref dst, op, ref left, ref right, ref span} => {
+                let typ = left.get_type(self.target.int_size);
+                if typ == Type::String {
+                    self.string_op(dst, op, left, right)?;
+                } else {
+                    let l = self.eval(left)?;
+                    let r = self.eval(right)?;
+                    match binary_op_value(op, &typ, l, r)
+                    {
+                        Ok(Some(result)) => self.write_var(dst, result)?,
+                        Ok(None) if is_division(op) && r.as_u64() == 0 => return self.panic(span, "division by zero"),
+                        Ok(None) => return self.panic(span, "integer overflow"),
+                        Err(msg) => return error(msg),
+                    }
+                }
+            },
+
+            Instruction::Call{ref dst, ref func, ref args} => {
+                self.call(dst.as_ref(), func, args)?;
+            },
+
+            Instruction::Slice{ref dst, ref src, ref start, ref len} => {
+                let (data, _) = self.member_address(src, start)?;
+                let len = self.eval(len)?.as_u64();
+                let address = self.var_address(dst);
+                self.write_slice(address, data, len);
+            },
+
+            Instruction::MakeSlice{ref dst, ref data, ref len} => {
+                let data = self.read_var(data)?.as_address();
+                let len = self.read_var(len)?.as_u64();
+                let address = self.var_address(dst);
+                self.write_slice(address, data, len);
+            },
+
+            Instruction::Cast{ref dst, ref src} => {
+                self.cast(dst, src)?;
+            },
+
+            Instruction::LoadOptionalFlag{ref dst, ref obj} => {
+                let (address, typ) = self.object(obj);
+                if !typ.is_optional() {
+                    return error(format!("{} is not an optional", obj));
+                }
+                let flag = unsafe { read_value(address, &Type::Bool, self.target) };
+                self.write_var(dst, flag)?;
+            },
+
+            Instruction::StoreNil(ref var) => {
+                let (address, typ) = self.object(var);
+                if !typ.is_optional() {
+                    return error(format!("{} is not an optional", var));
+                }
+                unsafe { write_value(address, &Type::Bool, Value::from_bool(false), self.target) };
+            },
+
+            Instruction::StackAlloc(ref var) => {
+                if self.lookup(&var.name).is_none() {
+                    self.bind_storage(var);
+                }
+            },
+
+            Instruction::HeapAlloc(ref var) => {
+                let size = match var.typ.get_pointer_element_type()
+                {
+                    Some(inner) => size_of(inner, self.target).max(1),
+                    None => return error(format!("Heap allocation of {}, which is not a pointer", var)),
+                };
+
+                let ptr = unsafe { libc::malloc(size) } as usize;
+                if ptr == 0 {
+                    return error("Out of memory".into());
+                }
+                self.write_var(var, Value::Int(ptr as u64))?;
+            },
+
+            Instruction::Delete(ref var) => {
+                let ptr = self.read_var(var)?.as_address();
+                unsafe { libc::free(ptr as *mut libc::c_void) };
+            },
+
+            Instruction::StartScope => {
+                self.frame_mut().scopes.push(Scope::new());
+            },
+
+            Instruction::EndScope => {
+                let frame = self.frame_mut();
+                if frame.scopes.len() > 1 {
+                    frame.scopes.pop();
+                }
+            },
+
+            Instruction::Return(ref op) => {
+                let value = self.return_value(op)?;
+                return Ok(Flow::Return(Some(value)));
+            },
+
+            Instruction::ReturnVoid => return Ok(Flow::Return(None)),
+
+            Instruction::Branch(bb) => return Ok(Flow::Jump(bb)),
+
+            Instruction::BranchIf{ref cond, on_true, on_false} => {
+                let cond = self.eval(cond)?.as_bool();
+                return Ok(Flow::Jump(if cond {on_true} else {on_false}));
+            },
+
+            Instruction::BoundsCheck{ref index, ref len, ref span} => {
+                self.bounds_check(index, len, span)?;
+            },
+
+            Instruction::RangeCheck{ref seq, ref start, ref end, ref span} => {
+                self.range_check(seq, start, end, span)?;
+            },
+
+            Instruction::Panic{ref msg, ref span} => {
+                let (address, _) = self.object(msg);
+                let data = unsafe { read_pointer(address) };
+                let len = self.read_len(address) as usize;
+                let msg = String::from_utf8_lossy(unsafe { read_bytes(data, len) }).into_owned();
+                return self.panic(span, &msg);
+            },
+
+            Instruction::Phi{..} => {
+                return error("Phi nodes cannot be interpreted, they have to be removed first".into());
+            },
+        }
+
+        Ok(Flow::Next)
+    }
+
+    // Run until the function on top of the call stack returns
+    fn run(&mut self) -> ExecResult<Option<ReturnValue>>
+    {
+        let depth = self.frames.len() - 1;
+        loop {
+            let (func, block, index) = {
+                let frame = self.frame();
+                (frame.func, frame.block, frame.index)
+            };
+
+            let instr = match func.blocks.get(&block).and_then(|bb| bb.instructions.get(index))
+            {
+                Some(instr) => instr,
+                None => return error(format!("Block {} of function {} does not end with a terminator", bb_name(block), func.sig.name)),
+            };
+
+            self.frame_mut().index += 1;
+            match self.execute(instr)?
+            {
+                Flow::Next => (),
+                Flow::Jump(bb) => {
+                    let frame = self.frame_mut();
+                    frame.block = bb;
+                    frame.index = 0;
+                },
+                Flow::Return(value) => {
+                    let frame = self.frames.pop().expect("Internal Compiler Error: empty call stack");
+                    if self.frames.len() == depth {
+                        return Ok(value);
+                    }
+
+                    if let Some(dst) = frame.return_dst {
+                        self.store_return_value(dst, value)?;
+                    }
+                },
+            }
+        }
+    }
+
+    // The string[] passed to main, the strings point into nul terminated copies of the arguments
+    fn command_line_arguments(&mut self, args: &[String], typ: &Type) -> usize
+    {
+        let stride = layout_of(&Type::String, self.target).stride();
+        let array = Allocation::new(stride * args.len());
+        for (idx, arg) in args.iter().enumerate() {
+            let data = self.string_data(arg);
+            self.write_slice(array.address() + idx * stride, data, arg.len() as u64);
+        }
+
+        let slice = Allocation::for_type(typ, self.target);
+        let address = slice.address();
+        self.write_slice(address, array.address(), args.len() as u64);
+        self.memory.push(array);
+        self.memory.push(slice);
+        address
+    }
+
+    fn run_main(&mut self, args: &[String]) -> ExecResult<i32>
+    {
+        let module = self.module;
+        let name = module.main_function_name();
+        let main = match module.functions.get(&name)
+        {
+            Some(main) => main,
+            None => return error(format!("No main function found in module {}", module.name)),
+        };
+
+        let mut scope = Scope::new();
+        if let Some(arg) = main.sig.args.first() {
+            let address = self.command_line_arguments(args, &arg.typ);
+            scope.bindings.insert(arg.name.clone(), Binding{address, alias: false});
+        }
+
+        self.frames.push(Frame::new(main, None, scope));
+        match self.run()?
+        {
+            Some(ReturnValue::Scalar(v)) => Ok(v.as_u64() as i32),
+            _ => Ok(0),
+        }
+    }
+}
+
+/*
+    Run the main function of a module, args are the command line arguments, starting with the program name.
+    Returns the exit code of the program.
+*/
+pub fn interpret(module: &ByteCodeModule, target: &Target, args: &[String]) -> Result<i32, String>
+{
+    let result = Interpreter::new(module, target).and_then(|mut interpreter| interpreter.run_main(args));
+    match result
+    {
+        Ok(code) | Err(Trap::Exit(code)) => Ok(code),
+        Err(Trap::Error(msg)) => Err(msg),
+    }
+}
@@ -0,0 +1,83 @@
+use ast::IntSize;
+use bytecode::{OptimizationLevel, optimize_module, parse_module, remove_phi_nodes};
+use bytecode::test::{Test, generate_byte_code, load_tests, run_tests};
+use target::Target;
+use super::interpret;
+
+fn run_code(code: &str, name: &str, dump: bool) -> Result<i64, String>
+{
+    let mut bc_mod = match generate_byte_code(code, dump)
+    {
+        Ok(bc_mod) => bc_mod,
+        Err(e) => return Err(format!("Compile error: {}", e)),
+    };
+
+    optimize_module(&mut bc_mod, OptimizationLevel::Normal).map_err(|e| format!("Optimization error: {}", e))?;
+    let target = Target::new(IntSize::I32, "");
+    let ret = interpret(&bc_mod, &target, &[name.into()])?;
+    // Exit codes are truncated to a byte by the operating system
+    Ok(i64::from(ret & 0xFF))
+}
+
+fn run(test: &Test) -> Result<i64, String>
+{
+    run_code(&test.code, &test.name, false)
+}
+
+// Tests which link C code, or import the std package, can only be run by the LLVM backend
+#[test]
+fn test_all()
+{
+    let tests: Vec<Test> = load_tests().into_iter().filter(|t| t.is_standalone()).collect();
+    assert!(run_tests(&tests, run) == 0);
+}
+
+#[test]
+fn test_recursion()
+{
+    let ret = run_code(r#"
+fn fib(n: int) -> int:
+    if n < 2: n else fib(n - 1) + fib(n - 2)
+
+fn main() -> int:
+    fib(15)
+"#, "test", false);
+
+    assert_eq!(ret, Ok(610 & 0xFF));
+}
+
+#[test]
+fn test_command_line_arguments()
+{
+    let ret = run_code(r#"
+fn main(args: string[]) -> int:
+    (args.len + args[0].len) as int
+"#, "program", false);
+
+    assert_eq!(ret, Ok(8));
+}
+
+#[test]
+fn test_phi_nodes()
+{
+    let mut module = parse_module(r#"module test
+options
+
+fn test::main() -> i32 {
+entry:
+  brif (bool false) block1 block2
+block1:
+  store ("x.1": i32) (i32 5)
+  br block2
+block2:
+  phi ("x.2": i32) [entry: (i32 7), block1: ("x.1": i32)]
+  ret ("x.2": i32)
+}
+"#, "test.bc").expect("Parsing succeeded");
+
+    let target = Target::new(IntSize::I32, "");
+    assert!(interpret(&module, &target, &["test".into()]).is_err());
+
+    remove_phi_nodes(&mut module).expect("Removing phi nodes succeeded");
+    assert_eq!(interpret(&module, &target, &["test".into()]), Ok(7));
+}
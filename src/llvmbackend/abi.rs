@@ -0,0 +1,403 @@
+/*
+    The C calling convention, used by extern functions and functions exported with export "C".
+
+    On x86-64 System V, small aggregates (structs, strings, slices, optionals and sum types of at most
+    16 bytes) are split in eightbytes which are passed in integer or SSE registers, and bigger aggregates
+    are passed in memory. On other platforms, aggregates are always passed in memory.
+*/
+use std::cmp;
+use libc::{c_char, c_int, c_uint};
+use llvm::{LLVMTypeKind, LLVMAttributeReturnIndex};
+use llvm::core::*;
+use llvm::prelude::*;
+use llvm::target::*;
+
+use ast::*;
+use bytecode::{Operand, Var};
+use super::context::Context;
+use super::instructions::{copy, get_function_arg, get_operand};
+use super::target::TargetMachine;
+use super::types::to_llvm_type;
+use super::valueref::ValueRef;
+
+const INTEGER_REGISTERS: usize = 6;
+const SSE_REGISTERS: usize = 8;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+enum Class
+{
+    NoClass,
+    Integer,
+    Float,
+    Double,
+}
+
+fn merge_classes(a: Class, b: Class) -> Class
+{
+    match (a, b)
+    {
+        (Class::NoClass, c) | (c, Class::NoClass) => c,
+        (Class::Integer, _) | (_, Class::Integer) => Class::Integer,
+        (Class::Double, _) | (_, Class::Double) => Class::Double,
+        _ => Class::Float,
+    }
+}
+
+// How an argument or return value is passed
+#[derive(Clone, Debug)]
+pub enum ArgPassing
+{
+    Direct,                     // As is, for scalars and pointers
+    Coerced(Vec<LLVMTypeRef>),  // Aggregate passed in registers, as one value per eightbyte
+    Indirect,                   // Aggregate passed in memory, as a byval argument or a sret return value
+}
+
+pub struct CFunctionABI
+{
+    pub function_type: LLVMTypeRef,
+    pub ret: ArgPassing,
+    pub args: Vec<ArgPassing>,
+}
+
+impl CFunctionABI
+{
+    // The index of the first LLVM parameter of each argument
+    pub fn param_indices(&self) -> Vec<usize>
+    {
+        let mut idx = if let ArgPassing::Indirect = self.ret {1} else {0};
+        let mut indices = Vec::with_capacity(self.args.len());
+        for arg in &self.args {
+            indices.push(idx);
+            idx += match *arg {
+                ArgPassing::Coerced(ref types) => types.len(),
+                _ => 1,
+            };
+        }
+        indices
+    }
+}
+
+fn is_system_v_x86_64(target_machine: &TargetMachine) -> bool
+{
+    let triplet = &target_machine.target.triplet;
+    triplet.starts_with("x86_64") && !triplet.contains("windows")
+}
+
+// Classify the scalars of typ, which starts at offset, returns false if typ has to be passed in memory
+unsafe fn classify(target_machine: &TargetMachine, typ: LLVMTypeRef, offset: usize, classes: &mut [Class]) -> bool
+{
+    let class = match LLVMGetTypeKind(typ)
+    {
+        LLVMTypeKind::LLVMIntegerTypeKind | LLVMTypeKind::LLVMPointerTypeKind => Class::Integer,
+        LLVMTypeKind::LLVMFloatTypeKind => Class::Float,
+        LLVMTypeKind::LLVMDoubleTypeKind => Class::Double,
+        LLVMTypeKind::LLVMStructTypeKind => {
+            for i in 0..LLVMCountStructElementTypes(typ) {
+                let element_offset = LLVMOffsetOfElement(target_machine.target_data, typ, i) as usize;
+                if !classify(target_machine, LLVMStructGetTypeAtIndex(typ, i), offset + element_offset, classes) {
+                    return false;
+                }
+            }
+            return true;
+        },
+        LLVMTypeKind::LLVMArrayTypeKind => {
+            let element_type = LLVMGetElementType(typ);
+            let element_size = LLVMABISizeOfType(target_machine.target_data, element_type) as usize;
+            for i in 0..LLVMGetArrayLength(typ) as usize {
+                if !classify(target_machine, element_type, offset + i * element_size, classes) {
+                    return false;
+                }
+            }
+            return true;
+        },
+        _ => return false,
+    };
+
+    let idx = offset / 8;
+    if idx >= classes.len() {
+        return false;
+    }
+
+    classes[idx] = merge_classes(classes[idx], class);
+    true
+}
+
+// The classes of the eightbytes of an aggregate, or None if it is passed in memory
+unsafe fn classify_aggregate(target_machine: &TargetMachine, typ: LLVMTypeRef) -> Option<Vec<Class>>
+{
+    if !is_system_v_x86_64(target_machine) {
+        return None;
+    }
+
+    let size = LLVMABISizeOfType(target_machine.target_data, typ) as usize;
+    if size == 0 || size > 16 {
+        return None;
+    }
+
+    let mut classes = vec![Class::NoClass; (size + 7) / 8];
+    if classify(target_machine, typ, 0, &mut classes) {
+        Some(classes)
+    } else {
+        None
+    }
+}
+
+// The type used to pass an eightbyte in a register
+unsafe fn eightbyte_type(context: LLVMContextRef, class: Class, size: usize) -> LLVMTypeRef
+{
+    match class
+    {
+        Class::Double => LLVMDoubleTypeInContext(context),
+        Class::Float if size <= 4 => LLVMFloatTypeInContext(context),
+        Class::Float => LLVMVectorType(LLVMFloatTypeInContext(context), 2),
+        Class::Integer | Class::NoClass => LLVMIntTypeInContext(context, (size * 8) as c_uint),
+    }
+}
+
+unsafe fn coerced_types(context: LLVMContextRef, target_machine: &TargetMachine, typ: LLVMTypeRef, classes: &[Class]) -> Vec<LLVMTypeRef>
+{
+    let size = LLVMABISizeOfType(target_machine.target_data, typ) as usize;
+    classes.iter()
+        .enumerate()
+        .map(|(idx, class)| eightbyte_type(context, *class, cmp::min(8, size - idx * 8)))
+        .collect()
+}
+
+fn is_sse(class: &Class) -> bool
+{
+    *class == Class::Float || *class == Class::Double
+}
+
+fn is_aggregate(typ: &Type) -> bool
+{
+    !typ.pass_by_value() && *typ != Type::Void
+}
+
+pub unsafe fn c_function_abi(context: LLVMContextRef, target_machine: &TargetMachine, ft: &FuncType) -> CFunctionABI
+{
+    let mut free_int_registers = INTEGER_REGISTERS;
+    let mut free_sse_registers = SSE_REGISTERS;
+    let mut param_types = Vec::with_capacity(ft.args.len());
+
+    let llvm_return_type = to_llvm_type(context, target_machine, &ft.return_type);
+    let (ret, ret_type) = if !is_aggregate(&ft.return_type) {
+        (ArgPassing::Direct, llvm_return_type)
+    } else if let Some(classes) = classify_aggregate(target_machine, llvm_return_type) {
+        let mut types = coerced_types(context, target_machine, llvm_return_type, &classes);
+        let ret_type = if types.len() == 1 {
+            types[0]
+        } else {
+            LLVMStructTypeInContext(context, types.as_mut_ptr(), types.len() as c_uint, 0)
+        };
+        (ArgPassing::Coerced(types), ret_type)
+    } else {
+        // The caller passes a pointer to the return value in the first integer register
+        free_int_registers -= 1;
+        param_types.push(LLVMPointerType(llvm_return_type, 0));
+        (ArgPassing::Indirect, LLVMVoidTypeInContext(context))
+    };
+
+    let mut args = Vec::with_capacity(ft.args.len());
+    for arg in &ft.args {
+        let llvm_type = to_llvm_type(context, target_machine, arg);
+        let passing = match *arg
+        {
+            // Arrays decay to a pointer, like in C
+            Type::Array(_) => {
+                free_int_registers = free_int_registers.saturating_sub(1);
+                param_types.push(LLVMPointerType(llvm_type, 0));
+                ArgPassing::Direct
+            },
+
+            _ if !is_aggregate(arg) => {
+                if let Type::Float(_) = *arg {
+                    free_sse_registers = free_sse_registers.saturating_sub(1);
+                } else {
+                    free_int_registers = free_int_registers.saturating_sub(1);
+                }
+                param_types.push(llvm_type);
+                ArgPassing::Direct
+            },
+
+            _ => {
+                // An aggregate is only passed in registers, if there are enough registers left for all of it
+                let in_registers = classify_aggregate(target_machine, llvm_type).and_then(|classes| {
+                    let sse = classes.iter().filter(|c| is_sse(c)).count();
+                    let int = classes.len() - sse;
+                    if int <= free_int_registers && sse <= free_sse_registers {
+                        free_int_registers -= int;
+                        free_sse_registers -= sse;
+                        Some(classes)
+                    } else {
+                        None
+                    }
+                });
+
+                if let Some(classes) = in_registers {
+                    let types = coerced_types(context, target_machine, llvm_type, &classes);
+                    param_types.extend(types.iter().cloned());
+                    ArgPassing::Coerced(types)
+                } else {
+                    param_types.push(LLVMPointerType(llvm_type, 0));
+                    ArgPassing::Indirect
+                }
+            },
+        };
+
+        args.push(passing);
+    }
+
+    CFunctionABI{
+        function_type: LLVMFunctionType(ret_type, param_types.as_mut_ptr(), param_types.len() as c_uint, ft.variadic as c_int),
+        ret: ret,
+        args: args,
+    }
+}
+
+// The C ABI of a function signature, None if it uses the Nomad calling convention
+pub unsafe fn sig_abi(ctx: &Context, sig: &FunctionSignature) -> Option<CFunctionABI>
+{
+    match sig.get_type()
+    {
+        Type::Func(ref ft) if ft.calling_convention == CallingConvention::C => Some(c_function_abi(ctx.context, ctx.target_machine, ft)),
+        _ => None,
+    }
+}
+
+unsafe fn enum_attribute(context: LLVMContextRef, name: &str) -> LLVMAttributeRef
+{
+    let kind = LLVMGetEnumAttributeKindForName(name.as_ptr() as *const c_char, name.len());
+    LLVMCreateEnumAttribute(context, kind, 0)
+}
+
+// Mark the sret and byval parameters, of a function or of a call
+pub unsafe fn add_abi_attributes(ctx: &Context, abi: &CFunctionABI, value: LLVMValueRef, call_site: bool)
+{
+    let add = |idx: usize, name: &str| {
+        // Parameter attributes start at index 1, index 0 is the return value
+        let attribute_idx = LLVMAttributeReturnIndex + idx as c_uint + 1;
+        let attribute = enum_attribute(ctx.context, name);
+        if call_site {
+            LLVMAddCallSiteAttribute(value, attribute_idx, attribute);
+        } else {
+            LLVMAddAttributeAtIndex(value, attribute_idx, attribute);
+        }
+    };
+
+    if let ArgPassing::Indirect = abi.ret {
+        add(0, "sret");
+    }
+
+    for (arg, idx) in abi.args.iter().zip(abi.param_indices()) {
+        if let ArgPassing::Indirect = *arg {
+            add(idx, "byval");
+        }
+    }
+}
+
+// Temporary memory to convert an aggregate from or to its eightbytes
+unsafe fn coerced_alloc(ctx: &mut Context, types: &[LLVMTypeRef], typ: &Type) -> (LLVMValueRef, LLVMValueRef)
+{
+    let mut types = types.to_vec();
+    let coerced_type = LLVMStructTypeInContext(ctx.context, types.as_mut_ptr(), types.len() as c_uint, 0);
+    let coerced = ctx.stack_alloc_llvm_type("coerced", coerced_type);
+    let aggregate = LLVMBuildBitCast(ctx.builder, coerced, LLVMPointerType(ctx.resolve_type(typ), 0), cstr!("aggregate"));
+    (coerced, aggregate)
+}
+
+// Split the aggregate pointed to by ptr into its eightbytes
+pub unsafe fn load_coerced(ctx: &mut Context, types: &[LLVMTypeRef], typ: &Type, ptr: LLVMValueRef) -> Vec<LLVMValueRef>
+{
+    let (coerced, aggregate) = coerced_alloc(ctx, types, typ);
+    copy(ctx, aggregate, ptr, ctx.resolve_type(typ));
+    (0..types.len())
+        .map(|idx| {
+            let element_ptr = LLVMBuildStructGEP(ctx.builder, coerced, idx as c_uint, cstr!("eightbyte_ptr"));
+            LLVMBuildLoad(ctx.builder, element_ptr, cstr!("eightbyte"))
+        })
+        .collect()
+}
+
+// Combine eightbytes into an aggregate, returns a pointer to it
+pub unsafe fn store_coerced(ctx: &mut Context, types: &[LLVMTypeRef], typ: &Type, values: &[LLVMValueRef]) -> LLVMValueRef
+{
+    let (coerced, aggregate) = coerced_alloc(ctx, types, typ);
+    for (idx, value) in values.iter().enumerate() {
+        let element_ptr = LLVMBuildStructGEP(ctx.builder, coerced, idx as c_uint, cstr!("eightbyte_ptr"));
+        LLVMBuildStore(ctx.builder, *value, element_ptr);
+    }
+    aggregate
+}
+
+// Call a function using the C calling convention
+pub unsafe fn gen_c_call(ctx: &mut Context, function: LLVMValueRef, ft: &FuncType, dst: Option<&Var>, args: &[Operand])
+{
+    let abi = c_function_abi(ctx.context, ctx.target_machine, ft);
+    let mut llvm_args = Vec::with_capacity(args.len() + 1);
+    if let ArgPassing::Indirect = abi.ret {
+        llvm_args.push(ctx.stack_alloc("sret", &ft.return_type));
+    }
+
+    for (idx, arg) in args.iter().enumerate() {
+        let value = get_function_arg(ctx, arg);
+        // The extra arguments of a variadic function are scalars, and are passed directly
+        match abi.args.get(idx)
+        {
+            Some(&ArgPassing::Coerced(ref types)) => llvm_args.extend(load_coerced(ctx, types, &ft.args[idx], value)),
+            _ => llvm_args.push(value),
+        }
+    }
+
+    let ret = LLVMBuildCall(ctx.builder, function, llvm_args.as_mut_ptr(), llvm_args.len() as c_uint, cstr!(""));
+    add_abi_attributes(ctx, &abi, ret, true);
+
+    let dst = match dst {
+        Some(dst) => dst,
+        None => return,
+    };
+
+    let ret_val = match abi.ret
+    {
+        ArgPassing::Direct => ValueRef::new(ret, ft.return_type.clone()),
+        ArgPassing::Indirect => ValueRef::new(llvm_args[0], ptr_type(ft.return_type.clone())),
+        ArgPassing::Coerced(ref types) => {
+            let values: Vec<_> = if types.len() == 1 {
+                vec![ret]
+            } else {
+                (0..types.len())
+                    .map(|idx| LLVMBuildExtractValue(ctx.builder, ret, idx as c_uint, cstr!("eightbyte")))
+                    .collect()
+            };
+            ValueRef::new(store_coerced(ctx, types, &ft.return_type, &values), ptr_type(ft.return_type.clone()))
+        },
+    };
+
+    ctx.set_variable(&dst.name, ret_val);
+}
+
+// Return from a function using the C calling convention
+pub unsafe fn gen_c_return(ctx: &mut Context, abi: &CFunctionABI, function: LLVMValueRef, return_type: &Type, operand: &Operand)
+{
+    let value = get_operand(ctx, operand);
+    match abi.ret
+    {
+        ArgPassing::Direct => {
+            LLVMBuildRet(ctx.builder, value.load(ctx));
+        },
+
+        ArgPassing::Indirect => {
+            let sret = ValueRef::new(LLVMGetParam(function, 0), ptr_type(return_type.clone()));
+            sret.store(ctx, &value);
+            LLVMBuildRetVoid(ctx.builder);
+        },
+
+        ArgPassing::Coerced(ref types) => {
+            let mut values = load_coerced(ctx, types, return_type, value.value);
+            if values.len() == 1 {
+                LLVMBuildRet(ctx.builder, values[0]);
+            } else {
+                LLVMBuildAggregateRet(ctx.builder, values.as_mut_ptr(), values.len() as c_uint);
+            }
+        },
+    }
+}
@@ -80,9 +80,14 @@ impl<'a> Context<'a>
     }
 
     pub fn stack_alloc(&mut self, name: &str, typ: &Type) -> LLVMValueRef
+    {
+        let typ = self.resolve_type(typ);
+        self.stack_alloc_llvm_type(name, typ)
+    }
+
+    pub fn stack_alloc_llvm_type(&mut self, name: &str, typ: LLVMTypeRef) -> LLVMValueRef
     {
         unsafe {
-            let typ = self.resolve_type(typ);
             let func = self.get_current_function();
             let entry_bb = LLVMGetEntryBasicBlock(func);
             let current_bb = LLVMGetInsertBlock(self.builder);
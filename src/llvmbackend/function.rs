@@ -2,12 +2,14 @@ use std::ffi::{CString};
 use std::collections::HashMap;
 use std::rc::Rc;
 use libc;
+use llvm::LLVMIntPredicate;
 use llvm::core::*;
 use llvm::prelude::*;
 
 use ast::*;
 use bytecode::*;
 use span::Span;
+use super::abi::{ArgPassing, sig_abi, add_abi_attributes, store_coerced, gen_c_return};
 use super::symboltable::FunctionInstance;
 use super::context::Context;
 use super::instructions::*;
@@ -16,21 +18,34 @@ use super::valueref::ValueRef;
 
 pub unsafe fn gen_function_sig(ctx: &mut Context, sig: &FunctionSignature, name_override: Option<&str>)
 {
-    let ret_type = ctx.resolve_type(&sig.return_type);
-    let mut arg_types: Vec<_> = sig.args.iter().map(|arg|{
-        let llvm_type = ctx.resolve_type(&arg.typ);
-        if arg.typ.pass_by_value() {
-            llvm_type
-        } else {
-            LLVMPointerType(llvm_type, 0)
-        }
-    }).collect();
+    let abi = sig_abi(ctx, sig);
+    let function_type = if let Some(ref abi) = abi {
+        abi.function_type
+    } else {
+        let ret_type = ctx.resolve_type(&sig.return_type);
+        let mut arg_types: Vec<_> = sig.args.iter().map(|arg|{
+            let llvm_type = ctx.resolve_type(&arg.typ);
+            if arg.typ.pass_by_value() {
+                llvm_type
+            } else {
+                LLVMPointerType(llvm_type, 0)
+            }
+        }).collect();
+
+        LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as libc::c_uint, sig.variadic as libc::c_int)
+    };
 
-    let function_type = LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as libc::c_uint, 0);
-    let llvm_name = name_override.unwrap_or(&sig.name);
+    let llvm_name = name_override.unwrap_or_else(|| sig.symbol_name());
     let cstring = CString::new(llvm_name.as_bytes()).expect("Invalid string");
     let name = cstring.as_ptr();
-    let func = LLVMAddFunction(ctx.module, name, function_type);
+    // Externs like exit, can already have been declared for the runtime
+    let mut func = LLVMGetNamedFunction(ctx.module, name);
+    if func.is_null() {
+        func = LLVMAddFunction(ctx.module, name, function_type);
+        if let Some(ref abi) = abi {
+            add_abi_attributes(ctx, abi, func, false);
+        }
+    }
     let fi = FunctionInstance::new(&sig.name, func, sig.return_type.clone(), sig.get_type());
     ctx.add_function(Rc::new(fi));
 }
@@ -41,6 +56,33 @@ pub unsafe fn gen_function_ptr(ctx: &mut Context, name: &str, func_ptr: LLVMValu
     ctx.add_function(Rc::new(fi));
 }
 
+unsafe fn gen_function_arg(ctx: &mut Context, arg: &Argument, var: LLVMValueRef)
+{
+    match arg.typ
+    {
+        Type::Func(ref ft) => {
+            gen_function_ptr(ctx, &arg.name, var, ft.return_type.clone(), arg.typ.clone());
+            ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
+        },
+
+        _ => {
+            if arg.typ.pass_by_value() {
+                if arg.mutable && !arg.typ.is_pointer() {
+                    // To make it mutable, copy the argument into a local variable
+                    // and use that instead
+                    let argcopy = LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&arg.typ), cstr!("argcopy"));
+                    LLVMBuildStore(ctx.builder, var, argcopy);
+                    ctx.set_variable(&arg.name, ValueRef::new(argcopy, ptr_type(arg.typ.clone())));
+                } else {
+                    ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
+                }
+            } else {
+                ctx.set_variable(&arg.name, ValueRef::new(var, ptr_type(arg.typ.clone())));
+            }
+        },
+    }
+}
+
 pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction)
 {
     let fi = ctx.get_function(&func.sig.name).expect("Internal Compiler Error: Unknown function");
@@ -49,30 +91,22 @@ pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction)
 
     ctx.push_stack(fi.function);
 
-    for (i, arg) in func.sig.args.iter().enumerate() {
-        let var = LLVMGetParam(fi.function, i as libc::c_uint);
-        match arg.typ
-        {
-            Type::Func(ref ft) => {
-                gen_function_ptr(ctx, &arg.name, var, ft.return_type.clone(), arg.typ.clone());
-                ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
-            },
-
-            _ => {
-                if arg.typ.pass_by_value() {
-                    if arg.mutable && !arg.typ.is_pointer() {
-                        // To make it mutable, copy the argument into a local variable
-                        // and use that instead
-                        let argcopy = LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&arg.typ), cstr!("argcopy"));
-                        LLVMBuildStore(ctx.builder, var, argcopy);
-                        ctx.set_variable(&arg.name, ValueRef::new(argcopy, ptr_type(arg.typ.clone())));
-                    } else {
-                        ctx.set_variable(&arg.name, ValueRef::new(var, arg.typ.clone()));
-                    }
-                } else {
-                    ctx.set_variable(&arg.name, ValueRef::new(var, ptr_type(arg.typ.clone())));
-                }
-            },
+    let abi = sig_abi(ctx, &func.sig);
+    if let Some(ref abi) = abi {
+        for ((arg, passing), idx) in func.sig.args.iter().zip(abi.args.iter()).zip(abi.param_indices()) {
+            if let ArgPassing::Coerced(ref types) = *passing {
+                let values: Vec<_> = (0..types.len())
+                    .map(|i| LLVMGetParam(fi.function, (idx + i) as libc::c_uint))
+                    .collect();
+                let aggregate = store_coerced(ctx, types, &arg.typ, &values);
+                ctx.set_variable(&arg.name, ValueRef::new(aggregate, ptr_type(arg.typ.clone())));
+            } else {
+                gen_function_arg(ctx, arg, LLVMGetParam(fi.function, idx as libc::c_uint));
+            }
+        }
+    } else {
+        for (i, arg) in func.sig.args.iter().enumerate() {
+            gen_function_arg(ctx, arg, LLVMGetParam(fi.function, i as libc::c_uint));
         }
     }
 
@@ -91,7 +125,11 @@ pub unsafe fn gen_function(ctx: &mut Context, func: &ByteCodeFunction)
         let bb = blocks.get(bb_ref).expect("Unknown basic block");
         LLVMPositionBuilderAtEnd(ctx.builder, *bb);
         for inst in &block.instructions {
-            gen_instruction(ctx, inst, &blocks);
+            match (inst, &abi)
+            {
+                (&Instruction::Return(ref operand), &Some(ref abi)) => gen_c_return(ctx, abi, fi.function, &func.sig.return_type, operand),
+                _ => gen_instruction(ctx, inst, &blocks),
+            }
         }
     }
 
@@ -113,4 +151,124 @@ pub unsafe fn add_libc_functions(ctx: &mut Context)
     );
 
     gen_function_sig(ctx, &memcpy_sig, None);
-}
\ No newline at end of file
+
+    // memcmp, used to compare strings
+    let memcmp_sig = sig(
+        "memcmp",
+        Type::Int(IntSize::I32),
+        vec![
+            Argument::new("a", ptr_type(Type::UInt(IntSize::I8)), false, Span::default()),
+            Argument::new("b", ptr_type(Type::UInt(IntSize::I8)), false, Span::default()),
+            Argument::new("size", ctx.target_machine.target.native_uint_type.clone(), false, Span::default())
+        ],
+        Span::default()
+    );
+
+    gen_function_sig(ctx, &memcmp_sig, None);
+
+    // strlen, used to convert the command line arguments to strings
+    let strlen_sig = sig(
+        "strlen",
+        ctx.target_machine.target.native_uint_type.clone(),
+        vec![
+            Argument::new("s", ptr_type(Type::UInt(IntSize::I8)), false, Span::default()),
+        ],
+        Span::default()
+    );
+
+    gen_function_sig(ctx, &strlen_sig, None);
+
+    // dprintf and exit, used to report runtime panics
+    let mut dprintf_arg_types = vec![
+        LLVMInt32TypeInContext(ctx.context),
+        LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0),
+    ];
+    let dprintf_type = LLVMFunctionType(LLVMInt32TypeInContext(ctx.context), dprintf_arg_types.as_mut_ptr(), dprintf_arg_types.len() as libc::c_uint, 1);
+    let dprintf = LLVMAddFunction(ctx.module, cstr!("dprintf"), dprintf_type);
+    let dprintf_return_type = Type::Int(IntSize::I32);
+    let dprintf_typ = func_type(vec![Type::Int(IntSize::I32), ptr_type(Type::UInt(IntSize::I8))], dprintf_return_type.clone());
+    gen_function_ptr(ctx, "dprintf", dprintf, dprintf_return_type, dprintf_typ);
+
+    let exit_sig = sig(
+        "exit",
+        Type::Void,
+        vec![
+            Argument::new("status", Type::Int(IntSize::I32), false, Span::default()),
+        ],
+        Span::default()
+    );
+
+    gen_function_sig(ctx, &exit_sig, None);
+}
+
+// Build a string[] slice on the stack from the argc and argv arguments of the C main function
+unsafe fn gen_args_slice(ctx: &mut Context, func: LLVMValueRef) -> LLVMValueRef
+{
+    let native_uint_type = ctx.resolve_type(&ctx.target_machine.target.native_uint_type);
+    let strlen = ctx.get_function("strlen").expect("strlen not found");
+    let argc = LLVMGetParam(func, 0);
+    let argv = LLVMGetParam(func, 1);
+
+    let len = LLVMBuildZExtOrBitCast(ctx.builder, argc, native_uint_type, cstr!("len"));
+    let strings = LLVMBuildArrayAlloca(ctx.builder, ctx.resolve_type(&Type::String), len, cstr!("strings"));
+    let counter = LLVMBuildAlloca(ctx.builder, native_uint_type, cstr!("counter"));
+    LLVMBuildStore(ctx.builder, LLVMConstInt(native_uint_type, 0, 0), counter);
+
+    let cond_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("cond"));
+    let body_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("body"));
+    let done_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("done"));
+    LLVMBuildBr(ctx.builder, cond_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, cond_bb);
+    let idx = LLVMBuildLoad(ctx.builder, counter, cstr!("idx"));
+    let in_range = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, idx, len, cstr!("in_range"));
+    LLVMBuildCondBr(ctx.builder, in_range, body_bb, done_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, body_bb);
+    let mut indices = vec![idx];
+    let arg_ptr = LLVMBuildGEP(ctx.builder, argv, indices.as_mut_ptr(), 1, cstr!("arg_ptr"));
+    let arg = LLVMBuildLoad(ctx.builder, arg_ptr, cstr!("arg"));
+    let mut strlen_args = vec![arg];
+    let arg_len = LLVMBuildCall(ctx.builder, strlen.function, strlen_args.as_mut_ptr(), 1, cstr!("arg_len"));
+    let dst = LLVMBuildGEP(ctx.builder, strings, indices.as_mut_ptr(), 1, cstr!("dst"));
+    LLVMBuildStore(ctx.builder, arg, LLVMBuildStructGEP(ctx.builder, dst, 0, cstr!("dst_data")));
+    LLVMBuildStore(ctx.builder, arg_len, LLVMBuildStructGEP(ctx.builder, dst, 1, cstr!("dst_len")));
+    let next = LLVMBuildAdd(ctx.builder, idx, LLVMConstInt(native_uint_type, 1, 0), cstr!("next"));
+    LLVMBuildStore(ctx.builder, next, counter);
+    LLVMBuildBr(ctx.builder, cond_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, done_bb);
+    let args = LLVMBuildAlloca(ctx.builder, ctx.resolve_type(&slice_type(Type::String)), cstr!("args"));
+    LLVMBuildStore(ctx.builder, strings, LLVMBuildStructGEP(ctx.builder, args, 0, cstr!("args_data")));
+    LLVMBuildStore(ctx.builder, len, LLVMBuildStructGEP(ctx.builder, args, 1, cstr!("args_len")));
+    args
+}
+
+// The C main function, which calls the main function of the module, passing the
+// command line arguments if main takes them, and returns its exit code
+pub unsafe fn gen_c_main(ctx: &mut Context, main_sig: &FunctionSignature)
+{
+    let int32_type = LLVMInt32TypeInContext(ctx.context);
+    let mut arg_types = vec![
+        int32_type,
+        LLVMPointerType(LLVMPointerType(LLVMInt8TypeInContext(ctx.context), 0), 0),
+    ];
+    let function_type = LLVMFunctionType(int32_type, arg_types.as_mut_ptr(), arg_types.len() as libc::c_uint, 0);
+    let func = LLVMAddFunction(ctx.module, cstr!("main"), function_type);
+    let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("entry"));
+    LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
+
+    let main = ctx.get_function(&main_sig.name).expect("Internal Compiler Error: Unknown main function");
+    let mut args = Vec::new();
+    if !main_sig.args.is_empty() {
+        args.push(gen_args_slice(ctx, func));
+    }
+
+    let ret = LLVMBuildCall(ctx.builder, main.function, args.as_mut_ptr(), args.len() as libc::c_uint, cstr!(""));
+    let exit_code = if main_sig.return_type == Type::Void {
+        LLVMConstInt(int32_type, 0, 0)
+    } else {
+        LLVMBuildIntCast(ctx.builder, ret, int32_type, cstr!("exit_code"))
+    };
+    LLVMBuildRet(ctx.builder, exit_code);
+}
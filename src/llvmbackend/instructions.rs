@@ -6,11 +6,15 @@ use llvm::*;
 use llvm::core::*;
 use llvm::prelude::*;
 use bytecode::*;
-use ast::{Type, UnaryOperator, BinaryOperator, ptr_type};
+use ast::{Type, IntSize, UnaryOperator, BinaryOperator, CallingConvention, ptr_type};
+use super::abi::gen_c_call;
 use super::function::gen_function_ptr;
 use super::valueref::ValueRef;
 use super::context::Context;
 use super::types::native_llvm_int_type;
+use super::runtime::{PANIC_FUNCTION, INDEX_OUT_OF_BOUNDS_FUNCTION, CHECK_RANGE_FUNCTION, CHECK_STRING_RANGE_FUNCTION,
+    STRING_COMPARE_FUNCTION, STRING_CONCAT_FUNCTION, gen_panic_location, gen_runtime_panic};
+use span::Span;
 
 pub unsafe fn const_int(ctx: &Context, v: i64) -> LLVMValueRef
 {
@@ -99,7 +103,7 @@ pub unsafe fn copy(ctx: &Context, dst: LLVMValueRef, src: LLVMValueRef, typ: LLV
     LLVMBuildCall(ctx.builder, func.function, args.as_mut_ptr(), args.len() as c_uint, cstr!("ac"));
 }
 
-unsafe fn get_function_arg(ctx: &mut Context, operand: &Operand) -> LLVMValueRef
+pub unsafe fn get_function_arg(ctx: &mut Context, operand: &Operand) -> LLVMValueRef
 {
     match *operand
     {
@@ -147,7 +151,14 @@ unsafe fn gen_unary_op(ctx: &mut Context, dst: &Var, operator: UnaryOperator, sr
 
 unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand)
 {
+    // Wrapping operators map directly on the LLVM instructions
+    let op = if op.is_wrapping() {op.base_operator()} else {op};
     let left_type = left.get_type(ctx.target_machine.target.int_size);
+    if left_type == Type::String {
+        gen_string_op(ctx, dst, op, left, right);
+        return;
+    }
+
     let left = get_operand(ctx, left).load(ctx);
     let right = get_operand(ctx, right).load(ctx);
 
@@ -216,15 +227,265 @@ unsafe fn gen_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left:
     ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
 }
 
+// Strings are compared and concatenated by the runtime
+unsafe fn gen_string_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand)
+{
+    let left = get_operand(ctx, left);
+    let right = get_operand(ctx, right);
+    let mut args = vec![
+        left.get_property(ctx, ByteCodeProperty::Data).value,
+        left.get_property(ctx, ByteCodeProperty::Len).value,
+        right.get_property(ctx, ByteCodeProperty::Data).value,
+        right.get_property(ctx, ByteCodeProperty::Len).value,
+    ];
+
+    if op == BinaryOperator::Add {
+        let concat = ctx.get_function(STRING_CONCAT_FUNCTION).expect("Internal Compiler Error: Unknown runtime function");
+        let data = LLVMBuildCall(ctx.builder, concat.function, args.as_mut_ptr(), args.len() as c_uint, cstr!("concat"));
+        let len = LLVMBuildAdd(ctx.builder, args[1], args[3], cstr!("len"));
+        let dst_var = ctx.get_variable(&dst.name, &dst.typ);
+        let native_uint_type = ctx.target_machine.target.native_uint_type.clone();
+        dst_var.create_slice(ctx, &ValueRef::new(data, ptr_type(Type::UInt(IntSize::I8))), &ValueRef::new(len, native_uint_type));
+        return;
+    }
+
+    let predicate = match op
+    {
+        BinaryOperator::LessThan => LLVMIntPredicate::LLVMIntSLT,
+        BinaryOperator::GreaterThan => LLVMIntPredicate::LLVMIntSGT,
+        BinaryOperator::LessThanEquals => LLVMIntPredicate::LLVMIntSLE,
+        BinaryOperator::GreaterThanEquals => LLVMIntPredicate::LLVMIntSGE,
+        BinaryOperator::Equals => LLVMIntPredicate::LLVMIntEQ,
+        BinaryOperator::NotEquals => LLVMIntPredicate::LLVMIntNE,
+        _ => panic!("Operator {} not supported on type string", op),
+    };
+
+    let compare = ctx.get_function(STRING_COMPARE_FUNCTION).expect("Internal Compiler Error: Unknown runtime function");
+    let cmp = LLVMBuildCall(ctx.builder, compare.function, args.as_mut_ptr(), args.len() as c_uint, cstr!("cmp"));
+    let zero = LLVMConstInt(LLVMInt32TypeInContext(ctx.context), 0, 0);
+    let value = LLVMBuildICmp(ctx.builder, predicate, cmp, zero, cstr!("bop"));
+    ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
+}
+
+// Panic with a constant message
+unsafe fn gen_panic(ctx: &mut Context, span: &Span, message: &str)
+{
+    let location = gen_panic_location(ctx, span);
+    let msg = CString::new(message).expect("Invalid string");
+    let msg_ptr = LLVMBuildGlobalStringPtr(ctx.builder, msg.as_ptr(), cstr!("panic_msg"));
+    let msg_len = const_uint(ctx, message.len() as u64);
+    gen_runtime_panic(ctx, PANIC_FUNCTION, &[location, msg_ptr, msg_len]);
+}
+
+// Panic if cond is true, the builder is positioned in the block following the check
+unsafe fn gen_panic_if<F: FnOnce(&mut Context)>(ctx: &mut Context, cond: LLVMValueRef, gen: F)
+{
+    let func = ctx.get_current_function();
+    let panic_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("panic"));
+    let ok_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("no_panic"));
+    LLVMBuildCondBr(ctx.builder, cond, panic_bb, ok_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, panic_bb);
+    gen(ctx);
+    LLVMPositionBuilderAtEnd(ctx.builder, ok_bb);
+}
+
+// Calls the llvm.*.with.overflow intrinsic for op, returns the result and the overflow flag
+unsafe fn gen_overflow_intrinsic(ctx: &mut Context, op: BinaryOperator, typ: &Type, left: LLVMValueRef, right: LLVMValueRef) -> (LLVMValueRef, LLVMValueRef)
+{
+    let (sign, int_size) = match *typ
+    {
+        Type::Int(int_size) => ("s", int_size),
+        Type::UInt(int_size) => ("u", int_size),
+        _ => panic!("Internal Compiler Error: overflow checks are only supported on integers, not on {}", typ),
+    };
+
+    let op_name = match op.base_operator()
+    {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Sub => "sub",
+        BinaryOperator::Mul => "mul",
+        _ => panic!("Internal Compiler Error: operator {} has no overflow intrinsic", op),
+    };
+
+    let name = CString::new(format!("llvm.{}{}.with.overflow.i{}", sign, op_name, int_size.size_in_bits())).expect("Invalid string");
+    let mut func = LLVMGetNamedFunction(ctx.module, name.as_ptr());
+    if func.is_null() {
+        let llvm_type = ctx.resolve_type(typ);
+        let mut member_types = vec![llvm_type, LLVMInt1TypeInContext(ctx.context)];
+        let ret_type = LLVMStructTypeInContext(ctx.context, member_types.as_mut_ptr(), member_types.len() as c_uint, 0);
+        let mut arg_types = vec![llvm_type, llvm_type];
+        let func_type = LLVMFunctionType(ret_type, arg_types.as_mut_ptr(), arg_types.len() as c_uint, 0);
+        func = LLVMAddFunction(ctx.module, name.as_ptr(), func_type);
+    }
+
+    let mut args = vec![left, right];
+    let result = LLVMBuildCall(ctx.builder, func, args.as_mut_ptr(), args.len() as c_uint, cstr!("ovf"));
+    (
+        LLVMBuildExtractValue(ctx.builder, result, 0, cstr!("ovf_value")),
+        LLVMBuildExtractValue(ctx.builder, result, 1, cstr!("ovf_flag")),
+    )
+}
+
+unsafe fn int_limits(ctx: &Context, typ: &Type) -> (LLVMValueRef, LLVMValueRef)
+{
+    use std::{i64, u64};
+    let llvm_type = ctx.resolve_type(typ);
+    match *typ
+    {
+        Type::Int(int_size) => {
+            let shift = 64 - int_size.size_in_bits();
+            (LLVMConstInt(llvm_type, (i64::MIN >> shift) as c_ulonglong, 1), LLVMConstInt(llvm_type, (i64::MAX >> shift) as c_ulonglong, 1))
+        },
+        Type::UInt(int_size) => (LLVMConstInt(llvm_type, 0, 0), LLVMConstInt(llvm_type, u64::MAX >> (64 - int_size.size_in_bits()), 0)),
+        _ => panic!("Internal Compiler Error: {} is not an integer type", typ),
+    }
+}
+
+unsafe fn gen_checked_binary_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand, span: &Span)
+{
+    let left_type = left.get_type(ctx.target_machine.target.int_size);
+    let left_value = get_operand(ctx, left).load(ctx);
+    let right_value = get_operand(ctx, right).load(ctx);
+    match op
+    {
+        BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul => {
+            let (value, overflow) = gen_overflow_intrinsic(ctx, op, &left_type, left_value, right_value);
+            gen_panic_if(ctx, overflow, |ctx| gen_panic(ctx, span, "integer overflow"));
+            ctx.set_variable(&dst.name, ValueRef::new(value, dst.typ.clone()));
+        },
+
+        BinaryOperator::Div | BinaryOperator::Mod => {
+            let llvm_type = ctx.resolve_type(&left_type);
+            let is_zero = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, right_value, LLVMConstInt(llvm_type, 0, 0), cstr!("is_zero"));
+            gen_panic_if(ctx, is_zero, |ctx| gen_panic(ctx, span, "division by zero"));
+
+            if let Type::Int(_) = left_type {
+                // The minimum value divided by -1 does not fit
+                let (min, _) = int_limits(ctx, &left_type);
+                let left_is_min = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, left_value, min, cstr!("is_min"));
+                let right_is_minus_one = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, right_value, LLVMConstInt(llvm_type, -1i64 as c_ulonglong, 1), cstr!("is_minus_one"));
+                let overflow = LLVMBuildAnd(ctx.builder, left_is_min, right_is_minus_one, cstr!("overflow"));
+                gen_panic_if(ctx, overflow, |ctx| gen_panic(ctx, span, "integer overflow"));
+            }
+
+            gen_binary_op(ctx, dst, op, left, right);
+        },
+
+        _ => gen_binary_op(ctx, dst, op, left, right),
+    }
+}
+
+unsafe fn gen_saturating_op(ctx: &mut Context, dst: &Var, op: BinaryOperator, left: &Operand, right: &Operand)
+{
+    let left_type = left.get_type(ctx.target_machine.target.int_size);
+    let left_value = get_operand(ctx, left).load(ctx);
+    let right_value = get_operand(ctx, right).load(ctx);
+    let (value, overflow) = gen_overflow_intrinsic(ctx, op, &left_type, left_value, right_value);
+    let (min, max) = int_limits(ctx, &left_type);
+
+    let saturated = match (op.base_operator(), &left_type)
+    {
+        (BinaryOperator::Sub, &Type::UInt(_)) => min,
+        (_, &Type::UInt(_)) => max,
+        (base_op, _) => {
+            let zero = LLVMConstInt(ctx.resolve_type(&left_type), 0, 0);
+            let to_max = match base_op
+            {
+                BinaryOperator::Add => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSGT, right_value, zero, cstr!("to_max")),
+                BinaryOperator::Sub => LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSLT, right_value, zero, cstr!("to_max")),
+                _ => {
+                    // Multiplying operands with the same sign results in a positive number
+                    let signs = LLVMBuildXor(ctx.builder, left_value, right_value, cstr!("signs"));
+                    LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntSGE, signs, zero, cstr!("to_max"))
+                },
+            };
+            LLVMBuildSelect(ctx.builder, to_max, max, min, cstr!("saturated"))
+        },
+    };
+
+    let result = LLVMBuildSelect(ctx.builder, overflow, saturated, value, cstr!("sop"));
+    ctx.set_variable(&dst.name, ValueRef::new(result, dst.typ.clone()));
+}
+
+unsafe fn gen_bounds_check(ctx: &mut Context, index: &Operand, len: &Operand, span: &Span)
+{
+    let index_type = index.get_type(ctx.target_machine.target.int_size);
+    let native_uint_type = ctx.target_machine.target.native_uint_type.clone();
+    let index_value = get_operand(ctx, index).load(ctx);
+    let len_value = get_operand(ctx, len).load(ctx);
+
+    // Compare as unsigned numbers, so negative indices are out of bounds as well
+    let unsigned_index = gen_int_cast(ctx, index_value, &native_uint_type, &index_type);
+    let out_of_bounds = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGE, unsigned_index, len_value, cstr!("out_of_bounds"));
+
+    let printed_index = gen_int_cast(ctx, index_value, &Type::Int(IntSize::I64), &index_type);
+    let printed_len = gen_int_cast(ctx, len_value, &Type::UInt(IntSize::I64), &native_uint_type);
+    let signed = const_bool(ctx, if let Type::Int(_) = index_type {true} else {false});
+    gen_panic_if(ctx, out_of_bounds, |ctx| {
+        let location = gen_panic_location(ctx, span);
+        gen_runtime_panic(ctx, INDEX_OUT_OF_BOUNDS_FUNCTION, &[location, printed_index, printed_len, signed]);
+    });
+}
+
+unsafe fn gen_range_check(ctx: &mut Context, seq: &Var, start: &Operand, end: &Operand, span: &Span)
+{
+    let seq_var = ctx.get_variable(&seq.name, &seq.typ);
+    let len = seq_var.get_property(ctx, ByteCodeProperty::Len).value;
+    let start = get_operand(ctx, start).load(ctx);
+    let end = get_operand(ctx, end).load(ctx);
+    let location = gen_panic_location(ctx, span);
+    let (func, mut args) = if seq.typ == Type::String {
+        let data = seq_var.get_property(ctx, ByteCodeProperty::Data).value;
+        (CHECK_STRING_RANGE_FUNCTION, vec![location, data, len, start, end])
+    } else {
+        (CHECK_RANGE_FUNCTION, vec![location, len, start, end])
+    };
+
+    let func = ctx.get_function(func).expect("Internal Compiler Error: Unknown runtime function");
+    LLVMBuildCall(ctx.builder, func.function, args.as_mut_ptr(), args.len() as c_uint, cstr!(""));
+}
+
+unsafe fn gen_int_cast(ctx: &mut Context, value: LLVMValueRef, dst_type: &Type, src_type: &Type) -> LLVMValueRef
+{
+    let (dst_size, src_size, src_signed) = match (dst_type, src_type)
+    {
+        (&Type::Int(d), &Type::Int(s)) | (&Type::UInt(d), &Type::Int(s)) => (d, s, true),
+        (&Type::Int(d), &Type::UInt(s)) | (&Type::UInt(d), &Type::UInt(s)) => (d, s, false),
+        _ => panic!("Internal Compiler Error: cannot cast {} to {}", src_type, dst_type),
+    };
+
+    let llvm_type = ctx.resolve_type(dst_type);
+    if dst_size.size_in_bits() < src_size.size_in_bits() {
+        LLVMBuildTrunc(ctx.builder, value, llvm_type, cstr!("cast_to_int"))
+    } else if dst_size == src_size {
+        value
+    } else if src_signed {
+        LLVMBuildSExt(ctx.builder, value, llvm_type, cstr!("cast_to_int"))
+    } else {
+        LLVMBuildZExt(ctx.builder, value, llvm_type, cstr!("cast_to_int"))
+    }
+}
+
 unsafe fn gen_cast(ctx: &mut Context, dst: &Var, src: &Operand)
 {
     let operand = get_operand(ctx, src);
     let src_type = src.get_type(ctx.target_machine.target.int_size);
     let casted = match (&dst.typ, &src_type)
     {
+        (&Type::Int(_), &Type::Int(_)) |
+        (&Type::UInt(_), &Type::UInt(_)) |
         (&Type::UInt(_), &Type::Int(_)) |
-        (&Type::Int(_), &Type::UInt(_)) =>
-            LLVMBuildIntCast(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_int")),
+        (&Type::Int(_), &Type::UInt(_)) => {
+            let value = operand.load(ctx);
+            gen_int_cast(ctx, value, &dst.typ, &src_type)
+        },
+
+        (&Type::Int(_), &Type::Bool) =>
+            LLVMBuildZExt(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_int")),
+
+        (&Type::Float(_), &Type::Float(_)) =>
+            LLVMBuildFPCast(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_float")),
 
         (&Type::Int(_), &Type::Float(_)) =>
             LLVMBuildFPToSI(ctx.builder, operand.load(ctx), ctx.resolve_type(&dst.typ), cstr!("cast_to_int")),
@@ -304,11 +565,42 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
         }
 
         Instruction::BinaryOp{ref dst, ref op, ref left, ref right} => {
-            gen_binary_op(ctx, dst, *op, left, right);
+            if op.is_saturating() {
+                gen_saturating_op(ctx, dst, *op, left, right);
+            } else {
+                gen_binary_op(ctx, dst, *op, left, right);
+            }
+        }
+
+        Instruction::CheckedBinaryOp{ref dst, ref op, ref left, ref right, ref span} => {
+            gen_checked_binary_op(ctx, dst, *op, left, right, span);
+        }
+
+        Instruction::BoundsCheck{ref index, ref len, ref span} => {
+            gen_bounds_check(ctx, index, len, span);
+        }
+
+        Instruction::RangeCheck{ref seq, ref start, ref end, ref span} => {
+            gen_range_check(ctx, seq, start, end, span);
+        }
+
+        Instruction::Panic{ref msg, ref span} => {
+            let msg_var = ctx.get_variable(&msg.name, &msg.typ);
+            let msg_ptr = msg_var.get_property(ctx, ByteCodeProperty::Data).value;
+            let msg_len = msg_var.get_property(ctx, ByteCodeProperty::Len).value;
+            let location = gen_panic_location(ctx, span);
+            gen_runtime_panic(ctx, PANIC_FUNCTION, &[location, msg_ptr, msg_len]);
         }
 
         Instruction::Call{ref dst, ref func, ref args} => {
             let func = ctx.get_function(func).expect("Unknown function");
+            if let Type::Func(ref ft) = func.typ {
+                if ft.calling_convention == CallingConvention::C {
+                    gen_c_call(ctx, func.function, ft, dst.as_ref(), args);
+                    return;
+                }
+            }
+
             let mut func_args = args.iter()
                 .map(|a| get_function_arg(ctx, a))
                 .collect::<Vec<_>>();
@@ -393,5 +685,9 @@ pub unsafe fn gen_instruction(ctx: &mut Context, instr: &Instruction, blocks: &H
         Instruction::Delete(ref var) => {
             LLVMBuildFree(ctx.builder, ctx.get_variable(&var.name, &var.typ).value);
         }
+
+        Instruction::Phi{..} => {
+            panic!("Internal Compiler Error: phi nodes must be removed before code generation");
+        }
     }
 }
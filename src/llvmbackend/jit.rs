@@ -65,9 +65,12 @@ impl JIT
             return Err(format!("Cannot execute module, no main function found"));
         }
 
-        let func: extern "C" fn() -> i64 = mem::transmute(main_func);
-        let ret = func();
-        Ok(ret)
+        // main is the C entry point, which gets the program name as its only argument
+        let program_name = cstr!("menhir-jit");
+        let mut argv = [program_name, ptr::null()];
+        let func: extern "C" fn(libc::c_int, *mut *const libc::c_char) -> libc::c_int = mem::transmute(main_func);
+        let ret = func(1, argv.as_mut_ptr());
+        Ok(ret as i64)
     }
 }
 
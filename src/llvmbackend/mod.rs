@@ -9,9 +9,11 @@ macro_rules! cstr {
 }
 
 
+mod abi;
 mod context;
 mod function;
 mod instructions;
+mod runtime;
 mod symboltable;
 mod target;
 mod types;
@@ -34,8 +36,9 @@ use llvm::core::*;
 use bytecode::{ByteCodeModule, Constant};
 pub use self::target::TargetMachine;
 use self::valueref::ValueRef;
-use self::function::{gen_function, gen_function_sig, add_libc_functions};
+use self::function::{gen_function, gen_function_sig, gen_c_main, add_libc_functions};
 use self::context::Context;
+use self::runtime::add_runtime_functions;
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum OutputType
@@ -119,7 +122,12 @@ unsafe fn gen_global(ctx: &mut Context, glob_name: &str, glob_value: &Constant)
     let name = CString::new(glob_name.as_bytes()).expect("Invalid string");
     let glob = LLVMAddGlobal(ctx.module, ctx.resolve_type(&v.typ), name.as_ptr());
     LLVMSetLinkage(glob, LLVMLinkage::LLVMExternalLinkage);
-    LLVMSetInitializer(glob, v.value);
+    let init = match *glob_value
+    {
+        Constant::Array(_) | Constant::Struct(..) => LLVMGetInitializer(v.value),
+        _ => v.value,
+    };
+    LLVMSetInitializer(glob, init);
     ctx.set_variable(glob_name, v);
 }
 
@@ -129,6 +137,7 @@ pub fn llvm_code_generation<'a>(bc_mod: &ByteCodeModule, target_machine: &'a Tar
 
     unsafe {
         add_libc_functions(&mut ctx);
+        add_runtime_functions(&mut ctx);
 
         for func in &bc_mod.imported_functions {
             gen_function_sig(&mut ctx, &func.sig, None);
@@ -139,11 +148,11 @@ pub fn llvm_code_generation<'a>(bc_mod: &ByteCodeModule, target_machine: &'a Tar
         }
 
         for func in bc_mod.functions.values() {
-            if func.sig.name == bc_mod.main_function_name() {
-                gen_function_sig(&mut ctx, &func.sig, Some("main"));
-            } else {
-                gen_function_sig(&mut ctx, &func.sig, None);
-            }
+            gen_function_sig(&mut ctx, &func.sig, None);
+        }
+
+        if let Some(main) = bc_mod.functions.get(&bc_mod.main_function_name()) {
+            gen_c_main(&mut ctx, &main.sig);
         }
 
         for func in bc_mod.functions.values() {
@@ -202,7 +211,8 @@ pub fn link(ctx: &Context, opts: &CodeGenOptions, linker_flags: &LinkerFlags) ->
 
         OutputType::StaticLib => {
             let mut cmd = Command::new("ar");
-            cmd.arg("rcs").arg(&output_file_path).arg(obj_file);
+            // Deterministic mode, so the archive doesn't contain timestamps, uids and gids
+            cmd.arg("rcsD").arg(&output_file_path).arg(obj_file);
             cmd
         }
 
@@ -0,0 +1,334 @@
+use std::ffi::CString;
+use libc::*;
+use llvm::{LLVMLinkage, LLVMIntPredicate};
+use llvm::core::*;
+use llvm::prelude::*;
+
+use ast::{Type, IntSize, Argument, sig, ptr_type};
+use bytecode::NEXT_CHAR_FUNCTION;
+use span::Span;
+use super::context::Context;
+use super::function::gen_function_sig;
+
+// Exit status of a program which panics at runtime
+const PANIC_EXIT_CODE: c_ulonglong = 101;
+
+// The runtime functions are generated in every module with internal linkage,
+// so no runtime library needs to be linked in
+pub const PANIC_FUNCTION: &'static str = "menhir_panic";
+pub const INDEX_OUT_OF_BOUNDS_FUNCTION: &'static str = "menhir_index_out_of_bounds";
+pub const CHECK_RANGE_FUNCTION: &'static str = "menhir_check_range";
+pub const CHECK_STRING_RANGE_FUNCTION: &'static str = "menhir_check_string_range";
+pub const STRING_COMPARE_FUNCTION: &'static str = "menhir_string_compare";
+pub const STRING_CONCAT_FUNCTION: &'static str = "menhir_string_concat";
+
+unsafe fn add_runtime_function(ctx: &mut Context, name: &str, return_type: Type, args: Vec<(&str, Type)>) -> LLVMValueRef
+{
+    let args = args.into_iter()
+        .map(|(arg_name, typ)| Argument::new(arg_name, typ, false, Span::default()))
+        .collect();
+
+    gen_function_sig(ctx, &sig(name, return_type, args, Span::default()), None);
+    let func = ctx.get_function(name).expect("Internal Compiler Error: Unknown runtime function").function;
+    LLVMSetLinkage(func, LLVMLinkage::LLVMInternalLinkage);
+
+    let entry_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("entry"));
+    LLVMPositionBuilderAtEnd(ctx.builder, entry_bb);
+    func
+}
+
+// Print a printf style message to stderr and exit
+unsafe fn gen_print_and_exit(ctx: &mut Context, format: LLVMValueRef, args: &[LLVMValueRef])
+{
+    let dprintf = ctx.get_function("dprintf").expect("dprintf not found");
+    let exit = ctx.get_function("exit").expect("exit not found");
+    let mut dprintf_args = vec![
+        LLVMConstInt(LLVMInt32TypeInContext(ctx.context), 2, 0),
+        format,
+    ];
+    dprintf_args.extend_from_slice(args);
+    LLVMBuildCall(ctx.builder, dprintf.function, dprintf_args.as_mut_ptr(), dprintf_args.len() as c_uint, cstr!(""));
+
+    let mut exit_args = vec![LLVMConstInt(LLVMInt32TypeInContext(ctx.context), PANIC_EXIT_CODE, 0)];
+    LLVMBuildCall(ctx.builder, exit.function, exit_args.as_mut_ptr(), exit_args.len() as c_uint, cstr!(""));
+    LLVMBuildUnreachable(ctx.builder);
+}
+
+pub unsafe fn add_runtime_functions(ctx: &mut Context)
+{
+    let c_string = ptr_type(Type::UInt(IntSize::I8));
+    let native_uint_type = ctx.target_machine.target.native_uint_type.clone();
+
+    // menhir_panic(location: *u8, message: *u8, len: uint)
+    let panic = add_runtime_function(ctx, PANIC_FUNCTION, Type::Void, vec![
+        ("location", c_string.clone()),
+        ("message", c_string.clone()),
+        ("len", native_uint_type.clone()),
+    ]);
+
+    let format = LLVMBuildGlobalStringPtr(ctx.builder, cstr!("Panic at %s: %.*s\n"), cstr!("panic_format"));
+    let len = LLVMBuildIntCast(ctx.builder, LLVMGetParam(panic, 2), LLVMInt32TypeInContext(ctx.context), cstr!("len"));
+    gen_print_and_exit(ctx, format, &[LLVMGetParam(panic, 0), len, LLVMGetParam(panic, 1)]);
+
+    // menhir_index_out_of_bounds(location: *u8, index: u64, len: u64, signed: bool)
+    let index_out_of_bounds = add_runtime_function(ctx, INDEX_OUT_OF_BOUNDS_FUNCTION, Type::Void, vec![
+        ("location", c_string.clone()),
+        ("index", Type::UInt(IntSize::I64)),
+        ("len", Type::UInt(IntSize::I64)),
+        ("signed", Type::Bool),
+    ]);
+
+    let signed_format = LLVMBuildGlobalStringPtr(ctx.builder,
+        cstr!("Panic at %s: index out of bounds, the index is %lld but the length is %llu\n"), cstr!("signed_format"));
+    let unsigned_format = LLVMBuildGlobalStringPtr(ctx.builder,
+        cstr!("Panic at %s: index out of bounds, the index is %llu but the length is %llu\n"), cstr!("unsigned_format"));
+    let format = LLVMBuildSelect(ctx.builder, LLVMGetParam(index_out_of_bounds, 3), signed_format, unsigned_format, cstr!("format"));
+    gen_print_and_exit(ctx, format, &[
+        LLVMGetParam(index_out_of_bounds, 0),
+        LLVMGetParam(index_out_of_bounds, 1),
+        LLVMGetParam(index_out_of_bounds, 2),
+    ]);
+
+    add_range_functions(ctx, &c_string, &native_uint_type);
+    add_string_functions(ctx, &c_string, &native_uint_type);
+}
+
+unsafe fn to_u64(ctx: &Context, value: LLVMValueRef) -> LLVMValueRef
+{
+    LLVMBuildZExtOrBitCast(ctx.builder, value, LLVMInt64TypeInContext(ctx.context), cstr!("u64"))
+}
+
+// Branch to ok_bb when cond is true, otherwise position the builder in a new block
+unsafe fn gen_check(ctx: &Context, func: LLVMValueRef, cond: LLVMValueRef, ok_bb: LLVMBasicBlockRef)
+{
+    let fail_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("fail"));
+    LLVMBuildCondBr(ctx.builder, cond, ok_bb, fail_bb);
+    LLVMPositionBuilderAtEnd(ctx.builder, fail_bb);
+}
+
+// A string index is on a character boundary, if it is the end of the string or not a continuation byte
+unsafe fn gen_is_char_boundary(ctx: &Context, func: LLVMValueRef, data: LLVMValueRef, len: LLVMValueRef, index: LLVMValueRef) -> LLVMValueRef
+{
+    let start_bb = LLVMGetInsertBlock(ctx.builder);
+    let check_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("check_byte"));
+    let done_bb = LLVMAppendBasicBlockInContext(ctx.context, func, cstr!("boundary"));
+    let at_end = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, index, len, cstr!("at_end"));
+    LLVMBuildCondBr(ctx.builder, at_end, done_bb, check_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, check_bb);
+    let i8_type = LLVMInt8TypeInContext(ctx.context);
+    let mut indices = vec![index];
+    let byte_ptr = LLVMBuildGEP(ctx.builder, data, indices.as_mut_ptr(), 1, cstr!("byte_ptr"));
+    let byte = LLVMBuildLoad(ctx.builder, byte_ptr, cstr!("byte"));
+    let masked = LLVMBuildAnd(ctx.builder, byte, LLVMConstInt(i8_type, 0xC0, 0), cstr!("masked"));
+    let not_continuation = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntNE, masked, LLVMConstInt(i8_type, 0x80, 0), cstr!("not_continuation"));
+    LLVMBuildBr(ctx.builder, done_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, done_bb);
+    let phi = LLVMBuildPhi(ctx.builder, LLVMInt1TypeInContext(ctx.context), cstr!("is_boundary"));
+    let mut values = vec![LLVMConstInt(LLVMInt1TypeInContext(ctx.context), 1, 0), not_continuation];
+    let mut blocks = vec![start_bb, check_bb];
+    LLVMAddIncoming(phi, values.as_mut_ptr(), blocks.as_mut_ptr(), 2);
+    phi
+}
+
+unsafe fn add_range_functions(ctx: &mut Context, c_string: &Type, native_uint_type: &Type)
+{
+    // menhir_check_range(location: *u8, len: uint, start: uint, end: uint)
+    let check_range = add_runtime_function(ctx, CHECK_RANGE_FUNCTION, Type::Void, vec![
+        ("location", c_string.clone()),
+        ("len", native_uint_type.clone()),
+        ("start", native_uint_type.clone()),
+        ("end", native_uint_type.clone()),
+    ]);
+
+    let len = LLVMGetParam(check_range, 1);
+    let start = LLVMGetParam(check_range, 2);
+    let end = LLVMGetParam(check_range, 3);
+    let ok_bb = LLVMAppendBasicBlockInContext(ctx.context, check_range, cstr!("ok"));
+    let start_ok = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, start, end, cstr!("start_ok"));
+    let end_ok = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULE, end, len, cstr!("end_ok"));
+    let in_bounds = LLVMBuildAnd(ctx.builder, start_ok, end_ok, cstr!("in_bounds"));
+    gen_check(ctx, check_range, in_bounds, ok_bb);
+
+    let format = LLVMBuildGlobalStringPtr(ctx.builder,
+        cstr!("Panic at %s: range %llu..%llu out of bounds, the length is %llu\n"), cstr!("range_format"));
+    let args = [LLVMGetParam(check_range, 0), to_u64(ctx, start), to_u64(ctx, end), to_u64(ctx, len)];
+    gen_print_and_exit(ctx, format, &args);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, ok_bb);
+    LLVMBuildRetVoid(ctx.builder);
+
+    // menhir_check_string_range(location: *u8, data: *u8, len: uint, start: uint, end: uint)
+    let check_string_range = add_runtime_function(ctx, CHECK_STRING_RANGE_FUNCTION, Type::Void, vec![
+        ("location", c_string.clone()),
+        ("data", c_string.clone()),
+        ("len", native_uint_type.clone()),
+        ("start", native_uint_type.clone()),
+        ("end", native_uint_type.clone()),
+    ]);
+
+    let location = LLVMGetParam(check_string_range, 0);
+    let data = LLVMGetParam(check_string_range, 1);
+    let len = LLVMGetParam(check_string_range, 2);
+    let start = LLVMGetParam(check_string_range, 3);
+    let end = LLVMGetParam(check_string_range, 4);
+    let mut check_args = vec![location, len, start, end];
+    LLVMBuildCall(ctx.builder, check_range, check_args.as_mut_ptr(), check_args.len() as c_uint, cstr!(""));
+
+    let start_ok = gen_is_char_boundary(ctx, check_string_range, data, len, start);
+    let end_ok = gen_is_char_boundary(ctx, check_string_range, data, len, end);
+    let on_boundaries = LLVMBuildAnd(ctx.builder, start_ok, end_ok, cstr!("on_boundaries"));
+    let ok_bb = LLVMAppendBasicBlockInContext(ctx.context, check_string_range, cstr!("ok"));
+    gen_check(ctx, check_string_range, on_boundaries, ok_bb);
+
+    let format = LLVMBuildGlobalStringPtr(ctx.builder,
+        cstr!("Panic at %s: range %llu..%llu is not on a character boundary\n"), cstr!("boundary_format"));
+    let args = [location, to_u64(ctx, start), to_u64(ctx, end)];
+    gen_print_and_exit(ctx, format, &args);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, ok_bb);
+    LLVMBuildRetVoid(ctx.builder);
+}
+
+unsafe fn add_string_functions(ctx: &mut Context, c_string: &Type, native_uint_type: &Type)
+{
+    let string_args = || vec![
+        ("a_data", c_string.clone()),
+        ("a_len", native_uint_type.clone()),
+        ("b_data", c_string.clone()),
+        ("b_len", native_uint_type.clone()),
+    ];
+
+    // menhir_string_compare(a_data: *u8, a_len: uint, b_data: *u8, b_len: uint) -> i32
+    // Returns a negative number if a < b, 0 if they are equal and a positive number if a > b
+    let compare = add_runtime_function(ctx, STRING_COMPARE_FUNCTION, Type::Int(IntSize::I32), string_args());
+    let a_len = LLVMGetParam(compare, 1);
+    let b_len = LLVMGetParam(compare, 3);
+    let a_shorter = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, a_len, b_len, cstr!("a_shorter"));
+    let a_longer = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGT, a_len, b_len, cstr!("a_longer"));
+    let min_len = LLVMBuildSelect(ctx.builder, a_shorter, a_len, b_len, cstr!("min_len"));
+
+    let memcmp = ctx.get_function("memcmp").expect("memcmp not found");
+    let mut memcmp_args = vec![LLVMGetParam(compare, 0), LLVMGetParam(compare, 2), min_len];
+    let cmp = LLVMBuildCall(ctx.builder, memcmp.function, memcmp_args.as_mut_ptr(), memcmp_args.len() as c_uint, cstr!("cmp"));
+
+    // With a common prefix, the shortest string comes first
+    let i32_type = LLVMInt32TypeInContext(ctx.context);
+    let zero = LLVMConstInt(i32_type, 0, 0);
+    let longer = LLVMBuildSelect(ctx.builder, a_longer, LLVMConstInt(i32_type, 1, 0), zero, cstr!("longer"));
+    let len_cmp = LLVMBuildSelect(ctx.builder, a_shorter, LLVMConstInt(i32_type, -1i64 as c_ulonglong, 1), longer, cstr!("len_cmp"));
+    let prefix_equal = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntEQ, cmp, zero, cstr!("prefix_equal"));
+    let result = LLVMBuildSelect(ctx.builder, prefix_equal, len_cmp, cmp, cstr!("result"));
+    LLVMBuildRet(ctx.builder, result);
+
+    // menhir_string_concat(a_data: *u8, a_len: uint, b_data: *u8, b_len: uint) -> *u8
+    // Returns a heap allocated buffer of a_len + b_len bytes
+    let concat = add_runtime_function(ctx, STRING_CONCAT_FUNCTION, c_string.clone(), string_args());
+    let a_len = LLVMGetParam(concat, 1);
+    let b_len = LLVMGetParam(concat, 3);
+    let total_len = LLVMBuildAdd(ctx.builder, a_len, b_len, cstr!("total_len"));
+    let data = LLVMBuildArrayMalloc(ctx.builder, LLVMInt8TypeInContext(ctx.context), total_len, cstr!("data"));
+
+    let mut indices = vec![a_len];
+    let tail = LLVMBuildGEP(ctx.builder, data, indices.as_mut_ptr(), 1, cstr!("tail"));
+    let memcpy = ctx.get_function("memcpy").expect("memcpy not found");
+    let void_ptr_type = LLVMPointerType(LLVMVoidTypeInContext(ctx.context), 0);
+    for &(dst, src, len) in &[(data, LLVMGetParam(concat, 0), a_len), (tail, LLVMGetParam(concat, 2), b_len)] {
+        let mut args = vec![
+            LLVMBuildBitCast(ctx.builder, dst, void_ptr_type, cstr!("dst_cast")),
+            LLVMBuildBitCast(ctx.builder, src, void_ptr_type, cstr!("src_cast")),
+            len,
+        ];
+        LLVMBuildCall(ctx.builder, memcpy.function, args.as_mut_ptr(), args.len() as c_uint, cstr!(""));
+    }
+    LLVMBuildRet(ctx.builder, data);
+
+    add_next_char_function(ctx, c_string, native_uint_type);
+}
+
+/*
+    menhir_next_char(data: *u8, len: uint, index: *uint) -> char
+    Decodes the UTF-8 character starting at index, and moves index past it.
+    The number of bytes is determined by the leading byte, a truncated sequence stops at the end of the string.
+*/
+unsafe fn add_next_char_function(ctx: &mut Context, c_string: &Type, native_uint_type: &Type)
+{
+    let next_char = add_runtime_function(ctx, NEXT_CHAR_FUNCTION, Type::Char, vec![
+        ("data", c_string.clone()),
+        ("len", native_uint_type.clone()),
+        ("index", ptr_type(native_uint_type.clone())),
+    ]);
+
+    let data = LLVMGetParam(next_char, 0);
+    let len = LLVMGetParam(next_char, 1);
+    let index_ptr = LLVMGetParam(next_char, 2);
+    let i32_type = LLVMInt32TypeInContext(ctx.context);
+    let uint_type = ctx.resolve_type(native_uint_type);
+    let const_i32 = |v: u64| LLVMConstInt(i32_type, v as c_ulonglong, 0);
+
+    let load_byte = |ctx: &Context, pos: LLVMValueRef| {
+        let mut indices = vec![pos];
+        let byte_ptr = LLVMBuildGEP(ctx.builder, data, indices.as_mut_ptr(), 1, cstr!("byte_ptr"));
+        let byte = LLVMBuildLoad(ctx.builder, byte_ptr, cstr!("byte"));
+        LLVMBuildZExt(ctx.builder, byte, i32_type, cstr!("byte"))
+    };
+
+    let index = LLVMBuildLoad(ctx.builder, index_ptr, cstr!("index"));
+    let lead = load_byte(ctx, index);
+
+    // (lead byte lower bound, number of bytes, mask for the bits in the lead byte)
+    let mut num_bytes = LLVMConstInt(uint_type, 1, 0);
+    let mut mask = const_i32(0x7F);
+    for &(lower_bound, n, m) in &[(0xC0, 2, 0x1F), (0xE0, 3, 0x0F), (0xF0, 4, 0x07)] {
+        let matches = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntUGE, lead, const_i32(lower_bound), cstr!("matches"));
+        num_bytes = LLVMBuildSelect(ctx.builder, matches, LLVMConstInt(uint_type, n, 0), num_bytes, cstr!("num_bytes"));
+        mask = LLVMBuildSelect(ctx.builder, matches, const_i32(m), mask, cstr!("mask"));
+    }
+
+    let code_point = LLVMBuildAlloca(ctx.builder, i32_type, cstr!("code_point"));
+    let offset = LLVMBuildAlloca(ctx.builder, uint_type, cstr!("offset"));
+    LLVMBuildStore(ctx.builder, LLVMBuildAnd(ctx.builder, lead, mask, cstr!("bits")), code_point);
+    LLVMBuildStore(ctx.builder, LLVMConstInt(uint_type, 1, 0), offset);
+
+    let cond_bb = LLVMAppendBasicBlockInContext(ctx.context, next_char, cstr!("cond"));
+    let body_bb = LLVMAppendBasicBlockInContext(ctx.context, next_char, cstr!("body"));
+    let done_bb = LLVMAppendBasicBlockInContext(ctx.context, next_char, cstr!("done"));
+    LLVMBuildBr(ctx.builder, cond_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, cond_bb);
+    let current_offset = LLVMBuildLoad(ctx.builder, offset, cstr!("offset"));
+    let pos = LLVMBuildAdd(ctx.builder, index, current_offset, cstr!("pos"));
+    let more_bytes = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, current_offset, num_bytes, cstr!("more_bytes"));
+    let in_string = LLVMBuildICmp(ctx.builder, LLVMIntPredicate::LLVMIntULT, pos, len, cstr!("in_string"));
+    let cond = LLVMBuildAnd(ctx.builder, more_bytes, in_string, cstr!("cond"));
+    LLVMBuildCondBr(ctx.builder, cond, body_bb, done_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, body_bb);
+    let byte = load_byte(ctx, pos);
+    let bits = LLVMBuildAnd(ctx.builder, byte, const_i32(0x3F), cstr!("bits"));
+    let shifted = LLVMBuildShl(ctx.builder, LLVMBuildLoad(ctx.builder, code_point, cstr!("cp")), const_i32(6), cstr!("shifted"));
+    LLVMBuildStore(ctx.builder, LLVMBuildOr(ctx.builder, shifted, bits, cstr!("cp")), code_point);
+    LLVMBuildStore(ctx.builder, LLVMBuildAdd(ctx.builder, current_offset, LLVMConstInt(uint_type, 1, 0), cstr!("offset")), offset);
+    LLVMBuildBr(ctx.builder, cond_bb);
+
+    LLVMPositionBuilderAtEnd(ctx.builder, done_bb);
+    LLVMBuildStore(ctx.builder, pos, index_ptr);
+    LLVMBuildRet(ctx.builder, LLVMBuildLoad(ctx.builder, code_point, cstr!("cp")));
+}
+
+// Location in the source code, passed to the runtime panic functions
+pub unsafe fn gen_panic_location(ctx: &Context, span: &Span) -> LLVMValueRef
+{
+    let location = CString::new(format!("{}:{}", span.file, span.start)).expect("Invalid string");
+    LLVMBuildGlobalStringPtr(ctx.builder, location.as_ptr(), cstr!("panic_location"))
+}
+
+// Call one of the runtime panic functions, they never return
+pub unsafe fn gen_runtime_panic(ctx: &Context, name: &str, args: &[LLVMValueRef])
+{
+    let func = ctx.get_function(name).expect("Internal Compiler Error: Unknown runtime function");
+    let mut args = args.to_vec();
+    LLVMBuildCall(ctx.builder, func.function, args.as_mut_ptr(), args.len() as c_uint, cstr!(""));
+    LLVMBuildUnreachable(ctx.builder);
+}
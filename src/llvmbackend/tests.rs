@@ -1,105 +1,122 @@
 use std::fs;
 use std::mem;
 use std::ptr;
-use std::io::Read;
-use std::path::{PathBuf, Path};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
 use bytecode::{OptimizationLevel, optimize_module};
-use bytecode::test::generate_byte_code;
+use bytecode::test::{Test, generate_byte_code, load_tests, run_tests};
 use target::register_target;
 use llvmbackend::target::TargetMachine;
 use llvmbackend::{llvm_init, llvm_code_generation};
 use llvmbackend::jit::JIT;
+use llvmbackend::OutputType;
+use packagebuild::{BuildOptions, PackageData};
 
-pub struct Test
+fn run(test: &Test, dump: bool, target_machine: &TargetMachine) -> Result<i64, String>
 {
-    pub name: String,
-    pub ret: i64,
-    pub code: String,
-}
-
-impl Test
-{
-    pub fn load(path: &Path) -> Test
+    let mut bc_mod = match generate_byte_code(&test.code, dump)
     {
-        let mut file = fs::File::open(path).unwrap();
-        let mut data = String::new();
-        file.read_to_string(&mut data).unwrap();
-
-        assert!(data.starts_with("#ret:"));
-        let ret: String = data.chars().skip(5).take_while(|c| c.is_numeric()).collect();
-        Test{
-            name: path.file_stem().unwrap().to_str().unwrap().into(),
-            ret: i64::from_str_radix(&ret, 10).unwrap(),
-            code: data,
-        }
-    }
+        Ok(bc_mod) => bc_mod,
+        Err(e) => return Err(format!("Compile error: {}", e)),
+    };
 
-    pub fn run(&self, dump: bool, target_machine: &TargetMachine) -> Result<i64, String>
-    {
-        let mut bc_mod = match generate_byte_code(&self.code, dump)
-        {
-            Ok(bc_mod) => bc_mod,
-            Err(e) => return Err(format!("Compile error: {}", e)),
-        };
-
-        optimize_module(&mut bc_mod, OptimizationLevel::Normal);
-        let mut ctx = llvm_code_generation(&bc_mod, target_machine)?;
-        unsafe {
-            let jit = JIT::new()?;
-            let llvm_module = mem::replace(&mut ctx.module, ptr::null_mut());
-            jit.run(llvm_module)
-        }
+    optimize_module(&mut bc_mod, OptimizationLevel::Normal).map_err(|e| format!("Optimization error: {}", e))?;
+    let mut ctx = llvm_code_generation(&bc_mod, target_machine)?;
+    unsafe {
+        let jit = JIT::new()?;
+        let llvm_module = mem::replace(&mut ctx.module, ptr::null_mut());
+        jit.run(llvm_module)
     }
 }
 
-
-fn run_test(prog: &Path, dump: bool, target_machine: &TargetMachine) -> Result<i64, String>
+#[test]
+fn test_all()
 {
-    let test = Test::load(prog);
-    let ret = test.run(dump, target_machine)?;
-    if ret != test.ret {
-        Err(format!("Return value doesn't match: {}, expecting {}", ret, test.ret))
-    } else {
-        Ok(ret)
-    }
+    let target_machine = llvm_init().expect("Cannot create llvm target machine");
+    register_target(&target_machine);
+    assert!(run_tests(&load_tests(), |test| run(test, false, &target_machine)) == 0);
 }
 
-fn run_tests_in_directory(dir: fs::ReadDir, target_machine: &TargetMachine) -> usize
+// Read all files below dir, keyed by their path relative to root
+fn read_files(root: &Path, dir: &Path, files: &mut BTreeMap<PathBuf, Vec<u8>>)
 {
-    println!();
-    println!("Running tests:");
-    println!("==============");
-    let mut failures = 0;
-    let mut paths : Vec<PathBuf> = dir.map(|dir_entry| dir_entry.unwrap().path()).collect();
-    paths.sort();
-
-    for path in &paths {
-        if path.extension().unwrap_or_default() == "mhr" {
-            let r = run_test(&path, false, target_machine);
-            println!("{}: {:?}", path.file_stem().unwrap().to_str().unwrap(), r);
-            if !r.is_ok()  {
-                failures += 1;
-            }
+    for entry in fs::read_dir(dir).expect("Cannot read build directory") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            read_files(root, &path, files);
+        } else {
+            let mut data = Vec::new();
+            fs::File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+            files.insert(path.strip_prefix(root).unwrap().to_owned(), data);
         }
     }
+}
 
-    failures
+// Build a package the same way menhir buildpkg does, and return the contents of everything it produced
+fn build_package(source: &Path, build_directory: &Path, target_machine: &TargetMachine) -> BTreeMap<PathBuf, Vec<u8>>
+{
+    let _ = fs::remove_dir_all(build_directory);
+    let build_options = BuildOptions{
+        optimize: true,
+        checked_arithmetic: false,
+        bounds_checks: true,
+        verify_bytecode: true,
+        dump_flags: String::new(),
+        target: target_machine.target.clone(),
+        sources_directory: String::new(),
+        import_directories: Vec::new(),
+        build_directory: build_directory.to_owned(),
+    };
+
+    let pkg = PackageData::single_file(source, OutputType::StaticLib).expect("Cannot create package");
+    pkg.build(&build_options, target_machine).expect("Build failed");
+
+    let mut files = BTreeMap::new();
+    read_files(build_directory, build_directory, &mut files);
+    fs::remove_dir_all(build_directory).unwrap();
+    files
 }
 
 #[test]
-fn test_all()
+fn test_reproducible_output()
 {
     let target_machine = llvm_init().expect("Cannot create llvm target machine");
     register_target(&target_machine);
 
-    let mut testcode_found = false;
-    for path in &["testcode", "../testcode"] {
-        if let Ok(dir) = fs::read_dir(path) {
-            assert!(run_tests_in_directory(dir, &target_machine) == 0);
-            testcode_found = true;
-            break;
-        }
-    }
+    let code = r#"
+let A = 1
+let B = 2
+let C = 3
+
+struct Pair{first: $a, second: $b}
 
-    assert!(testcode_found);
+fn add(x: $a, y: $a) -> $a: x + y
+fn mul(x: $a, y: $a) -> $a: x * y
+fn sum(p: Pair) -> $a: p.first + p.second
+
+fn apply(x: int, f: fn(int) -> int) -> int:
+    f(x)
+
+fn compute() -> int:
+    apply(add(A, B), fn(x) -> x * 2) + apply(mul(B, C), fn(x) -> x + 1) + sum(Pair{4, 14})
+"#;
+
+    let dir = ::std::env::temp_dir().join("menhir_reproducible");
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("reproducible.mhr");
+    fs::File::create(&source).unwrap().write_all(code.as_bytes()).unwrap();
+
+    // Hash maps are seeded differently every time one is created, so two builds are enough to catch any output depending on their order
+    let first = build_package(&source, &dir.join("build1"), &target_machine);
+    let second = build_package(&source, &dir.join("build2"), &target_machine);
+    fs::remove_dir_all(&dir).unwrap();
+
+    // The object file, the static library and the exports file
+    assert!(first.len() == 3);
+    assert!(first.keys().eq(second.keys()));
+    for (path, contents) in &first {
+        assert!(!contents.is_empty());
+        assert!(second[path] == *contents, "{} differs between builds", path.display());
+    }
 }
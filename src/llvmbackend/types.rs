@@ -3,6 +3,7 @@ use libc::*;
 use llvm::core::*;
 use llvm::prelude::*;
 
+use super::abi::c_function_abi;
 use super::target::TargetMachine;
 use ast::*;
 
@@ -24,7 +25,7 @@ unsafe fn slice_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMac
 unsafe fn array_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, at: &ArrayType) -> LLVMTypeRef
 {
     let element_type = to_llvm_type(context, target_machine, &at.element_type);
-    LLVMArrayType(element_type, at.len as c_uint)
+    LLVMArrayType(element_type, at.len() as c_uint)
 }
 
 unsafe fn sum_type_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, st: &SumType) -> LLVMTypeRef
@@ -47,6 +48,10 @@ unsafe fn sum_type_to_llvm_type(context: LLVMContextRef, target_machine: &Target
 
 unsafe fn func_to_llvm_type(context: LLVMContextRef, target_machine: &TargetMachine, ft: &FuncType) -> LLVMTypeRef
 {
+    if ft.calling_convention == CallingConvention::C {
+        return LLVMPointerType(c_function_abi(context, target_machine, ft).function_type, 0);
+    }
+
     let mut llvm_arg_types = Vec::with_capacity(ft.args.len());
     for arg in &ft.args {
         llvm_arg_types.push(to_llvm_type(context, target_machine, arg));
@@ -37,10 +37,21 @@ impl ValueRef
             Constant::Char(v) => ValueRef::new(const_char(ctx, v), Type::Char),
             Constant::Bool(v) => ValueRef::new(const_bool(ctx, v), Type::Bool),
             Constant::Array(ref elements) => ValueRef::const_array(ctx, elements),
+            Constant::Struct(ref typ, ref members) => ValueRef::const_struct(ctx, typ, members),
             Constant::NullPtr(ref typ) => ValueRef::new(LLVMConstNull(ctx.resolve_type(typ)), ptr_type(typ.clone())),
         }
     }
 
+    // Array and struct constants are internal globals, nested in other constants their initializer is needed
+    unsafe fn const_initializer(ctx: &Context, cst: &Constant) -> LLVMValueRef
+    {
+        let v = ValueRef::from_const(ctx, cst);
+        match *cst
+        {
+            Constant::Array(_) | Constant::Struct(..) => LLVMGetInitializer(v.value),
+            _ => v.value,
+        }
+    }
 
     unsafe fn const_string(ctx: &Context, s: &str) -> ValueRef
     {
@@ -79,7 +90,7 @@ impl ValueRef
 
         let mut array_data = Vec::with_capacity(elements.len());
         for e in elements {
-            array_data.push(ValueRef::from_const(ctx, e).value);
+            array_data.push(ValueRef::const_initializer(ctx, e));
         }
 
         let const_array = LLVMConstArray(element_type, array_data.as_mut_ptr(), array_data.len() as c_uint);
@@ -88,6 +99,23 @@ impl ValueRef
         ValueRef::new(glob, array_type)
     }
 
+    unsafe fn const_struct(ctx: &Context, typ: &Type, members: &[Constant]) -> ValueRef
+    {
+        let struct_type = ctx.resolve_type(typ);
+        let glob = LLVMAddGlobal(ctx.module, struct_type, cstr!("struct_constant"));
+        LLVMSetLinkage(glob, LLVMLinkage::LLVMInternalLinkage);
+
+        let mut member_data = Vec::with_capacity(members.len());
+        for m in members {
+            member_data.push(ValueRef::const_initializer(ctx, m));
+        }
+
+        let const_struct = LLVMConstNamedStruct(struct_type, member_data.as_mut_ptr(), member_data.len() as c_uint);
+        LLVMSetInitializer(glob, const_struct);
+
+        ValueRef::new(glob, typ.clone())
+    }
+
 
     pub unsafe fn store(&self, ctx: &Context, val: &ValueRef)
     {
@@ -238,6 +266,16 @@ impl ValueRef
                 )
             },
 
+            Type::String => unsafe {
+                let index = get_operand(ctx, index).load(ctx);
+                let data_ptr = LLVMBuildLoad(ctx.builder, self.slice_data_ptr(ctx), cstr!("data_ptr"));
+                let mut indices = vec![index];
+                ValueRef::new(
+                    LLVMBuildGEP(ctx.builder, data_ptr, indices.as_mut_ptr(), 1, cstr!("member")),
+                    ptr_type(Type::UInt(IntSize::I8))
+                )
+            },
+
             Type::Struct(ref st) => unsafe {
                 let index = match *index {
                     Operand::Const(Constant::Int(v, _)) => v as usize,
@@ -307,7 +345,7 @@ impl ValueRef
         {
             (&Type::Array(ref a), ByteCodeProperty::Len) => unsafe {
                 ValueRef::new(
-                    const_uint(ctx, a.len as u64),
+                    const_uint(ctx, a.len() as u64),
                     native_uint_type
                 )
             },
@@ -382,7 +420,7 @@ impl ValueRef
 
         match *inner_type
         {
-            Type::Array(_) | Type::Slice(_) => {
+            Type::Array(_) | Type::Slice(_) | Type::String => {
                 let data_ptr = self.slice_data_ptr(ctx);
                 let len_ptr = self.slice_len_ptr(ctx);
                 let member_ptr = array.get_member_ptr(ctx, start);
@@ -390,7 +428,7 @@ impl ValueRef
                 LLVMBuildStore(ctx.builder, get_operand(ctx, len).load(ctx), len_ptr);
             }
 
-            _ =>  panic!("Expecting an array type, not a {}", self.typ),
+            _ =>  panic!("Expecting an array, slice or string type, not a {}", self.typ),
         }
     }
 
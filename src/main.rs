@@ -3,7 +3,6 @@ extern crate libc;
 extern crate itertools;
 #[macro_use]
 extern crate clap;
-extern crate uuid;
 extern crate toml;
 #[macro_use]
 extern crate serde_derive;
@@ -26,6 +25,7 @@ mod ast;
 mod compileerror;
 mod bytecode;
 mod exportlibrary;
+mod interpreter;
 mod parser;
 mod typechecker;
 mod span;
@@ -36,27 +36,36 @@ mod package;
 mod packagebuild;
 
 use std::fs::File;
+use std::io::Read;
 use std::process::exit;
 use std::path::PathBuf;
 use clap::ArgMatches;
 
-use compileerror::{CompileResult};
+use compileerror::{CompileResult, CompileError};
+use bytecode::{parse_module, verify_module, remove_phi_nodes};
+use interpreter::interpret;
 use llvmbackend::{OutputType, llvm_init, llvm_shutdown};
 use packagebuild::{PackageData, BuildOptions};
 use exportlibrary::ExportLibrary;
+use target::Target;
 
 
 fn build_command(matches: &ArgMatches, dump_flags: &str) -> CompileResult<i32>
 {
     let input_file = matches.value_of("INPUT_FILE").expect("No input file given");
+    let target_machine = llvm_init()?;
     let build_options = BuildOptions{
         optimize: matches.is_present("OPTIMIZE"),
+        checked_arithmetic: matches.is_present("CHECKED_ARITHMETIC") || !matches.is_present("OPTIMIZE"),
+        bounds_checks: !matches.is_present("NO_BOUNDS_CHECKS"),
+        verify_bytecode: matches.is_present("VERIFY_BYTECODE"),
         dump_flags: dump_flags.into(),
-        target_machine: llvm_init()?,
+        target: target_machine.target.clone(),
         sources_directory: String::new(),
         import_directories: matches.value_of("IMPORTS")
             .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
             .unwrap_or_else(Vec::new),
+        build_directory: PathBuf::from("build"),
     };
 
     let output_type = match matches.value_of("LIB") {
@@ -66,7 +75,7 @@ fn build_command(matches: &ArgMatches, dump_flags: &str) -> CompileResult<i32>
     };
 
     let pkg = PackageData::single_file(&input_file, output_type)?;
-    pkg.build(&build_options)?;
+    pkg.build(&build_options, &target_machine)?;
     Ok(0)
 }
 
@@ -80,19 +89,70 @@ fn build_package_command(matches: &ArgMatches, dump_flags: &str) -> CompileResul
     };
 
     let pkg = PackageData::load(package_toml)?;
+    let target_machine = llvm_init()?;
     let build_options = BuildOptions{
         optimize: matches.is_present("OPTIMIZE"),
+        checked_arithmetic: matches.is_present("CHECKED_ARITHMETIC") || !matches.is_present("OPTIMIZE"),
+        bounds_checks: !matches.is_present("NO_BOUNDS_CHECKS"),
+        verify_bytecode: matches.is_present("VERIFY_BYTECODE"),
         dump_flags: dump_flags.into(),
-        target_machine: llvm_init()?,
+        target: target_machine.target.clone(),
         sources_directory: "src".into(),
         import_directories: matches.value_of("IMPORTS")
             .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
             .unwrap_or_else(Vec::new),
+        build_directory: PathBuf::from("build"),
     };
-    pkg.build(&build_options)?;
+    pkg.build(&build_options, &target_machine)?;
     Ok(0)
 }
 
+// Run a file with the bytecode interpreter, the exit code is the one of the program
+fn interp_command(matches: &ArgMatches, dump_flags: &str) -> CompileResult<i32>
+{
+    let input_file = matches.value_of("INPUT_FILE").expect("No input file given");
+    let build_options = BuildOptions{
+        optimize: matches.is_present("OPTIMIZE"),
+        checked_arithmetic: matches.is_present("CHECKED_ARITHMETIC") || !matches.is_present("OPTIMIZE"),
+        bounds_checks: !matches.is_present("NO_BOUNDS_CHECKS"),
+        verify_bytecode: matches.is_present("VERIFY_BYTECODE"),
+        dump_flags: dump_flags.into(),
+        target: Target::host(),
+        sources_directory: String::new(),
+        import_directories: matches.value_of("IMPORTS")
+            .map(|dirs| dirs.split(',').map(PathBuf::from).collect())
+            .unwrap_or_else(Vec::new),
+        build_directory: PathBuf::from("build"),
+    };
+
+    let args: Vec<String> = matches.values_of("ARGS")
+        .map(|args| args.map(String::from).collect())
+        .unwrap_or_else(Vec::new);
+
+    if input_file.ends_with(".bc") {
+        return interp_bytecode_file(input_file, &build_options, &args);
+    }
+
+    let pkg = PackageData::single_file(&input_file, OutputType::Binary)?;
+    pkg.interpret(&build_options, &args)
+}
+
+// Run a module in the textual bytecode format, as printed by --dump bytecode
+fn interp_bytecode_file(input_file: &str, build_options: &BuildOptions, args: &[String]) -> CompileResult<i32>
+{
+    let mut data = String::new();
+    let mut file = File::open(input_file)?;
+    file.read_to_string(&mut data)?;
+    let mut bc_mod = parse_module(&data, input_file)?;
+    // Hand written or edited modules are not checked by the compiler, so always verify them
+    verify_module(&bc_mod, "parsing")?;
+    remove_phi_nodes(&mut bc_mod)?;
+
+    let mut program_args = vec![input_file.to_string()];
+    program_args.extend(args.iter().cloned());
+    interpret(&bc_mod, &build_options.target, &program_args).map_err(CompileError::Other)
+}
+
 fn exports_command(matches: &ArgMatches) -> CompileResult<i32>
 {
     let exports_file_path = matches.value_of("EXPORTS_FILE").ok_or_else(|| "No exports file given".to_owned())?;
@@ -108,13 +168,16 @@ fn run() -> CompileResult<i32>
         (version: "0.1")
         (author: "Joris Guisson <joris.guisson@gmail.com>")
         (about: "Nomad language compiler")
-        (@arg DUMP: -d --dump +takes_value "Dump internal compiler state for debug purposes. Argument can be all, ast, bytecode or ir. A comma separated list of these values is also supported.")
+        (@arg DUMP: -d --dump +takes_value "Dump internal compiler state for debug purposes. Argument can be all, ast, bytecode, ssa, ir, escapes, cfg or callgraph (the last two are written as Graphviz files to the build directory). A comma separated list of these values is also supported.")
         (@arg TARGET_TRIPLET: -t --triplet "Print the default target triplet of the current system, and exit")
         (@subcommand build =>
             (about: "Build a menhir file")
             (@arg INPUT_FILE: +required "File to build")
             (@arg OUTPUT_FILE: -o --output +takes_value "Name of binary to create (by default input file without the extensions)")
             (@arg OPTIMIZE: -O --optimize "Optimize the code")
+            (@arg CHECKED_ARITHMETIC: --("checked-arithmetic") "Abort on integer overflow and division by zero, enabled by default when not optimizing")
+            (@arg NO_BOUNDS_CHECKS: --("no-bounds-checks") "Do not check array and slice indices at runtime")
+            (@arg VERIFY_BYTECODE: --("verify-bytecode") "Check the bytecode for consistency after compilation and after every optimization pass, enabled by default in debug builds of the compiler")
             (@arg IMPORTS: -I --imports +takes_value "Directory to look for imports, use a comma separated list for more then one.")
             (@arg LIB: -l --lib +takes_value possible_value[static shared] "Create a library, type of library must be pass")
         )
@@ -122,6 +185,19 @@ fn run() -> CompileResult<i32>
             (about: "Build a menhir package.")
             (@arg PACKAGE_TOML: -p --package +takes_value "Specify the package.toml file. If not specified, menhir will look in the current directory for one.")
             (@arg OPTIMIZE: -O --optimize "Optimize the code")
+            (@arg CHECKED_ARITHMETIC: --("checked-arithmetic") "Abort on integer overflow and division by zero, enabled by default when not optimizing")
+            (@arg NO_BOUNDS_CHECKS: --("no-bounds-checks") "Do not check array and slice indices at runtime")
+            (@arg VERIFY_BYTECODE: --("verify-bytecode") "Check the bytecode for consistency after compilation and after every optimization pass, enabled by default in debug builds of the compiler")
+            (@arg IMPORTS: -I --imports +takes_value "Directory to look for imports, use a comma separated list for more then one.")
+        )
+        (@subcommand interp =>
+            (about: "Run a menhir file with the bytecode interpreter, without generating machine code")
+            (@arg INPUT_FILE: +required "File to run, files with a .bc extension contain textual bytecode")
+            (@arg ARGS: ... "Arguments passed to the program")
+            (@arg OPTIMIZE: -O --optimize "Optimize the code")
+            (@arg CHECKED_ARITHMETIC: --("checked-arithmetic") "Abort on integer overflow and division by zero, enabled by default when not optimizing")
+            (@arg NO_BOUNDS_CHECKS: --("no-bounds-checks") "Do not check array and slice indices at runtime")
+            (@arg VERIFY_BYTECODE: --("verify-bytecode") "Check the bytecode for consistency after compilation and after every optimization pass, enabled by default in debug builds of the compiler")
             (@arg IMPORTS: -I --imports +takes_value "Directory to look for imports, use a comma separated list for more then one.")
         )
         (@subcommand exports =>
@@ -141,6 +217,8 @@ fn run() -> CompileResult<i32>
         build_command(matches, dump_flags)
     } else if let Some(matches) = matches.subcommand_matches("buildpkg") {
         build_package_command(matches, dump_flags)
+    } else if let Some(matches) = matches.subcommand_matches("interp") {
+        interp_command(matches, dump_flags)
     } else if let Some(matches) = matches.subcommand_matches("exports") {
         exports_command(matches)
     } else {
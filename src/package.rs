@@ -1,6 +1,6 @@
 use std::rc::Rc;
 use std::io::Read;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::ffi::OsStr;
 use either::Either;
@@ -11,14 +11,14 @@ use compileerror::{CompileResult, CompileError, type_error};
 use exportlibrary::ExportLibrary;
 use parser::parse_file;
 use target::Target;
-use typechecker::type_check_module;
+use typechecker::{type_check_module, ConstEvaluation};
 use span::Span;
 
-type MissingImportsMap = HashMap<String, Span>;
+type MissingImportsMap = BTreeMap<String, Span>;
 
 pub struct ImportData
 {
-    pub imports: HashMap<String, Rc<Import>>,
+    pub imports: BTreeMap<String, Rc<Import>>,
     pub libraries: Vec<ExportLibrary>,
 }
 
@@ -68,7 +68,7 @@ impl ImportData
 pub struct Package
 {
     pub name: String,
-    pub modules: HashMap<String, Module>,
+    pub modules: BTreeMap<String, Module>,
     pub import_data: ImportData,
     pub linker_flags: LinkerFlags,
 }
@@ -79,7 +79,7 @@ impl Package
     {
         Package{
             name: name.into(),
-            modules: HashMap::new(),
+            modules: BTreeMap::new(),
             import_data: ImportData{
                 imports: ImportMap::new(),
                 libraries: Vec::new(),
@@ -146,7 +146,16 @@ impl Package
         Ok(())
     }
 
-    pub fn type_check(&mut self, target: &Target) -> CompileResult<()>
+    pub fn imports_namespace(&self, namespace: &str) -> bool
+    {
+        let prefix = format!("{}::", namespace);
+        self.modules.values()
+            .flat_map(|module| module.import_names.iter())
+            .map(|import_name| import_name.to_namespace_string())
+            .any(|name| name == namespace || name.starts_with(&prefix))
+    }
+
+    pub fn type_check<E: ConstEvaluation>(&mut self, target: &Target, evaluator: &E) -> CompileResult<()>
     {
         let mut count = 0;
         while count < self.modules.len() {
@@ -160,13 +169,13 @@ impl Package
 
                 match self.import_data.resolve_module_imports(module) {
                     Either::Left(imports) => {
-                        type_check_module(module, target, &imports)?;
+                        type_check_module(module, target, &imports, evaluator)?;
                         self.import_data.imports.insert(module.name.clone(), Rc::new(module.get_exported_symbols(target)));
                         count += 1;
                     }
 
-                    Either::Right(mut missing) => {
-                        all_missing_imports.extend(missing.drain());
+                    Either::Right(missing) => {
+                        all_missing_imports.extend(missing);
                     }
                 }
             }
@@ -1,5 +1,6 @@
-use std::fs::{File};
-use std::io::{Read};
+use std::fmt;
+use std::fs::{DirBuilder, File};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::env;
 use toml;
@@ -7,20 +8,28 @@ use toml;
 use ast::{TreePrinter};
 use timer::{time_operation, time_operation_mut};
 use llvmbackend::TargetMachine;
-use bytecode::{compile_to_byte_code, optimize_module, OptimizationLevel};
+use bytecode::{ByteCodeModule, ByteCodeOptions, CompileTimeEvaluator, compile_to_byte_code, optimize_module, dump_ssa, write_cfg_graphs, write_call_graph, OptimizationLevel};
 use llvmbackend::{CodeGenOptions, OutputType, llvm_code_generation, link};
 use compileerror::{CompileResult, CompileError};
 use exportlibrary::ExportLibrary;
+use interpreter::interpret;
 use package::Package;
+use target::Target;
 
+const STD_PACKAGE: &str = "std";
 
 pub struct BuildOptions
 {
     pub optimize: bool,
+    pub checked_arithmetic: bool,
+    pub bounds_checks: bool,
+    pub verify_bytecode: bool,
     pub dump_flags: String,
-    pub target_machine: TargetMachine,
+    pub target: Target,
     pub sources_directory: String,
     pub import_directories: Vec<PathBuf>,
+    // Output goes to build_directory/<target triplet>/<target name>
+    pub build_directory: PathBuf,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -85,17 +94,57 @@ impl PackageData
         Ok(package)
     }
 
-    pub fn build(&self, build_options: &BuildOptions) -> CompileResult<()>
+    pub fn build(&self, build_options: &BuildOptions, target_machine: &TargetMachine) -> CompileResult<()>
     {
-        println!("Compiling for {}", build_options.target_machine.target.triplet);
+        println!("Compiling for {}", build_options.target.triplet);
         for t in &self.target {
             time_operation(2, "Total build time", ||{
-                t.build(build_options)
+                t.build(build_options, target_machine)
             })?;
         }
 
         Ok(())
     }
+
+    // Run the first target with the interpreter, returns the exit code of the program
+    pub fn interpret(&self, build_options: &BuildOptions, args: &[String]) -> CompileResult<i32>
+    {
+        match self.target.first()
+        {
+            Some(t) => t.interpret(build_options, args),
+            None => Err(CompileError::Other("No target to run".into())),
+        }
+    }
+}
+
+/*
+    The build directory of the std package shipped with the compiler, unless MENHIR_STD_DIR is set.
+    It is looked up next to the executable and in the directories above it, so it is found in an
+    installation as well as in the source tree when the compiler runs from target/debug.
+*/
+fn std_library_directory() -> CompileResult<PathBuf>
+{
+    if let Ok(dir) = env::var("MENHIR_STD_DIR") {
+        let std_dir = PathBuf::from(dir);
+        if !std_dir.is_dir() {
+            return Err(CompileError::Other(format!("MENHIR_STD_DIR is set to {}, which is not a directory", std_dir.display())));
+        }
+        return Ok(std_dir);
+    }
+
+    let exe = env::current_exe()?;
+    let mut dir = exe.parent();
+    while let Some(d) = dir {
+        let std_dir = d.join("std").join("build");
+        if std_dir.is_dir() {
+            return Ok(std_dir);
+        }
+        dir = d.parent();
+    }
+
+    Err(CompileError::Other(format!(
+        "Unable to find the std library, it is looked up in std/build next to {} and the directories above it, set MENHIR_STD_DIR to its build directory",
+        exe.display())))
 }
 
 fn output_file_name(name: &str, output_type: OutputType) -> String
@@ -126,24 +175,36 @@ impl PackageTarget
     fn find_dependency(&self, dep: &str, build_options: &BuildOptions, pkg: &mut Package) -> CompileResult<()>
     {
         // Always try the build directory first
-        if self.find_dependency_in_path(dep, "build", &build_options.target_machine.target.triplet, pkg)? {
+        if self.find_dependency_in_path(dep, &build_options.build_directory.to_string_lossy(), &build_options.target.triplet, pkg)? {
             return Ok(())
         }
 
         for import_dir in &build_options.import_directories {
-            if self.find_dependency_in_path(dep, &import_dir.to_string_lossy(), &build_options.target_machine.target.triplet, pkg)? {
+            if self.find_dependency_in_path(dep, &import_dir.to_string_lossy(), &build_options.target.triplet, pkg)? {
                 return Ok(())
             }
         }
 
         if let Ok(import_paths) = env::var("MENHIR_IMPORT_DIRS") {
             for path in import_paths.split(':') {
-                if self.find_dependency_in_path(dep, path, &build_options.target_machine.target.triplet, pkg)? {
+                if self.find_dependency_in_path(dep, path, &build_options.target.triplet, pkg)? {
                     return Ok(())
                 }
             }
         }
 
+        // Only a missing std package is an error, other packages are not expected in the std directory
+        let std_dir = match std_library_directory() {
+            Ok(std_dir) => Some(std_dir),
+            Err(e) => if dep == STD_PACKAGE {return Err(e)} else {None},
+        };
+
+        if let Some(std_dir) = std_dir {
+            if self.find_dependency_in_path(dep, &std_dir.to_string_lossy(), &build_options.target.triplet, pkg)? {
+                return Ok(())
+            }
+        }
+
         Err(CompileError::Other(format!("Unable to find dependency {}", dep)))
     }
 
@@ -158,10 +219,17 @@ impl PackageTarget
         Ok(())
     }
 
+    // Packages importing std, depend on it without listing it
+    fn needs_implicit_std(&self, pkg: &Package) -> bool
+    {
+        let listed = self.depends.as_ref().map(|deps| deps.iter().any(|dep| dep == STD_PACKAGE)).unwrap_or(false);
+        self.name != STD_PACKAGE && !listed && pkg.imports_namespace(STD_PACKAGE)
+    }
+
 
-    fn build(&self, build_options: &BuildOptions) -> CompileResult<()>
+    // Parse, type check and compile the target to optimized bytecode
+    fn compile(&self, build_options: &BuildOptions) -> CompileResult<(Package, ByteCodeModule)>
     {
-        println!("Building target {}", self.name);
         let single_file = format!("{}/{}.mhr", build_options.sources_directory, self.name);
         let dir_name = format!("{}/{}", build_options.sources_directory, self.name);
 
@@ -178,10 +246,13 @@ impl PackageTarget
 
         let mut pkg = Package::new(&self.name);
         self.find_dependencies(build_options, &mut pkg)?;
-        pkg.parse_files(path, &build_options.target_machine.target)?;
+        pkg.parse_files(path, &build_options.target)?;
+        if self.needs_implicit_std(&pkg) {
+            self.find_dependency(STD_PACKAGE, build_options, &mut pkg)?;
+        }
 
         time_operation_mut(2, "Type checking", ||{
-            pkg.type_check(&build_options.target_machine.target)
+            pkg.type_check(&build_options.target, &CompileTimeEvaluator)
         })?;
 
         if build_options.dump_flags.contains("ast") || build_options.dump_flags.contains("all") {
@@ -189,8 +260,15 @@ impl PackageTarget
             pkg.print(0);
         }
 
+        let bc_options = ByteCodeOptions{
+            checked_arithmetic: build_options.checked_arithmetic,
+            bounds_checks: build_options.bounds_checks,
+            verify: build_options.verify_bytecode || cfg!(debug_assertions),
+            dump_escape_analysis: build_options.dump_flags.contains("escapes") || build_options.dump_flags.contains("all"),
+        };
+
         let mut bc_mod = time_operation(2, "Compile to bytecode", ||{
-            compile_to_byte_code(&pkg, &build_options.target_machine.target)
+            compile_to_byte_code(&pkg, &build_options.target, bc_options)
         })?;
 
         if build_options.dump_flags.contains("bytecode") || build_options.dump_flags.contains("all") {
@@ -200,26 +278,68 @@ impl PackageTarget
             println!("------\n");
         }
 
+        if build_options.dump_flags.contains("cfg") || build_options.dump_flags.contains("all") {
+            self.write_dot_file(build_options, "cfg", |dot| write_cfg_graphs(dot, &bc_mod))?;
+        }
+
+        if build_options.dump_flags.contains("callgraph") || build_options.dump_flags.contains("all") {
+            self.write_dot_file(build_options, "callgraph", |dot| write_call_graph(dot, &bc_mod))?;
+        }
+
         time_operation_mut(2, "Optimization", ||{
             if build_options.optimize {
-                optimize_module(&mut bc_mod, OptimizationLevel::Normal);
+                optimize_module(&mut bc_mod, OptimizationLevel::Normal)
             } else {
-                optimize_module(&mut bc_mod, OptimizationLevel::Minimal);
+                optimize_module(&mut bc_mod, OptimizationLevel::Minimal)
             }
-        });
+        })?;
 
+        if build_options.dump_flags.contains("ssa") || build_options.dump_flags.contains("all") {
+            dump_ssa(&mut bc_mod)?;
+        }
+
+        Ok((pkg, bc_mod))
+    }
+
+    fn build_dir(&self, build_options: &BuildOptions) -> String
+    {
+        build_options.build_directory
+            .join(&build_options.target.triplet)
+            .join(&self.name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // Write a Graphviz file to the build directory
+    fn write_dot_file<F>(&self, build_options: &BuildOptions, kind: &str, write: F) -> CompileResult<()>
+        where F: FnOnce(&mut String) -> fmt::Result
+    {
+        let build_dir = self.build_dir(build_options);
+        DirBuilder::new().recursive(true).create(&build_dir)?;
+
+        let path = format!("{}/{}.{}.dot", build_dir, self.name, kind);
+        let mut dot = String::new();
+        write(&mut dot).map_err(|_| CompileError::Other(format!("Unable to generate {}", path)))?;
+        let mut file = File::create(&path)?;
+        println!("  Generating {}", path);
+        file.write_all(dot.as_bytes())?;
+        Ok(())
+    }
+
+    fn build(&self, build_options: &BuildOptions, target_machine: &TargetMachine) -> CompileResult<()>
+    {
+        println!("Building target {}", self.name);
+        let (pkg, bc_mod) = self.compile(build_options)?;
         let opts = CodeGenOptions{
             dump_ir: build_options.dump_flags.contains("ir") ||  build_options.dump_flags.contains("all"),
-            build_dir: format!("build/{}/{}", build_options.target_machine.target.triplet, self.name),
+            build_dir: self.build_dir(build_options),
             output_file_name: output_file_name(&self.name, self.output_type),
             output_type: self.output_type,
             optimize: build_options.optimize,
         };
 
-
-
         let ctx = time_operation(2, "Code generation", ||{
-            llvm_code_generation(&bc_mod, &build_options.target_machine).map_err(CompileError::Other)
+            llvm_code_generation(&bc_mod, target_machine).map_err(CompileError::Other)
         })?;
 
         time_operation(2, "Linking", ||{
@@ -240,4 +360,12 @@ impl PackageTarget
         }
         Ok(())
     }
+
+    fn interpret(&self, build_options: &BuildOptions, args: &[String]) -> CompileResult<i32>
+    {
+        let (_, bc_mod) = self.compile(build_options)?;
+        let mut program_args = vec![self.name.clone()];
+        program_args.extend(args.iter().cloned());
+        interpret(&bc_mod, &build_options.target, &program_args).map_err(CompileError::Other)
+    }
 }
\ No newline at end of file
@@ -138,6 +138,7 @@ impl Lexer
             "if" => TokenKind::If,
             "else" => TokenKind::Else,
             "extern" => TokenKind::Extern,
+            "export" => TokenKind::Export,
             "new" => TokenKind::New,
             "delete" => TokenKind::Delete,
             "while" => TokenKind::While,
@@ -145,10 +146,14 @@ impl Lexer
             "nil" => TokenKind::Nil,
             "null" => TokenKind::Null,
             "var" => TokenKind::Var,
+            "const" => TokenKind::Const,
             "as" => TokenKind::BinaryOperator(BinaryOperator::As),
             "interface" => TokenKind::Interface,
             "fn" => TokenKind::Func,
             "return" => TokenKind::Return,
+            "defer" => TokenKind::Defer,
+            "panic" => TokenKind::Panic,
+            "unreachable" => TokenKind::Unreachable,
             _ => TokenKind::Identifier(mem::replace(&mut self.data, String::new())),
         };
 
@@ -185,7 +190,20 @@ impl Lexer
 
     fn number(&mut self, c: char) -> CompileResult<()>
     {
-        if c.is_numeric() || c == '.' || c == 'e'
+        if c == '.' && self.data.ends_with('.')
+        {
+            // A range like 0..4, the previous dot is not part of the number
+            self.data.pop();
+            let mut span = self.current_span();
+            span.end.offset -= 1;
+            let num = mem::replace(&mut self.data, String::new());
+            self.add(TokenKind::Number(num), span);
+            self.token_start_pos = Pos::new(self.pos.line, self.pos.offset - 1);
+            self.state = LexState::Operator;
+            self.data.push_str("..");
+            Ok(())
+        }
+        else if c.is_numeric() || c == '.' || c == 'e'
         {
             self.data.push(c);
             Ok(())
@@ -209,6 +227,12 @@ impl Lexer
             "*" => Ok(TokenKind::BinaryOperator(BinaryOperator::Mul)),
             "/" => Ok(TokenKind::BinaryOperator(BinaryOperator::Div)),
             "%" => Ok(TokenKind::BinaryOperator(BinaryOperator::Mod)),
+            "+%" => Ok(TokenKind::BinaryOperator(BinaryOperator::WrappingAdd)),
+            "-%" => Ok(TokenKind::BinaryOperator(BinaryOperator::WrappingSub)),
+            "*%" => Ok(TokenKind::BinaryOperator(BinaryOperator::WrappingMul)),
+            "+|" => Ok(TokenKind::BinaryOperator(BinaryOperator::SaturatingAdd)),
+            "-|" => Ok(TokenKind::BinaryOperator(BinaryOperator::SaturatingSub)),
+            "*|" => Ok(TokenKind::BinaryOperator(BinaryOperator::SaturatingMul)),
             ">" => Ok(TokenKind::BinaryOperator(BinaryOperator::GreaterThan)),
             ">=" => Ok(TokenKind::BinaryOperator(BinaryOperator::GreaterThanEquals)),
             "<" => Ok(TokenKind::BinaryOperator(BinaryOperator::LessThan)),
@@ -231,6 +255,8 @@ impl Lexer
             "::" => Ok(TokenKind::DoubleColon),
             "|" => Ok(TokenKind::Pipe),
             "." => Ok(TokenKind::BinaryOperator(BinaryOperator::Dot)),
+            ".." => Ok(TokenKind::DoubleDot),
+            "..." => Ok(TokenKind::Ellipsis),
             "&" => Ok(TokenKind::Ampersand),
             _ => parse_error_result(&self.current_single_span(), format!("Invalid operator {}", self.data)),
         }
@@ -238,10 +264,12 @@ impl Lexer
 
     fn operator(&mut self, c: char) -> CompileResult<()>
     {
+        // A colon can only continue another colon, so the end of a generic type works: Vec<$T>:
         if c.is_whitespace() || c.is_alphanumeric() ||
             c == '{' || c == '(' || c == '[' ||
             c == '}' || c == ')' || c == ']' ||
-            c == '$' || c == ',' || c == '_'
+            c == '$' || c == ',' || c == '_' ||
+            (c == ':' && self.data != ":")
         {
             let kind = self.data_to_token_kind()?;
             self.state = LexState::Idle;
@@ -471,6 +499,27 @@ mod tests
         ]);
     }
 
+    #[test]
+    fn test_operator_followed_by_colon()
+    {
+        let mut cursor = Cursor::new("Vec<$T>:");
+        let tokens: Vec<Token> = Lexer::new("")
+            .read(&mut cursor)
+            .expect("Lexing failed")
+            .collect();
+
+        assert_eq!(tokens, vec![
+            tok(TokenKind::Indent(0), 1, 1, 1, 1),
+            tok(TokenKind::Identifier("Vec".into()), 1, 1, 1, 3),
+            tok(TokenKind::BinaryOperator(BinaryOperator::LessThan), 1, 4, 1, 4),
+            tok(TokenKind::Dollar, 1, 5, 1, 5),
+            tok(TokenKind::Identifier("T".into()), 1, 6, 1, 6),
+            tok(TokenKind::BinaryOperator(BinaryOperator::GreaterThan), 1, 7, 1, 7),
+            tok(TokenKind::Colon, 1, 8, 1, 8),
+            tok(TokenKind::EOF, 2, 1, 2, 1),
+        ]);
+    }
+
     #[test]
     fn test_string()
     {
@@ -36,7 +36,8 @@ fn is_end_of_expression(tok: &Token) -> bool
         TokenKind::Assign(_) |
         TokenKind::OpenParen |
         TokenKind::OpenBracket |
-        TokenKind::OpenCurly => false,
+        TokenKind::OpenCurly |
+        TokenKind::QuestionMark => false,
         _ => true,
     }
 }
@@ -167,7 +168,8 @@ fn combine_binary_op(op: BinaryOperator, lhs: Expression, rhs: Expression) -> Ex
 
 fn combine_type_cast(lhs: Expression, destination_type: Type, span: Span) -> Expression
 {
-    if lhs.is_binary_op()
+    // A binary op in parentheses has TOP_PRECEDENCE, and is cast as a whole
+    if lhs.is_binary_op() && lhs.precedence() < TOP_PRECEDENCE
     {
         let bop = lhs.extract_binary_op().expect("Not a binary op");
         let nrhs = combine_type_cast(bop.right.clone(), destination_type, span);
@@ -262,18 +264,18 @@ fn to_primitive(name: &str, target: &Target) -> Option<Type>
 {
     match name
     {
-        "int8" => Some(Type::Int(IntSize::I8)),
-        "int16" => Some(Type::Int(IntSize::I16)),
-        "int32" => Some(Type::Int(IntSize::I32)),
-        "int64" => Some(Type::Int(IntSize::I64)),
+        "i8" | "int8" => Some(Type::Int(IntSize::I8)),
+        "i16" | "int16" => Some(Type::Int(IntSize::I16)),
+        "i32" | "int32" => Some(Type::Int(IntSize::I32)),
+        "i64" | "int64" => Some(Type::Int(IntSize::I64)),
         "int" => Some(target.native_int_type.clone()),
-        "uint8" => Some(Type::UInt(IntSize::I8)),
-        "uint16" => Some(Type::UInt(IntSize::I16)),
-        "uint32" => Some(Type::UInt(IntSize::I32)),
-        "uint64" => Some(Type::UInt(IntSize::I64)),
+        "u8" | "uint8" => Some(Type::UInt(IntSize::I8)),
+        "u16" | "uint16" => Some(Type::UInt(IntSize::I16)),
+        "u32" | "uint32" => Some(Type::UInt(IntSize::I32)),
+        "u64" | "uint64" => Some(Type::UInt(IntSize::I64)),
         "uint" => Some(target.native_uint_type.clone()),
-        "float" | "float32" => Some(Type::Float(FloatSize::F32)),
-        "double" | "float64" => Some(Type::Float(FloatSize::F64)),
+        "f32" | "float32" | "float" => Some(Type::Float(FloatSize::F32)),
+        "f64" | "float64" | "double" => Some(Type::Float(FloatSize::F64)),
         "string" => Some(Type::String),
         "bool" => Some(Type::Bool),
         "char" => Some(Type::Char),
@@ -364,6 +366,15 @@ fn parse_type(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> Comp
         if tq.is_next(&TokenKind::CloseBracket) {
             tq.pop()?;
             typ = slice_type(typ);
+        } else if tq.is_next(&TokenKind::Dollar) {
+            tq.pop()?;
+            let (name, _span) = tq.expect_identifier()?;
+            typ = array_type_with_length(typ, ArrayLength::Generic(name));
+            tq.expect(&TokenKind::CloseBracket)?;
+        } else if let Some(&TokenKind::Identifier(_)) = tq.peek().map(|t| &t.kind) {
+            let (name, _span) = tq.expect_identifier()?;
+            typ = array_type_with_length(typ, ArrayLength::Const(name));
+            tq.expect(&TokenKind::CloseBracket)?;
         } else {
             let (len, _span) = tq.expect_int()?;
             typ = array_type(typ, len as usize);
@@ -425,13 +436,79 @@ fn parse_function_signature(tq: &mut TokenQueue, self_type: &Type, indent_level:
     Ok(sig(&name, ret_type, args, name_span.expanded(sig_span_end)))
 }
 
+// The arguments of an extern, which can end with ... to declare a C variadic function
+fn parse_external_function_arguments(tq: &mut TokenQueue, indent_level: usize, target: &Target) -> CompileResult<(Vec<Argument>, bool)>
+{
+    tq.expect(&TokenKind::OpenParen)?;
+    let mut args = Vec::new();
+    loop
+    {
+        if tq.is_next(&TokenKind::CloseParen) {
+            tq.pop()?;
+            return Ok((args, false));
+        }
+
+        if tq.is_next(&TokenKind::Ellipsis) {
+            tq.pop()?;
+            tq.expect(&TokenKind::CloseParen)?;
+            return Ok((args, true));
+        }
+
+        args.push(parse_function_argument(tq, &Type::Unknown, indent_level, target)?);
+        if !tq.is_next(&TokenKind::CloseParen) {
+            tq.expect(&TokenKind::Comma)?;
+        }
+    }
+}
+
 fn parse_external_function(tq: &mut TokenQueue, span: &Span, indent_level: usize, target: &Target) -> CompileResult<ExternalFunction>
 {
     tq.expect(&TokenKind::Func)?;
-    Ok(ExternalFunction::new(
-        parse_function_signature(tq, &Type::Unknown, indent_level, target)?,
-        span.expanded(tq.pos()),
-    ))
+    let (name, name_span) = tq.expect_identifier()?;
+    let (args, variadic) = parse_external_function_arguments(tq, indent_level, target)?;
+    let ret_type = if tq.is_next(&TokenKind::Arrow) {
+        tq.pop()?;
+        parse_type(tq, indent_level, target)?
+    } else {
+        Type::Void
+    };
+
+    let mut signature = sig(&name, ret_type, args, name_span.expanded(tq.pos()));
+    signature.variadic = variadic;
+    Ok(ExternalFunction::new(signature, span.expanded(tq.pos())))
+}
+
+// export "C" fn ..., a function which uses the C calling convention, so it can be called from C
+fn parse_exported_function(tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Function>
+{
+    let tok = tq.pop()?;
+    match tok.kind
+    {
+        TokenKind::StringLiteral(ref abi) if abi == "C" => (),
+        _ => return parse_error_result(&tok.span, format!("Expected \"C\" after export, found {}", tok)),
+    }
+
+    tq.expect(&TokenKind::Func)?;
+    let mut func = parse_function_declaration(tq, namespace, span, indent_level, target)?;
+    func.sig.calling_convention = CallingConvention::C;
+    Ok(func)
+}
+
+// @inline fn ... or @noinline fn ..., overrides the size heuristic of the inliner
+fn parse_annotated_function(tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Function>
+{
+    let (annotation, annotation_span) = tq.expect_identifier()?;
+    let inline_hint = match &annotation[..]
+    {
+        "inline" => InlineHint::Always,
+        "noinline" => InlineHint::Never,
+        _ => return parse_error_result(&annotation_span, format!("Unknown annotation @{}", annotation)),
+    };
+
+    tq.expect(&TokenKind::Func)?;
+    let mut func = parse_function_declaration(tq, namespace, span, indent_level, target)?;
+    func.sig.inline_hint = inline_hint;
+    Ok(func)
 }
 
 fn parse_function_declaration(tq: &mut TokenQueue, namespace: &str, span: &Span, indent_level: usize, target: &Target) -> CompileResult<Function>
@@ -705,7 +782,13 @@ fn parse_struct_type(tq: &mut TokenQueue, namespace: &str, indent_level: usize,
         let (member_name, member_name_span) = tq.expect_identifier()?;
         tq.expect(&TokenKind::Colon)?;
         let typ = parse_type(tq, indent_level, target)?;
-        Ok(struct_member_declaration(&member_name, typ, member_name_span.expanded(tq.pos())))
+        let mut member = struct_member_declaration(&member_name, typ, member_name_span.expanded(tq.pos()));
+        if tq.is_next(&TokenKind::Assign(AssignOperator::Assign)) {
+            tq.pop()?;
+            member.default = Some(parse_expression(tq, indent_level, target)?);
+            member.span = member.span.expanded(tq.pos());
+        }
+        Ok(member)
     };
 
     let members = if tq.is_next(&TokenKind::OpenCurly) {
@@ -885,13 +968,71 @@ fn parse_compiler_call(tq: &mut TokenQueue, start: &Span, indent_level: usize, t
             }))
         }
 
+        "try_cast" | "wrapping_cast" | "saturating_cast" => {
+            let mode = match &name[..] {
+                "try_cast" => ConversionMode::Checked,
+                "wrapping_cast" => ConversionMode::Wrapping,
+                _ => ConversionMode::Saturating,
+            };
+
+            tq.expect(&TokenKind::OpenParen)?;
+            let inner = parse_expression(tq, indent_level, target)?;
+            tq.expect(&TokenKind::Comma)?;
+            let typ = parse_type(tq, indent_level, target)?;
+            tq.expect(&TokenKind::CloseParen)?;
+
+            Ok(Expression::CompilerCall(CompilerCall::Convert{
+                inner: Box::new(inner),
+                typ: typ,
+                mode: mode,
+                span: start.expanded(tq.pos()),
+            }))
+        },
+
+        "assert" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let mut arguments = parse_comma_separated_list(tq, &TokenKind::CloseParen, parse_expression, indent_level, target)?;
+            let span = start.expanded(tq.pos());
+            let message = match arguments.len() {
+                1 => Expression::Literal(Literal::String(span.clone(), "assertion failed".into())),
+                2 => arguments.pop().expect("Missing assert message"),
+                _ => return parse_error_result(&span, "@assert expects a condition and an optional message"),
+            };
+
+            Ok(Expression::CompilerCall(CompilerCall::Assert{
+                condition: Box::new(arguments.pop().expect("Missing assert condition")),
+                message: Box::new(message),
+                span
+            }))
+        },
+
+        // The target is known while parsing, so it becomes a string literal
+        "target_os" => {
+            tq.expect(&TokenKind::OpenParen)?;
+            tq.expect(&TokenKind::CloseParen)?;
+            Ok(Expression::Literal(Literal::String(start.expanded(tq.pos()), target.os().into())))
+        },
+
         _ => parse_error_result(&name_span, format!("Unknown compiler call {}", name))
     }
 }
 
 fn parse_return(tq: &mut TokenQueue, start: &Span, indent_level: usize, target: &Target) -> CompileResult<Expression>
 {
-    if tq.peek().map(|tok| is_end_of_expression(tok)).unwrap_or(true) {
+    // Not is_end_of_expression, literals like true, nil or a char can follow a return
+    let returns_void = tq.peek().map(|tok| match tok.kind {
+        TokenKind::Indent(_) |
+        TokenKind::EOF |
+        TokenKind::Else |
+        TokenKind::Comma |
+        TokenKind::SemiColon |
+        TokenKind::CloseParen |
+        TokenKind::CloseBracket |
+        TokenKind::CloseCurly => true,
+        _ => false,
+    }).unwrap_or(true);
+
+    if returns_void {
         Ok(return_expr(Expression::Void, start.clone()))
     } else {
         let expr = parse_expression(tq, indent_level, target)?;
@@ -1031,6 +1172,22 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
             parse_return(tq, &tok.span, indent_level, target)?
         }
 
+        TokenKind::Defer => {
+            let inner = parse_expression(tq, indent_level, target)?;
+            defer(inner, tok.span.expanded(tq.pos()))
+        }
+
+        TokenKind::Panic => {
+            tq.expect(&TokenKind::OpenParen)?;
+            let message = parse_expression(tq, indent_level, target)?;
+            tq.expect(&TokenKind::CloseParen)?;
+            panic_expr(message, tok.span.expanded(tq.pos()))
+        }
+
+        TokenKind::Unreachable => {
+            unreachable_expr(tok.span)
+        }
+
         _ => return parse_error_result(&tok.span, format!("Unexpected token '{}'", tok)),
     };
 
@@ -1039,10 +1196,29 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
         match next.kind
         {
             TokenKind::OpenBracket => {
-                let index_expr = parse_expression(tq, indent_level, target)?;
-                tq.expect(&TokenKind::CloseBracket)?;
-                let span = lhs.span().expanded(tq.pos());
-                lhs = index_op(lhs, index_expr, span);
+                let index_expr = if tq.is_next(&TokenKind::DoubleDot) {
+                    None
+                } else {
+                    Some(parse_expression(tq, indent_level, target)?)
+                };
+
+                if tq.is_next(&TokenKind::DoubleDot) {
+                    tq.pop()?;
+                    let end = if tq.is_next(&TokenKind::CloseBracket) {
+                        None
+                    } else {
+                        Some(parse_expression(tq, indent_level, target)?)
+                    };
+                    tq.expect(&TokenKind::CloseBracket)?;
+                    let span = lhs.span().expanded(tq.pos());
+                    lhs = slice_op(lhs, index_expr, end, span);
+                } else if let Some(index_expr) = index_expr {
+                    tq.expect(&TokenKind::CloseBracket)?;
+                    let span = lhs.span().expanded(tq.pos());
+                    lhs = index_op(lhs, index_expr, span);
+                } else {
+                    return parse_error_result(&next.span, "Expecting an index or a range");
+                }
             },
 
             TokenKind::BinaryOperator(BinaryOperator::Dot) => {
@@ -1050,6 +1226,11 @@ fn parse_expression_start(tq: &mut TokenQueue, tok: Token, indent_level: usize,
                 lhs = parse_member_access(tq, lhs, indent_level, target)?;
             },
 
+            TokenKind::QuestionMark => {
+                let span = lhs.span().expanded(next.span.end);
+                lhs = propagate(lhs, span);
+            },
+
             _ => {
                 tq.push_front(next);
                 break
@@ -1071,7 +1252,7 @@ pub fn parse_expression(tq: &mut TokenQueue, indent_level: usize, target: &Targe
     }
 }
 
-fn parse_global_bindings(module: &mut Module, tq: &mut TokenQueue, mutable: bool, indent_level: usize, namespace: &str, target: &Target) -> CompileResult<()>
+fn parse_global_bindings(module: &mut Module, tq: &mut TokenQueue, mutable: bool, constant: bool, indent_level: usize, namespace: &str, target: &Target) -> CompileResult<()>
 {
     while !is_end_of_bindings(tq, indent_level)
     {
@@ -1084,7 +1265,9 @@ fn parse_global_bindings(module: &mut Module, tq: &mut TokenQueue, mutable: bool
         }
 
         let full_name = namespaced(namespace, &name);
-        module.globals.insert(full_name.clone(), global_binding(full_name, init, mutable, span.expanded(tq.pos())));
+        let mut global = global_binding(full_name.clone(), init, mutable, span.expanded(tq.pos()));
+        global.constant = constant;
+        module.globals.insert(full_name, global);
         eat_comma(tq)?;
     }
 
@@ -1102,14 +1285,26 @@ fn parse_interface(module: &mut Module, tq: &mut TokenQueue, namespace: &str, sp
     let self_type = ptr_type(Type::SelfType);
 
     let parse_interface_function = |tq: &mut TokenQueue, indent_level: usize, target: &Target| {
-        tq.expect(&TokenKind::Func)?;
-        parse_function_signature(tq, &self_type, indent_level, target)
+        let func_span = tq.expect(&TokenKind::Func)?.span;
+        let sig = parse_function_signature(tq, &self_type, indent_level, target)?;
+        if tq.is_next(&TokenKind::Colon) {
+            // Default implementation
+            tq.pop()?;
+            let expr = parse_block(tq, &func_span.file, indent_level, target)?;
+            let func_span = func_span.expanded(expr.span().end);
+            Ok((sig.clone(), Some(Function::new(sig, true, expr, func_span))))
+        } else {
+            Ok((sig, None))
+        }
     };
 
-    let functions = parse_indented_block(tq, indent_level, parse_interface_function, target)?;
+    let (functions, default_impls): (Vec<_>, Vec<_>) = parse_indented_block(tq, indent_level, parse_interface_function, target)?
+        .into_iter()
+        .unzip();
 
+    let default_impls = default_impls.into_iter().filter_map(|f| f).collect();
     let name = namespaced(namespace, &name);
-    module.types.insert(name.clone(), TypeDeclaration::Interface(interface(name, functions, span.expanded(tq.pos()))));
+    module.types.insert(name.clone(), TypeDeclaration::Interface(interface(name, functions, default_impls, span.expanded(tq.pos()))));
     Ok(())
 }
 
@@ -1153,6 +1348,14 @@ fn parse_module<Input: Read>(
         Ok(())
     };
 
+    let add_sum_type = |module: &mut Module, st: SumTypeDeclaration| -> CompileResult<()> {
+        if module.types.contains_key(&st.name) {
+            return parse_error_result(&st.span, format!("Type {} redefined", st.name));
+        }
+        module.types.insert(st.name.clone(), TypeDeclaration::Sum(st));
+        Ok(())
+    };
+
     let mut indent_level = 0;
     while !tq.is_next(&TokenKind::EOF)
     {
@@ -1168,11 +1371,15 @@ fn parse_module<Input: Read>(
             }
 
             TokenKind::Let => {
-                parse_global_bindings(module, &mut tq, false, indent_level, namespace, target)?;
+                parse_global_bindings(module, &mut tq, false, false, indent_level, namespace, target)?;
             }
 
             TokenKind::Var => {
-                parse_global_bindings(module, &mut tq, true, indent_level, namespace, target)?;
+                parse_global_bindings(module, &mut tq, true, false, indent_level, namespace, target)?;
+            }
+
+            TokenKind::Const => {
+                parse_global_bindings(module, &mut tq, false, true, indent_level, namespace, target)?;
             }
 
             TokenKind::Struct => {
@@ -1186,10 +1393,7 @@ fn parse_module<Input: Read>(
 
             TokenKind::Enum => {
                 let st = parse_sum_type(&mut tq, namespace, &tok.span, indent_level, target)?;
-                if module.types.contains_key(&st.name) {
-                    return parse_error_result(&st.span, format!("Type {} redefined", st.name));
-                }
-                module.types.insert(st.name.clone(), TypeDeclaration::Sum(st));
+                add_sum_type(module, st)?;
             }
 
             TokenKind::Type => {
@@ -1222,9 +1426,28 @@ fn parse_module<Input: Read>(
                 add_function(module, func)?;
             }
 
+            TokenKind::Export => {
+                let func = parse_exported_function(&mut tq, namespace, &tok.span, indent_level, target)?;
+                add_function(module, func)?;
+            }
+
+            // @result enum ..., marks a sum type as a result type, which can be used with the ? operator
+            TokenKind::At if tq.is_next(&TokenKind::Identifier("result".into())) => {
+                tq.pop()?;
+                tq.expect(&TokenKind::Enum)?;
+                let mut st = parse_sum_type(&mut tq, namespace, &tok.span, indent_level, target)?;
+                st.result = true;
+                add_sum_type(module, st)?;
+            }
+
+            TokenKind::At => {
+                let func = parse_annotated_function(&mut tq, namespace, &tok.span, indent_level, target)?;
+                add_function(module, func)?;
+            }
+
             _ => {
                 return parse_error_result(&tok.span,
-                    format!("Expected import, fn, let, var, extern, type, struct, enum or interface found token {}", tok));
+                    format!("Expected import, fn, let, var, const, extern, export, type, struct, enum or interface found token {}", tok));
             }
         }
     }
@@ -87,6 +87,12 @@ fn test_binary_ops()
         (BinaryOperator::Div, "/"),
         (BinaryOperator::Mul, "*"),
         (BinaryOperator::Mod, "%"),
+        (BinaryOperator::WrappingAdd, "+%"),
+        (BinaryOperator::WrappingSub, "-%"),
+        (BinaryOperator::WrappingMul, "*%"),
+        (BinaryOperator::SaturatingAdd, "+|"),
+        (BinaryOperator::SaturatingSub, "-|"),
+        (BinaryOperator::SaturatingMul, "*|"),
         (BinaryOperator::Equals, "=="),
         (BinaryOperator::NotEquals, "!="),
         (BinaryOperator::GreaterThan, ">"),
@@ -278,6 +284,24 @@ fn test_precedence_10()
     assert!(e == s3);
 }
 
+#[test]
+fn test_precedence_11()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr("(a + b) as u8", &target);
+    assert!(e == type_cast(
+        bin_op_with_precedence(
+            BinaryOperator::Add,
+            name_ref("a", span(1, 2, 1, 2)),
+            name_ref("b", span(1, 6, 1, 6)),
+            span(1, 2, 1, 6),
+            TOP_PRECEDENCE
+        ),
+        Type::UInt(IntSize::I8),
+        span(1, 2, 1, 13),
+    ));
+}
+
 #[test]
 fn test_namespaced_call()
 {
@@ -463,6 +487,61 @@ fn test_external_function()
     )
 }
 
+#[test]
+fn test_variadic_external_function()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("extern fn printf(fmt: *u8, ...) -> int", &target);
+    let mut expected = sig(
+        "printf",
+        target.native_int_type.clone(),
+        vec![
+            Argument::new("fmt", ptr_type(Type::UInt(IntSize::I8)), false, span(1, 18, 1, 25)),
+        ],
+        span(1, 11, 1, 38)
+    );
+    expected.variadic = true;
+    assert!(*md.externals.get("printf").unwrap() == ExternalFunction::new(expected, span(1, 1, 1, 38)));
+}
+
+#[test]
+fn test_exported_function()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("export \"C\" fn foo(a: int) -> int: 7", &target);
+    let mut expected = sig(
+        "test::foo",
+        target.native_int_type.clone(),
+        vec![
+            arg("a", target.native_int_type.clone(), span(1, 19, 1, 24)),
+        ],
+        span(1, 1, 1, 32)
+    );
+    expected.calling_convention = CallingConvention::C;
+    assert!(*md.functions.get("test::foo").unwrap() == Function::new(
+        expected,
+        true,
+        number(7, span(1, 35, 1, 35), &target),
+        span(1, 1, 1, 35))
+    );
+
+    assert!(parse_str("export \"Pascal\" fn foo() -> int: 7", "test", &target).is_err());
+}
+
+#[test]
+fn test_inline_annotations()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("@inline fn foo() -> int: 7\n@noinline fn bar() -> int: 8\nfn baz() -> int: 9", &target);
+    assert_eq!(md.functions.get("test::foo").unwrap().sig.inline_hint, InlineHint::Always);
+    assert_eq!(md.functions.get("test::bar").unwrap().sig.inline_hint, InlineHint::Never);
+    assert_eq!(md.functions.get("test::baz").unwrap().sig.inline_hint, InlineHint::Auto);
+    assert_eq!(md.functions.get("test::foo").unwrap().span.start, Pos::new(1, 1));
+
+    assert!(parse_str("@always fn foo() -> int: 7", "test", &target).is_err());
+    assert!(parse_str("@inline let x = 7", "test", &target).is_err());
+}
+
 #[test]
 fn test_lambda()
 {
@@ -523,6 +602,27 @@ struct Point:
     ))
 }
 
+#[test]
+fn test_struct_member_defaults()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+struct Point:
+    x: int
+    y: int = 7
+"#, &target);
+    let mut y = struct_member_declaration("y", target.native_int_type.clone(), span(4, 5, 4, 14));
+    y.default = Some(number(7, span(4, 14, 4, 14), &target));
+    assert!(*md.types.get("test::Point").unwrap() == TypeDeclaration::Struct(struct_declaration(
+        "test::Point",
+        vec![
+            struct_member_declaration("x", target.native_int_type.clone(), span(3, 5, 3, 10)),
+            y,
+        ],
+        span(2, 1, 4, 14))
+    ))
+}
+
 #[test]
 fn test_generic_struct()
 {
@@ -641,6 +741,21 @@ enum Option:
     ))
 }
 
+#[test]
+fn test_result_types()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod("@result enum Result:\n    Ok{v: int}\n    Error{code: int}\nenum Foo:\n    Bar{v: int}\n    Baz", &target);
+    let is_result = |name: &str| match *md.types.get(name).unwrap()
+    {
+        TypeDeclaration::Sum(ref st) => st.result,
+        _ => panic!("{} is not a sum type", name),
+    };
+    assert!(is_result("test::Result"));
+    assert!(!is_result("test::Foo"));
+    assert!(parse_str("@result fn foo() -> int: 7", "test", &target).is_err());
+}
+
 #[test]
 fn test_sum_types_with_data()
 {
@@ -755,6 +870,142 @@ fn test_block()
     ))
 }
 
+#[test]
+fn test_propagation()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+x? + 1"#, &target);
+    assert!(e == bin_op(
+        BinaryOperator::Add,
+        propagate(name_ref("x", span(2, 1, 2, 1)), span(2, 1, 2, 2)),
+        number(1, span(2, 6, 2, 6), &target),
+        span(2, 1, 2, 6)
+    ))
+}
+
+#[test]
+fn test_return_literal()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+return false"#, &target);
+    assert!(e == return_expr(
+        Expression::Literal(Literal::Bool(span(2, 8, 2, 12), false)),
+        span(2, 1, 2, 12)
+    ))
+}
+
+#[test]
+fn test_defer()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+defer delete x"#, &target);
+    assert!(e == defer(
+        delete(name_ref("x", span(2, 14, 2, 14)), span(2, 7, 2, 14)),
+        span(2, 1, 2, 14)
+    ))
+}
+
+#[test]
+fn test_conversion()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+@try_cast(x, u8)"#, &target);
+    assert!(e == Expression::CompilerCall(CompilerCall::Convert{
+        inner: Box::new(name_ref("x", span(2, 11, 2, 11))),
+        typ: Type::UInt(IntSize::I8),
+        mode: ConversionMode::Checked,
+        span: span(2, 1, 2, 16),
+    }))
+}
+
+#[test]
+fn test_panic()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+panic(msg)"#, &target);
+    assert!(e == panic_expr(name_ref("msg", span(2, 7, 2, 9)), span(2, 1, 2, 10)));
+
+    let e = th_expr(r#"
+unreachable"#, &target);
+    assert!(e == unreachable_expr(span(2, 1, 2, 11)));
+}
+
+#[test]
+fn test_slice_operation()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+s[1..3]"#, &target);
+    assert!(e == slice_op(
+        name_ref("s", span(2, 1, 2, 1)),
+        Some(number(1, span(2, 3, 2, 3), &target)),
+        Some(number(3, span(2, 6, 2, 6), &target)),
+        span(2, 1, 2, 7)));
+
+    let e = th_expr(r#"
+a[..n]"#, &target);
+    assert!(e == slice_op(name_ref("a", span(2, 1, 2, 1)), None, Some(name_ref("n", span(2, 5, 2, 5))), span(2, 1, 2, 6)));
+
+    let e = th_expr(r#"
+a[i..]"#, &target);
+    assert!(e == slice_op(name_ref("a", span(2, 1, 2, 1)), Some(name_ref("i", span(2, 3, 2, 3))), None, span(2, 1, 2, 6)));
+}
+
+#[test]
+fn test_assert()
+{
+    let target = Target::new(IntSize::I32, "");
+    let e = th_expr(r#"
+@assert(x, msg)"#, &target);
+    assert!(e == Expression::CompilerCall(CompilerCall::Assert{
+        condition: Box::new(name_ref("x", span(2, 9, 2, 9))),
+        message: Box::new(name_ref("msg", span(2, 12, 2, 14))),
+        span: span(2, 1, 2, 15),
+    }))
+}
+
+#[test]
+fn test_target_os()
+{
+    let target = Target::new(IntSize::I64, "x86_64-apple-darwin");
+    let e = th_expr(r#"
+@target_os()"#, &target);
+    assert!(e == Expression::Literal(Literal::String(span(2, 1, 2, 12), "darwin".into())));
+
+    let target = Target::new(IntSize::I64, "x86_64-unknown-linux-gnu");
+    assert!(th_expr("@target_os()", &target) == Expression::Literal(Literal::String(span(1, 1, 1, 12), "linux".into())));
+}
+
+#[test]
+fn test_float_type_names()
+{
+    let target = Target::new(IntSize::I32, "");
+    assert!(to_primitive("float", &target) == Some(Type::Float(FloatSize::F32)));
+    assert!(to_primitive("f32", &target) == Some(Type::Float(FloatSize::F32)));
+    assert!(to_primitive("double", &target) == Some(Type::Float(FloatSize::F64)));
+    assert!(to_primitive("f64", &target) == Some(Type::Float(FloatSize::F64)));
+}
+
+#[test]
+fn test_const()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+const N = 4
+
+fn foo(a: int[N]) -> int: 7"#, &target);
+    let global = md.globals.get("test::N").unwrap();
+    assert!(global.constant);
+    assert!(global.init == number(4, span(2, 11, 2, 11), &target));
+
+    let func = md.functions.get("test::foo").unwrap();
+    assert!(func.sig.args[0].typ == array_type_with_length(target.native_int_type.clone(), ArrayLength::Const("N".into())));
+}
 
 #[test]
 fn test_interface()
@@ -778,6 +1029,28 @@ interface Foo:
                 span(3, 8, 3, 31)
             )
         ],
+        Vec::new(),
         span(2, 1, 3, 31)
     )))
 }
+
+#[test]
+fn test_interface_with_default_impl()
+{
+    let target = Target::new(IntSize::I32, "");
+    let md = th_mod(r#"
+interface Foo:
+    fn bar(self) -> int
+    fn baz(self) -> int: self.bar() + 1
+"#, &target);
+    let result = md.types.get("test::Foo").unwrap();
+    if let TypeDeclaration::Interface(ref i) = *result {
+        assert!(i.functions.len() == 2);
+        assert!(i.default_impls.len() == 1);
+        assert!(i.get_default_impl("bar").is_none());
+        let baz = i.get_default_impl("baz").unwrap();
+        assert!(baz.sig == i.functions[1]);
+    } else {
+        panic!("Expecting an interface");
+    }
+}
@@ -13,6 +13,8 @@ pub enum TokenKind
     UnaryOperator(UnaryOperator),
     Colon,
     DoubleColon,
+    DoubleDot,
+    Ellipsis,
     SemiColon,
     Comma,
     OpenParen,
@@ -32,6 +34,7 @@ pub enum TokenKind
     Else,
     While,
     Extern,
+    Export,
     Dollar,
     Pipe,
     True,
@@ -46,6 +49,7 @@ pub enum TokenKind
     Nil,
     Null,
     Var,
+    Const,
     For,
     Interface,
     Func,
@@ -53,6 +57,9 @@ pub enum TokenKind
     Ampersand,
     At,
     Return,
+    Defer,
+    Panic,
+    Unreachable,
     EOF,
 }
 
@@ -70,6 +77,8 @@ impl Display for TokenKind
             TokenKind::UnaryOperator(ref op) => write!(fmt, "operator {}", op),
             TokenKind::Colon => write!(fmt, ":"),
             TokenKind::DoubleColon => write!(fmt, "::"),
+            TokenKind::DoubleDot => write!(fmt, ".."),
+            TokenKind::Ellipsis => write!(fmt, "..."),
             TokenKind::SemiColon => write!(fmt, ";"),
             TokenKind::Comma => write!(fmt, ","),
             TokenKind::OpenParen => write!(fmt, "("),
@@ -88,6 +97,7 @@ impl Display for TokenKind
             TokenKind::Else => write!(fmt, "else"),
             TokenKind::While => write!(fmt, "while"),
             TokenKind::Extern => write!(fmt, "extern"),
+            TokenKind::Export => write!(fmt, "export"),
             TokenKind::Assign(op) => write!(fmt, "{}", op),
             TokenKind::Dollar => write!(fmt, "$"),
             TokenKind::Pipe => write!(fmt, "|"),
@@ -103,6 +113,7 @@ impl Display for TokenKind
             TokenKind::Nil => write!(fmt, "nil"),
             TokenKind::Null => write!(fmt, "null"),
             TokenKind::Var => write!(fmt, "var"),
+            TokenKind::Const => write!(fmt, "const"),
             TokenKind::For => write!(fmt, "for"),
             TokenKind::Interface => write!(fmt, "interface"),
             TokenKind::Func => write!(fmt, "fn"),
@@ -110,6 +121,9 @@ impl Display for TokenKind
             TokenKind::Ampersand => write!(fmt, "&"),
             TokenKind::At => write!(fmt, "@"),
             TokenKind::Return => write!(fmt, "return"),
+            TokenKind::Defer => write!(fmt, "defer"),
+            TokenKind::Panic => write!(fmt, "panic"),
+            TokenKind::Unreachable => write!(fmt, "unreachable"),
             TokenKind::EOF => write!(fmt, "EOF"),
         }
     }
@@ -1,6 +1,7 @@
+use std::env::consts;
 use ast::{IntSize, Type};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Target
 {
     pub int_size: IntSize,
@@ -20,4 +21,24 @@ impl Target
             triplet: triplet.into(),
         }
     }
+
+    // The operating system part of the triplet, linux for x86_64-unknown-linux-gnu
+    pub fn os(&self) -> &str
+    {
+        self.triplet.split('-').nth(2).unwrap_or("")
+    }
+
+    // The system the compiler runs on, used when the code is interpreted instead of compiled
+    pub fn host() -> Target
+    {
+        let int_size = match ::std::mem::size_of::<usize>()
+        {
+            2 => IntSize::I16,
+            4 => IntSize::I32,
+            _ => IntSize::I64,
+        };
+
+        let env = if cfg!(target_env = "gnu") {"-gnu"} else {""};
+        Target::new(int_size, format!("{}-unknown-{}{}", consts::ARCH, consts::OS, env))
+    }
 }
@@ -23,6 +23,12 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
 
     let new_generic = make_concrete(ctx, known_types, generic, span)?;
     if !new_generic.is_generic() {
+        // A generic length which is already known, cannot be mapped on a different length
+        if let (&Type::Array(ref generic_at), &Type::Array(ref actual_at)) = (generic, actual) {
+            if generic_at.length.is_generic() {
+                add(known_types, &array_length_type(&generic_at.length), &array_length_type(&actual_at.length), span)?;
+            }
+        }
         return Ok(new_generic);
     }
 
@@ -48,16 +54,22 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
         },
 
         (&Type::Array(ref generic_at), &Type::Array(ref actual_at)) => {
+            if generic_at.length.is_generic() {
+                add(known_types, &array_length_type(&generic_at.length), &array_length_type(&actual_at.length), span)?;
+            } else if generic_at.length != actual_at.length {
+                return map_err();
+            }
+
             add(known_types, &generic_at.element_type, &actual_at.element_type, span)?;
             let new_el_type = fill_in_generics(ctx, &actual_at.element_type, &generic_at.element_type, known_types, span)?;
-            Ok(array_type(new_el_type, actual_at.len))
+            Ok(array_type_with_length(new_el_type, actual_at.length.clone()))
         },
 
         (&Type::Slice(ref generic_at), &Type::Array(ref actual_at)) => {
             // We support automatic conversion from array to slice
             add(known_types, &generic_at.element_type, &actual_at.element_type, span)?;
             let new_el_type = fill_in_generics(ctx, &actual_at.element_type, &generic_at.element_type, known_types, span)?;
-            Ok(array_type(new_el_type, actual_at.len))
+            Ok(array_type_with_length(new_el_type, actual_at.length.clone()))
         },
 
         (&Type::Func(ref generic_ft), &Type::Func(ref actual_ft)) => {
@@ -108,7 +120,7 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
                 new_cases.push(sum_type_case(&aa.name, nt));
             }
 
-            Ok(sum_type(&actual_st.name, new_cases))
+            Ok(sum_type(&actual_st.name, new_cases, actual_st.result))
         },
 
         (&Type::Pointer(ref generic_inner), &Type::Pointer(ref actual_inner)) => {
@@ -129,7 +141,7 @@ pub fn fill_in_generics(ctx: &TypeCheckerContext, actual: &Type, generic: &Type,
 mod tests
 {
     use super::*;
-    use ast::{Type, GenericMapping, array_type, slice_type, func_type, string_type, ptr_type, generic_type, IntSize};
+    use ast::{Type, GenericMapping, ArrayLength, array_type, array_type_with_length, slice_type, func_type, string_type, ptr_type, generic_type, IntSize};
     use typechecker::instantiate::make_concrete;
     use typechecker::typecheckercontext::ImportSymbolResolver;
     use span::Span;
@@ -173,6 +185,28 @@ mod tests
         assert!(make_concrete(&ctx, &tm, &generic_type("a"), &Span::default()).unwrap() == Type::Int(IntSize::I32));
     }
 
+    #[test]
+    fn test_array_generic_length()
+    {
+        let imports = ImportMap::new();
+        let ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(&imports));
+        let mut tm = GenericMapping::new();
+        let ga = array_type_with_length(generic_type("a"), ArrayLength::Generic("n".into()));
+        let r = fill_in_generics(&ctx, &array_type(Type::Int(IntSize::I32), 10), &ga, &mut tm, &Span::default());
+        assert!(r == Ok(array_type(Type::Int(IntSize::I32), 10)));
+        assert!(make_concrete(&ctx, &tm, &ga, &Span::default()).unwrap() == array_type(Type::Int(IntSize::I32), 10));
+
+        // A generic length cannot be mapped on two different lengths
+        let gf = func_type(vec![ga.clone(), ga.clone()], Type::Void);
+        let af = func_type(vec![array_type(Type::Int(IntSize::I32), 10), array_type(Type::Int(IntSize::I32), 5)], Type::Void);
+        let r = fill_in_generics(&ctx, &af, &gf, &mut GenericMapping::new(), &Span::default());
+        assert!(r.is_err());
+
+        let af = func_type(vec![array_type(Type::Int(IntSize::I32), 10), array_type(Type::Int(IntSize::I32), 10)], Type::Void);
+        let r = fill_in_generics(&ctx, &af, &gf, &mut GenericMapping::new(), &Span::default());
+        assert!(r == Ok(af));
+    }
+
     #[test]
     fn test_pointer()
     {
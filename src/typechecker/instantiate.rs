@@ -1,18 +1,28 @@
 use std::ops::Deref;
+use std::rc::Rc;
 use ast::*;
 use span::Span;
 use compileerror::{CompileResult, type_error};
 use super::typecheckercontext::TypeCheckerContext;
 
-fn matches_function_signature(expected: &Type, actual: &Type, concrete_type: &Type, interface: &Type, method_name: &str) -> Result<(), String>
+fn matches_function_signature(expected: &Type, actual: &Type, concrete_type: &Type, interface: &Type, method_name: &str, bindings: &mut GenericMapping) -> Result<(), String>
 {
-    fn type_matches(expected: &Type, actual: &Type, concrete_type: &Type, interface: &Type) -> bool {
+    fn type_matches(expected: &Type, actual: &Type, concrete_type: &Type, interface: &Type, bindings: &mut GenericMapping) -> bool {
         match (expected, actual)
         {
             (&Type::Pointer(ref e), &Type::Pointer(ref a)) |
-            (&Type::Optional(ref e), &Type::Optional(ref a)) => type_matches(e, a, concrete_type, interface),
-            (&Type::Array(ref e), &Type::Array(ref a)) => type_matches(&e.element_type, &a.element_type, concrete_type, interface),
-            (&Type::Slice(ref e), &Type::Slice(ref a)) => type_matches(&e.element_type, &a.element_type, concrete_type, interface),
+            (&Type::Optional(ref e), &Type::Optional(ref a)) => type_matches(e, a, concrete_type, interface, bindings),
+            (&Type::Array(ref e), &Type::Array(ref a)) => type_matches(&e.element_type, &a.element_type, concrete_type, interface, bindings),
+            (&Type::Slice(ref e), &Type::Slice(ref a)) => type_matches(&e.element_type, &a.element_type, concrete_type, interface, bindings),
+            // A generic argument of an interface is bound to the type it is first matched with
+            (&Type::Generic(_), _) => match bindings.get(expected).cloned()
+            {
+                Some(bound) => bound == *actual,
+                None => {
+                    bindings.insert(expected.clone(), actual.clone());
+                    true
+                },
+            },
             _ => *expected == *actual || (*expected == Type::SelfType && *actual == *concrete_type),
         }
     }
@@ -24,12 +34,12 @@ fn matches_function_signature(expected: &Type, actual: &Type, concrete_type: &Ty
                 return Err(format!("Argument count mismatch for method {}", method_name));
             }
 
-            if !type_matches(&e.return_type, &a.return_type, concrete_type, interface) {
+            if !type_matches(&e.return_type, &a.return_type, concrete_type, interface, bindings) {
                 return Err(format!("Return types do not match on method {}", method_name));
             }
 
             for (idx, (e_arg, a_arg)) in e.args.iter().zip(a.args.iter()).enumerate() {
-                if !type_matches(e_arg, a_arg, concrete_type, interface) {
+                if !type_matches(e_arg, a_arg, concrete_type, interface, bindings) {
                     return Err(format!("The type of argument {} does not match on method {}.", idx, method_name));
                 }
             }
@@ -41,7 +51,7 @@ fn matches_function_signature(expected: &Type, actual: &Type, concrete_type: &Ty
     }
 }
 
-fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface: &Type) -> Result<(), String>
+pub fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface: &Type) -> Result<(), String>
 {
     let it = if let Type::Interface(ref it) = *interface {
         it
@@ -50,11 +60,15 @@ fn satisfies_interface(ctx: &TypeCheckerContext, concrete_type: &Type, interface
     };
 
     let concrete_type_name = concrete_type.name();
+    let mut bindings = GenericMapping::new();
     for func in &it.functions {
-        let r = ctx.resolve(&format!("{}.{}", concrete_type_name, func.name))
-            .ok_or_else(|| format!("No method {} found on type {}", func.name, concrete_type_name))?;
+        let r = match ctx.resolve(&format!("{}.{}", concrete_type_name, func.name)) {
+            Some(r) => r,
+            None if it.default_impls.contains(&func.name) => continue,
+            None => return Err(format!("No method {} found on type {}", func.name, concrete_type_name)),
+        };
 
-        matches_function_signature(&func.typ, &r.typ, concrete_type, interface, &func.name)?;
+        matches_function_signature(&func.typ, &r.typ, concrete_type, interface, &func.name, &mut bindings)?;
     }
 
     Ok(())
@@ -87,7 +101,7 @@ fn check_interface_constraints(ctx: &TypeCheckerContext, generic: &Type, concret
 }
 
 
-fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generic: &Type) -> Result<Type, String>
+pub fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generic: &Type) -> Result<Type, String>
 {
     if !generic.is_generic() {
         return Ok(generic.clone());
@@ -100,7 +114,11 @@ fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generi
     let typ = match *generic
     {
         Type::Array(ref at) => {
-            array_type(make_concrete_type(ctx, mapping, &at.element_type)?, at.len)
+            let length = match mapping.get(&array_length_type(&at.length)) {
+                Some(&Type::Array(ref concrete)) => concrete.length.clone(),
+                _ => at.length.clone(),
+            };
+            array_type_with_length(make_concrete_type(ctx, mapping, &at.element_type)?, length)
         },
 
         Type::Slice(ref st) => {
@@ -113,7 +131,12 @@ fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generi
                 args.push(make_concrete_type(ctx, mapping, t)?);
             }
 
-            func_type(args, make_concrete_type(ctx, mapping, &ft.return_type)?)
+            Type::Func(Rc::new(FuncType{
+                args: args,
+                return_type: make_concrete_type(ctx, mapping, &ft.return_type)?,
+                variadic: ft.variadic,
+                calling_convention: ft.calling_convention,
+            }))
         },
 
         Type::Struct(ref st) => {
@@ -131,7 +154,7 @@ fn make_concrete_type(ctx: &TypeCheckerContext, mapping: &GenericMapping, generi
                 cases.push(sum_type_case(&c.name, make_concrete_type(ctx, mapping, &c.typ)?));
             }
 
-            sum_type(&st.name, cases)
+            sum_type(&st.name, cases, st.result)
         },
 
         Type::Pointer(ref inner) => {
@@ -256,6 +279,23 @@ fn substitute_name_ref(ctx: &TypeCheckerContext, generic_args: &GenericMapping,
     Ok(new_nr)
 }
 
+// A generic array length used as a value in the body, becomes the known length
+fn generic_length_literal(generic_args: &GenericMapping, nr: &NameRef) -> Option<Literal>
+{
+    let int_size = match nr.typ {
+        Type::Int(int_size) => int_size,
+        _ => return None,
+    };
+
+    match generic_args.get(&array_length_type(&ArrayLength::Generic(nr.name.clone()))) {
+        Some(&Type::Array(ref at)) => match at.length {
+            ArrayLength::Known(len) => Some(Literal::Int(nr.span.clone(), len as i64, int_size)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn substitute_member_access(ctx: &TypeCheckerContext, generic_args: &GenericMapping, sma: &MemberAccess) -> CompileResult<MemberAccess>
 {
     let left = substitute_expr(ctx, generic_args, &sma.left)?;
@@ -353,6 +393,9 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
         Expression::Literal(ref lit) => Ok(Expression::Literal(lit.clone())),
 
         Expression::NameRef(ref nr) => {
+            if let Some(lit) = generic_length_literal(generic_args, nr) {
+                return Ok(Expression::Literal(lit));
+            }
             Ok(Expression::NameRef(substitute_name_ref(ctx, generic_args, nr)?))
         },
 
@@ -440,8 +483,11 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             Ok(for_loop(&f.loop_variable, i, b, f.span.clone()))
         },
 
-        Expression::Nil(ref span) => {
-            Ok(Expression::Nil(span.clone()))
+        Expression::Nil(ref nt) => {
+            Ok(Expression::Nil(Nil{
+                typ: make_concrete(ctx, generic_args, &nt.typ, &nt.span)?,
+                span: nt.span.clone(),
+            }))
         },
 
         Expression::OptionalToBool(ref inner) => {
@@ -450,7 +496,8 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
 
         Expression::ToOptional(ref t) => {
             let inner = substitute_expr(ctx, generic_args, &t.inner)?;
-            Ok(to_optional(inner, t.optional_type.clone()))
+            let span = inner.span();
+            Ok(to_optional(inner, make_concrete(ctx, generic_args, &t.optional_type, &span)?))
         },
 
         Expression::Cast(ref t) => {
@@ -477,16 +524,65 @@ fn substitute_expr(ctx: &TypeCheckerContext, generic_args: &GenericMapping, e: &
             }))
         },
 
+        Expression::CompilerCall(CompilerCall::Convert{ref inner, ref typ, mode, ref span}) => {
+            let new_inner = substitute_expr(ctx, generic_args, inner)?;
+            let new_type = make_concrete(ctx, generic_args, typ, span)?;
+            Ok(Expression::CompilerCall(CompilerCall::Convert{
+                inner: Box::new(new_inner),
+                typ: new_type,
+                mode: mode,
+                span: span.clone(),
+            }))
+        },
+
+        Expression::CompilerCall(CompilerCall::Assert{ref condition, ref message, ref span}) => {
+            let new_condition = substitute_expr(ctx, generic_args, condition)?;
+            let new_message = substitute_expr(ctx, generic_args, message)?;
+            Ok(Expression::CompilerCall(CompilerCall::Assert{
+                condition: Box::new(new_condition),
+                message: Box::new(new_message),
+                span: span.clone(),
+            }))
+        },
+
         Expression::IndexOperation(ref iop) => {
             let target = substitute_expr(ctx, generic_args, &iop.target)?;
             let index_expr = substitute_expr(ctx, generic_args, &iop.index_expr)?;
             Ok(index_op(target, index_expr, iop.span.clone()))
         }
 
+        Expression::SliceOperation(ref sop) => {
+            let target = substitute_expr(ctx, generic_args, &sop.target)?;
+            let start = match sop.start {
+                Some(ref start) => Some(substitute_expr(ctx, generic_args, start)?),
+                None => None,
+            };
+            let end = match sop.end {
+                Some(ref end) => Some(substitute_expr(ctx, generic_args, end)?),
+                None => None,
+            };
+            Ok(slice_op(target, start, end, sop.span.clone()))
+        }
+
         Expression::Return(ref r) => {
             let e = substitute_expr(ctx, generic_args, &r.expression)?;
             Ok(return_expr(e, r.span.clone()))
         }
+
+        Expression::Propagate(ref p) => {
+            let e = substitute_expr(ctx, generic_args, &p.inner)?;
+            Ok(propagate(e, p.span.clone()))
+        }
+
+        Expression::Defer(ref d) => {
+            let e = substitute_expr(ctx, generic_args, &d.expression)?;
+            Ok(defer(e, d.span.clone()))
+        }
+
+        Expression::Panic(ref p) => {
+            let e = substitute_expr(ctx, generic_args, &p.message)?;
+            Ok(panic_expr(e, p.span.clone()))
+        }
     }
 }
 
@@ -507,8 +603,55 @@ pub fn instantiate(ctx: &TypeCheckerContext, func: &Function, generic_args: &Gen
         args: args,
         span: func.sig.span.clone(),
         typ: func_type(arg_types, return_type),
+        variadic: false,
+        calling_convention: CallingConvention::Nomad,
+        inline_hint: func.sig.inline_hint,
     };
 
     let body = substitute_expr(ctx, generic_args, &func.expression)?;
     Ok(Function::new(sig, func.public, body, func.span.clone()))
-}
\ No newline at end of file
+}
+
+pub fn replace_self_type(typ: &Type, concrete_type: &Type) -> Type
+{
+    match *typ
+    {
+        Type::SelfType => concrete_type.clone(),
+        Type::Pointer(ref inner) => ptr_type(replace_self_type(inner, concrete_type)),
+        Type::Optional(ref inner) => optional_type(replace_self_type(inner, concrete_type)),
+        Type::Array(ref at) => array_type_with_length(replace_self_type(&at.element_type, concrete_type), at.length.clone()),
+        Type::Slice(ref st) => slice_type(replace_self_type(&st.element_type, concrete_type)),
+        Type::Func(ref ft) => Type::Func(Rc::new(FuncType{
+            args: ft.args.iter().map(|arg| replace_self_type(arg, concrete_type)).collect(),
+            return_type: replace_self_type(&ft.return_type, concrete_type),
+            variadic: ft.variadic,
+            calling_convention: ft.calling_convention,
+        })),
+        _ => typ.clone(),
+    }
+}
+
+/*
+    Instantiate the default implementation of an interface method for a concrete type,
+    this results in a regular member function of that type.
+*/
+pub fn instantiate_default_impl(func: &Function, concrete_type: &Type) -> Function
+{
+    let args: Vec<Argument> = func.sig.args.iter()
+        .map(|arg| Argument::new(arg.name.clone(), replace_self_type(&arg.typ, concrete_type), arg.mutable, arg.span.clone()))
+        .collect();
+
+    let return_type = replace_self_type(&func.sig.return_type, concrete_type);
+    let sig = FunctionSignature{
+        name: format!("{}.{}", concrete_type.name(), func.sig.name),
+        return_type: return_type.clone(),
+        typ: func_type(args.iter().map(|arg| arg.typ.clone()).collect(), return_type),
+        args: args,
+        span: func.sig.span.clone(),
+        variadic: false,
+        calling_convention: CallingConvention::Nomad,
+        inline_hint: func.sig.inline_hint,
+    };
+
+    Function::new(sig, func.public, func.expression.clone(), func.span.clone())
+}
@@ -1,27 +1,122 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use ast::*;
 use target::Target;
-use super::instantiate::instantiate;
+use super::instantiate::{instantiate, instantiate_default_impl};
 use super::typecheck::type_check_function;
 use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
-use compileerror::{CompileResult, unknown_name_result};
+use compileerror::{CompileResult, unknown_name, unknown_name_result};
 
 
-type FunctionMap = HashMap<String, Function>;
+type FunctionMap = BTreeMap<String, Function>;
+
+fn find_default_impl<'a>(module: &'a Module, imports: &'a ImportMap, interface: &str, method: &str) -> Option<&'a Function>
+{
+    if let Some(&TypeDeclaration::Interface(ref i)) = module.types.get(interface) {
+        return i.get_default_impl(method);
+    }
+
+    let name = format!("{}.{}", interface, method);
+    imports.values()
+        .filter_map(|import| import.default_impls.get(&name))
+        .next()
+}
+
+/*
+    If a concrete type lacks a method which has a default implementation in one of the
+    interfaces it has to satisfy, instantiate the default implementation for that type.
+*/
+fn instantiate_default_impls(
+    ctx: &mut TypeCheckerContext,
+    new_functions: &mut FunctionMap,
+    imports: &ImportMap,
+    module: &Module,
+    call: &Call,
+    target: &Target) -> CompileResult<()>
+{
+    let mut default_impls = Vec::new();
+    for (generic, concrete) in &call.generic_args {
+        let interfaces = match *generic {
+            Type::Generic(ref gt) => match **gt {
+                GenericType::Restricted(ref interfaces) => interfaces,
+                GenericType::Any(_) => continue,
+            },
+            _ => continue,
+        };
+
+        for interface in interfaces {
+            let it = if let Type::Interface(ref it) = *interface {it} else {continue};
+            for method in &it.default_impls {
+                let name = format!("{}.{}", concrete.name(), method);
+                if ctx.resolve(&name).is_some() || new_functions.contains_key(&name) || default_impls.iter().any(|f: &Function| f.sig.name == name) {
+                    continue;
+                }
+
+                let func = find_default_impl(module, imports, &it.name, method)
+                    .ok_or_else(|| unknown_name(&call.span, format!("Cannot find default implementation of {}.{}", it.name, method)))?;
+                default_impls.push(instantiate_default_impl(func, concrete));
+            }
+        }
+    }
+
+    // Add them all first, default implementations can call each other
+    for func in &default_impls {
+        ctx.add(Symbol::new(&func.sig.name, &func.sig.typ, false, &func.sig.span, SymbolType::Normal))?;
+    }
+
+    for mut func in default_impls {
+        type_check_function(ctx, &mut func, target)?;
+        new_functions.insert(func.sig.name.clone(), func);
+    }
+
+    Ok(())
+}
+
+// Instantiate the default implementations which are called directly on a concrete type
+fn instantiate_called_default_impls(
+    ctx: &mut TypeCheckerContext,
+    new_functions: &mut FunctionMap,
+    imports: &ImportMap,
+    module: &Module,
+    target: &Target) -> CompileResult<()>
+{
+    // Type checking a default implementation can add new calls, when it calls other default implementations
+    loop {
+        let calls = ctx.take_default_impl_calls();
+        if calls.is_empty() {
+            return Ok(());
+        }
+
+        for call in calls {
+            let name = format!("{}.{}", call.concrete_type.name(), call.method);
+            if new_functions.contains_key(&name) || module.functions.contains_key(&name) {
+                continue;
+            }
+
+            let func = find_default_impl(module, imports, &call.interface, &call.method)
+                .ok_or_else(|| unknown_name(&call.span, format!("Cannot find default implementation of {}.{}", call.interface, call.method)))?;
+            let mut func = instantiate_default_impl(func, &call.concrete_type);
+            type_check_function(ctx, &mut func, target)?;
+            new_functions.insert(name, func);
+        }
+    }
+}
 
 fn do_instantiation(
     ctx: &mut TypeCheckerContext,
     new_functions: &mut FunctionMap,
     func: &Function,
+    imports: &ImportMap,
     module: &Module,
     call: &Call,
     target: &Target) -> CompileResult<()>
 {
     let name = new_func_name(&func.sig.name, &call.generic_args);
     if !new_functions.contains_key(&name) && !module.functions.contains_key(&name) {
+        instantiate_default_impls(ctx, new_functions, imports, module, call, target)?;
         let mut new_func = instantiate(ctx, func, &call.generic_args)?;
         type_check_function(ctx, &mut new_func, target)?;
         new_functions.insert(name, new_func);
+        instantiate_called_default_impls(ctx, new_functions, imports, module, target)?;
     }
 
     Ok(())
@@ -30,13 +125,13 @@ fn do_instantiation(
 fn resolve_generic_call(ctx: &mut TypeCheckerContext, new_functions: &mut FunctionMap, imports: &ImportMap, module: &Module, call: &Call, target: &Target) -> CompileResult<()>
 {
     if let Some(func) = module.functions.get(&call.callee.name) {
-        return do_instantiation(ctx, new_functions, func, module, call, target)
+        return do_instantiation(ctx, new_functions, func, imports, module, call, target)
     }
 
     for import in imports.values() {
         if let Some(func) = import.generics.get(&call.callee.name) {
             let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ExternalImport(import));
-            return do_instantiation(&mut ctx, new_functions, func, module, call, target);
+            return do_instantiation(&mut ctx, new_functions, func, imports, module, call, target);
         }
     }
 
@@ -83,6 +178,7 @@ pub fn instantiate_generics(module: &mut Module, ctx: &mut TypeCheckerContext, i
         }
     }
 
+    instantiate_called_default_impls(ctx, &mut new_functions, imports, module, target)?;
     for f in module.functions.values_mut()
     {
         if !f.generics_resolved && !f.is_generic() {
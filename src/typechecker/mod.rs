@@ -5,7 +5,8 @@ mod instantiategenerics;
 mod genericmapper;
 mod matchchecker;
 mod typeresolver;
+mod operatorinterfaces;
 #[cfg(test)]
 mod tests;
 
-pub use self::typecheck::{type_check_module};
+pub use self::typecheck::{type_check_module, ConstEvaluation};
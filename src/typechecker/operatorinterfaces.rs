@@ -0,0 +1,68 @@
+use ast::*;
+use span::Span;
+
+/*
+    Built-in interfaces, which allow user types to overload operators.
+*/
+pub const ADD_INTERFACE: &'static str = "Add";
+pub const SUB_INTERFACE: &'static str = "Sub";
+pub const MUL_INTERFACE: &'static str = "Mul";
+pub const EQ_INTERFACE: &'static str = "Eq";
+pub const ORD_INTERFACE: &'static str = "Ord";
+pub const INDEX_INTERFACE: &'static str = "Index";
+
+fn method(name: &str, args: Vec<(&str, Type)>, ret: Type) -> FunctionSignature
+{
+    let mut all_args = vec![Argument::new("self", ptr_type(Type::SelfType), false, Span::default())];
+    all_args.extend(args.into_iter().map(|(name, typ)| Argument::new(name, typ, false, Span::default())));
+    let mut s = sig(name, ret, all_args, Span::default());
+    s.typ = s.get_type();
+    s
+}
+
+fn binary_operator_interface(name: &str, method_name: &str, ret: Type) -> Type
+{
+    interface_type(
+        name,
+        Vec::new(),
+        vec![method(method_name, vec![("other", Type::SelfType)], ret)],
+        Vec::new()
+    )
+}
+
+pub fn operator_interfaces() -> Vec<Type>
+{
+    let index_type = generic_type("Idx");
+    let element_type = generic_type("T");
+    vec![
+        binary_operator_interface(ADD_INTERFACE, "add", Type::SelfType),
+        binary_operator_interface(SUB_INTERFACE, "sub", Type::SelfType),
+        binary_operator_interface(MUL_INTERFACE, "mul", Type::SelfType),
+        binary_operator_interface(EQ_INTERFACE, "eq", Type::Bool),
+        binary_operator_interface(ORD_INTERFACE, "less_than", Type::Bool),
+        interface_type(
+            INDEX_INTERFACE,
+            vec![index_type.clone(), element_type.clone()],
+            vec![method("index", vec![("idx", index_type)], element_type)],
+            Vec::new()
+        ),
+    ]
+}
+
+// Returns the interface and method name which is used to overload a binary operator
+pub fn binary_operator_interface_method(op: BinaryOperator) -> Option<(&'static str, &'static str)>
+{
+    match op
+    {
+        BinaryOperator::Add => Some((ADD_INTERFACE, "add")),
+        BinaryOperator::Sub => Some((SUB_INTERFACE, "sub")),
+        BinaryOperator::Mul => Some((MUL_INTERFACE, "mul")),
+        BinaryOperator::Equals |
+        BinaryOperator::NotEquals => Some((EQ_INTERFACE, "eq")),
+        BinaryOperator::LessThan |
+        BinaryOperator::GreaterThan |
+        BinaryOperator::LessThanEquals |
+        BinaryOperator::GreaterThanEquals => Some((ORD_INTERFACE, "less_than")),
+        _ => None,
+    }
+}
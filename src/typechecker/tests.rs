@@ -1,9 +1,11 @@
 use parser::{th_expr, th_mod};
 use super::typecheck::{type_check_expression, type_check_module};
 use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
-use ast::{IntSize, Type, ImportMap};
+use ast::{IntSize, Type, ImportMap, Expression, Literal};
 use compileerror::{CompileResult};
+use bytecode::CompileTimeEvaluator;
 use target::Target;
+use span::Span;
 
 
 fn type_check(expr: &str) -> CompileResult<Type>
@@ -23,7 +25,7 @@ fn type_check_mod(expr: &str) -> CompileResult<()>
     let target = Target::new(IntSize::I32, "");
 	let mut md = th_mod(expr, &target);
 	let imports = ImportMap::new();
-	let r = type_check_module(&mut md, &target, &imports);
+	let r = type_check_module(&mut md, &target, &imports, &CompileTimeEvaluator);
 	println!("result: {:?}", r);
 	r
 }
@@ -157,3 +159,751 @@ fn test_mutability()
         "#).is_ok()
 	);
 }
+
+#[test]
+fn test_interface_default_impl()
+{
+    assert!(
+        type_check_mod(r#"
+interface Sum:
+    fn sum(self) -> int
+    fn double_sum(self) -> int: self.sum() * 2
+
+struct Point:
+    x: int
+    y: int
+
+fn Point.sum(self) -> int:
+    self.x + self.y
+
+fn foo(x: $Sum) -> int:
+    x.double_sum()
+
+fn main() -> int:
+    let p = Point{7, 8}
+    foo(p)
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+interface Sum:
+    fn sum(self) -> int
+    fn double_sum(self) -> int: self.sum() * 2
+
+struct Point:
+    x: int
+    y: int
+
+fn foo(x: $Sum) -> int:
+    x.double_sum()
+
+fn main() -> int:
+    let p = Point{7, 8}
+    foo(p)
+        "#).is_err()
+    );
+
+    // Default implementations can be called directly on a concrete type
+    assert!(
+        type_check_mod(r#"
+interface Sum:
+    fn sum(self) -> int
+    fn double_sum(self) -> int: self.sum() * 2
+    fn apply(self, other: Self, f: fn(Self) -> int) -> int: f(other)
+
+struct Point:
+    x: int
+    y: int
+
+fn Point.sum(self) -> int:
+    self.x + self.y
+
+fn first(p: Point) -> int: p.x
+
+fn main() -> int:
+    let p = Point{7, 8}
+    p.double_sum() + p.apply(Point{1, 2}, first)
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+interface Sum:
+    fn sum(self) -> int
+    fn double_sum(self) -> int: self.sum() * 2
+
+struct Point:
+    x: int
+    y: int
+
+fn main() -> int:
+    let p = Point{7, 8}
+    p.double_sum()
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_operator_overloading()
+{
+    assert!(
+        type_check_mod(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn Vec2.add(self, other: Vec2) -> Vec2:
+    Vec2{self.x + other.x, self.y + other.y}
+
+fn Vec2.eq(self, other: Vec2) -> bool:
+    self.x == other.x && self.y == other.y
+
+fn Vec2.index(self, idx: int) -> int:
+    if idx == 0: self.x else self.y
+
+fn main() -> int:
+    let a = Vec2{1, 2}
+    let b = Vec2{3, 4}
+    let c = a + b
+    if c != b: c[0] else c[1]
+        "#).is_ok()
+    );
+
+    // No Sub implementation
+    assert!(
+        type_check_mod(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn main() -> int:
+    let a = Vec2{1, 2}
+    let b = Vec2{3, 4}
+    let c = a - b
+    c.x
+        "#).is_err()
+    );
+
+    // Mixed types
+    assert!(
+        type_check_mod(r#"
+struct Vec2:
+    x: int
+    y: int
+
+fn Vec2.add(self, other: Vec2) -> Vec2:
+    Vec2{self.x + other.x, self.y + other.y}
+
+fn main() -> int:
+    let a = Vec2{1, 2}
+    let c = a + 5
+    c.x
+        "#).is_err()
+    );
+
+    // A generic argument of an interface has to be the same type everywhere it is used
+    let wrap = |ret: &str, body: &str| format!(r#"
+interface Wrap:
+    fn wrap(self, x: $T) -> $T
+
+struct Foo:
+    v: int
+
+fn Foo.wrap(self, x: int) -> {}: {}
+
+fn use_wrap(w: $Wrap) -> int: 0
+
+fn main() -> int: use_wrap(Foo{{1}})
+        "#, ret, body);
+    assert!(type_check_mod(&wrap("int", "x + self.v")).is_ok());
+    assert!(type_check_mod(&wrap("bool", "x > self.v")).is_err());
+}
+
+#[test]
+fn test_propagation()
+{
+    assert!(
+        type_check_mod(r#"
+fn half(x: int) -> ?int:
+    if x % 2 == 0: x / 2 else nil
+
+fn quarter(x: int) -> ?int:
+    let h = half(x)?
+    half(h)
+        "#).is_ok()
+    );
+
+    // Enclosing function does not return an optional
+    assert!(
+        type_check_mod(r#"
+fn half(x: int) -> ?int:
+    if x % 2 == 0: x / 2 else nil
+
+fn quarter(x: int) -> int:
+    half(half(x)?) || 0
+        "#).is_err()
+    );
+
+    assert!(
+        type_check_mod(r#"
+@result enum Result:
+    Ok{v: int}
+    Error{code: int}
+
+fn half(x: int) -> Result:
+    if x % 2 == 0: Result::Ok{x / 2} else Result::Error{x}
+
+fn quarter(x: int) -> Result:
+    let h = half(x)?
+    half(h)
+        "#).is_ok()
+    );
+
+    // Not declared as a result type, even though it has an Ok case
+    assert!(
+        type_check_mod(r#"
+enum Result:
+    Ok{v: int}
+    Error{code: int}
+
+fn half(x: int) -> Result:
+    if x % 2 == 0: Result::Ok{x / 2} else Result::Error{x}
+
+fn quarter(x: int) -> Result:
+    let h = half(x)?
+    half(h)
+        "#).is_err()
+    );
+
+    // The failure can be propagated to a result type with another success value
+    let convert = |failure: &str| format!(r#"
+@result enum Half:
+    Even{{v: int}}
+    Odd{{code: int}}
+
+@result enum Check:
+    Valid{{ok: bool}}
+    Invalid{{{}}}
+
+fn half(x: int) -> Half:
+    if x % 2 == 0: Half::Even{{x / 2}} else Half::Odd{{x}}
+
+fn check(x: int) -> Check:
+    let h = half(x)?
+    Check::Valid{{h > 10}}
+        "#, failure);
+    assert!(type_check_mod(&convert("code: int")).is_ok());
+    assert!(type_check_mod(&convert("code: bool")).is_err());
+
+    // A result type needs a success case with a single value, and a failure case
+    assert!(
+        type_check_mod(r#"
+@result enum Result:
+    Ok{v: int, w: int}
+    Error{code: int}
+
+fn main() -> int: 0
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_destructors_and_defer()
+{
+    assert!(
+        type_check_mod(r#"
+struct Foo:
+    data: *int
+
+fn ~Foo(self):
+    delete self.data
+
+fn main() -> int:
+    let p = new 7
+    defer delete p
+    let f = Foo{new 5}
+    *p
+        "#).is_ok()
+    );
+
+    // Destructors cannot have extra arguments
+    assert!(
+        type_check_mod(r#"
+struct Foo:
+    data: *int
+
+fn ~Foo(self, x: int):
+    delete self.data
+        "#).is_err()
+    );
+
+    // No returning from a defer
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let p = new 7
+    defer return 5
+    *p
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_consts()
+{
+    assert!(
+        type_check_mod(r#"
+const SIZE = square(2) + 1
+
+fn square(x: int) -> int: x * x
+
+fn sum(a: int[SIZE]) -> int:
+    a[0] + a[SIZE - 1]
+
+fn describe(x: int) -> int:
+    match x:
+        SIZE => 1
+        _ => 0
+
+fn main() -> int:
+    sum([1, 2, 3, 4, 5]) + describe(5)
+        "#).is_ok()
+    );
+
+    // Casts are evaluated at compile time
+    assert!(
+        type_check_mod(r#"
+const SIX = 6 as float
+const TRUNCATED = 3.9 as int
+
+fn main() -> int: TRUNCATED
+        "#).is_ok()
+    );
+
+    // Consts must be computable at compile time
+    assert!(
+        type_check_mod(r#"
+var counter = 0
+const N = counter + 1
+        "#).is_err()
+    );
+
+    // Consts cannot depend on themselves
+    assert!(
+        type_check_mod(r#"
+const A = B + 1
+const B = A + 1
+        "#).is_err()
+    );
+
+    // Runaway recursion is stopped
+    assert!(
+        type_check_mod(r#"
+fn forever(x: int) -> int: forever(x + 1)
+
+const N = forever(0)
+        "#).is_err()
+    );
+
+    // Struct consts, and their members
+    assert!(
+        type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+const ORIGIN = Point{0, 1 + 1}
+const Y = ORIGIN.y
+
+fn main() -> int: ORIGIN.x + Y
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+const ORIGIN = Point{0}
+        "#).is_err()
+    );
+
+    // Only consts are evaluated, other globals keep their initializer
+    let target = Target::new(IntSize::I32, "");
+    let mut md = th_mod(r#"
+const SIZE = square(2)
+let AREA = square(3)
+
+fn square(x: int) -> int: x * x
+    "#, &target);
+    assert!(type_check_module(&mut md, &target, &ImportMap::new(), &CompileTimeEvaluator).is_ok());
+    match md.globals.get("test::SIZE").map(|g| &g.init) {
+        Some(&Expression::Literal(Literal::Int(_, 4, _))) => (),
+        init => panic!("SIZE is initialized with {:?}", init),
+    }
+    match md.globals.get("test::AREA").map(|g| &g.init) {
+        Some(&Expression::Call(_)) => (),
+        init => panic!("AREA is initialized with {:?}", init),
+    }
+}
+
+#[test]
+fn test_struct_member_defaults()
+{
+    assert!(
+        type_check_mod(r#"
+const SIZE = 4
+
+struct Config:
+    name: string
+    size: int = SIZE * 2
+    verbose: bool = false
+
+const SMALL = Config{"small", 1}
+
+fn main() -> int:
+    let a = Config{"a"}
+    let b = Config{"b", 3, true}
+    a.size + b.size + SMALL.size
+        "#).is_ok()
+    );
+
+    // Only members at the end can be left out
+    assert!(
+        type_check_mod(r#"
+struct Config:
+    name: string
+    size: int = 8
+
+fn main() -> int:
+    let a = Config{}
+    a.size
+        "#).is_err()
+    );
+
+    // Default values are evaluated at compile time
+    assert!(
+        type_check_mod(r#"
+var counter = 0
+
+struct Config:
+    size: int = counter
+        "#).is_err()
+    );
+
+    assert!(
+        type_check_mod(r#"
+struct Config:
+    size: int = true
+
+fn main() -> int:
+    let a = Config{}
+    a.size
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_const_namespaces()
+{
+    let imports = ImportMap::new();
+    let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(&imports));
+    ctx.add_const("a::N", Expression::Literal(Literal::Int(Span::default(), 1, IntSize::I32)));
+    ctx.add_const("b::N", Expression::Literal(Literal::Int(Span::default(), 2, IntSize::I32)));
+    ctx.set_namespace("b");
+
+    match ctx.resolve_const("N") {
+        Some(Expression::Literal(Literal::Int(_, 2, _))) => (),
+        c => panic!("N resolved to {:?}", c),
+    }
+
+    match ctx.resolve_const("a::N") {
+        Some(Expression::Literal(Literal::Int(_, 1, _))) => (),
+        c => panic!("a::N resolved to {:?}", c),
+    }
+
+    assert!(ctx.resolve_const("c::N").is_none());
+}
+
+#[test]
+fn test_numeric_conversions()
+{
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let a = 1000 as i64
+    let b = @try_cast(a, u8)
+    let c = @saturating_cast(a, u8)
+    let d = @wrapping_cast(a, i16)
+    let e = @saturating_cast(1.5, f32)
+    let f = @saturating_cast(e, u32)
+    d as int
+        "#).is_ok()
+    );
+
+    // Wrapping only makes sense for integers
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let a = 1000.5 as f64
+    @wrapping_cast(a, i32)
+        "#).is_err()
+    );
+
+    // Only numbers can be converted
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let a = @try_cast(true, i32)
+    0
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_wrapping_and_saturating_operators()
+{
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let a = 200 as u8
+    let b = a +% (100 as u8)
+    let c = a *| (2 as u8)
+    let d = 5 as i16
+    let e = d -| 10
+    (b as int) + (c as int) + (e as int)
+        "#).is_ok()
+    );
+
+    // Only integers wrap or saturate
+    assert!(
+        type_check_mod(r#"
+fn main() -> float:
+    1.5 +% 2.5
+        "#).is_err()
+    );
+
+    // Overflow in a constant expression is an error, unless it is explicitly wrapping or saturating
+    assert!(
+        type_check_mod(r#"
+const WRAPPED = 2147483647 +% 1
+const SATURATED = 2147483647 +| 1
+
+fn main() -> int: WRAPPED + SATURATED
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+const OVERFLOW = 2147483647 + 1
+
+fn main() -> int: OVERFLOW
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_panic()
+{
+    // A panic can take on the type of the other branch
+    assert!(
+        type_check_mod(r#"
+fn div(a: int, b: int) -> int:
+    if b != 0: a / b else panic("division by zero")
+
+fn first(v: int[]) -> int:
+    match v:
+        [] => unreachable
+        [head | tail] => head
+
+fn fail() -> int: panic("not implemented")
+
+fn main() -> int:
+    @assert(div(4, 2) == 2)
+    @assert(first([1, 2]) == 1, "first element")
+    div(10, 5) + first([3])
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    panic(5)
+        "#).is_err()
+    );
+
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    @assert(5)
+    0
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_strings()
+{
+    assert!(
+        type_check_mod(r#"
+fn greeting(name: string) -> string: "Hello " + name
+
+fn main() -> int:
+    let s = greeting("world")
+    let hello = s[..5]
+    let arr = [1, 2, 3, 4]
+    let tail = arr[1..]
+    if hello == "Hello" && "abc" < "abd": tail.len as int else 0
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let s = "hello" - "world"
+    0
+        "#).is_err()
+    );
+
+    assert!(
+        type_check_mod(r#"
+fn main() -> int:
+    let s = "hello"[0.5..]
+    0
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_generic_array_length_mismatch()
+{
+    assert!(
+        type_check_mod(r#"
+fn dot(a: int[$N], b: int[$N]) -> int:
+    a[0] * b[0]
+
+fn main() -> int:
+    dot([1, 2], [1, 2, 3])
+        "#).is_err()
+    );
+}
+
+#[test]
+fn test_generic_optionals()
+{
+    assert!(
+        type_check_mod(r#"
+struct Point{x: int}
+fn origin(valid: bool) -> ?Point:
+    if valid:
+        return Point{0}
+    nil
+fn is_set(v: ?$T) -> bool: if v: true else false
+fn main() -> int:
+    if is_set(origin(true)) && !is_set(origin(false)): 1 else 0
+        "#).is_ok()
+    );
+}
+
+#[test]
+fn test_generic_type_arguments()
+{
+    assert!(
+        type_check_mod(r#"
+struct Box{value: $T}
+fn unbox(b: Box<int>) -> int: b.value
+fn main() -> int: unbox(Box{5})
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+struct Box{value: $T}
+fn unbox(b: Box<string>) -> string: b.value
+fn main() -> int:
+    unbox(Box{5})
+    0
+        "#).is_err()
+    );
+
+    // Generic arguments map onto the generic parameters in the order they are declared
+    assert!(
+        type_check_mod(r#"
+struct Pair{first: $A, second: $B}
+fn first(p: Pair<int, int>) -> int: p.first
+fn main() -> int: first(Pair{1, 2})
+        "#).is_ok()
+    );
+
+    assert!(
+        type_check_mod(r#"
+struct Pair{first: $A, second: $B}
+fn second(p: Pair<int, string>) -> string: p.second
+fn main() -> int:
+    second(Pair{1, 2})
+    0
+        "#).is_err()
+    );
+
+    // The number of generic arguments must match the number of generic parameters
+    let r = type_check_mod(r#"
+struct Pair{first: $A, second: $B}
+fn first(p: Pair<int>) -> $A: p.first
+fn main() -> int: first(Pair{1, 2})
+        "#);
+    assert!(r.is_err());
+    assert!(format!("{}", r.unwrap_err()).contains("expects 2 generic arguments, but 1 are given"));
+}
+
+#[test]
+fn test_main_signature()
+{
+    assert!(type_check_mod("fn main(args: string[]) -> int: args.len as int").is_ok());
+    assert!(type_check_mod("fn main():\n    let x = 5").is_ok());
+    assert!(type_check_mod("fn main(args: int[]) -> int: 0").is_err());
+    assert!(type_check_mod("fn main(a: string[], b: string[]) -> int: 0").is_err());
+    assert!(type_check_mod("fn main() -> bool: true").is_err());
+}
+
+#[test]
+fn test_variadic_extern()
+{
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *u8, ...) -> i32
+fn main() -> int:
+    printf("%d %f %s\n".data, 4 as u8, 1.5 as f32, "x".data)
+    printf("\n".data)
+    0
+        "#).is_ok());
+
+    // At least the fixed arguments must be passed
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *u8, ...) -> i32
+fn main() -> int: printf() as int
+        "#).is_err());
+
+    // Strings and other aggregates cannot be passed as variadic arguments
+    assert!(type_check_mod(r#"
+extern fn printf(fmt: *u8, ...) -> i32
+fn main() -> int: printf("%s".data, "x") as int
+        "#).is_err());
+}
+
+#[test]
+fn test_exported_function()
+{
+    assert!(type_check_mod(r#"
+struct Point:
+    x: int
+    y: int
+
+export "C" fn add(a: Point, b: Point) -> Point: Point{a.x + b.x, a.y + b.y}
+        "#).is_ok());
+
+    // Generic functions cannot be exported to C
+    assert!(type_check_mod(r#"
+export "C" fn id(a: $T) -> $T: a
+        "#).is_err());
+}
@@ -1,12 +1,15 @@
 use std::ops::Deref;
+use std::mem;
+use std::collections::BTreeMap;
 use ast::*;
-use compileerror::{CompileResult, CompileError, type_error, unknown_type_result, unknown_name, type_error_result};
-use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver};
+use compileerror::{CompileResult, CompileError, type_error, unknown_type_result, unknown_name, type_error_result, print_message};
+use super::typecheckercontext::{TypeCheckerContext, ImportSymbolResolver, DefaultImplCall};
 use super::instantiategenerics::instantiate_generics;
 use super::typeresolver::{resolve_type, resolve_types, TypeResolved};
 use super::matchchecker::check_match_is_exhaustive;
 use super::genericmapper::fill_in_generics;
-use super::instantiate::make_concrete;
+use super::instantiate::{make_concrete, satisfies_interface, replace_self_type};
+use super::operatorinterfaces::{binary_operator_interface_method, INDEX_INTERFACE};
 use target::Target;
 use span::Span;
 
@@ -41,6 +44,21 @@ fn replace_by(e: Expression) -> TypeCheckResult
     Ok(TypeCheckAction::ReplaceBy(e))
 }
 
+// Literals are converted implicitly when they fit in the destination type, warn if that loses precision
+#[cfg_attr(feature = "cargo-clippy", allow(float_cmp))]
+fn convert_literal(lit: &Literal, dst_type: &Type) -> Option<Literal>
+{
+    let new_lit = try_opt!(lit.try_convert(dst_type));
+    if let (&Literal::Float(ref span, ref value, FloatSize::F64), &Literal::Float(_, _, FloatSize::F32)) = (lit, &new_lit) {
+        let v = value.parse::<f64>().expect("Invalid floating point number");
+        if (v as f32) as f64 != v {
+            print_message(&format!("Warning: implicit narrowing of {} to {} loses precision", value, dst_type), span);
+        }
+    }
+
+    Some(new_lit)
+}
+
 fn convert_type(ctx: &mut TypeCheckerContext, dst_type: &Type, src_type: &Type, expr: &mut Expression, target: &Target) -> CompileResult<()>
 {
     if *dst_type == *src_type {
@@ -54,7 +72,7 @@ fn convert_type(ctx: &mut TypeCheckerContext, dst_type: &Type, src_type: &Type,
     }
 
     if let Expression::Literal(ref mut lit) = *expr {
-        if let Some(new_lit) = lit.try_convert(dst_type) {
+        if let Some(new_lit) = convert_literal(lit, dst_type) {
             *lit = new_lit;
             converted = true;
         }
@@ -127,6 +145,60 @@ fn basic_bin_op_checks(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, left_type
     }
 }
 
+fn is_overloadable(typ: &Type) -> bool
+{
+    match *typ
+    {
+        Type::Struct(_) => true,
+        _ => false,
+    }
+}
+
+fn overloaded_method_call(receiver: &Expression, method_name: &str, arg: &Expression, span: &Span) -> Expression
+{
+    let call = Call::new(NameRef::new(method_name.into(), span.clone()), vec![arg.clone()], span.clone());
+    member_access(receiver.clone(), MemberAccessType::Call(Box::new(call)), span.clone())
+}
+
+fn check_operator_interface(ctx: &TypeCheckerContext, interface_name: &str, typ: &Type, operator: &str, span: &Span) -> CompileResult<()>
+{
+    let interface = ctx.resolve(interface_name)
+        .ok_or_else(|| unknown_name(span, format!("Unknown interface {}", interface_name)))?;
+
+    satisfies_interface(ctx, typ, &interface.typ)
+        .map_err(|msg| type_error(span, format!("Operator {} requires type {} to implement the interface {}: {}", operator, typ, interface_name, msg)))
+}
+
+// Operators on user types are turned into member calls of the corresponding operator interface
+fn type_check_overloaded_binary_op(ctx: &mut TypeCheckerContext, b: &BinaryOp, left_type: &Type, right_type: &Type) -> TypeCheckResult
+{
+    let (interface_name, method_name) = match binary_operator_interface_method(b.operator) {
+        Some(im) => im,
+        None => return type_error_result(&b.span, format!("Operator {} is not supported on {}", b.operator, left_type)),
+    };
+
+    check_operator_interface(ctx, interface_name, left_type, &format!("{}", b.operator), &b.span)?;
+    if *left_type != *right_type {
+        return type_error_result(
+            &b.span,
+            format!("Operator {} of interface {} expects operands of type {}, but the right hand side has type {}",
+                b.operator, interface_name, left_type, right_type));
+    }
+
+    let call = |receiver: &Expression, arg: &Expression| overloaded_method_call(receiver, method_name, arg, &b.span);
+    let not = |e: Expression| unary_op(UnaryOperator::Not, e, b.span.clone());
+    let e = match b.operator
+    {
+        BinaryOperator::NotEquals => not(call(&b.left, &b.right)),
+        BinaryOperator::GreaterThan => call(&b.right, &b.left),
+        BinaryOperator::LessThanEquals => not(call(&b.right, &b.left)),
+        BinaryOperator::GreaterThanEquals => not(call(&b.left, &b.right)),
+        _ => call(&b.left, &b.right),
+    };
+
+    replace_by(e)
+}
+
 fn type_check_binary_op(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, target: &Target) -> TypeCheckResult
 {
     let left_type = type_check_expression(ctx, &mut b.left, None, target)?;
@@ -135,13 +207,23 @@ fn type_check_binary_op(ctx: &mut TypeCheckerContext, b: &mut BinaryOp, target:
         return valid(left_type);
     }
 
+    if is_overloadable(&left_type) {
+        return type_check_overloaded_binary_op(ctx, b, &left_type, &right_type);
+    }
+
     match b.operator
     {
         BinaryOperator::Add |
         BinaryOperator::Sub |
         BinaryOperator::Mul |
         BinaryOperator::Div |
-        BinaryOperator::Mod => {
+        BinaryOperator::Mod |
+        BinaryOperator::WrappingAdd |
+        BinaryOperator::WrappingSub |
+        BinaryOperator::WrappingMul |
+        BinaryOperator::SaturatingAdd |
+        BinaryOperator::SaturatingSub |
+        BinaryOperator::SaturatingMul => {
             basic_bin_op_checks(ctx, b, left_type, right_type, target)?;
             b.typ = b.left.get_type(target.int_size);
             valid(b.typ.clone())
@@ -252,6 +334,28 @@ fn resolve_generic_args_in_call(ctx: &mut TypeCheckerContext, ft: &FuncType, c:
 }
 
 
+// The extra arguments of a C variadic function get the C default argument promotions:
+// integers smaller than an int become an int, and f32 becomes f64
+fn type_check_variadic_arg(ctx: &mut TypeCheckerContext, arg: &mut Expression, target: &Target) -> CompileResult<()>
+{
+    let arg_type = type_check_expression(ctx, arg, None, target)?;
+    let promoted_type = match arg_type
+    {
+        Type::Int(IntSize::I8) | Type::Int(IntSize::I16) |
+        Type::UInt(IntSize::I8) | Type::UInt(IntSize::I16) |
+        Type::Bool => Type::Int(IntSize::I32),
+        Type::Float(FloatSize::F32) => Type::Float(FloatSize::F64),
+        Type::Int(_) | Type::UInt(_) | Type::Float(_) | Type::Char | Type::Enum(_) | Type::Pointer(_) => return Ok(()),
+        Type::String => return type_error_result(&arg.span(), "Strings cannot be passed as a variadic argument, pass the data member instead"),
+        _ => return type_error_result(&arg.span(), format!("An expression of type {} cannot be passed as a variadic argument", arg_type)),
+    };
+
+    let span = arg.span();
+    let inner = mem::replace(arg, Expression::Void);
+    *arg = type_cast(inner, promoted_type, span);
+    Ok(())
+}
+
 fn type_check_call(ctx: &mut TypeCheckerContext, c: &mut Call, target: &Target) -> TypeCheckResult
 {
     let resolved = ctx.resolve(&c.callee.name)
@@ -260,19 +364,27 @@ fn type_check_call(ctx: &mut TypeCheckerContext, c: &mut Call, target: &Target)
     c.callee.name = resolved.name;
     if let Type::Func(ref ft) = resolved.typ
     {
-        if ft.args.len() != c.args.len() {
+        if ft.variadic && c.args.len() < ft.args.len() {
+            return type_error_result(&c.span,
+                format!("Attempting to call {} with {} arguments, but it needs at least {}", c.callee.name, c.args.len(), ft.args.len()));
+        } else if !ft.variadic && ft.args.len() != c.args.len() {
             return type_error_result(&c.span,
                 format!("Attempting to call {} with {} arguments, but it needs {}", c.callee.name, c.args.len(), ft.args.len()));
         }
 
         let arg_types = resolve_generic_args_in_call(ctx, ft, c, target)?;
-        for (idx, arg) in c.args.iter_mut().enumerate()
+        for (idx, arg) in c.args.iter_mut().enumerate().take(ft.args.len())
         {
             let expected_arg_type = make_concrete(ctx, &c.generic_args, &ft.args[idx], &arg.span())?;
             let arg_type = &arg_types[idx];
             convert_type(ctx, &expected_arg_type, arg_type, arg, target)?;
         }
 
+        for arg in c.args.iter_mut().skip(ft.args.len())
+        {
+            type_check_variadic_arg(ctx, arg, target)?;
+        }
+
         if ft.return_type.is_generic() {
             c.return_type = make_concrete(ctx, &c.generic_args, &ft.return_type, &c.span)?;
             return valid(c.return_type.clone());
@@ -286,12 +398,37 @@ fn type_check_call(ctx: &mut TypeCheckerContext, c: &mut Call, target: &Target)
     }
 }
 
+fn generic_lengths(typ: &Type) -> Vec<String>
+{
+    match *typ
+    {
+        Type::Array(ref at) => {
+            let mut names = generic_lengths(&at.element_type);
+            if let ArrayLength::Generic(ref name) = at.length {
+                names.push(name.clone());
+            }
+            names
+        },
+        Type::Slice(ref st) => generic_lengths(&st.element_type),
+        Type::Pointer(ref inner) => generic_lengths(inner),
+        _ => Vec::new(),
+    }
+}
+
 pub fn type_check_function(ctx: &mut TypeCheckerContext, fun: &mut Function, target: &Target) -> CompileResult<()>
 {
+    if is_destructor_name(&fun.sig.name) && (fun.sig.args.len() != 1 || fun.sig.return_type != Type::Void) {
+        return type_error_result(&fun.span, format!("Destructor {} must take self as its only argument and return nothing", fun.sig.name));
+    }
+
     ctx.enter_scope(Some(fun.sig.return_type.clone()));
     for arg in &mut fun.sig.args
     {
         ctx.add(Symbol::new(&arg.name, &arg.typ, arg.mutable, &arg.span, SymbolType::Normal))?;
+        // Generic array lengths can be used as values in the body
+        for name in generic_lengths(&arg.typ) {
+            ctx.add(Symbol::new(&name, &target.native_int_type, false, &arg.span, SymbolType::Normal))?;
+        }
     }
 
     let et = match type_check_expression(ctx, &mut fun.expression, Some(&fun.sig.return_type), target)
@@ -305,7 +442,9 @@ pub fn type_check_function(ctx: &mut TypeCheckerContext, fun: &mut Function, tar
     };
 
     ctx.exit_scope();
-    if et != fun.sig.return_type {
+    if et != fun.sig.return_type && fun.expression.diverges() {
+        set_diverging_type(&mut fun.expression, &fun.sig.return_type);
+    } else if et != fun.sig.return_type {
         if let Some(expression) = fun.sig.return_type.convert(&et, &fun.expression) {
             fun.expression = expression;
         } else {
@@ -314,10 +453,26 @@ pub fn type_check_function(ctx: &mut TypeCheckerContext, fun: &mut Function, tar
         }
     }
 
+    name_lambdas(fun)?;
     fun.type_checked = true;
     Ok(())
 }
 
+// Lambdas are named after the function they are defined in, so the names are unique, and the same in every build
+fn name_lambdas(fun: &mut Function) -> CompileResult<()>
+{
+    let func_name = fun.sig.name.clone();
+    let mut count = 0;
+    let mut name_lambda = |e: &mut Expression| {
+        if let Expression::Lambda(ref mut l) = *e {
+            l.sig.name = format!("{}::lambda-{}", func_name, count);
+            count += 1;
+        }
+        Ok(())
+    };
+    fun.expression.visit_mut(&mut name_lambda)
+}
+
 fn is_result_mutable(ctx: &TypeCheckerContext, e: &Expression) -> bool
 {
     match *e {
@@ -334,6 +489,19 @@ fn is_result_mutable(ctx: &TypeCheckerContext, e: &Expression) -> bool
     }
 }
 
+// Name patterns which refer to a const, are matched against the value of the const
+fn const_pattern(ctx: &TypeCheckerContext, p: &Pattern) -> Option<Literal>
+{
+    match *p
+    {
+        Pattern::Name(ref nr) => match ctx.resolve_const(&nr.name) {
+            Some(Expression::Literal(lit)) => Some(lit),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, target: &Target) -> TypeCheckResult
 {
     let target_type = type_check_expression(ctx, &mut m.target, None, target)?;
@@ -344,13 +512,19 @@ fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, targe
     {
         let infer_case_type = |ctx: &mut TypeCheckerContext, e: &mut Expression, return_type: &Type| {
             let tt = type_check_expression(ctx, e, None, target)?;
-            if *return_type != Type::Unknown && *return_type != tt {
+            if e.diverges() {
+                Ok(return_type.clone())
+            } else if *return_type != Type::Unknown && *return_type != tt {
                 type_error_result(&e.span(), "Expressions in match statements must return the same type")
             } else {
                 Ok(tt)
             }
         };
 
+        if let Some(lit) = const_pattern(ctx, &c.pattern) {
+            c.pattern = Pattern::Literal(lit);
+        }
+
         let match_span = c.pattern.span();
         let case_type = match c.pattern
         {
@@ -473,6 +647,16 @@ fn type_check_match(ctx: &mut TypeCheckerContext, m: &mut MatchExpression, targe
         }
     }
 
+    if return_type == Type::Unknown {
+        return_type = Type::Void;
+    }
+
+    for c in &mut m.cases {
+        if c.to_execute.diverges() {
+            set_diverging_type(&mut c.to_execute, &return_type);
+        }
+    }
+
     m.typ = return_type.clone();
     check_match_is_exhaustive(m, &target_type)?;
     valid(return_type)
@@ -496,8 +680,6 @@ fn type_check_lambda(ctx: &mut TypeCheckerContext, m: &mut Lambda, type_hint: Op
     match type_hint
     {
         Some(typ) => {
-            use uuid::{Uuid};
-            m.sig.name = format!("lambda-{}", Uuid::new_v4()); // Add a uuid, so we don't get name clashes
             m.apply_type(typ)?;
             let infered_type = type_check_lambda_body(ctx, m, target)?.unwrap();
             if infered_type != *typ {
@@ -556,8 +738,19 @@ fn type_check_name(ctx: &mut TypeCheckerContext, nr: &mut NameRef, type_hint: Op
         return valid(nr.typ.clone()); // We have already determined the type
     }
 
-    let resolved = ctx.resolve(&nr.name)
-        .ok_or_else(|| unknown_name(&nr.span, format!("Unknown name {}", nr.name)))?;
+    let resolved = ctx.resolve(&nr.name);
+    let is_local = match resolved {
+        Some(Symbol{symbol_type: SymbolType::Normal, ..}) => true,
+        _ => false,
+    };
+
+    if !is_local {
+        if let Some(value) = ctx.resolve_const(&nr.name) {
+            return replace_by(value);
+        }
+    }
+
+    let resolved = resolved.ok_or_else(|| unknown_name(&nr.span, format!("Unknown name {}", nr.name)))?;
     nr.name = resolved.name;
 
     if let Some(typ) = type_hint {
@@ -669,6 +862,22 @@ fn update_binding_type(ctx: &mut TypeCheckerContext, e: &mut Expression, name: &
     e.visit_mut(&mut update_binding)
 }
 
+// Give an expression which never completes the type of the expression it is combined with
+fn set_diverging_type(e: &mut Expression, typ: &Type)
+{
+    match *e
+    {
+        Expression::Panic(ref mut p) => p.typ = typ.clone(),
+        Expression::Block(ref mut b) => {
+            b.typ = typ.clone();
+            if let Some(last) = b.expressions.last_mut() {
+                set_diverging_type(last, typ);
+            }
+        },
+        _ => (),
+    }
+}
+
 fn type_check_if(ctx: &mut TypeCheckerContext, i: &mut IfExpression, type_hint: Option<&Type>, target: &Target) -> TypeCheckResult
 {
     type_check_with_conversion(ctx, &mut i.condition, &Type::Bool, target)?;
@@ -682,6 +891,18 @@ fn type_check_if(ctx: &mut TypeCheckerContext, i: &mut IfExpression, type_hint:
 
     if on_true_type != on_false_type
     {
+        if let Some(ref mut on_false) = i.on_false {
+            if on_false.diverges() {
+                set_diverging_type(on_false, &on_true_type);
+                i.typ = on_true_type.clone();
+                return valid(on_true_type);
+            } else if i.on_true.diverges() {
+                set_diverging_type(&mut i.on_true, &on_false_type);
+                i.typ = on_false_type.clone();
+                return valid(on_false_type);
+            }
+        }
+
         if i.on_false.is_none()
         {
             type_error_result(&i.span, format!("If expressions without an else part, must return void (type of then part is {})", on_true_type))
@@ -722,6 +943,16 @@ fn type_check_if(ctx: &mut TypeCheckerContext, i: &mut IfExpression, type_hint:
 
 fn type_check_struct_members_in_initializer(ctx: &mut TypeCheckerContext, st: &StructType, si: &mut StructInitializer, target: &Target) -> CompileResult<Type>
 {
+    // Members left out at the end are initialized with their default values
+    if si.member_initializers.len() < st.members.len() {
+        if let Some(defaults) = ctx.member_defaults(&si.struct_name) {
+            let missing: Option<Vec<Expression>> = defaults.iter().skip(si.member_initializers.len()).cloned().collect();
+            if let Some(missing) = missing {
+                si.member_initializers.extend(missing);
+            }
+        }
+    }
+
     if st.members.len() != si.member_initializers.len() {
         return type_error_result(&si.span,
             format!("Type {} has {} members, but attempting to initialize {} members", si.struct_name, st.members.len(), si.member_initializers.len()));
@@ -802,7 +1033,7 @@ fn type_check_struct_initializer(ctx: &mut TypeCheckerContext, si: &mut StructIn
                 sum_type_cases.push(sum_type_case(&case.name, typ))
             }
 
-            si.typ = sum_type(&st.name, sum_type_cases);
+            si.typ = sum_type(&st.name, sum_type_cases, st.result);
             valid(si.typ.clone())
         },
 
@@ -887,6 +1118,39 @@ fn type_check_generic_member_call(ctx: &mut TypeCheckerContext, call: &mut Call,
     }
 }
 
+/*
+    When a concrete type doesn't have a method, but satisfies an interface with a default implementation of it,
+    the default implementation is declared here, and instantiated together with the generics.
+*/
+fn declare_default_impl_call(ctx: &mut TypeCheckerContext, typ: &Type, call: &Call) -> CompileResult<()>
+{
+    let name = format!("{}.{}", typ.name(), call.callee.name);
+    if ctx.resolve(&name).is_some() {
+        return Ok(());
+    }
+
+    for interface in ctx.interfaces_with_default_impl(&call.callee.name) {
+        if satisfies_interface(ctx, typ, &interface).is_err() {
+            continue;
+        }
+
+        if let Type::Interface(ref it) = interface {
+            if let Some(sig) = it.functions.iter().find(|f| f.name == call.callee.name) {
+                ctx.add(Symbol::new(&name, &replace_self_type(&sig.typ, typ), false, &call.span, SymbolType::Global))?;
+                ctx.add_default_impl_call(DefaultImplCall{
+                    concrete_type: typ.clone(),
+                    interface: it.name.clone(),
+                    method: call.callee.name.clone(),
+                    span: call.span.clone(),
+                });
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn to_static_function_call(ctx: &mut TypeCheckerContext, sma: &MemberAccess) -> Option<Call> 
 {
     if let Expression::NameRef(ref nr) = sma.left {
@@ -958,12 +1222,14 @@ fn type_check_member_access(ctx: &mut TypeCheckerContext, sma: &mut MemberAccess
         },
 
         (&mut MemberAccessType::Call(ref mut call), &Type::Struct(ref st)) => {
+            declare_default_impl_call(ctx, left_type_ref, call)?;
             let call_name = format!("{}.{}", st.name, call.callee.name);
             call.callee.name = call_name;
             return replace_by(member_call_to_call(&sma.left, call, target.int_size));
         },
 
         (&mut MemberAccessType::Call(ref mut call), &Type::Sum(ref st)) => {
+            declare_default_impl_call(ctx, left_type_ref, call)?;
             let call_name = format!("{}.{}", st.name, call.callee.name);
             call.callee.name = call_name;
             return replace_by(member_call_to_call(&sma.left, call, target.int_size));
@@ -1091,10 +1357,15 @@ fn type_check_dereference(ctx: &mut TypeCheckerContext, a: &mut DereferenceExpre
     }
 }
 
-fn type_check_index_operation(ctx: &mut TypeCheckerContext, iop: &mut IndexOperation, target: &Target) -> CompileResult<Type>
+fn type_check_index_operation(ctx: &mut TypeCheckerContext, iop: &mut IndexOperation, target: &Target) -> TypeCheckResult
 {
     let target_type = type_check_expression(ctx, &mut iop.target, None, target)?;
     let index_type = type_check_expression(ctx, &mut iop.index_expr, None, target)?;
+    if is_overloadable(&target_type) {
+        check_operator_interface(ctx, INDEX_INTERFACE, &target_type, "[]", &iop.span)?;
+        return replace_by(overloaded_method_call(&iop.target, "index", &iop.index_expr, &iop.span));
+    }
+
     match index_type {
         Type::Int(_) | Type::UInt(_) => (),
         _ => return type_error_result(&iop.span, format!("An expression of type {}, cannot be used to index something. Only integers are supported.", index_type))
@@ -1108,7 +1379,29 @@ fn type_check_index_operation(ctx: &mut TypeCheckerContext, iop: &mut IndexOpera
     };
 
     iop.typ = typ.clone();
-    Ok(typ)
+    valid(typ)
+}
+
+fn type_check_slice_operation(ctx: &mut TypeCheckerContext, sop: &mut SliceOperation, target: &Target) -> TypeCheckResult
+{
+    let target_type = type_check_expression(ctx, &mut sop.target, None, target)?;
+    for bound in sop.start.iter_mut().chain(sop.end.iter_mut()) {
+        let bound_type = type_check_expression(ctx, bound, None, target)?;
+        match bound_type {
+            Type::Int(_) | Type::UInt(_) => (),
+            _ => return type_error_result(&bound.span(), format!("An expression of type {}, cannot be used as a slice bound. Only integers are supported.", bound_type))
+        }
+    }
+
+    let typ = match target_type {
+        Type::String => Type::String,
+        Type::Slice(ref st) => slice_type(st.element_type.clone()),
+        Type::Array(ref at) => slice_type(at.element_type.clone()),
+        _ => return type_error_result(&sop.span, format!("Cannot slice an expression of type {}", target_type)),
+    };
+
+    sop.typ = typ.clone();
+    valid(typ)
 }
 
 
@@ -1166,7 +1459,12 @@ fn type_check_assign(ctx: &mut TypeCheckerContext, a: &mut Assign, target: &Targ
         }
 
         AssignTarget::IndexOperation(ref mut iop) => {
-            type_check_index_operation(ctx, iop, target)?
+            match type_check_index_operation(ctx, iop, target)? {
+                TypeCheckAction::Valid(typ) => typ,
+                TypeCheckAction::ReplaceBy(_) => {
+                    return type_error_result(&iop.span, "Cannot assign to an overloaded index operation");
+                }
+            }
         }
     };
 
@@ -1228,12 +1526,16 @@ fn type_check_cast(ctx: &mut TypeCheckerContext, c: &mut TypeCast, target: &Targ
     let inner_type = type_check_expression(ctx, &mut c.inner, None, target)?;
     match (inner_type, &c.destination_type)
     {
+        (Type::Int(_), &Type::Int(_)) |
+        (Type::UInt(_), &Type::UInt(_)) |
+        (Type::Float(_), &Type::Float(_)) |
         (Type::Int(_), &Type::UInt(_)) |
         (Type::Int(_), &Type::Float(_)) |
         (Type::UInt(_), &Type::Int(_)) |
         (Type::UInt(_), &Type::Float(_)) |
         (Type::Float(_), &Type::Int(_)) |
-        (Type::Float(_), &Type::UInt(_)) => valid(c.destination_type.clone()),
+        (Type::Float(_), &Type::UInt(_)) |
+        (Type::Bool, &Type::Int(_)) => valid(c.destination_type.clone()),
         (Type::Pointer(_), &Type::Pointer(ref to)) if *to.deref() == Type::Void => valid(c.destination_type.clone()),
         (Type::Pointer(ref from), &Type::Pointer(_)) if *from.deref() == Type::Void => valid(c.destination_type.clone()),
         (Type::Pointer(_), &Type::Bool) => valid(Type::Bool),
@@ -1246,7 +1548,7 @@ fn type_check_compiler_call(ctx: &mut TypeCheckerContext, cc: &mut CompilerCall,
 {
     match *cc {
         CompilerCall::SizeOf(ref mut typ, ref span) => {
-            if resolve_type(ctx, typ) == TypeResolved::No {
+            if resolve_type(ctx, typ, span)? == TypeResolved::No {
                 type_error_result(span, format!("Unable to resolve type {}", typ))
             } else {
                 valid(target.native_uint_type.clone())
@@ -1269,6 +1571,31 @@ fn type_check_compiler_call(ctx: &mut TypeCheckerContext, cc: &mut CompilerCall,
                 type_error_result(span, format!("The first argument of @slice, must be a pointer, not a {}", data_type))
             }
         }
+
+        CompilerCall::Convert{ref mut inner, ref mut typ, mode, ref span} => {
+            if resolve_type(ctx, typ, span)? == TypeResolved::No {
+                return type_error_result(span, format!("Unable to resolve type {}", typ));
+            }
+
+            let inner_type = type_check_expression(ctx, inner, None, target)?;
+            if !inner_type.is_numeric() || !typ.is_numeric() {
+                return type_error_result(span, format!("{} can only convert between numeric types, not from {} to {}", mode, inner_type, typ));
+            }
+
+            match mode
+            {
+                ConversionMode::Wrapping if !inner_type.is_integer() || !typ.is_integer() =>
+                    type_error_result(span, format!("{} can only convert between integer types, not from {} to {}", mode, inner_type, typ)),
+                ConversionMode::Checked => valid(optional_type(typ.clone())),
+                _ => valid(typ.clone()),
+            }
+        }
+
+        CompilerCall::Assert{ref mut condition, ref mut message, ..} => {
+            type_check_with_conversion(ctx, condition, &Type::Bool, target)?;
+            type_check_with_conversion(ctx, message, &Type::String, target)?;
+            valid(Type::Void)
+        }
     }
 }
 
@@ -1294,7 +1621,7 @@ fn type_check_literal(ctx: &mut TypeCheckerContext, lit: &mut Literal, type_hint
                 None => valid(typ),
                 Some(expected) if typ == *expected => valid(typ),
                 Some(expected) => {
-                    if let Some(new_lit) = lit.try_convert(expected) {
+                    if let Some(new_lit) = convert_literal(lit, expected) {
                         replace_by(Expression::Literal(new_lit))
                     } else {
                         valid(typ)
@@ -1305,6 +1632,93 @@ fn type_check_literal(ctx: &mut TypeCheckerContext, lit: &mut Literal, type_hint
     }
 }
 
+fn type_check_defer(ctx: &mut TypeCheckerContext, d: &mut Defer, target: &Target) -> TypeCheckResult
+{
+    type_check_expression(ctx, &mut d.expression, None, target)?;
+    d.expression.visit(&mut |e: &Expression| {
+        match *e
+        {
+            Expression::Return(_) | Expression::Propagate(_) =>
+                type_error_result(&e.span(), "Cannot return from a defer expression"),
+            _ => Ok(()),
+        }
+    })?;
+    valid(Type::Void)
+}
+
+// A failure can be propagated to another result type, when both failure cases hold the same values
+fn same_failure(a: &SumType, b: &SumType) -> bool
+{
+    let values = |typ: Type| -> Vec<Type> {
+        match typ
+        {
+            Type::Struct(ref st) => st.members.iter().map(|m| m.typ.clone()).collect(),
+            _ => vec![typ.clone()],
+        }
+    };
+
+    match (a.failure_case(), b.failure_case())
+    {
+        (Some((_, fa)), Some((_, fb))) => b.success_case().is_some() && values(fa) == values(fb),
+        _ => false,
+    }
+}
+
+fn type_check_propagation(ctx: &mut TypeCheckerContext, p: &mut Propagation, target: &Target) -> TypeCheckResult
+{
+    let inner_type = type_check_expression(ctx, &mut p.inner, None, target)?;
+    let return_type = match ctx.get_function_return_type()
+    {
+        Some(rt) => rt,
+        None => return type_error_result(&p.span, "? operator used outside of a function"),
+    };
+
+    // On failure, the var holds the failure converted to the return type
+    let (typ, on_failure, failure_type) = match inner_type
+    {
+        Type::Optional(ref inner) => {
+            if !return_type.is_optional() {
+                return type_error_result(&p.span,
+                    format!("? operator on an optional requires the enclosing function to return an optional, not {}", return_type));
+            }
+            (inner.deref().clone(), return_expr(nil_expr(p.span.clone()), p.span.clone()), inner_type.clone())
+        },
+
+        Type::Sum(ref st) => {
+            let (_, ok_type) = match st.success_case()
+            {
+                Some(sc) => sc,
+                None => return type_error_result(&p.span,
+                    format!("? operator requires a sum type declared with @result, {} is not", st.name)),
+            };
+
+            let compatible = match return_type
+            {
+                Type::Sum(ref rst) => same_failure(st, rst),
+                _ => false,
+            };
+
+            if !compatible {
+                return type_error_result(&p.span,
+                    format!("? operator on type {} requires the enclosing function to return a result type with the same failure, not {}", inner_type, return_type));
+            }
+
+            (ok_type, return_expr(Expression::NameRef(NameRef::new(p.var_name.clone(), p.span.clone())), p.span.clone()), return_type.clone())
+        },
+
+        _ => return type_error_result(&p.span,
+            format!("? operator expects an optional or a result type, not {}", inner_type)),
+    };
+
+    ctx.enter_scope(None);
+    ctx.add(Symbol::new(&p.var_name, &failure_type, false, &p.span, SymbolType::Normal))?;
+    p.on_failure = on_failure;
+    let result = type_check_expression(ctx, &mut p.on_failure, None, target);
+    ctx.exit_scope();
+    result?;
+    p.typ = typ.clone();
+    valid(typ)
+}
 
 pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, type_hint: Option<&Type>, target: &Target) -> CompileResult<Type>
 {
@@ -1358,7 +1772,8 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
         },
         Expression::Cast(ref mut t) => type_check_cast(ctx, t, target),
         Expression::CompilerCall(ref mut cc) => type_check_compiler_call(ctx, cc, type_hint, target),
-        Expression::IndexOperation(ref mut iop) => valid(type_check_index_operation(ctx, iop, target)?),
+        Expression::IndexOperation(ref mut iop) => type_check_index_operation(ctx, iop, target),
+        Expression::SliceOperation(ref mut sop) => type_check_slice_operation(ctx, sop, target),
         Expression::Return(ref mut r) => {
             if let Some(return_type) = ctx.get_function_return_type() {
                 type_check_with_conversion(ctx, &mut r.expression, &return_type, target)?;
@@ -1367,6 +1782,12 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
                 type_error_result(&r.span, "return expression outside of a function")
             }
         },
+        Expression::Propagate(ref mut p) => type_check_propagation(ctx, p, target),
+        Expression::Defer(ref mut d) => type_check_defer(ctx, d, target),
+        Expression::Panic(ref mut p) => {
+            type_check_with_conversion(ctx, &mut p.message, &Type::String, target)?;
+            valid(p.typ.clone())
+        },
     };
 
     match type_check_result
@@ -1380,10 +1801,125 @@ pub fn type_check_expression(ctx: &mut TypeCheckerContext, e: &mut Expression, t
     }
 }
 
-pub fn type_check_module(module: &mut Module, target: &Target, imports: &ImportMap) -> CompileResult<()>
+/*
+    Consts are evaluated at compile time, before type checking, so they can be used in array lengths, match patterns
+    and default values of struct members. The evaluation is done outside of the type checker, which only uses the values.
+*/
+pub trait ConstEvaluation
+{
+    // Evaluate an expression using the globals and functions of module, the value is a literal or a struct initializer
+    fn evaluate(&self, module: &Module, expression: &Expression, target: &Target) -> CompileResult<Expression>;
+}
+
+// The inits of consts are replaced by their values, other globals are left alone
+fn evaluate_consts<E: ConstEvaluation>(module: &mut Module, evaluator: &E, target: &Target) -> CompileResult<BTreeMap<String, Expression>>
+{
+    let mut consts = BTreeMap::new();
+    for global in module.globals.values().filter(|g| g.constant) {
+        let value = evaluator.evaluate(module, &global.init, target)?;
+        consts.insert(global.name.clone(), value);
+    }
+
+    for (name, value) in &consts {
+        if let Some(global) = module.globals.get_mut(name) {
+            global.init = value.clone();
+        }
+    }
+
+    Ok(consts)
+}
+
+// The default values of the members of the structs in a module, sum type cases included
+fn evaluate_member_defaults<E: ConstEvaluation>(module: &Module, evaluator: &E, target: &Target) -> CompileResult<BTreeMap<String, Vec<Option<Expression>>>>
+{
+    let mut structs = Vec::new();
+    for td in module.types.values()
+    {
+        match *td
+        {
+            TypeDeclaration::Struct(ref sd) => structs.push((&sd.name, sd)),
+            TypeDeclaration::Sum(ref st) => structs.extend(st.cases.iter().filter_map(|c| c.data.as_ref().map(|sd| (&c.name, sd)))),
+            TypeDeclaration::Interface(_) => (),
+        }
+    }
+
+    let mut defaults = BTreeMap::new();
+    for (name, sd) in structs {
+        if sd.members.iter().all(|m| m.default.is_none()) {
+            continue;
+        }
+
+        let mut values = Vec::with_capacity(sd.members.len());
+        for m in &sd.members {
+            let value = match m.default {
+                Some(ref d) => Some(evaluator.evaluate(module, d, target)?),
+                None => None,
+            };
+            values.push(value);
+        }
+        defaults.insert(name.clone(), values);
+    }
+
+    Ok(defaults)
+}
+
+// main can take the command line arguments, and can return an exit code
+fn check_main_function(module: &Module, target: &Target) -> CompileResult<()>
+{
+    let main = match module.functions.get(&format!("{}::main", module.name)) {
+        Some(main) => main,
+        None => return Ok(()),
+    };
+
+    let args_ok = match main.sig.args.len() {
+        0 => true,
+        1 => main.sig.args[0].typ == slice_type(Type::String),
+        _ => false,
+    };
+
+    let return_type_ok = main.sig.return_type == target.native_int_type || main.sig.return_type == Type::Void;
+    if !args_ok || !return_type_ok {
+        return type_error_result(&main.sig.span,
+            format!("main must have the signature fn main() -> int or fn main(args: string[]) -> int, returning void is also allowed, not {}", main.sig.typ));
+    }
+
+    Ok(())
+}
+
+// Functions exported with export "C" are called from C, so they need a plain, non generic signature
+fn check_exported_functions(module: &Module) -> CompileResult<()>
 {
+    for func in module.functions.values() {
+        if func.sig.calling_convention != CallingConvention::C {
+            continue;
+        }
+
+        if func.is_generic() {
+            return type_error_result(&func.sig.span, format!("Function {} cannot be exported to C, because it is generic", func.sig.name));
+        }
+
+        let name = func.sig.symbol_name();
+        if name.contains('.') || is_destructor_name(name) {
+            return type_error_result(&func.sig.span, format!("Member function {} cannot be exported to C", func.sig.name));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn type_check_module<E: ConstEvaluation>(module: &mut Module, target: &Target, imports: &ImportMap, evaluator: &E) -> CompileResult<()>
+{
+    let consts = evaluate_consts(module, evaluator, target)?;
+    let member_defaults = evaluate_member_defaults(module, evaluator, target)?;
     loop {
         let mut ctx = TypeCheckerContext::new(ImportSymbolResolver::ImportMap(imports));
+        ctx.set_namespace(&module.name);
+        for (name, value) in &consts {
+            ctx.add_const(name, value.clone());
+        }
+        for (name, defaults) in &member_defaults {
+            ctx.add_member_defaults(name, defaults.clone());
+        }
         resolve_types(&mut ctx, module, target)?;
 
         for global in module.globals.values_mut() {
@@ -1407,6 +1943,8 @@ pub fn type_check_module(module: &mut Module, target: &Target, imports: &ImportM
         }
     }
 
+    check_main_function(module, target)?;
+    check_exported_functions(module)?;
     module.type_checked = true;
     Ok(())
 }
\ No newline at end of file
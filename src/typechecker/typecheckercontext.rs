@@ -1,10 +1,13 @@
-use std::collections::hash_map::{HashMap, Entry};
+use std::mem;
+use std::collections::btree_map::{BTreeMap, Entry};
 use ast::*;
 use compileerror::*;
+use span::Span;
+use super::operatorinterfaces::operator_interfaces;
 
 struct Scope
 {
-    symbols: HashMap<String, Symbol>,
+    symbols: BTreeMap<String, Symbol>,
     function_return_type: Option<Type>,
 }
 
@@ -14,7 +17,7 @@ impl Scope
     pub fn new(function_return_type: Option<Type>) -> Scope
     {
         Scope {
-            symbols: HashMap::new(),
+            symbols: BTreeMap::new(),
             function_return_type,
         }
     }
@@ -82,6 +85,28 @@ impl<'a> ImportSymbolResolver<'a>
             }
         }
     }
+
+    fn symbols(&self) -> Vec<&Symbol>
+    {
+        match *self {
+            ImportSymbolResolver::ImportMap(ref imports) => {
+                imports.values().flat_map(|import| import.symbols.values()).collect()
+            }
+
+            ImportSymbolResolver::ExternalImport(ref import) => {
+                import.symbols.values().chain(import.imported_symbols.values()).collect()
+            }
+        }
+    }
+}
+
+// A default interface method called on a concrete type, which still has to be instantiated
+pub struct DefaultImplCall
+{
+    pub concrete_type: Type,
+    pub interface: String,
+    pub method: String,
+    pub span: Span,
 }
 
 pub struct TypeCheckerContext<'a>
@@ -89,6 +114,11 @@ pub struct TypeCheckerContext<'a>
     stack: Vec<Scope>,
     globals: Scope,
     externals: Scope,
+    builtins: Scope,
+    consts: BTreeMap<String, Expression>,
+    member_defaults: BTreeMap<String, Vec<Option<Expression>>>,
+    namespace: String,
+    default_impl_calls: Vec<DefaultImplCall>,
     import_resolver: ImportSymbolResolver<'a>,
 }
 
@@ -96,10 +126,20 @@ impl<'a> TypeCheckerContext<'a>
 {
     pub fn new(isr: ImportSymbolResolver<'a>) -> TypeCheckerContext<'a>
     {
+        let mut builtins = Scope::new(None);
+        for interface in operator_interfaces() {
+            builtins.update(Symbol::new(&interface.name(), &interface, false, &Span::default(), SymbolType::Normal));
+        }
+
         TypeCheckerContext {
             stack: Vec::new(),
             globals: Scope::new(None),
             externals: Scope::new(None),
+            builtins: builtins,
+            consts: BTreeMap::new(),
+            member_defaults: BTreeMap::new(),
+            namespace: String::new(),
+            default_impl_calls: Vec::new(),
             import_resolver: isr
         }
     }
@@ -139,7 +179,11 @@ impl<'a> TypeCheckerContext<'a>
             return Some(s)
         }
 
-        self.import_resolver.resolve(name)
+        if let Some(s) = self.import_resolver.resolve(name) {
+            return Some(s)
+        }
+
+        self.builtins.resolve(name)
     }
 
     pub fn add(&mut self, symbol: Symbol) -> CompileResult<()>
@@ -163,6 +207,59 @@ impl<'a> TypeCheckerContext<'a>
         }
     }
 
+    pub fn set_namespace(&mut self, namespace: &str)
+    {
+        self.namespace = namespace.into();
+    }
+
+    // The value of a const is a literal or a struct initializer
+    pub fn add_const(&mut self, name: &str, value: Expression)
+    {
+        self.consts.insert(name.into(), value);
+    }
+
+    // Consts are only known for the module being checked, consts of imported modules are plain globals
+    pub fn resolve_const(&self, name: &str) -> Option<Expression>
+    {
+        self.consts.get(name)
+            .or_else(|| self.consts.get(&format!("{}::{}", self.namespace, name)))
+            .cloned()
+    }
+
+    pub fn add_member_defaults(&mut self, struct_name: &str, defaults: Vec<Option<Expression>>)
+    {
+        self.member_defaults.insert(struct_name.into(), defaults);
+    }
+
+    // Like consts, default values of members are only known for the structs of the module being checked
+    pub fn member_defaults(&self, struct_name: &str) -> Option<Vec<Option<Expression>>>
+    {
+        self.member_defaults.get(struct_name).cloned()
+    }
+
+    // Interfaces of the module and its imports, which have a default implementation of a method
+    pub fn interfaces_with_default_impl(&self, method: &str) -> Vec<Type>
+    {
+        self.globals.symbols.values()
+            .chain(self.import_resolver.symbols().into_iter())
+            .filter(|s| match s.typ {
+                Type::Interface(ref it) => it.default_impls.iter().any(|name| name == method),
+                _ => false,
+            })
+            .map(|s| s.typ.clone())
+            .collect()
+    }
+
+    pub fn add_default_impl_call(&mut self, call: DefaultImplCall)
+    {
+        self.default_impl_calls.push(call);
+    }
+
+    pub fn take_default_impl_calls(&mut self) -> Vec<DefaultImplCall>
+    {
+        mem::replace(&mut self.default_impl_calls, Vec::new())
+    }
+
     pub fn get_function_return_type(&self) -> Option<Type>
     {
         for sf in self.stack.iter().rev() {
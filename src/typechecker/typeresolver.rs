@@ -1,9 +1,11 @@
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::ops::Deref;
 use ast::*;
 use target::Target;
-use compileerror::{CompileResult, unknown_name_result};
+use span::Span;
+use compileerror::{CompileResult, unknown_name_result, type_error_result};
 use super::typecheckercontext::TypeCheckerContext;
+use super::instantiate::make_concrete_type;
 
 #[derive(Eq, PartialEq, Debug)]
 pub enum TypeResolved
@@ -19,15 +21,107 @@ enum ResolveMode
     Forced,
 }
 
-fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, TypeResolved)
+// The distinct generic types used in the declaration of typ
+fn generic_parameters(typ: &Type, params: &mut Vec<Type>)
+{
+    match *typ
+    {
+        Type::Generic(_) => {
+            if !params.contains(typ) {
+                params.push(typ.clone());
+            }
+        },
+        Type::Pointer(ref inner) | Type::Optional(ref inner) => generic_parameters(inner, params),
+        Type::Array(ref at) => generic_parameters(&at.element_type, params),
+        Type::Slice(ref st) => generic_parameters(&st.element_type, params),
+        Type::Func(ref ft) => {
+            for arg in &ft.args {
+                generic_parameters(arg, params);
+            }
+            generic_parameters(&ft.return_type, params);
+        },
+        Type::Struct(ref st) => {
+            for m in &st.members {
+                generic_parameters(&m.typ, params);
+            }
+        },
+        Type::Sum(ref st) => {
+            for c in &st.cases {
+                generic_parameters(&c.typ, params);
+            }
+        },
+        _ => (),
+    }
+}
+
+// Types like Map<string, int> must get as many generic arguments as the type has generic parameters
+fn check_generic_arg_counts(ctx: &TypeCheckerContext, typ: &Type, span: &Span) -> CompileResult<()>
 {
     match *typ
     {
         Type::Unresolved(ref ut) => {
             if let Some(r) = ctx.resolve(&ut.name) {
-                (Some(r.typ.clone()), TypeResolved::Yes)
-            } else {
-                (None, TypeResolved::No)
+                let mut params = Vec::new();
+                generic_parameters(&r.typ, &mut params);
+                if !ut.generic_args.is_empty() && params.len() != ut.generic_args.len() {
+                    return type_error_result(span,
+                        format!("Type {} expects {} generic arguments, but {} are given", ut.name, params.len(), ut.generic_args.len()));
+                }
+            }
+
+            for arg in &ut.generic_args {
+                check_generic_arg_counts(ctx, arg, span)?;
+            }
+            Ok(())
+        },
+        Type::Pointer(ref inner) | Type::Optional(ref inner) => check_generic_arg_counts(ctx, inner, span),
+        Type::Array(ref at) => check_generic_arg_counts(ctx, &at.element_type, span),
+        _ => Ok(()),
+    }
+}
+
+// Substitute the generic arguments of a type like Map<string, int>. Declarations have no generic
+// parameter list, so the arguments map to the generic parameters in the order they are first used.
+fn resolve_generic_args(ctx: &TypeCheckerContext, typ: &Type, generic_args: &[Type]) -> (Option<Type>, TypeResolved)
+{
+    let mut params = Vec::new();
+    generic_parameters(typ, &mut params);
+    if params.len() != generic_args.len() {
+        // Reported by check_generic_arg_counts
+        return (None, TypeResolved::No);
+    }
+
+    let mut mapping = GenericMapping::new();
+    for (param, generic_arg) in params.iter().zip(generic_args.iter()) {
+        let arg = match resolve_type_helper(ctx, generic_arg)
+        {
+            (Some(resolved), TypeResolved::Yes) => resolved,
+            (None, TypeResolved::Yes) => generic_arg.clone(),
+            _ => return (None, TypeResolved::No),
+        };
+
+        if *param != arg {
+            mapping.insert(param.clone(), arg);
+        }
+    }
+
+    match make_concrete_type(ctx, &mapping, typ)
+    {
+        Ok(concrete) => (Some(concrete), TypeResolved::Yes),
+        Err(_) => (None, TypeResolved::No),
+    }
+}
+
+fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, TypeResolved)
+{
+    match *typ
+    {
+        Type::Unresolved(ref ut) => {
+            match ctx.resolve(&ut.name)
+            {
+                Some(ref r) if !ut.generic_args.is_empty() => resolve_generic_args(ctx, &r.typ, &ut.generic_args),
+                Some(r) => (Some(r.typ.clone()), TypeResolved::Yes),
+                None => (None, TypeResolved::No),
             }
         },
 
@@ -40,6 +134,15 @@ fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, T
             }
         },
 
+        Type::Optional(ref inner) => {
+            let r = resolve_type_helper(ctx, inner);
+            if let (Some(typ), TypeResolved::Yes) = r {
+                (Some(optional_type(typ)), TypeResolved::Yes)
+            } else {
+                r
+            }
+        },
+
         Type::Generic(ref gt) => {
             match *gt.deref()
             {
@@ -69,19 +172,42 @@ fn resolve_type_helper(ctx: &TypeCheckerContext, typ: &Type) -> (Option<Type>, T
             }
         },
 
+        Type::Array(ref at) => {
+            let length = match at.length
+            {
+                ArrayLength::Known(_) | ArrayLength::Generic(_) => at.length.clone(),
+                ArrayLength::Const(ref name) => {
+                    match ctx.resolve_const(name)
+                    {
+                        Some(Expression::Literal(Literal::Int(_, v, _))) if v >= 0 => ArrayLength::Known(v as usize),
+                        Some(Expression::Literal(Literal::UInt(_, v, _))) => ArrayLength::Known(v as usize),
+                        _ => return (None, TypeResolved::No),
+                    }
+                },
+            };
+
+            match resolve_type_helper(ctx, &at.element_type)
+            {
+                (Some(element_type), TypeResolved::Yes) => (Some(array_type_with_length(element_type, length)), TypeResolved::Yes),
+                _ if length != at.length => (Some(array_type_with_length(at.element_type.clone(), length)), TypeResolved::Yes),
+                _ => (None, TypeResolved::Yes),
+            }
+        },
+
         _ => (None, TypeResolved::Yes),
     }
 }
 
-pub fn resolve_type(ctx: &TypeCheckerContext, typ: &mut Type) -> TypeResolved
+pub fn resolve_type(ctx: &TypeCheckerContext, typ: &mut Type, span: &Span) -> CompileResult<TypeResolved>
 {
+    check_generic_arg_counts(ctx, typ, span)?;
     match resolve_type_helper(ctx, typ)
     {
         (Some(resolved_typ), TypeResolved::Yes) => {
             *typ = resolved_typ;
-            TypeResolved::Yes
+            Ok(TypeResolved::Yes)
         },
-        (_, result) => result,
+        (_, result) => Ok(result),
     }
 }
 
@@ -91,24 +217,21 @@ fn resolve_function_args_and_ret_type(ctx: &mut TypeCheckerContext, sig: &mut Fu
         return Ok(TypeResolved::Yes);
     }
 
-    if resolve_type(ctx, &mut sig.return_type) == TypeResolved::No {
+    if resolve_type(ctx, &mut sig.return_type, &sig.span)? == TypeResolved::No {
         return unknown_name_result(&sig.span, format!("Unknown function return type {}", sig.return_type));
     }
 
-    let mut args = Vec::with_capacity(sig.args.len());
     for ref mut arg in &mut sig.args {
-        if resolve_type(ctx, &mut arg.typ) == TypeResolved::No {
+        if resolve_type(ctx, &mut arg.typ, &arg.span)? == TypeResolved::No {
             if mode == ResolveMode::Lazy {
                 return Ok(TypeResolved::No);
             } else {
                 return unknown_name_result(&arg.span, format!("Unknown function argument type {}", arg.typ));
             }
         }
-
-        args.push(arg.typ.clone());
     }
 
-    sig.typ = func_type(args, sig.return_type.clone());
+    sig.typ = sig.get_type();
     Ok(TypeResolved::Yes)
 }
 
@@ -121,7 +244,7 @@ fn resolve_struct_member_types(ctx: &mut TypeCheckerContext, sd: &mut StructDecl
     let mut member_types = Vec::with_capacity(sd.members.len());
     for m in &mut sd.members
     {
-        if resolve_type(ctx, &mut m.typ) == TypeResolved::No {
+        if resolve_type(ctx, &mut m.typ, &m.span)? == TypeResolved::No {
             if mode == ResolveMode::Lazy {
                 return Ok(TypeResolved::No);
             } else {
@@ -162,6 +285,18 @@ fn resolve_sum_case_types(ctx: &mut TypeCheckerContext, st: &mut SumTypeDeclarat
         }
     }
 
+    if st.result {
+        let success_ok = match case_types[0].typ {
+            Type::Struct(ref s) => s.members.len() == 1,
+            _ => false,
+        };
+
+        if case_types.len() != 2 || !success_ok {
+            return type_error_result(&st.span,
+                format!("Result type {} must have two cases, a success case holding a single value, followed by a failure case", st.name));
+        }
+    }
+
     if case_types.iter().all(|ct| ct.typ == target.native_uint_type)
     {
         let case_names: Vec<String> = st.cases.iter().map(|c| c.name.clone()).collect();
@@ -169,7 +304,7 @@ fn resolve_sum_case_types(ctx: &mut TypeCheckerContext, st: &mut SumTypeDeclarat
     }
     else
     {
-        st.typ = sum_type(&st.name, case_types);
+        st.typ = sum_type(&st.name, case_types, st.result);
     }
 
     Ok(TypeResolved::Yes)
@@ -181,7 +316,7 @@ fn resolve_interface_types(ctx: &mut TypeCheckerContext, i: &mut Interface, mode
         return Ok(TypeResolved::Yes);
     }
 
-    let mut generic_args = HashSet::new();
+    let mut generic_args = BTreeSet::new();
     let mut functions = Vec::new();
     for func in &mut i.functions
     {
@@ -202,7 +337,17 @@ fn resolve_interface_types(ctx: &mut TypeCheckerContext, i: &mut Interface, mode
         functions.push(func.clone());
     }
 
-    i.typ = interface_type(&i.name, generic_args.into_iter().collect(), functions);
+    let mut default_impls = Vec::with_capacity(i.default_impls.len());
+    for func in &mut i.default_impls
+    {
+        if let Some(sig) = functions.iter().find(|sig| sig.name == func.sig.name) {
+            func.sig = sig.clone();
+        }
+
+        default_impls.push(func.sig.name.clone());
+    }
+
+    i.typ = interface_type(&i.name, generic_args.into_iter().collect(), functions, default_impls);
     Ok(TypeResolved::Yes)
 }
 
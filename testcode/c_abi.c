@@ -0,0 +1,114 @@
+#include <stdbool.h>
+#include <stdint.h>
+
+typedef struct { int64_t x; int64_t y; } Point;
+typedef struct { double x; double y; } Vec2;
+typedef struct { int32_t a; float b; } Mixed;
+typedef struct { double d; int32_t i; } DoubleInt;
+typedef struct { int64_t a; int64_t b; int64_t c; } Big;
+typedef struct { const uint8_t* data; uint64_t len; } String;
+typedef struct { bool valid; int32_t value; } OptionalInt;
+typedef struct { const int64_t* data; uint64_t len; } Slice;
+
+Point nomad_add_points(Point a, Point b);
+Vec2 nomad_scale(Vec2 v, double f);
+Big nomad_make_big(int64_t a);
+int64_t nomad_sum_big(Big b);
+int32_t nomad_unwrap_or(OptionalInt o, int32_t def);
+int32_t nomad_call_c(void);
+
+Point c_add_points(Point a, Point b)
+{
+    Point p = {a.x + b.x, a.y + b.y};
+    return p;
+}
+
+Vec2 c_scale(Vec2 v, double f)
+{
+    Vec2 r = {v.x * f, v.y * f};
+    return r;
+}
+
+int32_t c_mixed_sum(Mixed m)
+{
+    return m.a + (int32_t)m.b;
+}
+
+DoubleInt c_make_double_int(double d, int32_t i)
+{
+    DoubleInt r = {d, i};
+    return r;
+}
+
+Big c_make_big(int64_t a, int64_t b, int64_t c)
+{
+    Big r = {a, b, c};
+    return r;
+}
+
+int64_t c_sum_big(Big b)
+{
+    return b.a + b.b + b.c;
+}
+
+uint64_t c_string_len(String s)
+{
+    uint64_t len = 0;
+    while (len < s.len && s.data[len] != 0) {
+        len++;
+    }
+    return len;
+}
+
+int32_t c_unwrap_or(OptionalInt o, int32_t def)
+{
+    return o.valid ? o.value : def;
+}
+
+int64_t c_slice_sum(Slice s)
+{
+    int64_t sum = 0;
+    for (uint64_t i = 0; i < s.len; i++) {
+        sum += s.data[i];
+    }
+    return sum;
+}
+
+int64_t c_sum_points(Point a, Point b, Point c, Point d)
+{
+    return a.x + a.y + b.x + b.y + c.x + c.y + d.x + d.y;
+}
+
+int main(void)
+{
+    Point a = {1, 2};
+    Point b = {10, 20};
+    Point p = nomad_add_points(a, b);
+    if (p.x != 11 || p.y != 22) {
+        return 1;
+    }
+
+    Vec2 v = {1.5, -2.0};
+    Vec2 scaled = nomad_scale(v, 2.0);
+    if (scaled.x != 3.0 || scaled.y != -4.0) {
+        return 2;
+    }
+
+    Big big = nomad_make_big(5);
+    if (big.a != 5 || big.b != 10 || big.c != 15) {
+        return 3;
+    }
+
+    Big sum = {1, 2, 3};
+    if (nomad_sum_big(sum) != 6) {
+        return 4;
+    }
+
+    OptionalInt some = {true, 7};
+    OptionalInt none = {false, 0};
+    if (nomad_unwrap_or(some, 1) != 7 || nomad_unwrap_or(none, 1) != 1) {
+        return 5;
+    }
+
+    return nomad_call_c();
+}